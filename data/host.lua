@@ -78,6 +78,7 @@ local PARSE_TEMPLATE = 2
 local PARSE_TAB = 3
 local PARSE_VAR = 4
 local PARSE_CREATE = 8
+local PARSE_FALLBACK = 16
 
 local function toseqidx(graph, x)
 	if type(x) == "string" then
@@ -95,6 +96,7 @@ end
 
 local function checkparse(graph, tab, what, src, ...)
 	local res
+	local num = API.fhk_objnum(graph.G)
 	local args = {...}
 	if #args == 0 and (type(src) == "string" or type(src) == "cdata" or type(src) == "userdata") then
 		res = API.fhk_parse(graph.G, tab, src, #src, what)
@@ -106,6 +108,8 @@ local function checkparse(graph, tab, what, src, ...)
 		local cap, n = setbuf(graph, args)
 		res = API.fhk_tparse(graph.G, tab, src, cap, n, what)
 	end
+	-- new objects invalidate the snapshot, see graph_key.
+	if API.fhk_objnum(graph.G) ~= num then graph.snapshot = nil end
 	return assert(checkres(graph, res))
 end
 
@@ -118,6 +122,14 @@ local function graph_define(graph, src, ...)
 	checkparse(graph, 0, PARSE_DEF, src, ...)
 end
 
+-- define fallback models for missing inputs, eg. graph:impute("model global x = 0").
+-- they are selected only when no other model of the variable applies. the value may be a
+-- constant default or a call back into the host, and a guard that fails leaves the variable
+-- missing, like it would be without the fallback.
+local function graph_impute(graph, src, ...)
+	checkparse(graph, 0, PARSE_DEF+PARSE_FALLBACK, src, ...)
+end
+
 local function createflag(create)
 	if create == false then
 		return 0
@@ -156,16 +168,303 @@ local function graph_dump(graph, flags)
 	local buf = buffer.new()
 	flags = flags or "o"
 	if flags:match("o") then
-		API.fhk_dumpobjs(graph.G)
+		API.fhk_dumpobjs(graph.G, 0)
+		buf:put(getstrbuf(graph))
+	end
+	if flags:match("d") then
+		API.fhk_dumpobjs(graph.G, 1)
 		buf:put(getstrbuf(graph))
 	end
 	return buf:get()
 end
 
+local function graph_hashstats(graph)
+	API.fhk_hashstats(graph.G)
+	return getstrbuf(graph)
+end
+
+-- diagnostics of the compile errors and warnings so far. fmt: "json" for a JSON array, nil for text.
+-- clear: forget them afterwards.
+local function graph_diagnostics(graph, fmt, clear)
+	API.fhk_diagnostics(graph.G, fmt == "json" and 1 or 0, clear and 1 or 0)
+	return getstrbuf(graph)
+end
+
+-- number of functions of the last compilation that depend on any of the given objects,
+-- ie. how much of the graph an edit to them touches.
+local function graph_affected(graph, ...)
+	return tonumber(API.fhk_affected(graph.G, setbufo(graph, {...})))
+end
+
+-- forget cached fields of an edited object.
+local function objreload(obj)
+	for k in pairs(obj) do
+		if k ~= "i" then obj[k] = nil end
+	end
+end
+
+local function checkedit(graph, res)
+	graph.snapshot = nil
+	local _, err = checkres(graph, res)
+	if err then error(err, 3) end
+end
+
+-- set the value of a numeric constant expression, eg. one from graph:expr().
+local function graph_setconst(graph, expr, value)
+	checkedit(graph, API.fhk_setconst(graph.G, expr.i, value))
+	objreload(expr)
+	-- an integer constant may change between KINT and KINT64.
+	setmetatable(expr, graph.obj_mt[API.fhk_objs(graph.G)[expr.i].obj.op])
+end
+
+-- replace the expression that `model` computes `var` with. parse it in the model's table, eg.
+-- graph:expr(model.tab, "x+1"). see graph:affected() for what the edit touches.
+local function graph_setvalue(graph, model, var, expr)
+	checkedit(graph, API.fhk_setvalue(graph.G, model.i, var.i, expr.i))
+	for _,vset in ipairs(model.value) do objreload(vset) end
+end
+
+-- replace the guard of `model`, or remove it with nil.
+local function graph_setguard(graph, model, expr)
+	checkedit(graph, API.fhk_setguard(graph.G, model.i, expr and expr.i or 0))
+	objreload(model)
+end
+
 ---- Settings ------------------------------------------------------------------
 
+-- flags: string of flag letters, or an optimization level 0-3 or "Os". a level also sets the
+-- inline cost, iteration limit, switch minimum and code generator mode, see OptLevel.
 local function graph_optimize(graph, flags)
-	API.fhk_optimize(graph.G, flags, #flags)
+	local res
+	if type(flags) == "number" then
+		res = API.fhk_optlevel(graph.G, flags)
+	elseif flags == "Os" then
+		res = API.fhk_optlevel(graph.G, string.byte("s"))
+	else
+		res = API.fhk_optimize(graph.G, flags, #flags)
+	end
+	local _, err = checkres(graph, res)
+	if err then error(err, 2) end
+end
+
+-- passes: list of pass names in order, nil keeps the current order. maxiter: nil keeps the current limit.
+local function graph_passes(graph, passes, maxiter)
+	local names = passes and table.concat(passes, ",")
+	local _, err = checkres(graph, API.fhk_optpasses(graph.G, names, names and #names or 0, maxiter or 0))
+	if err then error(err, 2) end
+end
+
+local function graph_optstats(graph)
+	API.fhk_optstats(graph.G)
+	return getstrbuf(graph)
+end
+
+-- depth: max expression nesting depth, size: max object graph size in 32-bit words,
+-- memory: max bytes of objects and interned data when compiling, ins: max IR instructions.
+-- nil keeps the current value.
+local function graph_limits(graph, depth, size, memory, ins)
+	API.fhk_parselimits(graph.G, depth or 0, size or 0)
+	API.fhk_compilelimits(graph.G, memory or 0, ins or 0)
+end
+
+-- parse `1,5` as 1.5. arguments must then be separated by a comma and a space.
+local function graph_decimalcomma(graph, on)
+	API.fhk_decimalcomma(graph.G, on == false and 0 or 1)
+end
+
+-- value for constant integer division by zero. nil leaves it to trap at runtime.
+local function graph_divzero(graph, value)
+	local _, err = checkres(graph, API.fhk_divzero(graph.G, value and 1 or 0, value or 0))
+	if err then error(err, 2) end
+end
+
+-- tol=false disables the check
+local function graph_icheck(graph, tol)
+	if tol == false then tol = -1 end
+	local _, err = checkres(graph, API.fhk_icheck(graph.G, tol or 0))
+	if err then error(err, 2) end
+end
+
+-- tolerance of float `=` and `!=` in model guards: a = b is true when |a-b| <= eps.
+-- eps=false compares exactly, and exact float equality in a guard gets a warning.
+local function graph_guardeps(graph, eps)
+	API.fhk_guardeps(graph.G, eps or -1)
+end
+
+-- minimum number of cases to emit an IF chain as a jump table or search tree. 0 disables.
+local function graph_switchmin(graph, min)
+	local _, err = checkres(graph, API.fhk_switchmin(graph.G, min or 0))
+	if err then error(err, 2) end
+end
+
+-- latencies the optimizer assumes: "x86_64", "aarch64" or "generic". defaults to the host.
+local function graph_costmodel(graph, name)
+	local _, err = checkres(graph, API.fhk_costmodel(graph.G, name, #name))
+	if err then error(err, 2) end
+end
+
+-- write a report into `dir` if the compiler crashes. nil disables.
+local function graph_crashdir(graph, dir)
+	API.fhk_crashdir(graph.G, dir, dir and #dir or 0)
+end
+
+-- check IR invariants before and after every optimizer pass
+local function graph_verify(graph, on)
+	local _, err = checkres(graph, API.fhk_verify(graph.G, on == false and 0 or 1))
+	if err then error(err, 2) end
+end
+
+local ASSUME_OP = { ["<"]=0, ["<="]=1, [">"]=2, [">="]=3, ["=="]=4 }
+
+-- declare that `var op rhs` always holds, where rhs is a number or a variable of the same table.
+-- the optimizer may rely on it, see graph:checkassume().
+local function graph_assume(graph, var, op, rhs)
+	local opno = ASSUME_OP[op]
+	if not opno then error(string.format("invalid assumption operator: %s", op), 2) end
+	if not isobj(var) then var = graph_var(graph, nil, var) end
+	local other, k = 0, 0
+	if type(rhs) == "number" then
+		k = rhs
+	else
+		if not isobj(rhs) then rhs = graph_var(graph, nil, rhs) end
+		other = rhs.i
+	end
+	local _, err = checkres(graph, API.fhk_assume(graph.G, var.i, opno, other, k))
+	if err then error(err, 2) end
+end
+
+-- check assumptions at runtime and abort the query if one fails. on by default in debug builds.
+local function graph_checkassume(graph, on)
+	API.fhk_checkassume(graph.G, on == false and 0 or 1)
+end
+
+-- allow float rewrites that differ for signed zeros, infinities or nans, eg. x^0.5 -> sqrt(x)
+local function graph_fastmath(graph, on)
+	local _, err = checkres(graph, API.fhk_fastmath(graph.G, on == false and 0 or 1))
+	if err then error(err, 2) end
+end
+
+-- type of numeric values the model doesn't constrain: "f64" (default) or "f32"
+local function graph_floattype(graph, ty)
+	if ty ~= "f64" and ty ~= "f32" then
+		error(string.format("float type must be f64 or f32: %s", ty), 2)
+	end
+	API.fhk_floattype(graph.G, ty == "f32" and 1 or 0)
+end
+
+-- count calls and cycles of each compiled function, see image:profile()
+local function graph_profile(graph, on)
+	local _, err = checkres(graph, API.fhk_profile(graph.G, on == false and 0 or 1))
+	if err then error(err, 2) end
+end
+
+-- call host hooks on entering and leaving each compiled function, see image:sethooks()
+local function graph_hooks(graph, on)
+	API.fhk_hooks(graph.G, on == false and 0 or 1)
+end
+
+-- let queries compute the chunks they may need ahead on a thread pool, see query:parallel()
+local function graph_parallel(graph, on)
+	API.fhk_parallel(graph.G, on == false and 0 or 1)
+end
+
+-- load the native functions of a plugin library (see plugin.rs), callable in models like host
+-- functions. returns the number of functions loaded.
+local function graph_loadplugin(graph, path)
+	local num, err = checkres(graph, API.fhk_loadplugin(graph.G, path, #path))
+	if err then error(err, 2) end
+	table.insert(graph.plugins, path)
+	return num
+end
+
+-- register a native function, callable in models as name(x, ...). `func` is a C function pointer
+-- called as `ret func(NULL, params...)`, types are type names, eg.
+--   graph:hostfunc("hypot", "f64", {"f64", "f64"}, ffi.cast("double (*)(void *, double, double)", f))
+-- the pointer must stay valid as long as any image that calls it. calls to a `pure` function
+-- may be merged, reordered or folded away.
+local function graph_hostfunc(graph, name, ret, params, func, pure)
+	local sig = ret .. " " .. table.concat(params, " ")
+	local _, err = checkres(graph, API.fhk_hostfunc(graph.G, name, #name, sig, #sig, func,
+		pure and 1 or 0))
+	if err then error(err, 2) end
+end
+
+-- record every rewrite the optimizer applies, see graph:auditlog()
+local function graph_audit(graph, on)
+	API.fhk_audit(graph.G, on == false and 0 or 1)
+end
+
+-- rewrites applied by the last compilation. fmt: "json" for a JSON array, nil for text.
+local function graph_auditlog(graph, fmt)
+	API.fhk_auditlog(graph.G, fmt == "json" and 1 or 0)
+	return getstrbuf(graph)
+end
+
+-- which passes changed each function of the last compilation, and how many logged rewrites
+-- they applied. needs graph:audit(). fmt: "json" for a JSON array, "table" for
+--   { {source=..., passes={fold={changed=..., rewrites=...}, ...}}, ... }
+-- nil for text.
+local function graph_passeffects(graph, fmt)
+	API.fhk_passeffects(graph.G, (fmt == "json" or fmt == "table") and 1 or 0)
+	local s = getstrbuf(graph)
+	if fmt ~= "table" then return s end
+	local out = {}
+	for source, passes in s:gmatch('{"source":"(.-)","passes":{(.-)}}') do
+		local t = {}
+		for pass, changed, rewrites in passes:gmatch('"(%w+)":{"changed":(%d+),"rewrites":(%d+)') do
+			t[pass] = { changed=tonumber(changed), rewrites=tonumber(rewrites) }
+		end
+		table.insert(out, { source=source, passes=t })
+	end
+	return out
+end
+
+-- IR of the last compilation, as a JSON array of functions (see analysis.rs for the format).
+-- fmt="canonical": as text that numbers instructions by reachability instead of id, for
+-- comparing compilations with fhk.irdiff().
+local function graph_ir(graph, fmt)
+	if fmt == "canonical" then
+		API.fhk_ircanon(graph.G)
+	else
+		API.fhk_irjson(graph.G)
+	end
+	return getstrbuf(graph)
+end
+
+-- call f(point, json) with the IR as JSON (see graph:ir()) at each of the pipeline points:
+-- "lower", "optimize", or the name of an optimizer pass, after each of its runs.
+-- points is a list of names or a comma-separated string.
+local function graph_observe(graph, points, f)
+	if type(points) == "table" then points = table.concat(points, ",") end
+	local cb = ffi.cast("fhk_Observe *", function(_, point, plen, json, jlen)
+		f(ffi.string(point, plen), ffi.string(json, jlen))
+	end)
+	local _, err = checkres(graph, API.fhk_observe(graph.G, points, #points, cb, nil))
+	if err then
+		cb:free()
+		error(err, 2)
+	end
+	table.insert(graph.observers, cb)
+end
+
+-- serialize the counters of a profiled image, to be fed back with graph:setprofile()
+local function graph_saveprofile(graph, image)
+	local len = tonumber(API.fhk_saveprofile(graph.G, image))
+	if len < 0 then error("image wasn't compiled with profiling", 2) end
+	return ffi.string(API.fhk_buf(graph.G), len)
+end
+
+-- optimize the next compilation for a profile saved from a previous run
+local function graph_setprofile(graph, data)
+	local _, err = checkres(graph, API.fhk_setprofile(graph.G, data, #data))
+	if err then error(err, 2) end
+end
+
+-- check that every IR opcode's operand encoding round-trips through its accessors
+local function graph_selfcheck(graph)
+	if API.fhk_selfcheck(graph.G) == 0 then
+		error(string.format("IR self check failed: %s", getstrbuf(graph)), 2)
+	end
 end
 
 ---- Object management ---------------------------------------------------------
@@ -471,9 +770,59 @@ end
 local function compilequery(query, image)
 	local ct = queryctype(query)
 	local mcode = ffi.cast("const uint8_t *", image) + query.obj.mcode
+	query.ctype = ct
+	query.mcode = mcode
 	query.query = queryfunc(ct, mcode)
 end
 
+-- evaluate the query on each of `instances`. `pool` is an optional fhk_Pool that runs the
+-- instances on multiple threads.
+-- without `errors`, the first failed instance raises its error. with an `errors` table, every
+-- failure is appended to it as {instance=i, kind="abort"|"error", message=...}, the result of
+-- the failed instance is false, and the other results are returned as usual.
+local function query_many(query, instances, pool, ud, errors)
+	local n = #instances
+	local insts = ffi.new("fhk_Instance *[?]", n)
+	local rets = ffi.new("void *[?]", n)
+	local status = ffi.new("int32_t[?]", n)
+	local res = {}
+	for i=1, n do
+		res[i] = query.ctype()
+		insts[i-1] = instances[i]
+		rets[i-1] = res[i]
+	end
+	if API.fhk_vmcallmany(insts, rets, n, ffi.cast("uintptr_t", query.mcode), status, pool, ud) > 0 then
+		for i=0, n-1 do
+			if status[i] ~= 0 then
+				local message = ffi.string(API.fhk_vmerr(insts[i]))
+				if not errors then error(message, 2) end
+				table.insert(errors, {
+					instance = i+1,
+					kind     = API.fhk_vmaborted(insts[i]) ~= 0 and "abort" or "error",
+					message  = message
+				})
+				res[i+1] = false
+			end
+		end
+	end
+	return res
+end
+
+-- evaluate the query on `instance` of `image`. with an image compiled with graph:parallel() and
+-- a fhk_Pool `pool`, the chunks the query may need are first computed on the pool, those that
+-- don't depend on each other in parallel. the models must not call Lua, and the allocator of the
+-- instance must be thread-safe. otherwise this is the same as query.query(instance, res).
+local function query_parallel(query, image, instance, pool, ud, res)
+	local ret = res or query.ctype()
+	if API.fhk_vmcallpar(image, instance, ret, ffi.cast("uintptr_t", query.mcode), pool, ud) ~= 0 then
+		error(ffi.string(API.fhk_vmerr(instance)), 2)
+	end
+	return ret
+end
+
+query_mt.many = query_many
+query_mt.parallel = query_parallel
+
 ---- Resets --------------------------------------------------------------------
 
 local function reset_add(reset, obj)
@@ -512,28 +861,276 @@ end
 ---- Instances -----------------------------------------------------------------
 
 local function image_newinstance(image, alloc, udata, prev, mask)
+	if ffi.istype("fhk_Guard *", alloc) then
+		alloc, udata = API.fhk_guardalloc, alloc
+	end
 	return API.fhk_newinstance(image, alloc, udata or nil, prev or nil, mask or -1ull)
 end
 
+-- describe the source expression, or if it's not known, the model that the machine code
+-- address `pc` belongs to, eg. for a fault address.
+local function image_srcloc(image, pc)
+	local s = API.fhk_srcloc(image, ffi.cast("uintptr_t", pc))
+	if s ~= nil then return ffi.string(s) end
+end
+
+-- start address of the machine code.
+local function image_mcode(image)
+	return API.fhk_mcode(image)
+end
+
+-- counters of the functions called since compilation or the last reset:
+--   { {name=..., calls=..., cycles=...}, ... }
+-- cycles include callees and are counted in whatever unit the cpu's tick counter uses.
+local function image_profile(image)
+	local out = {}
+	local ctr = ffi.new("uint64_t[2]")
+	for i=0, API.fhk_profnum(image)-1 do
+		API.fhk_profread(image, i, ctr)
+		if ctr[0] > 0 then
+			table.insert(out, {
+				name   = ffi.string(API.fhk_profname(image, i)),
+				calls  = tonumber(ctr[0]),
+				cycles = tonumber(ctr[1])
+			})
+		end
+	end
+	return out
+end
+
+local function image_profreset(image)
+	API.fhk_profreset(image)
+end
+
+-- the profile as a text table, most cycles first.
+local function image_profreport(image)
+	local s = API.fhk_profreport(image)
+	if s ~= nil then return ffi.string(s) end
+end
+
+-- lua callbacks must stay alive as long as the image calls them.
+local image_hookrefs = setmetatable({}, {__mode="k"})
+
+-- set the callbacks of an image compiled with graph:hooks():
+--   enter(func, start, end), exit(func, start, end)
+-- func identifies the compiled function (see image:hookname()), start..end is the instance
+-- range it computes, empty for queries. a query that fails doesn't call exit for the
+-- functions it leaves. either callback may be nil, or a fhk_Hook pointer taking udata.
+local function image_sethooks(image, enter, exit, udata)
+	for _,cb in ipairs(image_hookrefs[image] or {}) do
+		cb:free()
+	end
+	local refs = {}
+	local function hook(f)
+		if type(f) ~= "function" then return f end
+		local cb = ffi.cast("fhk_Hook *", function(_, func, start, end_) f(func, start, end_) end)
+		table.insert(refs, cb)
+		return cb
+	end
+	image_hookrefs[image] = refs
+	if API.fhk_sethooks(image, hook(enter), hook(exit), udata) < 0 then
+		error("image wasn't compiled with hooks", 2)
+	end
+end
+
+-- describe the function a hook was called for.
+local function image_hookname(image, func)
+	return ffi.string(API.fhk_hookname(image, func))
+end
+
+-- emitted code size of each model, query, etc., largest first, as a text table.
+-- when compiled with profiling, also the cycles spent in each.
+local function image_sizereport(image)
+	return ffi.string(API.fhk_sizereport(image))
+end
+
 local image_mt = {
-	newinstance = image_newinstance
+	newinstance = image_newinstance,
+	srcloc      = image_srcloc,
+	mcode       = image_mcode,
+	profile     = image_profile,
+	profreset   = image_profreset,
+	profreport  = image_profreport,
+	sethooks    = image_sethooks,
+	hookname    = image_hookname,
+	sizereport  = image_sizereport
 }
 image_mt.__index = image_mt
 
 ffi.metatype("fhk_Image", image_mt)
 
+---- Guard allocator -----------------------------------------------------------
+
+-- debug allocator for instance state: every allocation gets its own mapping between guard pages,
+-- so out-of-bounds accesses fault at the offending instruction. pass it in place of the alloc
+-- function:
+--   image:newinstance(guard, nil, prev, mask)
+-- the memory is released when the guard is collected, so it must outlive its instances.
+local function newguard()
+	return ffi.gc(API.fhk_newguard(), API.fhk_destroyguard)
+end
+
+-- address of the first overwritten padding byte, or nil if all padding is intact.
+local function guard_check(guard)
+	local p = API.fhk_guardcheck(guard)
+	if p ~= nil then return p end
+end
+
+local guard_mt = {
+	check = guard_check
+}
+guard_mt.__index = guard_mt
+
+ffi.metatype("fhk_Guard", guard_mt)
+
+---- Compilation database ------------------------------------------------------
+
+-- the database is a text file with one line per compilation:
+--   hash <TAB> status <TAB> time <TAB> date <TAB> message
+-- where status is ok, cached (loaded from opt.cache) or error. it only records history: images
+-- are not stored in the database, see opt.cache for that, and failed compilations are always
+-- retried, since whatever made them fail may be outside the graph.
+
+local function db_escape(s)
+	return (s:gsub("[\\\n\t]", {["\\"]="\\\\", ["\n"]="\\n", ["\t"]="\\t"}))
+end
+
+local function db_unescape(s)
+	return (s:gsub("\\(.)", {["\\"]="\\", n="\n", t="\t"}))
+end
+
+local function db_history(path, hash)
+	local fp = io.open(path)
+	local recs = {}
+	if not fp then return recs end
+	for line in fp:lines() do
+		local h, status, time, date, message = line:match("^(%d+)\t(%a+)\t([^\t]+)\t(%d+)\t(.*)$")
+		if h and (hash == nil or h == hash) then
+			table.insert(recs, {
+				hash    = h,
+				status  = status,
+				time    = tonumber(time),
+				date    = tonumber(date),
+				message = db_unescape(message)
+			})
+		end
+	end
+	fp:close()
+	return recs
+end
+
+local function db_record(path, hash, status, time, message)
+	local fp = assert(io.open(path, "a"))
+	fp:write(hash, "\t", status, "\t", string.format("%.6f", time), "\t", os.time(), "\t",
+		db_escape(message or ""), "\n")
+	fp:close()
+end
+
+local function keystr(key)
+	return (tostring(key):gsub("ULL$", ""))
+end
+
+-- hash of the objects as they were before the first compilation since they changed. compiling
+-- annotates the objects and adds query objects, and new objects reset the snapshot.
+local function snapshot(graph)
+	graph.snapshot = graph.snapshot or keystr(API.fhk_hashobjs(graph.G))
+	return graph.snapshot
+end
+
+local function objidx(objs)
+	local idx = {}
+	for i,o in ipairs(objs) do idx[i] = o.i end
+	return table.concat(idx, ",")
+end
+
+-- hash of everything that affects the compilation result: the settings, the object snapshot, the
+-- queries and resets, and the files the graph depends on without containing them: its plugins
+-- and `sources`, a list of paths (eg. R or Lua files that the model calls into).
+local function graph_key(graph, sources)
+	local key = API.fhk_hash(graph.G)
+	local function mix(s) key = API.fhk_hashmix(key, s, #s) end
+	mix(snapshot(graph))
+	for _,query in ipairs(graph.queries) do
+		mix(string.format("query %d %s", query.tab.i, objidx(query.values)))
+	end
+	for _,reset in ipairs(graph.resets) do
+		mix(string.format("reset %s", objidx(reset.objs)))
+	end
+	local function file(path)
+		local fp = assert(io.open(path, "rb"))
+		local data = fp:read("*a")
+		fp:close()
+		mix(path)
+		mix(data)
+	end
+	for _,path in ipairs(graph.plugins) do file(path) end
+	for _,path in ipairs(sources or {}) do file(path) end
+	return key
+end
+
+local function graph_hash(graph, sources)
+	return keystr(graph_key(graph, sources))
+end
+
 ---- Compilation ---------------------------------------------------------------
 
-local function graph_compile(graph)
+local function cache_load(graph, path, key, image)
+	local fp = io.open(path, "rb")
+	if not fp then return false end
+	local data = fp:read("*a")
+	fp:close()
+	-- a stale or corrupt image is not an error, it just gets recompiled and overwritten.
+	return API.fhk_loadimage(graph.G, data, #data, key, image) == 0
+end
+
+local function cache_save(graph, path, key, image)
+	local len = tonumber(API.fhk_saveimage(graph.G, image, key))
+	if len < 0 then return end
+	local fp = io.open(path, "wb")
+	if not fp then return end
+	fp:write(ffi.string(API.fhk_buf(graph.G), len))
+	fp:close()
+end
+
+local function newobjs(graph)
+	snapshot(graph)
 	for _,query in ipairs(graph.queries) do
 		query.obj = graph.objs[API.fhk_newquery(graph.G, query.tab.i, setbufo(graph, query.values))]
 	end
 	for _,reset in ipairs(graph.resets) do
 		reset.obj = graph.objs[API.fhk_newreset(graph.G, setbufo(graph, reset.objs))]
 	end
+end
+
+-- opt.db: path to compilation database (optional)
+-- opt.cache: directory for cached images (optional)
+-- opt.sources: files that the model depends on outside of the graph, for opt.db and opt.cache
+local function graph_compile(graph, opt)
+	local db = opt and opt.db
+	local cache = opt and opt.cache
+	local key, hash
+	if db or cache then
+		key = graph_key(graph, opt.sources)
+		hash = keystr(key)
+	end
+	newobjs(graph)
 	local image = ffi.new("fhk_Image *[1]");
-	assert(checkres(graph, API.fhk_compile(graph.G, image)))
+	local start = os.clock()
+	local path = cache and string.format("%s/%s.fhkimg", cache, hash)
+	local ok, err, cached
+	if cache and cache_load(graph, path, key, image) then
+		ok, cached = true, true
+	else
+		ok, err = checkres(graph, API.fhk_compile(graph.G, image))
+	end
+	if db then
+		db_record(db, hash, ok and (cached and "cached" or "ok") or "error", os.clock()-start, err)
+	end
+	assert(ok, err)
 	local ptr = image[0]
+	if cache and not cached then
+		cache_save(graph, path, key, ptr)
+	end
 	local base = API.fhk_mcode(ptr)
 	for _,query in ipairs(graph.queries) do
 		compilequery(query, base)
@@ -544,18 +1141,76 @@ local function graph_compile(graph)
 	return ffi.gc(ptr, API.fhk_destroyimage)
 end
 
+-- write the compiled queries into a relocatable object file at `path`.
+-- symbols are named `<prefix>_query<i>`, `<prefix>_reset<i>` and `<prefix>_image`.
+local function graph_compileobject(graph, path, prefix)
+	newobjs(graph)
+	prefix = prefix or "fhk"
+	local len = assert(checkres(graph, API.fhk_compileobj(graph.G, prefix, #prefix)))
+	local fp = assert(io.open(path, "wb"))
+	fp:write(ffi.string(API.fhk_buf(graph.G), len))
+	fp:close()
+end
+
+-- typed bindings for the query entry points of graph:compileobject(), as Rust source, or a C
+-- header with lang="c". call after compiling.
+local function graph_bindings(graph, prefix, lang)
+	prefix = prefix or "fhk"
+	local _, err = checkres(graph, API.fhk_bindings(graph.G, prefix, #prefix, lang == "c" and 1 or 0))
+	if err then error(err, 2) end
+	return getstrbuf(graph)
+end
+
 --------------------------------------------------------------------------------
 
 local graph_mt = {
 	objects  = graph_objects,
 	define   = graph_define,
+	impute   = graph_impute,
 	var      = graph_var,
 	expr     = graph_expr,
 	newquery = graph_newquery,
 	newreset = graph_newreset,
 	dump     = graph_dump,
 	optimize = graph_optimize,
-	compile  = graph_compile
+	passes   = graph_passes,
+	optstats = graph_optstats,
+	diagnostics = graph_diagnostics,
+	affected = graph_affected,
+	setconst = graph_setconst,
+	setvalue = graph_setvalue,
+	setguard = graph_setguard,
+	limits   = graph_limits,
+	decimalcomma = graph_decimalcomma,
+	icheck   = graph_icheck,
+	guardeps = graph_guardeps,
+	divzero  = graph_divzero,
+	switchmin = graph_switchmin,
+	costmodel = graph_costmodel,
+	crashdir = graph_crashdir,
+	verify   = graph_verify,
+	assume   = graph_assume,
+	checkassume = graph_checkassume,
+	fastmath = graph_fastmath,
+	floattype = graph_floattype,
+	profile  = graph_profile,
+	hooks    = graph_hooks,
+	parallel = graph_parallel,
+	loadplugin = graph_loadplugin,
+	hostfunc = graph_hostfunc,
+	audit    = graph_audit,
+	auditlog = graph_auditlog,
+	passeffects = graph_passeffects,
+	ir       = graph_ir,
+	observe  = graph_observe,
+	saveprofile = graph_saveprofile,
+	setprofile = graph_setprofile,
+	selfcheck = graph_selfcheck,
+	compile  = graph_compile,
+	compileobject = graph_compileobject,
+	bindings = graph_bindings,
+	hash     = graph_hash,
+	hashstats = graph_hashstats
 }
 graph_mt.__index = graph_mt
 
@@ -567,6 +1222,8 @@ local function newgraph()
 		num     = 0,
 		queries = {},
 		resets  = {},
+		plugins = {},
+		observers = {},
 	}, graph_mt)
 	graph.obj_mt = makeobjmts(graph)
 	graph.objs   = makeobjtab(graph)
@@ -575,9 +1232,344 @@ local function newgraph()
 	return graph
 end
 
+---- Precision -----------------------------------------------------------------
+
+local function flatvalue(x, out)
+	if type(x) == "table" then
+		for _,v in ipairs(x) do flatvalue(v, out) end
+	elseif tensor.istensor(x) then
+		flatvalue(x:totable(), out)
+	else
+		table.insert(out, tonumber(x))
+	end
+	return out
+end
+
+-- compile the models defined by `build(graph)` twice, with f64 and with f32 as the float type,
+-- and compare the values of `exprs` in table `tab` between the two. for checking a model before
+-- switching it to f32.
+--   opt.samples: number of evaluations (default 1)
+--   opt.sample(i): called before the i'th evaluation, eg. to set the inputs the models read
+-- returns one entry per expression, with the errors of the f32 values relative to f64:
+--   { {expr=..., n=..., maxabs=..., maxrel=..., meanrel=..., p99rel=...}, ... }
+local function compareprecision(build, tab, exprs, opt)
+	local samples = opt and opt.samples or 1
+	local sample = opt and opt.sample
+	local queries, images = {}, {}
+	for i,ty in ipairs({"f64", "f32"}) do
+		local graph = newgraph()
+		graph_floattype(graph, ty)
+		build(graph)
+		queries[i] = graph_newquery(graph, tab, unpack(exprs))
+		images[i] = graph_compile(graph)
+	end
+	local errs = {}
+	for j=1, #exprs do errs[j] = {abs={}, rel={}} end
+	local guard = newguard()
+	for s=1, samples do
+		if sample then sample(s) end
+		local ref = {queries[1].query(image_newinstance(images[1], guard)):unpack()}
+		local val = {queries[2].query(image_newinstance(images[2], guard)):unpack()}
+		for j=1, #exprs do
+			local a, b = flatvalue(ref[j], {}), flatvalue(val[j], {})
+			assert(#a == #b, "shape differs between f64 and f32")
+			for k=1, #a do
+				local d = math.abs(a[k]-b[k])
+				table.insert(errs[j].abs, d)
+				table.insert(errs[j].rel, a[k] == 0 and d or d/math.abs(a[k]))
+			end
+		end
+	end
+	local out = {}
+	for j,e in ipairs(errs) do
+		local n = #e.rel
+		local maxabs, maxrel, sum = 0, 0, 0
+		for k=1, n do
+			maxabs = math.max(maxabs, e.abs[k])
+			maxrel = math.max(maxrel, e.rel[k])
+			sum = sum+e.rel[k]
+		end
+		table.sort(e.rel)
+		out[j] = {
+			expr    = exprs[j],
+			n       = n,
+			maxabs  = maxabs,
+			maxrel  = maxrel,
+			meanrel = n > 0 and sum/n or 0,
+			p99rel  = n > 0 and e.rel[math.max(1, math.ceil(0.99*n))] or 0
+		}
+	end
+	return out
+end
+
+---- Reduction -----------------------------------------------------------------
+
+local function splitlines(src)
+	local lines = {}
+	for line in (src.."\n"):gmatch("(.-)\n") do
+		table.insert(lines, line)
+	end
+	return lines
+end
+
+-- shrink `src` to a smaller source that is still `interesting(src)`, eg. one that still fails to
+-- compile with the same error, or still gives different results at two optimization levels.
+-- the predicate gets the candidate source and must not raise. until neither step changes
+-- anything:
+--   * remove chunks of lines, halving the chunk size down to single lines
+--   * replace the right-hand side of each `name = expr` line with 0 or 1
+-- returns the reduced source and the number of predicate calls.
+local function reduce(src, interesting)
+	if not interesting(src) then
+		error("the original source is not interesting", 2)
+	end
+	local lines = splitlines(src)
+	local calls = 1
+	local function try(cand)
+		calls = calls+1
+		return interesting(table.concat(cand, "\n"))
+	end
+	local changed = true
+	while changed do
+		changed = false
+		local n = math.ceil(#lines/2)
+		while n > 0 do
+			local i = 1
+			while i <= #lines do
+				local cand = {}
+				for j=1, #lines do
+					if j < i or j >= i+n then table.insert(cand, lines[j]) end
+				end
+				if try(cand) then
+					lines, changed = cand, true
+				else
+					i = i+n
+				end
+			end
+			n = n > 1 and math.ceil(n/2) or 0
+		end
+		for i,line in ipairs(lines) do
+			local lhs, rhs = line:match("^(%s*[%w_]+%s*=%s*)(.-)%s*$")
+			if rhs and rhs ~= "" and rhs ~= "0" and rhs ~= "1" then
+				for _,k in ipairs({"0", "1"}) do
+					local cand = {unpack(lines)}
+					cand[i] = lhs..k
+					if try(cand) then
+						lines, changed = cand, true
+						break
+					end
+				end
+			end
+		end
+	end
+	return table.concat(lines, "\n"), calls
+end
+
+---- IR diff -------------------------------------------------------------------
+
+local function irfuncs(ir)
+	local funcs, order, lines = {}, {}, nil
+	for line in ir:gmatch("[^\n]+") do
+		local header = line:match("^%-+ FUNC (.-) %-+$")
+		if header then
+			lines = {}
+			funcs[header] = lines
+			table.insert(order, header)
+		elseif lines then
+			table.insert(lines, line)
+		end
+	end
+	return funcs, order
+end
+
+-- compare two canonical IR listings (graph:ir("canonical")). returns nil if they are the same,
+-- otherwise a report of the functions that exist in only one of them, and the first differing
+-- line of each function that changed.
+local function irdiff(a, b)
+	local fa, oa = irfuncs(a)
+	local fb, ob = irfuncs(b)
+	local out = {}
+	for _,f in ipairs(oa) do
+		if not fb[f] then
+			table.insert(out, string.format("- %s", f))
+		end
+	end
+	for _,f in ipairs(ob) do
+		if not fa[f] then
+			table.insert(out, string.format("+ %s", f))
+		end
+	end
+	for _,f in ipairs(oa) do
+		local la, lb = fa[f], fb[f]
+		if lb then
+			for i=1, math.max(#la, #lb) do
+				if la[i] ~= lb[i] then
+					table.insert(out, string.format("~ %s\n  - %s\n  + %s", f,
+						la[i] or "(end)", lb[i] or "(end)"))
+					break
+				end
+			end
+		end
+	end
+	if #out > 0 then return table.concat(out, "\n") end
+end
+
+---- Test corpus ---------------------------------------------------------------
+
+-- a corpus is a directory with one subdirectory per case, and optionally a file `cases` that
+-- lists them (see runcorpus). a case has:
+--   model.fhk        model source (required)
+--   input.lua        returns the inputs as { tab = { var = value, ... }, ... }. values are
+--                    numbers, or (nested) tables of numbers for vectors and matrices.
+--   expected.lua     returns the expected values as { tab = { expr = value, ... }, ... }
+--   diagnostics.txt  one Lua pattern per line that the diagnostics must match. a case with
+--                    this file may fail to compile, then expected.lua is not checked.
+-- opt.setup(graph): called on each new graph before defining anything, eg. to set options.
+-- opt.tol: allowed absolute difference of numbers (default 0).
+
+local function readfile(path)
+	local fp = io.open(path, "rb")
+	if not fp then return end
+	local data = fp:read("*a")
+	fp:close()
+	return data
+end
+
+local function casefile(path)
+	local src = readfile(path)
+	if src then return assert(loadstring(src, "@"..path))() end
+end
+
+local function inputvalue(value)
+	if type(value) == "number" then
+		return string.format("%.17g", value)
+	end
+	local rows = {}
+	for i,row in ipairs(value) do
+		if type(row) == "table" then
+			local cols = {}
+			for j,v in ipairs(row) do cols[j] = string.format("%.17g", v) end
+			rows[i] = table.concat(cols, " ")
+		else
+			rows[i] = string.format("%.17g", row)
+		end
+	end
+	return string.format("[| %s |]", table.concat(rows, type(value[1]) == "table" and "; " or " "))
+end
+
+local function sortedkeys(tab)
+	local keys = {}
+	for k in pairs(tab) do table.insert(keys, k) end
+	table.sort(keys)
+	return keys
+end
+
+local function checkvalue(expr, value, expected, tol)
+	local a, b = flatvalue(value, {}), flatvalue(expected, {})
+	local ok = #a == #b
+	for i=1, ok and #a or 0 do
+		if math.abs(a[i]-b[i]) > tol then ok = false end
+	end
+	if not ok then
+		error(string.format("%s: computed %s, expected %s", expr, table.concat(a, " "),
+			table.concat(b, " ")), 0)
+	end
+end
+
+-- run the case in `dir`. raises an error describing the first mismatch.
+local function runcase(dir, opt)
+	local graph = newgraph()
+	if opt and opt.setup then opt.setup(graph) end
+	local src = readfile(dir.."/model.fhk")
+	if not src then error(string.format("%s: missing model.fhk", dir), 0) end
+	local input = casefile(dir.."/input.lua") or {}
+	local expected = casefile(dir.."/expected.lua") or {}
+	local patterns = readfile(dir.."/diagnostics.txt")
+	local queries = {}
+	local ok, image = pcall(function()
+		graph_define(graph, src)
+		for _,tab in ipairs(sortedkeys(input)) do
+			local defs = {}
+			for _,var in ipairs(sortedkeys(input[tab])) do
+				table.insert(defs, string.format("\t%s = %s", var, inputvalue(input[tab][var])))
+			end
+			graph_define(graph, string.format("model %s {\n%s\n}", tab, table.concat(defs, "\n")))
+		end
+		for _,tab in ipairs(sortedkeys(expected)) do
+			local exprs = sortedkeys(expected[tab])
+			local query = graph_newquery(graph, tab, unpack(exprs))
+			table.insert(queries, {tab=tab, exprs=exprs, query=query})
+		end
+		return graph_compile(graph)
+	end)
+	if patterns then
+		local text = graph_diagnostics(graph)
+		if not ok then text = text.."\n"..tostring(image) end
+		for pat in patterns:gmatch("[^\n]+") do
+			if not text:match(pat) then
+				error(string.format("diagnostics don't match `%s`:\n%s", pat, text), 0)
+			end
+		end
+		if not ok then return end
+	end
+	if not ok then error(image, 0) end
+	local guard = newguard()
+	local instance = image_newinstance(image, guard)
+	local tol = opt and opt.tol or 0
+	for _,q in ipairs(queries) do
+		local values = {q.query.query(instance):unpack()}
+		for i,expr in ipairs(q.exprs) do
+			checkvalue(q.tab.."."..expr, values[i], expected[q.tab][expr], tol)
+		end
+	end
+end
+
+-- run the cases of the corpus in `dir` listed in opt.cases, or in the file `cases` of the corpus,
+-- one name per line. returns the number of failed cases and the results:
+--   { {name=..., ok=..., err=...}, ... }
+local function runcorpus(dir, opt)
+	local cases = opt and opt.cases
+	if not cases then
+		local list = readfile(dir.."/cases")
+		if not list then error(string.format("%s: no opt.cases and no cases file", dir), 2) end
+		cases = {}
+		for name in list:gmatch("[^\r\n]+") do table.insert(cases, name) end
+	end
+	local results, failed = {}, 0
+	for i,name in ipairs(cases) do
+		local ok, err = pcall(runcase, dir.."/"..name, opt)
+		if not ok then failed = failed+1 end
+		results[i] = {name=name, ok=ok, err=err}
+	end
+	return failed, results
+end
+
+---- Trace ---------------------------------------------------------------------
+
+-- convert a binary trace file (FHK_TRACE_FILE) to text, or trace-event json if fmt="json".
+local function tracedump(path, fmt)
+	local fp = assert(io.open(path, "rb"))
+	local data = fp:read("*a")
+	fp:close()
+	local G = ffi.gc(API.fhk_newgraph(), API.fhk_destroygraph)
+	if API.fhk_tracedump(G, data, #data, fmt == "json" and 1 or 0) == 0 then
+		error(string.format("%s: not a binary trace", path))
+	end
+	return ffi.string(API.fhk_buf(G))
+end
+
 return {
 	version  = version,
 	newgraph = newgraph,
+	compareprecision = compareprecision,
+	newguard = newguard,
 	refs     = obj_refs,
-	istensor = tensor.istensor
+	history  = db_history,
+	irdiff   = irdiff,
+	reduce   = reduce,
+	runcase  = runcase,
+	runcorpus = runcorpus,
+	trace    = tracedump,
+	istensor = tensor.istensor,
+	interp   = API.fhk_interp() ~= 0
 }
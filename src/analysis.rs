@@ -0,0 +1,343 @@
+//! Read-only views of the IR.
+
+// the IR types change whenever the optimizer needs them to, so code that only reads the IR
+// (observers, the host's IR export) goes through these views instead. a view borrows the IR and
+// the object graph, so it can't outlive the compilation that produced it or change anything, and
+// it only exposes what doesn't depend on how the optimizer stores things:
+//
+//   * functions: kind, source, signature, phis and code.
+//   * instructions: opcode, type and decoded operands.
+//
+// ids are plain numbers. instruction, control and phi ids are local to their function, function
+// ids are global. types and opcodes are given by name.
+//
+// ids are not stable across compilations: any pass that adds or removes an instruction, and CSE
+// in particular, renumbers everything after it. the canonical listing (`write_canonical`) numbers
+// instructions in the order they are reached from the entry instead, and names functions by their
+// source, so two compilations that produce the same code list the same text.
+
+use core::fmt::Write;
+
+use alloc::vec::Vec;
+
+use crate::bump::Bump;
+use crate::dump::dump_kvalue;
+use crate::index;
+use crate::intern::Intern;
+use crate::ir::{Func, FuncId, FuncKind, Ins, InsId, Opcode, OperandData, PhiId, IR};
+use crate::obj::Objects;
+use crate::symbol::write_source;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FuncRole {
+    User,  // called from other functions
+    Query, // entry point of a query
+    Chunk  // computes a variable or model
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Operand {
+    Value(u16),       // instruction in the same function
+    Control(u16),     // control instruction in the same function
+    Phi(u16),         // phi in the same function
+    Func(u16),        // function
+    LangOp(u8, u8),   // language, language-specific opcode
+    Literal(i64)
+}
+
+#[derive(Clone, Copy)]
+pub struct IrView<'a> {
+    ir: &'a IR,
+    intern: &'a Intern,
+    objs: &'a Objects
+}
+
+#[derive(Clone, Copy)]
+pub struct FuncView<'a> {
+    view: IrView<'a>,
+    id: FuncId,
+    func: &'a Func
+}
+
+#[derive(Clone, Copy)]
+pub struct InsView {
+    id: InsId,
+    ins: Ins
+}
+
+impl<'a> IrView<'a> {
+
+    pub fn new(ir: &'a IR, intern: &'a Intern, objs: &'a Objects) -> Self {
+        Self { ir, intern, objs }
+    }
+
+    pub fn funcs(self) -> impl Iterator<Item=FuncView<'a>> {
+        self.ir.funcs.pairs().map(move |(id, func)| FuncView { view: self, id, func })
+    }
+
+    pub fn func(self, id: u16) -> Option<FuncView<'a>> {
+        self.ir.funcs.raw.get(id as usize)
+            .map(|func| FuncView { view: self, id: (id as usize).into(), func })
+    }
+
+}
+
+impl<'a> FuncView<'a> {
+
+    pub fn id(self) -> u16 {
+        zerocopy::transmute!(self.id)
+    }
+
+    pub fn role(self) -> FuncRole {
+        match self.func.kind {
+            FuncKind::User() => FuncRole::User,
+            FuncKind::Query(_) => FuncRole::Query,
+            FuncKind::Chunk(_) => FuncRole::Chunk
+        }
+    }
+
+    // description of the object the function was generated from, eg. `global.x.value`.
+    pub fn write_source(self, buf: &mut Bump) {
+        write_source(buf, self.view.intern, self.view.objs, self.func.source);
+    }
+
+    pub fn entry(self) -> u16 {
+        zerocopy::transmute!(self.func.entry)
+    }
+
+    // type of each phi. the first `returns` phis are the return values, the next `params`
+    // phis are the parameters.
+    pub fn phis(self) -> impl Iterator<Item=&'static str> + 'a {
+        index::iter_span(self.func.phis.end())
+            .map(move |p: PhiId| self.func.phis.at(p).type_.name())
+    }
+
+    pub fn returns(self) -> usize {
+        let n: u16 = zerocopy::transmute!(self.func.ret);
+        n as _
+    }
+
+    pub fn params(self) -> usize {
+        let (ret, arg): (u16, u16) = (zerocopy::transmute!(self.func.ret),
+            zerocopy::transmute!(self.func.arg));
+        (arg - ret) as _
+    }
+
+    pub fn code(self) -> impl Iterator<Item=InsView> + 'a {
+        self.func.code.pairs().map(|(id, ins)| InsView { id, ins })
+    }
+
+    pub fn ins(self, id: u16) -> Option<InsView> {
+        let id: InsId = (id as usize).into();
+        match id < self.func.code.end() {
+            true => Some(InsView { id, ins: self.func.code.at(id) }),
+            false => None
+        }
+    }
+
+}
+
+impl InsView {
+
+    pub fn id(self) -> u16 {
+        zerocopy::transmute!(self.id)
+    }
+
+    pub fn opcode(self) -> &'static str {
+        self.ins.opcode().name()
+    }
+
+    pub fn type_(self) -> &'static str {
+        self.ins.type_().name()
+    }
+
+    pub fn operands(self) -> impl Iterator<Item=Operand> {
+        self.ins.operands().map(|op| match op {
+            OperandData::V(v) => Operand::Value(zerocopy::transmute!(v)),
+            OperandData::C(c) => Operand::Control(zerocopy::transmute!(c)),
+            OperandData::P(p) => Operand::Phi(zerocopy::transmute!(p)),
+            OperandData::F(f) => Operand::Func(zerocopy::transmute!(f)),
+            OperandData::L(l) => Operand::LangOp(l.lang, l.op),
+            OperandData::X(x) => Operand::Literal(x as i16 as _),
+            OperandData::XX(x) => Operand::Literal(x as i32 as _)
+        })
+    }
+
+}
+
+fn write_json_operand(buf: &mut Bump, op: Operand) {
+    match op {
+        Operand::Value(v) => write!(buf, "{{\"value\":{}}}", v),
+        Operand::Control(c) => write!(buf, "{{\"control\":{}}}", c),
+        Operand::Phi(p) => write!(buf, "{{\"phi\":{}}}", p),
+        Operand::Func(f) => write!(buf, "{{\"func\":{}}}", f),
+        Operand::LangOp(lang, op) => write!(buf, "{{\"lang\":{},\"op\":{}}}", lang, op),
+        Operand::Literal(k) => write!(buf, "{{\"literal\":{}}}", k)
+    }.unwrap()
+}
+
+// the whole IR as a JSON array of functions, for tools outside the process:
+//   {"id", "role", "source", "entry", "returns", "params", "phis": [type],
+//    "code": [{"id", "op", "type", "operands": [{"value"|"control"|"phi"|"func"|"literal": n}
+//                                               | {"lang", "op"}]}]}
+pub fn write_json(buf: &mut Bump, view: IrView) {
+    let mut name = Bump::default();
+    buf.push(b'[');
+    for (i, func) in view.funcs().enumerate() {
+        if i > 0 { buf.push(b','); }
+        name.clear();
+        func.write_source(&mut name);
+        write!(buf, "{{\"id\":{},\"role\":\"{}\",\"source\":\"", func.id(), match func.role() {
+            FuncRole::User => "user",
+            FuncRole::Query => "query",
+            FuncRole::Chunk => "chunk"
+        }).unwrap();
+        for &c in name.as_slice::<u8>() {
+            if c == b'"' || c == b'\\' { buf.push(b'\\'); }
+            buf.push(c);
+        }
+        write!(buf, "\",\"entry\":{},\"returns\":{},\"params\":{},\"phis\":[", func.entry(),
+            func.returns(), func.params()).unwrap();
+        for (j, ty) in func.phis().enumerate() {
+            if j > 0 { buf.push(b','); }
+            write!(buf, "\"{}\"", ty).unwrap();
+        }
+        buf.write("],\"code\":[");
+        for (j, ins) in func.code().enumerate() {
+            if j > 0 { buf.push(b','); }
+            write!(buf, "{{\"id\":{},\"op\":\"{}\",\"type\":\"{}\",\"operands\":[", ins.id(),
+                ins.opcode(), ins.type_()).unwrap();
+            for (k, op) in ins.operands().enumerate() {
+                if k > 0 { buf.push(b','); }
+                write_json_operand(buf, op);
+            }
+            buf.write("]}");
+        }
+        buf.write("]}");
+    }
+    buf.push(b']');
+}
+
+/* ---- Canonical listing --------------------------------------------------- */
+
+// instructions reachable from the entry: control instructions in preorder, each preceded by its
+// value operands (depth-first, in operand order) that aren't listed yet.
+fn canonorder(func: FuncView) -> Vec<u16> {
+    let n = func.code().count();
+    let mut controls = Vec::new();
+    let mut seen: Vec<bool> = alloc::vec![false; n];
+    let mut stack = Vec::from([func.entry()]);
+    while let Some(c) = stack.pop() {
+        if seen[c as usize] { continue }
+        seen[c as usize] = true;
+        controls.push(c);
+        let succ = stack.len();
+        stack.extend(func.ins(c).unwrap().operands().filter_map(|op| match op {
+            Operand::Control(s) => Some(s),
+            _ => None
+        }));
+        stack[succ..].reverse();
+    }
+    let mut order = Vec::new();
+    let mut placed: Vec<bool> = alloc::vec![false; n];
+    let mut work: Vec<(u16, bool)> = Vec::new();
+    for c in controls {
+        work.push((c, false));
+        while let Some((id, expanded)) = work.pop() {
+            if placed[id as usize] { continue }
+            if expanded {
+                placed[id as usize] = true;
+                order.push(id);
+                continue
+            }
+            work.push((id, true));
+            let ops = work.len();
+            work.extend(func.ins(id).unwrap().operands().filter_map(|op| match op {
+                Operand::Value(v) if !placed[v as usize] => Some((v, false)),
+                _ => None
+            }));
+            work[ops..].reverse();
+        }
+    }
+    order
+}
+
+fn write_canonical_func(buf: &mut Bump, func: FuncView, source: &[u8]) {
+    let order = canonorder(func);
+    let n = func.code().count();
+    let mut canon: Vec<u16> = alloc::vec![!0; n];
+    for (i, &id) in order.iter().enumerate() {
+        canon[id as usize] = i as _;
+    }
+    // signature phis keep their place, the others are numbered by first use.
+    let sig = func.returns() + func.params();
+    let phitypes: Vec<&str> = func.phis().collect();
+    let mut phis: Vec<u16> = alloc::vec![!0; phitypes.len()];
+    let mut phiorder: Vec<u16> = (0..sig as u16).collect();
+    for (i, p) in phis.iter_mut().enumerate().take(sig) {
+        *p = i as _;
+    }
+    for &id in &order {
+        for op in func.ins(id).unwrap().operands() {
+            if let Operand::Phi(p) = op {
+                if phis[p as usize] == !0 {
+                    phis[p as usize] = phiorder.len() as _;
+                    phiorder.push(p);
+                }
+            }
+        }
+    }
+    buf.write("---------- FUNC ");
+    buf.write(match func.role() {
+        FuncRole::User => "user ",
+        FuncRole::Query => "query ",
+        FuncRole::Chunk => "chunk "
+    });
+    buf.write(source);
+    write!(buf, " ----------\nR{} A{}", func.returns(), func.params()).unwrap();
+    for &p in &phiorder {
+        write!(buf, " {}", phitypes[p as usize]).unwrap();
+    }
+    buf.push(b'\n');
+    for &id in &order {
+        let ins = func.ins(id).unwrap();
+        write!(buf, "{:04} {:-3} {:-6}", canon[id as usize], ins.type_(), ins.opcode()).unwrap();
+        // constants stored in the intern table are listed by value, not by reference.
+        if (Opcode::KINT64|Opcode::KFP64).contains(ins.ins.opcode()) {
+            dump_kvalue(buf, func.view.intern, ins.ins);
+            buf.push(b'\n');
+            continue;
+        }
+        for op in ins.operands() {
+            match op {
+                Operand::Value(v) => write!(buf, " {:04}", canon[v as usize]).unwrap(),
+                Operand::Control(c) => write!(buf, " ->{:04}", canon[c as usize]).unwrap(),
+                Operand::Phi(p) => write!(buf, " P{}", phis[p as usize]).unwrap(),
+                Operand::Func(f) => {
+                    buf.write(" <");
+                    func.view.func(f).unwrap().write_source(buf);
+                    buf.push(b'>');
+                },
+                Operand::LangOp(lang, op) => write!(buf, " L{}.{}", lang, op).unwrap(),
+                Operand::Literal(k) => write!(buf, " {}", k).unwrap()
+            }
+        }
+        buf.push(b'\n');
+    }
+}
+
+// every function of the IR in canonical form, ordered by source.
+pub fn write_canonical(buf: &mut Bump, view: IrView) {
+    let mut names = Bump::default();
+    let mut funcs: Vec<(usize, usize, FuncView)> = Vec::new();
+    for func in view.funcs() {
+        let start = names.end().ptr() as usize;
+        func.write_source(&mut names);
+        funcs.push((start, names.end().ptr() as usize, func));
+    }
+    let names = names.as_slice::<u8>();
+    funcs.sort_by(|a, b| names[a.0..a.1].cmp(&names[b.0..b.1]));
+    for &(start, end, func) in &funcs {
+        write_canonical_func(buf, func, &names[start..end]);
+    }
+}
@@ -0,0 +1,342 @@
+//! Ahead-of-time emission to relocatable object files.
+
+// instead of linking the machine code into executable memory, write it into an ELF relocatable
+// object that can be linked into a C program (together with the fhk static library, which
+// provides `fhk_vmcall` and the runtime support functions):
+//
+//   .text      emitted code | query entry points
+//   .rodata    emitted data | image descriptor | reset masks
+//   .rela.text code relocations
+//
+// each query gets a C entry point `<prefix>_query<i>` (in object graph order), with the signature
+//   int32_t (fhk_Instance *, void *result)
+// which tail calls `fhk_vmcall` with the query's function. `<prefix>_image` describes the
+// instance memory:
+//   struct { uint32_t size; uint32_t breakpoints[65]; }
+// and `<prefix>_reset<i>` holds the mask of each RESET (again in object graph order) as an
+// uint64_t. a fresh instance is `size` zeroed bytes aligned to 8, e.g. from calloc(). models that
+// allocate at runtime also need the host allocator, which `fhk_newinstance` sets up.
+//
+// only x86_64 ELF is supported for now, other targets get an error instead of an object they
+// can't link. COFF and other architectures need their own entry point stubs and relocation
+// mappings.
+
+use alloc::format;
+use alloc::vec::Vec;
+
+use crate::compile::{self, Ccx, CompileError};
+use crate::mcode::{Reloc, Sym};
+use crate::obj::{ObjectRef, QUERY, RESET};
+use crate::support::NativeFunc;
+use crate::typestate::{Absent, R};
+
+#[derive(Clone, Copy)]
+pub enum AotError {
+    Target,
+    Finalizers,
+    Reloc
+}
+
+impl CompileError for AotError {
+    fn write(self, ccx: &mut Ccx<(), R, R>) {
+        ccx.host.buf.write(match self {
+            AotError::Target => "object emission only supports x86_64 ELF targets",
+            AotError::Finalizers => "images with runtime state can't be emitted as objects",
+            AotError::Reloc => "unsupported relocation in object emission"
+        });
+    }
+}
+
+const SHT_PROGBITS: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHT_STRTAB: u32 = 3;
+const SHT_RELA: u32 = 4;
+const SHF_ALLOC: u64 = 2;
+const SHF_EXECINSTR: u64 = 4;
+const SHF_INFO_LINK: u64 = 0x40;
+const STB_GLOBAL: u8 = 1;
+const STT_NOTYPE: u8 = 0;
+const STT_OBJECT: u8 = 1;
+const STT_FUNC: u8 = 2;
+const STT_SECTION: u8 = 3;
+const EM_X86_64: u16 = 62;
+const R_X86_64_64: u32 = 1;
+const R_X86_64_PC32: u32 = 2;
+const R_X86_64_PLT32: u32 = 4;
+const R_X86_64_32: u32 = 10;
+
+// section indices
+const TEXT: u16 = 1;
+const RODATA: u16 = 2;
+const SYMTAB: u32 = 4;
+const STRTAB: u32 = 5;
+const SHSTRTAB: u32 = 6;
+const NSECTIONS: u32 = 8; // including the null section and .note.GNU-stack
+
+// symbol indices of the section symbols
+const SYM_TEXT: u32 = 1;
+const SYM_RODATA: u32 = 2;
+
+//   lea rdx, [rip+query]
+//   jmp fhk_vmcall
+//   int3 (x4)
+const ENTRY: [u8; 16] = [
+    0x48, 0x8d, 0x15, 0, 0, 0, 0,
+    0xe9, 0, 0, 0, 0,
+    0xcc, 0xcc, 0xcc, 0xcc
+];
+
+struct Symbol {
+    name: u32,
+    info: u8,
+    shndx: u16,
+    value: u64,
+    size: u64
+}
+
+struct Rela {
+    at: u64,
+    sym: u32,
+    kind: u32,
+    add: i64
+}
+
+struct StrTab {
+    data: Vec<u8>
+}
+
+impl StrTab {
+
+    fn new() -> Self {
+        Self { data: Vec::from([0]) }
+    }
+
+    fn add(&mut self, s: &str) -> u32 {
+        let ofs = self.data.len() as u32;
+        self.data.extend_from_slice(s.as_bytes());
+        self.data.push(0);
+        ofs
+    }
+
+}
+
+fn relockind(kind: cranelift_codegen::binemit::Reloc) -> Option<u32> {
+    use cranelift_codegen::binemit::Reloc::*;
+    Some(match kind {
+        Abs4 => R_X86_64_32,
+        Abs8 => R_X86_64_64,
+        X86PCRel4 => R_X86_64_PC32,
+        X86CallPCRel4 => R_X86_64_PLT32,
+        _ => return None
+    })
+}
+
+fn pad(buf: &mut Vec<u8>, align: usize) {
+    buf.resize((buf.len() + align - 1) & !(align - 1), 0);
+}
+
+fn put16(buf: &mut Vec<u8>, v: u16) { buf.extend_from_slice(&v.to_le_bytes()); }
+fn put32(buf: &mut Vec<u8>, v: u32) { buf.extend_from_slice(&v.to_le_bytes()); }
+fn put64(buf: &mut Vec<u8>, v: u64) { buf.extend_from_slice(&v.to_le_bytes()); }
+
+fn undef(syms: &mut Vec<Symbol>, strtab: &mut StrTab, name: &str) -> u32 {
+    let idx = syms.len() as u32;
+    syms.push(Symbol {
+        name: strtab.add(name),
+        info: (STB_GLOBAL << 4) | STT_NOTYPE,
+        shndx: 0,
+        value: 0,
+        size: 0
+    });
+    idx
+}
+
+fn section(
+    buf: &mut Vec<u8>,
+    name: u32,
+    type_: u32,
+    flags: u64,
+    offset: usize,
+    size: usize,
+    link: u32,
+    info: u32,
+    align: u64,
+    entsize: u64
+) {
+    put32(buf, name);
+    put32(buf, type_);
+    put64(buf, flags);
+    put64(buf, 0);
+    put64(buf, offset as _);
+    put64(buf, size as _);
+    put32(buf, link);
+    put32(buf, info);
+    put64(buf, align);
+    put64(buf, entsize);
+}
+
+// must be called after the emit stage.
+pub fn emit_object(ccx: &mut Ccx<Absent>, prefix: &str) -> compile::Result<Vec<u8>> {
+    if !cfg!(all(target_arch="x86_64", not(windows))) {
+        return ccx.error(AotError::Target);
+    }
+    if !ccx.fin.is_empty() || ccx.mcode.prof.is_some() || ccx.mcode.hooks.is_some() {
+        return ccx.error(AotError::Finalizers);
+    }
+    if ccx.mcode.relocs.iter().any(|r| relockind(r.kind).is_none()) {
+        return ccx.error(AotError::Reloc);
+    }
+    ccx.mcode.align_code();
+    let mcode = &ccx.mcode;
+    let mut text: Vec<u8> = mcode.code.as_slice().into();
+    let mut rodata: Vec<u8> = mcode.data.bump().as_slice().into();
+    let mut strtab = StrTab::new();
+    let mut syms: Vec<Symbol> = Vec::new();
+    let mut relas: Vec<Rela> = Vec::new();
+    syms.push(Symbol { name: 0, info: 0, shndx: 0, value: 0, size: 0 });
+    syms.push(Symbol { name: 0, info: STT_SECTION, shndx: TEXT, value: 0, size: 0 });
+    syms.push(Symbol { name: 0, info: STT_SECTION, shndx: RODATA, value: 0, size: 0 });
+    let nlocal = syms.len() as u32;
+    // undefined symbols are created on first use
+    let mut natives: [Option<u32>; 9] = Default::default();
+    let mut vmcall = None;
+    for &Reloc { at, add, kind, sym, which } in &mcode.relocs {
+        let kind = relockind(kind).unwrap();
+        let (sym, base) = match sym {
+            Sym::Data => (SYM_RODATA, which as i64),
+            Sym::Label => (SYM_TEXT, mcode.labels[zerocopy::transmute!(which)] as i64),
+            Sym::Native => {
+                let func = NativeFunc::from_u8(which as _);
+                let idx = match natives[which as usize] {
+                    Some(idx) => idx,
+                    None => *natives[which as usize].insert(
+                        undef(&mut syms, &mut strtab, func.symbol()))
+                };
+                (idx, 0)
+            }
+        };
+        relas.push(Rela { at: at as _, sym, kind, add: base + add as i64 });
+    }
+    let mut nquery = 0;
+    let mut nreset = 0;
+    for (_, obj) in ccx.objs.pairs() {
+        match obj {
+            ObjectRef::QUERY(&QUERY { mcode, .. }) => {
+                let vmcall = *vmcall.get_or_insert_with(
+                    || undef(&mut syms, &mut strtab, "fhk_vmcall"));
+                let at = text.len() as u64;
+                text.extend_from_slice(&ENTRY);
+                relas.push(Rela {
+                    at: at+3,
+                    sym: SYM_TEXT,
+                    kind: R_X86_64_PC32,
+                    add: mcode as i64 - 4
+                });
+                relas.push(Rela { at: at+8, sym: vmcall, kind: R_X86_64_PLT32, add: -4 });
+                syms.push(Symbol {
+                    name: strtab.add(&format!("{}_query{}", prefix, nquery)),
+                    info: (STB_GLOBAL << 4) | STT_FUNC,
+                    shndx: TEXT,
+                    value: at,
+                    size: ENTRY.len() as _
+                });
+                nquery += 1;
+            },
+            ObjectRef::RESET(&RESET { mlo, mhi, .. }) => {
+                pad(&mut rodata, 8);
+                syms.push(Symbol {
+                    name: strtab.add(&format!("{}_reset{}", prefix, nreset)),
+                    info: (STB_GLOBAL << 4) | STT_OBJECT,
+                    shndx: RODATA,
+                    value: rodata.len() as _,
+                    size: 8
+                });
+                put64(&mut rodata, (mlo as u64) | ((mhi as u64) << 32));
+                nreset += 1;
+            },
+            _ => {}
+        }
+    }
+    pad(&mut rodata, 4);
+    let image = rodata.len();
+    put32(&mut rodata, ccx.layout.size);
+    for &b in &ccx.layout.breakpoints.raw {
+        put32(&mut rodata, b);
+    }
+    syms.push(Symbol {
+        name: strtab.add(&format!("{}_image", prefix)),
+        info: (STB_GLOBAL << 4) | STT_OBJECT,
+        shndx: RODATA,
+        value: image as _,
+        size: (rodata.len() - image) as _
+    });
+    // section names
+    let mut shstrtab = StrTab::new();
+    let sh_text = shstrtab.add(".text");
+    let sh_rodata = shstrtab.add(".rodata");
+    let sh_rela = shstrtab.add(".rela.text");
+    let sh_symtab = shstrtab.add(".symtab");
+    let sh_strtab = shstrtab.add(".strtab");
+    let sh_shstrtab = shstrtab.add(".shstrtab");
+    let sh_stack = shstrtab.add(".note.GNU-stack");
+    // file contents
+    let mut buf: Vec<u8> = Vec::new();
+    buf.resize(64, 0);
+    pad(&mut buf, 32);
+    let text_ofs = buf.len();
+    buf.extend_from_slice(&text);
+    pad(&mut buf, 32);
+    let rodata_ofs = buf.len();
+    buf.extend_from_slice(&rodata);
+    pad(&mut buf, 8);
+    let rela_ofs = buf.len();
+    for &Rela { at, sym, kind, add } in &relas {
+        put64(&mut buf, at);
+        put64(&mut buf, ((sym as u64) << 32) | kind as u64);
+        put64(&mut buf, add as _);
+    }
+    let symtab_ofs = buf.len();
+    for &Symbol { name, info, shndx, value, size } in &syms {
+        put32(&mut buf, name);
+        buf.push(info);
+        buf.push(0);
+        put16(&mut buf, shndx);
+        put64(&mut buf, value);
+        put64(&mut buf, size);
+    }
+    let strtab_ofs = buf.len();
+    buf.extend_from_slice(&strtab.data);
+    let shstrtab_ofs = buf.len();
+    buf.extend_from_slice(&shstrtab.data);
+    pad(&mut buf, 8);
+    let shoff = buf.len();
+    buf.resize(buf.len() + 64, 0); // null section
+    section(&mut buf, sh_text, SHT_PROGBITS, SHF_ALLOC|SHF_EXECINSTR, text_ofs, text.len(),
+        0, 0, 32, 0);
+    section(&mut buf, sh_rodata, SHT_PROGBITS, SHF_ALLOC, rodata_ofs, rodata.len(), 0, 0, 32, 0);
+    section(&mut buf, sh_rela, SHT_RELA, SHF_INFO_LINK, rela_ofs, relas.len()*24, SYMTAB,
+        TEXT as _, 8, 24);
+    section(&mut buf, sh_symtab, SHT_SYMTAB, 0, symtab_ofs, syms.len()*24, STRTAB, nlocal, 8, 24);
+    section(&mut buf, sh_strtab, SHT_STRTAB, 0, strtab_ofs, strtab.data.len(), 0, 0, 1, 0);
+    section(&mut buf, sh_shstrtab, SHT_STRTAB, 0, shstrtab_ofs, shstrtab.data.len(), 0, 0, 1, 0);
+    section(&mut buf, sh_stack, SHT_PROGBITS, 0, shoff, 0, 0, 0, 1, 0);
+    // elf header
+    let mut header: Vec<u8> = Vec::new();
+    header.extend_from_slice(b"\x7fELF\x02\x01\x01");
+    header.resize(16, 0);
+    put16(&mut header, 1); // ET_REL
+    put16(&mut header, EM_X86_64);
+    put32(&mut header, 1); // EV_CURRENT
+    put64(&mut header, 0); // entry
+    put64(&mut header, 0); // phoff
+    put64(&mut header, shoff as _);
+    put32(&mut header, 0); // flags
+    put16(&mut header, 64); // ehsize
+    put16(&mut header, 0); // phentsize
+    put16(&mut header, 0); // phnum
+    put16(&mut header, 64); // shentsize
+    put16(&mut header, NSECTIONS as _);
+    put16(&mut header, SHSTRTAB as _);
+    buf[..64].copy_from_slice(&header);
+    Ok(buf)
+}
@@ -0,0 +1,173 @@
+//! Optimizer decision log.
+
+// with `Session::audit` on, the optimizer records each rewrite it applies: the pass, the function
+// and its source object, the rule, and the instruction before and after the rewrite. instruction
+// ids refer to the function as it was when the pass ran (fold renumbers every instruction), so
+// the log reads in order, one pass at a time. the log holds the last compilation only.
+//
+// currently logged:
+//   fold    host fold rules by name, and the built-in rules as "const" (folded to a constant),
+//           "cse" (replaced by an equal instruction) and "simplify" (anything else)
+//   range   "decide" for each IF replaced by a GOTO
+//
+// the log also keeps a summary per function and pass: how many runs of the pass changed the
+// function, and how many logged rewrites they applied. a pass changed a function if its code
+// differs after the run. functions are identified by source, because inline and merge renumber
+// them. functions that the pipeline deletes are kept in the summary.
+
+use core::fmt::Write;
+
+use alloc::vec::Vec;
+use enumset::EnumSet;
+
+use crate::bump::Bump;
+use crate::hash::{fxhash, HashMap};
+use crate::intern::Intern;
+use crate::ir::{DebugSource, Func, FuncId, InsId, IR};
+use crate::obj::{ObjRef, Objects};
+use crate::optimize::{OptPass, NUM_PASS};
+use crate::symbol::write_source;
+
+pub struct Decision {
+    pub pass: OptPass,
+    pub func: FuncId,
+    pub source: ObjRef,
+    pub rule: &'static str,
+    pub before: InsId,
+    pub after: InsId
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct PassEffect {
+    pub changed: u32,  // runs that changed the function
+    pub rewrites: u32  // logged rewrites in the function
+}
+
+#[derive(Default)]
+pub struct AuditLog {
+    list: Vec<Decision>,
+    effects: HashMap<DebugSource, [PassEffect; NUM_PASS]>,
+    order: Vec<DebugSource>, // keys of `effects` in first-seen order
+    stamps: HashMap<DebugSource, u64>,
+    mark: usize
+}
+
+fn func_stamp(func: &Func) -> u64 {
+    let mut stamp = fxhash((func.entry, func.code.end(), func.phis.end()));
+    for (_, ins) in func.code.pairs() {
+        stamp = fxhash((stamp, ins));
+    }
+    stamp
+}
+
+impl AuditLog {
+
+    pub fn clear(&mut self) {
+        self.list.clear();
+        self.effects.clear();
+        self.order.clear();
+    }
+
+    // call before running `pass` ...
+    pub fn begin(&mut self, ir: &IR) {
+        self.stamps.clear();
+        self.stamps.extend(ir.funcs.raw.iter().map(|f| (f.source, func_stamp(f))));
+        self.mark = self.list.len();
+    }
+
+    // ... and after it.
+    pub fn end(&mut self, ir: &IR, pass: OptPass) {
+        for func in &ir.funcs.raw {
+            if self.stamps.get(&func.source) != Some(&func_stamp(func)) {
+                self.effect(func.source, pass).changed += 1;
+            }
+        }
+        for i in self.mark..self.list.len() {
+            let src = ir.funcs[self.list[i].func].source;
+            self.effect(src, pass).rewrites += 1;
+        }
+    }
+
+    fn effect(&mut self, src: DebugSource, pass: OptPass) -> &mut PassEffect {
+        let order = &mut self.order;
+        let effects = self.effects.entry(src).or_insert_with(|| {
+            order.push(src);
+            Default::default()
+        });
+        &mut effects[pass as usize]
+    }
+
+    pub fn push(&mut self, decision: Decision) {
+        self.list.push(decision);
+    }
+
+    pub fn render(&self, buf: &mut Bump) {
+        for d in &self.list {
+            write!(buf, "{:-8} {:?} (source {:?}) {}: {:?} -> {:?}\n",
+                d.pass.name(), d.func, d.source, d.rule, d.before, d.after).unwrap();
+        }
+    }
+
+    pub fn render_json(&self, buf: &mut Bump) {
+        buf.push(b'[');
+        for (i, d) in self.list.iter().enumerate() {
+            if i > 0 { buf.push(b','); }
+            let func: usize = d.func.into();
+            let source: u32 = zerocopy::transmute!(d.source);
+            let (before, after): (usize, usize) = (d.before.into(), d.after.into());
+            write!(buf,
+                "{{\"pass\":\"{}\",\"func\":{},\"source\":{},\"rule\":\"{}\",\"before\":{},\"after\":{}}}",
+                d.pass.name(), func, source, d.rule, before, after).unwrap();
+        }
+        buf.push(b']');
+    }
+
+    // one line per function that any pass changed:
+    //   OPref(name).value  fold 3 (12)  cfg 1
+    // ie. pass name, runs that changed the function, and (if any) logged rewrites.
+    pub fn render_effects(&self, buf: &mut Bump, intern: &Intern, objs: &Objects) {
+        for &src in &self.order {
+            let effects = &self.effects[&src];
+            write_source(buf, intern, objs, src);
+            for pass in EnumSet::<OptPass>::all() {
+                let PassEffect { changed, rewrites } = effects[pass as usize];
+                if changed == 0 { continue }
+                write!(buf, "  {} {}", pass.name(), changed).unwrap();
+                if rewrites > 0 {
+                    write!(buf, " ({})", rewrites).unwrap();
+                }
+            }
+            buf.push(b'\n');
+        }
+    }
+
+    // [{"source":"OPref(name).value","passes":{"fold":{"changed":3,"rewrites":12},...}},...]
+    pub fn render_effects_json(&self, buf: &mut Bump, intern: &Intern, objs: &Objects) {
+        let mut name = Bump::default();
+        buf.push(b'[');
+        for (i, &src) in self.order.iter().enumerate() {
+            if i > 0 { buf.push(b','); }
+            name.clear();
+            write_source(&mut name, intern, objs, src);
+            buf.write("{\"source\":\"");
+            for &c in name.as_slice::<u8>() {
+                if c == b'"' || c == b'\\' { buf.push(b'\\'); }
+                buf.push(c);
+            }
+            buf.write("\",\"passes\":{");
+            let effects = &self.effects[&src];
+            let mut first = true;
+            for pass in EnumSet::<OptPass>::all() {
+                let PassEffect { changed, rewrites } = effects[pass as usize];
+                if changed == 0 { continue }
+                if !first { buf.push(b','); }
+                first = false;
+                write!(buf, "\"{}\":{{\"changed\":{},\"rewrites\":{}}}", pass.name(), changed,
+                    rewrites).unwrap();
+            }
+            buf.write("}}");
+        }
+        buf.push(b']');
+    }
+
+}
@@ -0,0 +1,199 @@
+//! Host binding generation.
+
+// after compiling, the query result types are known from the annotated objects. this writes them
+// out as source for the embedder, so that the entry points of an object file (see aot.rs) can be
+// called with typed results:
+//
+//   * Rust: one #[repr(C)] struct per query, the `<prefix>_query<i>` declarations, and a safe(r)
+//     wrapper `query<i>` returning the struct.
+//   * C: a header with one struct typedef and declaration per query.
+//
+// queries are numbered in object graph order, like the object file entry points. a result field
+// is named after the variable it reads when the value is a plain variable, otherwise it's `v<i>`
+// like in the Lua query ctype. the layout matches the query result layout: fields in order,
+// tensors as { elem *e; int32_t n[dim]; }.
+//
+// inputs don't get setters: the compiled graph reads its inputs through model calls and loads,
+// not through variables the host writes.
+
+use core::fmt::{self, Display, Write};
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::bump::Bump;
+use crate::intern::Intern;
+use crate::obj::{ObjRef, ObjectRef, Objects, EXPR, QUERY, TPRI, TTEN, VGET};
+use crate::typing::Primitive;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum BindLang {
+    Rust,
+    C
+}
+
+pub enum BindError {
+    Type(usize, usize) // query, value
+}
+
+impl Display for BindError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BindError::Type(q, v) =>
+                write!(f, "query {} value {} has a type without a binding", q, v+1)
+        }
+    }
+}
+
+enum BindType {
+    Scalar(Primitive),
+    Tensor(Primitive, u8)
+}
+
+fn bindtype(objs: &Objects, expr: ObjRef<EXPR>) -> Option<BindType> {
+    let scalar = |ty: ObjRef| match objs.get(ty) {
+        ObjectRef::TPRI(&TPRI { ty, .. }) if ty != Primitive::STR as u8 =>
+            Some(Primitive::from_u8(ty)),
+        _ => None
+    };
+    let ann = objs[expr].ann;
+    match objs.get(ann) {
+        ObjectRef::TTEN(&TTEN { dim, elem, .. }) => Some(BindType::Tensor(scalar(elem)?, dim)),
+        _ => scalar(ann).map(BindType::Scalar)
+    }
+}
+
+fn rusttype(pri: Primitive) -> &'static str {
+    use Primitive::*;
+    match pri {
+        F64 => "f64", F32 => "f32",
+        I64 => "i64", I32 => "i32", I16 => "i16", I8 => "i8",
+        U64 => "u64", U32 => "u32", U16 => "u16", U8 => "u8",
+        B1 => "bool",
+        PTR => "*mut c_void",
+        STR => unreachable!()
+    }
+}
+
+fn ctype(pri: Primitive) -> &'static str {
+    use Primitive::*;
+    match pri {
+        F64 => "double", F32 => "float",
+        I64 => "int64_t", I32 => "int32_t", I16 => "int16_t", I8 => "int8_t",
+        U64 => "uint64_t", U32 => "uint32_t", U16 => "uint16_t", U8 => "uint8_t",
+        B1 => "bool",
+        PTR => "void *",
+        STR => unreachable!()
+    }
+}
+
+// reserved in Rust or C, and valid as identifiers in fhk.
+const KEYWORDS: &[&str] = &[
+    "as", "async", "auto", "await", "bool", "box", "break", "case", "char", "const", "continue",
+    "crate", "default", "do", "double", "dyn", "else", "enum", "extern", "false", "float", "fn",
+    "for", "goto", "if", "impl", "in", "inline", "int", "let", "long", "loop", "match", "mod",
+    "move", "mut", "pub", "ref", "register", "restrict", "return", "self", "short", "signed",
+    "sizeof", "static", "struct", "super", "switch", "trait", "true", "type", "typedef", "union",
+    "unsafe", "unsigned", "use", "void", "volatile", "where", "while", "yield"
+];
+
+// variable name if it's usable as an identifier in both languages and not taken yet.
+fn fieldname(intern: &Intern, objs: &Objects, expr: ObjRef<EXPR>, i: usize, taken: &[String])
+    -> String
+{
+    if let ObjectRef::VGET(&VGET { var, ref idx, .. }) = objs.get(expr.erase()) {
+        let name = intern.get_slice(objs[var].name);
+        let valid = name.first().is_some_and(|c| c.is_ascii_alphabetic() || *c == b'_')
+            && name.iter().all(|c| c.is_ascii_alphanumeric() || *c == b'_');
+        if idx.is_empty() && valid {
+            let name = String::from(core::str::from_utf8(name).unwrap());
+            let generic = name.len() > 1 && name.starts_with('v')
+                && name[1..].bytes().all(|c| c.is_ascii_digit());
+            if !taken.contains(&name) && !generic && !KEYWORDS.contains(&name.as_str()) {
+                return name;
+            }
+        }
+    }
+    alloc::format!("v{}", i+1)
+}
+
+struct QueryBinding {
+    fields: Vec<(String, BindType)>
+}
+
+fn collect(intern: &Intern, objs: &Objects) -> Result<Vec<QueryBinding>, BindError> {
+    let mut queries = Vec::new();
+    for (_, o) in objs.pairs() {
+        let ObjectRef::QUERY(QUERY { value, .. }) = o else { continue };
+        let mut fields: Vec<(String, BindType)> = Vec::new();
+        for (i, &v) in value.iter().enumerate() {
+            let ty = bindtype(objs, v).ok_or(BindError::Type(queries.len(), i))?;
+            let taken: Vec<String> = fields.iter().map(|(n, _)| n.clone()).collect();
+            fields.push((fieldname(intern, objs, v, i, &taken), ty));
+        }
+        queries.push(QueryBinding { fields });
+    }
+    Ok(queries)
+}
+
+fn write_rust(buf: &mut Bump, queries: &[QueryBinding], prefix: &str) {
+    write!(buf, "// generated by fhk, do not edit.\n\n").unwrap();
+    write!(buf, "use core::ffi::c_void;\n\n").unwrap();
+    write!(buf, "#[repr(C)]\npub struct fhk_Instance {{ _private: [u8; 0] }}\n\n").unwrap();
+    write!(buf, "#[repr(C)]\npub struct Tensor<T, const N: usize> {{\n    pub e: *mut T,\n    \
+        pub n: [i32; N]\n}}\n").unwrap();
+    for (q, query) in queries.iter().enumerate() {
+        write!(buf, "\n#[repr(C)]\npub struct Query{} {{\n", q).unwrap();
+        for (name, ty) in &query.fields {
+            match *ty {
+                BindType::Scalar(pri) => write!(buf, "    pub {}: {},\n", name, rusttype(pri)),
+                BindType::Tensor(pri, dim) =>
+                    write!(buf, "    pub {}: Tensor<{}, {}>,\n", name, rusttype(pri), dim)
+            }.unwrap();
+        }
+        write!(buf, "}}\n\nunsafe extern \"C\" {{\n    \
+            fn {p}_query{q}(instance: *mut fhk_Instance, result: *mut Query{q}) -> i32;\n}}\n\n",
+            p=prefix, q=q).unwrap();
+        write!(buf, "pub unsafe fn query{q}(instance: *mut fhk_Instance) \
+            -> Result<Query{q}, i32> {{\n    \
+            let mut result = core::mem::MaybeUninit::<Query{q}>::uninit();\n    \
+            match unsafe {{ {p}_query{q}(instance, result.as_mut_ptr()) }} {{\n        \
+            0 => Ok(unsafe {{ result.assume_init() }}),\n        \
+            e => Err(e)\n    \
+            }}\n}}\n", p=prefix, q=q).unwrap();
+    }
+}
+
+fn write_c(buf: &mut Bump, queries: &[QueryBinding], prefix: &str) {
+    write!(buf, "/* generated by fhk, do not edit. */\n\n").unwrap();
+    write!(buf, "#pragma once\n\n#include <stdbool.h>\n#include <stdint.h>\n\n").unwrap();
+    write!(buf, "typedef struct fhk_Instance fhk_Instance;\n").unwrap();
+    for (q, query) in queries.iter().enumerate() {
+        write!(buf, "\ntypedef struct {{\n").unwrap();
+        for (name, ty) in &query.fields {
+            match *ty {
+                BindType::Scalar(pri) => write!(buf, "    {} {};\n", ctype(pri), name),
+                BindType::Tensor(pri, dim) => write!(buf,
+                    "    struct {{ {} *e; int32_t n[{}]; }} {};\n", ctype(pri), dim, name)
+            }.unwrap();
+        }
+        write!(buf, "}} {p}_query{q}_result;\n\n\
+            int32_t {p}_query{q}(fhk_Instance *instance, {p}_query{q}_result *result);\n",
+            p=prefix, q=q).unwrap();
+    }
+}
+
+pub fn write_bindings(
+    buf: &mut Bump,
+    intern: &Intern,
+    objs: &Objects,
+    prefix: &str,
+    lang: BindLang
+) -> Result<(), BindError> {
+    let queries = collect(intern, objs)?;
+    match lang {
+        BindLang::Rust => write_rust(buf, &queries, prefix),
+        BindLang::C => write_c(buf, &queries, prefix)
+    }
+    Ok(())
+}
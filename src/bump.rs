@@ -434,12 +434,24 @@ impl<W: Aligned> Bump<W> {
     {
         let Range { start, end } = range;
         let len = end.index() - start.index();
-        let (ptr, data) = self.reserve_dst::<[T]>(len);
-        let data = data as *mut [T] as *mut T;
-        unsafe {
-            let src = self.ptr.as_ptr().cast::<T>().add(start.index());
-            core::ptr::copy_nonoverlapping(src, data, len);
-        }
+        #[cfg(not(feature="checked"))]
+        let ptr = {
+            let (ptr, data) = self.reserve_dst::<[T]>(len);
+            let data = data as *mut [T] as *mut T;
+            unsafe {
+                let src = self.ptr.as_ptr().cast::<T>().add(start.index());
+                core::ptr::copy_nonoverlapping(src, data, len);
+            }
+            ptr
+        };
+        // the copy above reads through `self.ptr` while `data` is borrowed, which miri rejects.
+        #[cfg(feature="checked")]
+        let ptr = {
+            let (ptr, _) = self.reserve_dst::<[T]>(len);
+            let src = start.ptr();
+            self.as_mut_slice::<u8>().copy_within(src..src+len*size_of::<T>(), ptr.ptr());
+            ptr
+        };
         ptr.cast()
     }
 
@@ -0,0 +1,244 @@
+//! Compiled image cache.
+
+// a compiled image is stored as its unlinked machine code and relocations, plus the parts of the
+// object graph that the host reads after compilation (query entry points and reset masks):
+//
+//   magic | version | key | arch | isa | size | breakpoints | code | data | relocs | labels | objs
+//
+// `isa` lists the host cpu features that the code may use, so that an image compiled on one cpu
+// is not loaded on another that lacks them. everything is little endian. `objs` lists (ref, mcode) for each QUERY and (ref, mlo, mhi) for
+// each RESET, in object graph order.
+//
+// the key is chosen by the host, and should be a hash of everything that affects the compilation
+// result (see `fhk_hash` and `fhk_hashobjs`). loading checks that the graph has the same queries
+// and resets as the cached one, patches them and links the code. the host still runs type
+// inference afterwards, since it reads the annotated types.
+//
+// images that carry runtime state (ie. anything that registers a finalizer, such as embedded
+// interpreters) can't be cached, and neither can images that call C or host functions through
+// pointers, since the addresses change from one process to the next. neither can interpreter
+// (feature `interp`) images, since they don't contain machine code.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::mem::take;
+
+use crate::compile::{self, Ccx, CompileError};
+use crate::image::Image;
+use crate::index::IndexVec;
+use crate::link::link;
+use crate::mcode::{Reloc, Sym};
+use crate::mem::BreakpointId;
+use crate::obj::{Obj, ObjRef, ObjectRef, QUERY, RESET};
+use crate::typestate::{Absent, R};
+
+const MAGIC: &[u8; 8] = b"fhkimg\x00\x01";
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[cfg(target_arch="x86_64")]  const ARCH: u32 = 1;
+#[cfg(target_arch="aarch64")] const ARCH: u32 = 2;
+#[cfg(target_arch="s390x")]   const ARCH: u32 = 3;
+#[cfg(not(any(target_arch="x86_64", target_arch="aarch64", target_arch="s390x")))]
+const ARCH: u32 = 0;
+
+// isa flags of the host, in the same form that `Emit` compiles with.
+fn isaflags() -> String {
+    let isa = cranelift_native::builder()
+        .unwrap()
+        .finish(cranelift_codegen::settings::Flags::new(cranelift_codegen::settings::builder()))
+        .unwrap();
+    let mut flags = String::new();
+    for v in isa.isa_flags() {
+        let _ = write!(flags, "{},", v);
+    }
+    flags
+}
+
+// relocations that `link` knows how to apply, in serialization order.
+const RELOC_KINDS: &[cranelift_codegen::binemit::Reloc] = {
+    use cranelift_codegen::binemit::Reloc::*;
+    &[Abs4, Abs8, X86PCRel4, X86CallPCRel4, S390xPCRel32Dbl, S390xPLTRel32Dbl, Arm64Call]
+};
+
+#[derive(Clone, Copy)]
+pub enum CacheError {
+    BadImage,
+    Mismatch
+}
+
+impl CompileError for CacheError {
+    fn write(self, ccx: &mut Ccx<(), R, R>) {
+        ccx.host.buf.write(match self {
+            CacheError::BadImage => "invalid cached image",
+            CacheError::Mismatch => "cached image doesn't match graph"
+        });
+    }
+}
+
+fn put32(buf: &mut Vec<u8>, v: u32) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn putbytes(buf: &mut Vec<u8>, v: &[u8]) {
+    put32(buf, v.len() as _);
+    buf.extend_from_slice(v);
+}
+
+// returns none if the image can't be cached.
+pub fn save<P>(ccx: &Ccx<P>, image: &Image, key: u64) -> Option<Vec<u8>> {
+    if ARCH == 0 || cfg!(feature="interp") || !image.fin.is_empty() || image.profile.is_some()
+        || image.hooks.is_some() || image.par.is_some() || ccx.mcode.hostptr
+    {
+        return None;
+    }
+    let mcode = &ccx.mcode;
+    let mut buf = Vec::new();
+    buf.extend_from_slice(MAGIC);
+    putbytes(&mut buf, VERSION.as_bytes());
+    buf.extend_from_slice(&key.to_le_bytes());
+    put32(&mut buf, ARCH);
+    putbytes(&mut buf, isaflags().as_bytes());
+    put32(&mut buf, ccx.layout.size);
+    for &b in &ccx.layout.breakpoints.raw {
+        put32(&mut buf, b);
+    }
+    putbytes(&mut buf, mcode.code.as_slice());
+    putbytes(&mut buf, mcode.data.bump().as_slice());
+    put32(&mut buf, mcode.relocs.len() as _);
+    for &Reloc { at, add, kind, sym, which } in &mcode.relocs {
+        let kind = RELOC_KINDS.iter().position(|&k| k == kind)?;
+        put32(&mut buf, at);
+        put32(&mut buf, add as _);
+        put32(&mut buf, (kind as u32) | ((sym as u32) << 8));
+        put32(&mut buf, which);
+    }
+    put32(&mut buf, mcode.labels.raw.len() as _);
+    for &ofs in &mcode.labels.raw {
+        put32(&mut buf, ofs);
+    }
+    for (idx, obj) in ccx.objs.pairs() {
+        match obj {
+            ObjectRef::QUERY(&QUERY { mcode, .. }) => {
+                put32(&mut buf, zerocopy::transmute!(idx));
+                put32(&mut buf, mcode);
+            },
+            ObjectRef::RESET(&RESET { mlo, mhi, .. }) => {
+                put32(&mut buf, zerocopy::transmute!(idx));
+                put32(&mut buf, mlo);
+                put32(&mut buf, mhi);
+            },
+            _ => {}
+        }
+    }
+    Some(buf)
+}
+
+struct Reader<'a> {
+    data: &'a [u8]
+}
+
+impl<'a> Reader<'a> {
+
+    fn bytes(&mut self, n: usize) -> Option<&'a [u8]> {
+        let (head, rest) = self.data.split_at_checked(n)?;
+        self.data = rest;
+        Some(head)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn slice(&mut self) -> Option<&'a [u8]> {
+        let n = self.u32()?;
+        self.bytes(n as _)
+    }
+
+}
+
+fn read(ccx: &mut Ccx<Absent>, data: &[u8], key: u64) -> Result<Image, CacheError> {
+    use CacheError::*;
+    let mut r = Reader { data };
+    if cfg!(feature="interp") || r.bytes(MAGIC.len()) != Some(MAGIC) || r.slice() != Some(VERSION.as_bytes())
+        || r.u64() != Some(key) || r.u32() != Some(ARCH)
+        || r.slice() != Some(isaflags().as_bytes())
+    {
+        return Err(Mismatch);
+    }
+    let size = r.u32().ok_or(BadImage)?;
+    let mut breakpoints = ccx.layout.breakpoints;
+    for i in 0..BreakpointId::MAXNUM+1 {
+        breakpoints.raw[i] = r.u32().ok_or(BadImage)?;
+    }
+    let code = r.slice().ok_or(BadImage)?;
+    let mdata = r.slice().ok_or(BadImage)?;
+    let mut relocs = Vec::new();
+    for _ in 0..r.u32().ok_or(BadImage)? {
+        let at = r.u32().ok_or(BadImage)?;
+        let add = r.u32().ok_or(BadImage)? as i32;
+        let ks = r.u32().ok_or(BadImage)?;
+        let which = r.u32().ok_or(BadImage)?;
+        let kind = *RELOC_KINDS.get((ks & 0xff) as usize).ok_or(BadImage)?;
+        if (ks >> 8) as usize >= 3 { return Err(BadImage) }
+        relocs.push(Reloc { at, add, kind, sym: Sym::from_u8((ks >> 8) as _), which });
+    }
+    let mut labels: IndexVec<_, _> = Default::default();
+    for _ in 0..r.u32().ok_or(BadImage)? {
+        labels.raw.push(r.u32().ok_or(BadImage)?);
+    }
+    let nlabels = labels.raw.len() as u32;
+    for &Reloc { at, kind, sym, which, .. } in &relocs {
+        let size = if kind == cranelift_codegen::binemit::Reloc::Abs8 { 8 } else { 4 };
+        if at as usize + size > code.len() { return Err(BadImage) }
+        match sym {
+            Sym::Data if which as usize >= mdata.len() => return Err(BadImage),
+            Sym::Label if which >= nlabels => return Err(BadImage),
+            _ => {}
+        }
+    }
+    // the graph must have the same queries and resets, in the same order.
+    let mut idx = ObjRef::NIL;
+    while let Some(i) = ccx.objs.next(idx) {
+        idx = i;
+        let op = ccx.objs[idx].op;
+        if op != Obj::QUERY && op != Obj::RESET { continue }
+        let raw: u32 = zerocopy::transmute!(idx);
+        if r.u32() != Some(raw) { return Err(Mismatch) }
+        if op == Obj::QUERY {
+            ccx.objs[idx.cast::<QUERY>()].mcode = r.u32().ok_or(BadImage)?;
+        } else {
+            let reset = idx.cast::<RESET>();
+            ccx.objs[reset].mlo = r.u32().ok_or(BadImage)?;
+            ccx.objs[reset].mhi = r.u32().ok_or(BadImage)?;
+        }
+    }
+    if !r.data.is_empty() {
+        return Err(Mismatch);
+    }
+    let mem = link(code, mdata, &relocs, &labels).map_err(|_| BadImage)?;
+    Ok(Image {
+        mem,
+        fin: take(&mut ccx.fin).build(),
+        srcmap: Default::default(),
+        codesize: Default::default(),
+        profile: None,
+        hooks: None,
+        par: None,
+        breakpoints,
+        size
+    })
+}
+
+// queries and resets are created before compilation, so the image can be validated (and the
+// graph patched) before type inference.
+pub fn load(ccx: &mut Ccx<Absent>, data: &[u8], key: u64) -> compile::Result<Image> {
+    match read(ccx, data, key) {
+        Ok(image) => Ok(image),
+        Err(e) => ccx.error(e)
+    }
+}
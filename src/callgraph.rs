@@ -0,0 +1,123 @@
+//! Call graph.
+
+// edges go from a function to the chunks it calls with CALLC or CALLCI. the graph is split into
+// strongly connected components with Tarjan's algorithm. components are completed callees first,
+// so `order` lists the functions bottom-up: a function always comes after every function it calls,
+// except for calls within its own component.
+//
+// the graph isn't kept up to date: interprocedural passes rebuild it on entry.
+
+use alloc::vec::Vec;
+
+use crate::index::{self, IndexSet, IndexVec};
+use crate::ir::{FuncId, Opcode, IR};
+
+const UNVISITED: u32 = !0;
+
+#[derive(Default)]
+pub struct CallGraph {
+    start: IndexVec<FuncId, u32>, // callees of f are callees[start[f]..start[f+1]]
+    callees: Vec<FuncId>,
+    scc: IndexVec<FuncId, u32>,   // function -> component, numbered bottom-up
+    order: Vec<FuncId>,           // functions bottom-up, grouped by component
+    recursive: IndexSet<FuncId>,  // functions that can call themselves
+    // tarjan state
+    index: IndexVec<FuncId, u32>,
+    low: IndexVec<FuncId, u32>,
+    stack: Vec<FuncId>,
+    onstack: IndexSet<FuncId>,
+    next: u32,
+    nscc: u32
+}
+
+impl CallGraph {
+
+    pub fn build(&mut self, ir: &IR) {
+        self.start.clear();
+        self.callees.clear();
+        for func in &ir.funcs.raw {
+            let base = self.callees.len();
+            self.start.push(base as _);
+            for (_, ins) in func.code.pairs() {
+                if (Opcode::CALLC|Opcode::CALLCI).contains(ins.opcode()) {
+                    let (_, _, f) = ins.decode_CALLC();
+                    if !self.callees[base..].contains(&f) {
+                        self.callees.push(f);
+                    }
+                }
+            }
+        }
+        self.start.push(self.callees.len() as _);
+        let n = ir.funcs.raw.len();
+        self.scc.raw.clear();
+        self.scc.raw.resize(n, 0);
+        self.index.raw.clear();
+        self.index.raw.resize(n, UNVISITED);
+        self.low.raw.clear();
+        self.low.raw.resize(n, 0);
+        self.order.clear();
+        self.recursive.clear();
+        self.onstack.clear();
+        self.next = 0;
+        self.nscc = 0;
+        for f in index::iter_span(ir.funcs.end()) {
+            if self.index[f] == UNVISITED {
+                self.visit(f);
+            }
+        }
+    }
+
+    // functions called by `f`, each listed once.
+    pub fn callees(&self, f: FuncId) -> &[FuncId] {
+        &self.callees[self.start[f] as usize .. self.start[f+1] as usize]
+    }
+
+    fn visit(&mut self, f: FuncId) {
+        self.index[f] = self.next;
+        self.low[f] = self.next;
+        self.next += 1;
+        self.stack.push(f);
+        self.onstack.insert(f);
+        for i in self.start[f] .. self.start[f+1] {
+            let g = self.callees[i as usize];
+            if self.index[g] == UNVISITED {
+                self.visit(g);
+                self.low[f] = self.low[f].min(self.low[g]);
+            } else if self.onstack.contains(g) {
+                self.low[f] = self.low[f].min(self.index[g]);
+            }
+        }
+        if self.low[f] == self.index[f] {
+            let base = self.order.len();
+            loop {
+                let g = self.stack.pop().unwrap();
+                self.onstack.remove(g);
+                self.scc[g] = self.nscc;
+                self.order.push(g);
+                if g == f { break }
+            }
+            if self.order.len() - base > 1 || self.callees(f).contains(&f) {
+                for &g in &self.order[base..] {
+                    self.recursive.insert(g);
+                }
+            }
+            self.nscc += 1;
+        }
+    }
+
+    // functions bottom-up.
+    pub fn order(&self) -> &[FuncId] {
+        &self.order
+    }
+
+    // component of `f`. components of callees have smaller numbers than their callers.
+    pub fn scc(&self, f: FuncId) -> u32 {
+        self.scc[f]
+    }
+
+    // can `f` call itself, directly or through other functions?
+    pub fn is_recursive(&self, f: FuncId) -> bool {
+        self.recursive.contains(f)
+    }
+
+}
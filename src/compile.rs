@@ -1,27 +1,40 @@
 //! Compiler pipeline.
 
+use alloc::vec::Vec;
 use core::mem::{transmute, ManuallyDrop};
 
-use enumset::EnumSet;
 use zerocopy::IntoBytes;
 
+use crate::aot;
 use crate::bump::{Bump, BumpRef};
+use crate::cache;
+use crate::crash;
+use crate::deps::Deps;
+use crate::diag::{Diagnostics, Severity, Span};
 use crate::emit::Emit;
 use crate::finalize::FinalizerBuilder;
+use crate::hash::HashMap;
 use crate::host::HostCtx;
 use crate::image::Image;
 use crate::index::IndexSet;
 use crate::intern::{Intern, IRef};
+#[cfg(feature="interp")]
+use crate::interp::Interp;
 use crate::ir::{InsId, IR};
 use crate::layout::ComputeLayout;
-use crate::lex::Token;
+use crate::lang_Host::{HostClosure, HostFunc};
+use crate::lex::{SourceLocation, Token};
+use crate::limits;
 use crate::link::Link;
 use crate::lower::Lower;
 use crate::mcode::MCode;
 use crate::mem::{Layout, ResetSeq};
-use crate::obj::Objects;
-use crate::optimize::{OptFlag, Optimize};
+use crate::obj::{ObjRef, Objects, EXPR, MOD};
+use crate::optimize::{Optimize, Pipeline};
 use crate::parser::Parser;
+use crate::pgo::{self, PgoError};
+use crate::session::Session;
+use crate::trace::trace_span;
 use crate::typeinfer::TypeInfer;
 use crate::typestate::{typestate_union, Absent, Access, R, RW};
 
@@ -33,13 +46,13 @@ pub trait Stage: Sized {
 }
 
 macro_rules! define_stages {
-    ($( $name:ident $data:ty; )*) => {
+    ($( $(#[$meta:meta])* $name:ident $data:ty; )*) => {
         typestate_union! {
             pub union StageData:_StageData {
-                $($name: $data),*
+                $($(#[$meta])* $name: $data),*
             }
         }
-        $( unsafe impl StageMarker for $data {} )*
+        $( $(#[$meta])* unsafe impl StageMarker for $data {} )*
     };
 }
 
@@ -56,6 +69,8 @@ define_stages! {
     LAYOUT      ComputeLayout;
     EMIT        Emit;
     LINK        Link;
+    #[cfg(feature="interp")]
+    INTERP      Interp;
 }
 
 impl Stage for Absent { fn new(_: &mut Ccx<Absent>) -> Result<Self> { Ok(Self) } }
@@ -84,6 +99,12 @@ pub struct Ccx<P=(), O=RW, I=RW> {
     pub intern: Intern,
     // finalizers
     pub fin: FinalizerBuilder,
+    // source line of each model definition
+    pub srclines: HashMap<ObjRef<MOD>, u32>,
+    // source location of each parsed expression
+    pub srclocs: HashMap<ObjRef<EXPR>, SourceLocation>,
+    // functions lowered from each object and the calls between them, from the last lowering
+    pub deps: Deps,
     // vmctx memory layout information
     pub layout: Layout,
     // mcode functions and data
@@ -92,8 +113,12 @@ pub struct Ccx<P=(), O=RW, I=RW> {
     pub host: HostCtx,
     // compilation result
     pub image: Option<Image>,
-    // optimization flags
-    pub flags: EnumSet<OptFlag>,
+    // diagnostics of compile errors and warnings, until the host clears them
+    pub diag: Diagnostics,
+    // optimizer pass order, iteration limit and statistics
+    pub pipeline: Pipeline,
+    // configuration shared by every compile
+    pub session: Session,
     // markers for algorithms
     pub mark1: IndexSet<InsId>,
     pub mark2: IndexSet<InsId>
@@ -108,7 +133,7 @@ impl Ccx<Absent> {
 
     pub const SEQ_GLOBAL: IRef<[u8]> = IRef::small_from_end_size(11, 5);
 
-    pub fn new(host: HostCtx) -> Self {
+    pub fn new(host: HostCtx, session: Session) -> Self {
         let mut intern = Intern::default();
         let global_str = intern.intern("global");
         debug_assert!(global_str == IRef::small_from_end_size(6, 6));
@@ -125,11 +150,16 @@ impl Ccx<Absent> {
             tmp: Default::default(),
             intern,
             fin: Default::default(),
+            srclines: Default::default(),
+            srclocs: Default::default(),
+            deps: Default::default(),
             data: Default::default(),
             mcode: Default::default(),
             image: Default::default(),
+            diag: Default::default(),
             layout: Default::default(),
-            flags: EnumSet::all(),
+            pipeline: Default::default(),
+            session,
             mark1: Default::default(),
             mark2: Default::default()
         }
@@ -152,6 +182,35 @@ impl<P: StageMarker> Ccx<P, RW, RW> {
         Ok(CompileStage { ccx, data })
     }
 
+    // feed back a profile saved from a previously profiled image (see pgo::save).
+    pub fn set_profile(&mut self, data: &[u8]) -> Result {
+        match pgo::parse(data) {
+            Some(pgo) => {
+                self.session.pgo = Some(pgo);
+                Ok(())
+            },
+            None => self.error(PgoError::BadProfile)
+        }
+    }
+
+    // register a native function callable from models, see lang_Host.
+    // returns None if the name is already taken.
+    pub fn add_hostfunc(&mut self, func: HostFunc) -> Option<()> {
+        self.session.add_hostfunc(func)
+    }
+
+    // register a rust closure callable from models. the signature comes from the closure's
+    // parameter and return types, eg. `|x: f64, n: i32| x*n as f64` is `f64 (f64, i32)`.
+    // returns None if the name is already taken.
+    #[allow(dead_code)] // for rust embedders
+    pub fn add_hostclosure<F,Args>(&mut self, name: &[u8], f: F, pure: bool) -> Option<()>
+        where F: HostClosure<Args>
+    {
+        let mut func = HostFunc::closure(name, f);
+        func.pure = pure;
+        self.add_hostfunc(func)
+    }
+
 }
 
 impl<P,G> Ccx<P, G, RW> {
@@ -180,6 +239,11 @@ impl<P,I> Ccx<P, RW, I> {
 pub trait CompileError<P=()> {
     #[cold]
     fn write(self, ccx: &mut Ccx<P, R, R>);
+    // diagnostic code. E00xx = parser, E01xx = types, E02xx = lowering,
+    // E03xx = resource limits, E0000 = other.
+    fn code(&self) -> &'static str { "E0000" }
+    // where in the source the error is, if known.
+    fn span(&self, _: &mut Ccx<P, R, R>) -> Option<Span> { None }
 }
 
 // shorthand so that you don't have to write ccx.erase().error(...) for generic errors.
@@ -187,6 +251,12 @@ impl<T,P> CompileError<P> for T where T: CompileError<()>, P: StageMarker {
     fn write(self, ccx: &mut Ccx<P, R, R>) {
         self.write(ccx.erase())
     }
+    fn code(&self) -> &'static str {
+        CompileError::<()>::code(self)
+    }
+    fn span(&self, ccx: &mut Ccx<P, R, R>) -> Option<Span> {
+        CompileError::<()>::span(self, ccx.erase())
+    }
 }
 
 impl<P,G,I> Ccx<P,G,I> {
@@ -207,7 +277,11 @@ impl<P,G,I> Ccx<P,G,I> {
         self.host.buf.clear();
         // safety: this is basically the same as calling erase+freeze_ir+freeze_graph but without
         // the necessary type acrobatics
-        e.write(unsafe { transmute(self) });
+        let ccx: &mut Ccx<P, R, R> = unsafe { transmute(self) };
+        let code = e.code();
+        let span = e.span(ccx);
+        e.write(ccx);
+        ccx.diag.push(code, Severity::Error, span, ccx.host.buf.as_slice());
     }
 
     // this is split to avoid monomorphization for each T
@@ -246,18 +320,50 @@ impl<'a, P, T: StageMarker> Drop for CompileStage<'a, P, T> {
 }
 
 fn run<P: StageMarker>(ccx: &mut Ccx<Absent>) -> Result {
+    let name = core::any::type_name::<P>().rsplit("::").next().unwrap();
+    let _span = trace_span!("{}", name);
+    let _crash = crash::stage(ccx, name);
     P::run(ccx.begin::<P>()?.ccx)
 }
 
 impl Ccx<Absent> {
 
     pub fn compile(&mut self) -> Result {
+        limits::check_memory(self)?;
         run::<TypeInfer>(self)?;
         run::<Lower>(self)?;
+        limits::check_ins(self)?;
         run::<Optimize>(self)?;
+        limits::check_ins(self)?;
+        run::<ComputeLayout>(self)?;
+        #[cfg(not(feature="interp"))]
+        {
+            run::<Emit>(self)?;
+            run::<Link>(self)?;
+        }
+        #[cfg(feature="interp")]
+        run::<Interp>(self)?;
+        Ok(())
+    }
+
+    // like compile, but write the machine code into a relocatable object instead of linking it.
+    pub fn compile_object(&mut self, prefix: &str) -> Result<Vec<u8>> {
+        limits::check_memory(self)?;
+        run::<TypeInfer>(self)?;
+        run::<Lower>(self)?;
+        limits::check_ins(self)?;
+        run::<Optimize>(self)?;
+        limits::check_ins(self)?;
         run::<ComputeLayout>(self)?;
         run::<Emit>(self)?;
-        run::<Link>(self)?;
+        aot::emit_object(self, prefix)
+    }
+
+    // like compile, but take the machine code from a cached image instead of generating it.
+    pub fn compile_cached(&mut self, data: &[u8], key: u64) -> Result {
+        let image = cache::load(self, data, key)?;
+        run::<TypeInfer>(self)?;
+        self.image = Some(image);
         Ok(())
     }
 
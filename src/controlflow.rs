@@ -9,8 +9,10 @@ use alloc::vec::Vec;
 
 use crate::bitmap::{BitMatrix, BitmapVec, Bitmap};
 use crate::graph::{Graph, GraphPtr};
+use crate::hash::fxhash;
 use crate::index::{self, index, Index, IndexSet, IndexSlice, IndexVec, InvalidValue};
 use crate::ir::{Func, Ins, InsId, Opcode};
+use crate::obj::{ObjRef, EXPR};
 
 /* ---- Control flow graph -------------------------------------------------- */
 
@@ -162,6 +164,135 @@ fn compute_domtree(cfg: &GraphPtr<BlockId>, idom: &mut IndexSlice<BlockId, Block
     debug_assert!(idom.pairs().all(|(i,&d)| d <= i));
 }
 
+/* ---- Dominators and loops ------------------------------------------------ */
+
+// dominator tree and loop nesting forest of the reachable control instructions of a function.
+// blocks are numbered in preorder from the entry, so a block's dominators (and the headers of
+// the loops containing it) always have smaller ids than the block itself.
+//
+// a loop is the natural loop of a header and its back edges. cycles that aren't entered through
+// a dominating header (irreducible control flow) aren't loops.
+//
+// the structure is recomputed by `update` only when the function's control instructions change,
+// so passes can ask for it as often as they like.
+#[derive(Default)]
+pub struct Structure {
+    pub blocks: IndexVec<BlockId, InsId>,   // block -> control instruction
+    pub idom: IndexVec<BlockId, BlockId>,   // block -> immediate dominator
+    pub header: IndexVec<BlockId, BlockId>, // block -> innermost loop header, INVALID if none
+    outer: IndexVec<BlockId, BlockId>,      // loop header -> enclosing loop header
+    block: IndexVec<InsId, BlockId>,        // control instruction -> block, INVALID if unreachable
+    cfg: Graph<BlockId>,
+    work: Vec<BlockId>,
+    stamp: u64
+}
+
+fn structure_stamp(func: &Func) -> u64 {
+    let mut stamp = fxhash((func.entry, func.code.end()));
+    for (_, ins) in func.code.pairs() {
+        if ins.opcode().is_control() {
+            stamp = fxhash((stamp, ins));
+        }
+    }
+    stamp
+}
+
+fn preorder(
+    code: &IndexSlice<InsId, Ins>,
+    blocks: &mut IndexVec<BlockId, InsId>,
+    block: &mut IndexSlice<InsId, BlockId>,
+    id: InsId
+) {
+    if block[id] != BlockId::INVALID.into() {
+        return;
+    }
+    block[id] = blocks.push(id);
+    for &c in code[id].controls() {
+        preorder(code, blocks, block, c);
+    }
+}
+
+impl Structure {
+
+    pub fn update(&mut self, func: &Func) {
+        let stamp = structure_stamp(func);
+        if stamp == self.stamp && !self.blocks.is_empty() {
+            return;
+        }
+        self.stamp = stamp;
+        let code = func.code.take_inner();
+        self.compute(&code, func.entry);
+        func.code.replace_inner(code);
+    }
+
+    fn compute(&mut self, code: &IndexSlice<InsId, Ins>, entry: InsId) {
+        self.blocks.clear();
+        self.block.clear();
+        self.block.raw.resize(code.raw.len(), BlockId::INVALID.into());
+        preorder(code, &mut self.blocks, &mut self.block, entry);
+        let end = self.blocks.end();
+        self.cfg.clear(end);
+        for (b, &ctr) in self.blocks.pairs() {
+            self.cfg.add_inputs_iter(b, code[ctr].controls().iter().map(|&c| self.block[c]));
+        }
+        self.idom.clear();
+        self.idom.raw.resize(end.into(), BlockId::START);
+        compute_domtree(&self.cfg, &mut self.idom);
+        // find loops, innermost first. a header is dominated by the headers of all loops
+        // containing it, so walking headers in reverse preorder visits inner loops first.
+        self.header.clear();
+        self.header.raw.resize(end.into(), BlockId::INVALID.into());
+        self.outer.clear();
+        self.outer.raw.resize(end.into(), BlockId::INVALID.into());
+        for h in index::iter_span(end).rev() {
+            self.work.clear();
+            self.work.extend(self.cfg.uses(h).iter().cloned().filter(|&p| dom(&self.idom, h, p)));
+            if self.work.is_empty() { continue }
+            self.header[h] = h;
+            while let Some(b) = self.work.pop() {
+                if self.header[b] == BlockId::INVALID.into() {
+                    self.header[b] = h;
+                    self.work.extend_from_slice(self.cfg.uses(b));
+                    continue;
+                }
+                // b is already in a loop. find the outermost one found so far.
+                let mut inner = self.header[b];
+                while inner != h && self.outer[inner] != BlockId::INVALID.into() {
+                    inner = self.outer[inner];
+                }
+                if inner != h {
+                    self.outer[inner] = h;
+                    self.work.extend_from_slice(self.cfg.uses(inner));
+                }
+            }
+        }
+    }
+
+    pub fn block(&self, ctr: InsId) -> Option<BlockId> {
+        let idx: usize = ctr.into();
+        match self.block.raw.get(idx) {
+            Some(&b) if b != BlockId::INVALID.into() => Some(b),
+            _ => None
+        }
+    }
+
+    pub fn dominates(&self, a: BlockId, b: BlockId) -> bool {
+        dom(&self.idom, a, b)
+    }
+
+    // number of loops containing `block`
+    pub fn loop_depth(&self, block: BlockId) -> u32 {
+        let mut depth = 0;
+        let mut h = self.header[block];
+        while h != BlockId::INVALID.into() {
+            depth += 1;
+            h = self.outer[h];
+        }
+        depth
+    }
+
+}
+
 /* ---- Dataflow systems ---------------------------------------------------- */
 
 pub struct DataflowSystem<I: Index> {
@@ -616,6 +747,20 @@ impl InstanceMap {
         self.new.len()
     }
 
+    // source expression of each new instruction, see `Func::spans`.
+    pub fn map_spans(&self, func: &Func, spans: &mut IndexVec<InsId, ObjRef<EXPR>>) {
+        spans.clear();
+        spans.raw.resize(self.new.len(), ObjRef::NIL.cast());
+        let mut cursor = 0;
+        for (old, &end) in self.old.raw[1..].iter().enumerate() {
+            let span = func.span(old.into());
+            while cursor < end as usize {
+                spans[self.new[cursor]] = span;
+                cursor += 1;
+            }
+        }
+    }
+
 }
 
 impl Schedule {
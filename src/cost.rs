@@ -0,0 +1,76 @@
+//! Target cost model.
+
+// approximate latencies, in cycles, of the operations that the optimizer trades against each
+// other. a rewrite that is only worth it on some targets asks the model instead of hard-coding
+// the choice, eg. whether x/k should become x*(1/k). the numbers only need to be right relative
+// to each other. a new session uses the model of the host.
+
+use crate::ir::{Opcode, Type};
+
+pub struct CostModel {
+    pub name: &'static str,
+    // integer add, multiply, divide
+    iadd: u8,
+    imul: u8,
+    idiv: u8,
+    // float add, multiply, divide, square root, pow (a libm call)
+    fadd: u8,
+    fmul: u8,
+    fdiv: u8,
+    fsqrt: u8,
+    fpow: u8
+}
+
+pub const X86_64: CostModel = CostModel {
+    name: "x86_64",
+    iadd: 1, imul: 3, idiv: 40,
+    fadd: 3, fmul: 4, fdiv: 13, fsqrt: 18, fpow: 80
+};
+
+pub const AARCH64: CostModel = CostModel {
+    name: "aarch64",
+    iadd: 1, imul: 3, idiv: 12,
+    fadd: 2, fmul: 3, fdiv: 10, fsqrt: 12, fpow: 80
+};
+
+pub const GENERIC: CostModel = CostModel {
+    name: "generic",
+    iadd: 1, imul: 3, idiv: 20,
+    fadd: 3, fmul: 4, fdiv: 15, fsqrt: 15, fpow: 80
+};
+
+const MODELS: &[&CostModel] = &[&X86_64, &AARCH64, &GENERIC];
+
+impl CostModel {
+
+    pub fn host() -> &'static CostModel {
+        if cfg!(target_arch="x86_64") {
+            &X86_64
+        } else if cfg!(target_arch="aarch64") {
+            &AARCH64
+        } else {
+            &GENERIC
+        }
+    }
+
+    pub fn from_name(name: &[u8]) -> Option<&'static CostModel> {
+        MODELS.iter().copied().find(|m| m.name.as_bytes() == name)
+    }
+
+    // latency of `op` on values of type `ty`. operations the model doesn't distinguish cost 1.
+    pub fn latency(&self, op: Opcode, ty: Type) -> u32 {
+        use Opcode::*;
+        (match (op, ty.is_fp()) {
+            (ADD|SUB|NEG, false)  => self.iadd,
+            (MUL, false)          => self.imul,
+            (DIV|UDIV, false)     => self.idiv,
+            (ADD|SUB|NEG, true)   => self.fadd,
+            (MUL, true)           => self.fmul,
+            (DIV, true)           => self.fdiv,
+            (SQRT, _)             => self.fsqrt,
+            (POW, _)              => self.fpow,
+            _                     => 1
+        }) as _
+    }
+
+}
@@ -0,0 +1,139 @@
+//! Crash reports for internal compiler errors.
+
+// while a stage runs, its Ccx is reachable from the panic hook. if the compiler panics, the hook
+// writes a report into `Session::crashdir` with everything needed to reproduce the bug: options,
+// stage, last optimizer pass, object graph, IR and a backtrace. the hook runs before unwinding,
+// so this works with panic=abort, too.
+//
+// crash reports need std. without the `std` feature, `Session::crashdir` does nothing.
+
+#[cfg(feature="std")]
+mod crash_impl {
+
+    extern crate std;
+
+    use core::cell::Cell;
+    use core::fmt::Write;
+    use core::sync::atomic::{AtomicU32, Ordering};
+    use std::backtrace::Backtrace;
+    use std::panic::PanicHookInfo;
+    use std::sync::Once;
+
+    use alloc::boxed::Box;
+    use alloc::format;
+
+    use crate::bump::Bump;
+    use crate::compile::Ccx;
+    use crate::dump::{dump_ir, dump_objs};
+    use crate::obj::ObjRef;
+    use crate::FHK_VERSION_STRING;
+
+    struct Current {
+        ccx: Cell<*const Ccx>,
+        stage: Cell<&'static str>,
+        pass: Cell<&'static str>
+    }
+
+    std::thread_local! {
+        static CURRENT: Current = const {
+            Current {
+                ccx: Cell::new(core::ptr::null()),
+                stage: Cell::new(""),
+                pass: Cell::new("")
+            }
+        };
+    }
+
+    static HOOK: Once = Once::new();
+    static SEQ: AtomicU32 = AtomicU32::new(0);
+
+    pub struct StageGuard;
+
+    impl Drop for StageGuard {
+        fn drop(&mut self) {
+            CURRENT.with(|c| c.ccx.set(core::ptr::null()));
+        }
+    }
+
+    pub fn stage<P>(ccx: &Ccx<P>, name: &'static str) -> StageGuard {
+        HOOK.call_once(|| {
+            let prev = std::panic::take_hook();
+            std::panic::set_hook(Box::new(move |info| {
+                report(info);
+                prev(info);
+            }));
+        });
+        CURRENT.with(|c| {
+            c.ccx.set(ccx as *const Ccx<P> as *const Ccx);
+            c.stage.set(name);
+            c.pass.set("");
+        });
+        StageGuard
+    }
+
+    pub fn pass(name: &'static str) {
+        CURRENT.with(|c| c.pass.set(name));
+    }
+
+    #[cold]
+    fn report(info: &PanicHookInfo) {
+        // take the pointer so that a panic while writing the report doesn't recurse.
+        let Some((ccx, stage, pass)) = CURRENT.try_with(|c| {
+            (c.ccx.replace(core::ptr::null()), c.stage.get(), c.pass.get())
+        }).ok() else { return };
+        if ccx.is_null() { return }
+        // safety: the stage that set the pointer is still running, and it's not going to touch the
+        // Ccx again before the panic unwinds or aborts.
+        let ccx = unsafe { &*ccx };
+        let Some(dir) = &ccx.session.crashdir else { return };
+        let s = &ccx.session;
+        let mut buf: Bump = Default::default();
+        write!(buf, "fhk internal compiler error\n").unwrap();
+        let version = core::str::from_utf8(FHK_VERSION_STRING).unwrap_or("?");
+        write!(buf, "version: {}\n", version).unwrap();
+        write!(buf, "stage: {}\n", stage).unwrap();
+        if !pass.is_empty() {
+            write!(buf, "pass: {}\n", pass).unwrap();
+        }
+        write!(buf, "{}\n", info).unwrap();
+        write!(buf, "\n---- options ----\n").unwrap();
+        write!(buf, "flags: {:?}\n", s.flags).unwrap();
+        write!(buf, "passes: {:?} (max {})\n", ccx.pipeline.passes, ccx.pipeline.max_iter).unwrap();
+        write!(buf, "icheck: {:?}\ndivzero: {:?}\nswitchmin: {}\nguardeps: {:?}\ninlinecost: {}\n",
+            s.icheck, s.divzero, s.switchmin, s.guardeps, s.inlinecost).unwrap();
+        write!(buf, "cgspeed: {}\ncgsize: {}\ncost: {}\nfastmath: {}\nfloat: {}\n",
+            s.cgspeed, s.cgsize, s.cost.name, s.fastmath, s.float.name()).unwrap();
+        write!(buf, "profile: {}\nhooks: {}\npgo: {}\n", s.profile, s.hooks, s.pgo.is_some())
+            .unwrap();
+        write!(buf, "maxmem: {}\nmaxins: {}\n", s.maxmem, s.maxins).unwrap();
+        write!(buf, "\n---- backtrace ----\n{}\n", Backtrace::force_capture()).unwrap();
+        write!(buf, "\n---- objects ----\n").unwrap();
+        dump_objs(&mut buf, &ccx.intern, &ccx.objs, ObjRef::NIL);
+        write!(buf, "\n---- IR ----\n").unwrap();
+        dump_ir(&mut buf, &ccx.ir, &ccx.intern, &ccx.objs);
+        let path = format!("{}/fhk-crash-{}-{}.txt", dir, std::process::id(),
+            SEQ.fetch_add(1, Ordering::Relaxed));
+        match std::fs::write(&path, buf.as_slice::<u8>()) {
+            Ok(()) => std::eprintln!("fhk: crash report written to {}", path),
+            Err(e) => std::eprintln!("fhk: failed to write crash report {}: {}", path, e)
+        }
+    }
+
+}
+
+#[cfg(not(feature="std"))]
+mod crash_impl {
+
+    use crate::compile::Ccx;
+
+    pub struct StageGuard;
+
+    pub fn stage<P>(_: &Ccx<P>, _: &'static str) -> StageGuard {
+        StageGuard
+    }
+
+    pub fn pass(_: &'static str) {}
+
+}
+
+pub use crash_impl::{pass, stage};
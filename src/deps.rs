@@ -0,0 +1,75 @@
+//! Object -> function dependencies.
+
+// after lowering, each function is attributed to the object it was lowered from (its debug
+// source), and each function depends on the chunks it calls or initializes. editing an object
+// affects the functions lowered from it and, through inlining, every function that calls them.
+//
+// this is the bookkeeping for incremental recompilation: the host can ask which functions an
+// edit to objects touches. the pipeline itself still recompiles the whole graph, because the
+// memory layout is shared by all functions, the image can't be patched in place, and the IR is
+// lowered again from the objects on every compile, so there is no optimized IR of the clean
+// functions to keep.
+
+use alloc::vec::Vec;
+
+use crate::index::IndexSet;
+use crate::ir::{FuncId, Opcode, IR};
+use crate::obj::ObjRef;
+use crate::symbol::Symbols;
+
+#[derive(Default)]
+pub struct Deps {
+    symbols: Symbols,
+    calls: Vec<(FuncId, FuncId)> // (caller, callee)
+}
+
+impl Deps {
+
+    pub fn collect(ir: &IR) -> Self {
+        let mut deps = Self { symbols: Symbols::new(ir), calls: Default::default() };
+        for (f, func) in ir.funcs.pairs() {
+            for (_, ins) in func.code.pairs() {
+                let g = match ins.opcode() {
+                    Opcode::CALLC|Opcode::CALLCI => ins.decode_CALLC().2,
+                    Opcode::CINIT => ins.decode_CINIT().1,
+                    _ => continue
+                };
+                if f != g {
+                    deps.calls.push((f, g));
+                }
+            }
+        }
+        deps
+    }
+
+    // mark the functions that must be recompiled if any of `objs` changes, returns their number.
+    pub fn affected(&self, objs: &[ObjRef], mark: &mut IndexSet<FuncId>) -> usize {
+        mark.clear();
+        let mut num = 0;
+        for &obj in objs {
+            for &f in self.symbols.funcs(obj) {
+                if !mark.test_and_set(f) {
+                    num += 1;
+                }
+            }
+        }
+        num + self.callers(mark)
+    }
+
+    // extend `mark` to every transitive caller, returns the number of new functions.
+    fn callers(&self, mark: &mut IndexSet<FuncId>) -> usize {
+        let mut num = 0;
+        loop {
+            let mut fixpoint = true;
+            for &(f, g) in &self.calls {
+                if mark.contains(g) && !mark.test_and_set(f) {
+                    fixpoint = false;
+                    num += 1;
+                }
+            }
+            if fixpoint { break }
+        }
+        num
+    }
+
+}
@@ -0,0 +1,143 @@
+//! Structured diagnostics.
+
+// every compile error is also recorded as a diagnostic on the Ccx, with an error code and the
+// source span, if the error knows one (currently only parser errors do). the first line of the
+// error message is the diagnostic message and the remaining lines (eg. the macro traceback) are
+// its notes. diagnostics accumulate until the host clears them, and render either as text or as
+// a JSON array for tools.
+
+use core::fmt::Write;
+use core::ops::Range;
+
+use alloc::vec::Vec;
+
+use crate::bump::Bump;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Severity {
+    Error,
+    Warning
+}
+
+impl Severity {
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Severity::Error   => "error",
+            Severity::Warning => "warning"
+        }
+    }
+
+}
+
+// byte range in the parsed source, with the line and column of `start`.
+#[derive(Clone, Copy, Debug)]
+pub struct Span {
+    pub start: u32,
+    pub end: u32,
+    pub line: u32,
+    pub col: u32
+}
+
+pub struct Diagnostic {
+    pub code: &'static str,
+    pub severity: Severity,
+    pub span: Option<Span>,
+    message: Range<u32>, // in Diagnostics::text
+    notes: Range<u32>    // in Diagnostics::text, one note per line
+}
+
+#[derive(Default)]
+pub struct Diagnostics {
+    list: Vec<Diagnostic>,
+    text: Vec<u8>
+}
+
+impl Diagnostics {
+
+    pub fn push(&mut self, code: &'static str, severity: Severity, span: Option<Span>, text: &[u8]) {
+        let text = text.trim_ascii_end();
+        let split = text.iter().position(|&c| c == b'\n').unwrap_or(text.len());
+        let base = self.text.len() as u32;
+        self.text.extend_from_slice(text);
+        let notes = match split < text.len() { true => split+1, false => split };
+        self.list.push(Diagnostic {
+            code,
+            severity,
+            span,
+            message: base .. base+split as u32,
+            notes: base+notes as u32 .. base+text.len() as u32
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.list.clear();
+        self.text.clear();
+    }
+
+    pub fn message(&self, diag: &Diagnostic) -> &[u8] {
+        &self.text[diag.message.start as usize .. diag.message.end as usize]
+    }
+
+    pub fn notes<'a>(&'a self, diag: &Diagnostic) -> impl Iterator<Item=&'a [u8]> {
+        let notes = &self.text[diag.notes.start as usize .. diag.notes.end as usize];
+        notes.split(|&c| c == b'\n').filter(|n| !n.is_empty())
+    }
+
+    pub fn render(&self, buf: &mut Bump) {
+        for diag in &self.list {
+            write!(buf, "{}[{}]: ", diag.severity.name(), diag.code).unwrap();
+            buf.write(self.message(diag));
+            buf.push(b'\n');
+            if let Some(span) = diag.span {
+                write!(buf, "  --> line {} col {} (bytes {}..{})\n",
+                    span.line, span.col, span.start, span.end).unwrap();
+            }
+            for note in self.notes(diag) {
+                buf.write(b"  = note: ");
+                buf.write(note);
+                buf.push(b'\n');
+            }
+        }
+    }
+
+    pub fn render_json(&self, buf: &mut Bump) {
+        buf.push(b'[');
+        for (i, diag) in self.list.iter().enumerate() {
+            if i > 0 { buf.push(b','); }
+            write!(buf, "{{\"code\":\"{}\",\"severity\":\"{}\",\"message\":",
+                diag.code, diag.severity.name()).unwrap();
+            jsonstr(buf, self.message(diag));
+            buf.write(b",\"span\":");
+            match diag.span {
+                Some(Span { start, end, line, col }) => write!(buf,
+                    "{{\"start\":{},\"end\":{},\"line\":{},\"col\":{}}}",
+                    start, end, line, col).unwrap(),
+                None => { buf.write(b"null"); }
+            }
+            buf.write(b",\"notes\":[");
+            for (j, note) in self.notes(diag).enumerate() {
+                if j > 0 { buf.push(b','); }
+                jsonstr(buf, note);
+            }
+            buf.write(b"]}");
+        }
+        buf.push(b']');
+    }
+
+}
+
+fn jsonstr(buf: &mut Bump, s: &[u8]) {
+    buf.push(b'"');
+    for &c in s {
+        match c {
+            b'"'  => { buf.write(b"\\\""); },
+            b'\\' => { buf.write(b"\\\\"); },
+            b'\n' => { buf.write(b"\\n"); },
+            b'\t' => { buf.write(b"\\t"); },
+            0..0x20 => write!(buf, "\\u{:04x}", c).unwrap(),
+            _ => { buf.push(c); }
+        }
+    }
+    buf.push(b'"');
+}
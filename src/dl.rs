@@ -39,6 +39,11 @@ pub fn open(mut name: &[u8]) -> Option<LibBox> {
     None
 }
 
+// the running program and (on unix) every library it has loaded.
+pub fn this() -> Option<LibBox> {
+    unsafe { target::this() }
+}
+
 impl Lib {
 
     pub fn sym(&self, name: &CStr) -> *mut c_void {
@@ -107,6 +112,11 @@ mod target {
         Some(LibBox(NonNull::new(unsafe { libc::dlopen(name, libc::RTLD_LAZY) }.cast())?))
     }
 
+    pub unsafe fn this() -> Option<LibBox> {
+        let lib = unsafe { libc::dlopen(core::ptr::null(), libc::RTLD_LAZY) };
+        Some(LibBox(NonNull::new(lib.cast())?))
+    }
+
     pub unsafe fn sym(lib: &Lib, name: *const c_char) -> *mut c_void {
         // i'm not sure if const ref -> mut pointer is technically haram, but lib is zero-sized and
         // never dereferenced in rust code so it's probably fine (?).
@@ -137,12 +147,21 @@ mod target {
         fn LoadLibraryA(lpLibFileName: *const c_char) -> *mut c_void;
         fn GetProcAddress(hModule: *mut c_void, lpProcName: *const c_char) -> *mut c_void;
         fn FreeLibrary(hLibModule: *mut c_void) -> c_int;
+        fn GetModuleHandleExA(dwFlags: u32, lpModuleName: *const c_char, phModule: *mut *mut c_void) -> c_int;
     }
 
     pub unsafe fn open(name: *const c_char) -> Option<LibBox> {
         Some(LibBox(NonNull::new(unsafe { LoadLibraryA(name) }.cast())?))
     }
 
+    // note: unlike dlopen(NULL), this only finds symbols exported by the executable itself.
+    pub unsafe fn this() -> Option<LibBox> {
+        // flags=0 increments the refcount, so the handle can be closed with FreeLibrary.
+        let mut handle = core::ptr::null_mut();
+        unsafe { GetModuleHandleExA(0, core::ptr::null(), &mut handle); }
+        Some(LibBox(NonNull::new(handle.cast())?))
+    }
+
     pub unsafe fn sym(lib: &Lib, name: *const c_char) -> *mut c_void {
         unsafe { GetProcAddress(lib as *const Lib as *mut Lib as *mut c_void, name) }
     }
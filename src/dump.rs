@@ -3,20 +3,40 @@
 use core::fmt::Write;
 use core::str;
 
+use alloc::vec::Vec;
 use cfg_if::cfg_if;
+use zerocopy::Unalign;
 
 use crate::bitmap::BitMatrix;
-use crate::bump::Bump;
+use crate::bump::{Bump, BumpRef};
 use crate::controlflow::BlockId;
 use crate::emit::InsValue;
 use crate::index::{self, IndexSlice};
 use crate::intern::Intern;
-use crate::ir::{DebugFlag, DebugSource, Func, FuncId, Ins, InsId, OperandData, PhiId, IR};
+use crate::ir::{Func, FuncId, Ins, InsId, Opcode, OperandData, PhiId, IR};
 use crate::mem::{BreakpointId, Layout};
-use crate::obj::{FieldType, ObjRef, ObjectRef, Objects, Operator, FUNC, MOD, TAB, VAR, VSET};
+use crate::obj::{FieldType, ObjRef, Objects};
 use crate::parser::{stringify, SequenceType};
+use crate::symbol::write_source;
 use crate::trace::trace;
 
+/* ---- DOT ----------------------------------------------------------------- */
+
+// escape everything written into `buf` since `start` for use inside a quoted DOT string.
+fn dot_escape(buf: &mut Bump, start: usize) {
+    let label = &buf.as_slice::<u8>()[start..];
+    if !label.iter().any(|c| b"\"\\\n".contains(c)) { return }
+    let label: Vec<u8> = label.to_vec();
+    buf.truncate(BumpRef::<u8>::from_ptr(start));
+    for c in label {
+        match c {
+            b'"' | b'\\' => { buf.push(b'\\'); buf.push(c); },
+            b'\n' => { buf.write(b"\\l"); },
+            _ => { buf.push(c); }
+        }
+    }
+}
+
 /* ---- Objects ------------------------------------------------------------- */
 
 fn dump_field(buf: &mut Bump, intern: &Intern, fty: FieldType, value: u32) {
@@ -76,50 +96,82 @@ pub fn dump_objs(buf: &mut Bump, intern: &Intern, objs: &Objects, start: ObjRef)
     }
 }
 
+// one node per object, one edge per reference. literals and names go in the node label.
+pub fn dump_objs_dot(buf: &mut Bump, intern: &Intern, objs: &Objects, start: ObjRef) {
+    buf.write("digraph objs {\n\tnode [shape=box fontname=monospace];\n");
+    let mut next = match start == objs.end() { true => None, false => Some(start) };
+    while let Some(idx) = next {
+        let obj = &objs[idx];
+        let op = obj.operator();
+        let raw = objs.get_raw(idx);
+        let id: u32 = zerocopy::transmute!(idx);
+        write!(buf, "\to{} [label=\"", id).unwrap();
+        let label = buf.as_slice::<u8>().len();
+        write!(buf, "{:?} {}", idx, op.name()).unwrap();
+        let mut i = 1;
+        for (fty, name) in op.fields() {
+            use FieldType::*;
+            match fty {
+                Spec => write!(buf, ".{}", obj.data).unwrap(),
+                Lit | Name => {
+                    write!(buf, " {}:", name).unwrap();
+                    dump_field(buf, intern, fty, raw[i]);
+                    i += 1;
+                },
+                Ref => i += 1,
+                _ /* VLit | VRef */ => {
+                    if fty == VLit {
+                        write!(buf, " {}:[", name).unwrap();
+                        for (j, &v) in raw[i..].iter().enumerate() {
+                            if j > 0 { buf.push(b' '); }
+                            dump_field(buf, intern, Lit, v);
+                        }
+                        buf.push(b']');
+                    }
+                    break
+                }
+            }
+        }
+        dot_escape(buf, label);
+        buf.write("\"];\n");
+        let mut i = 1;
+        for (fty, name) in op.fields() {
+            use FieldType::*;
+            match fty {
+                Spec => continue,
+                Lit | Name => {},
+                Ref | VRef => {
+                    let end = match fty { Ref => i+1, _ => raw.len() };
+                    for (j, &r) in raw[i..end].iter().enumerate() {
+                        if r == zerocopy::transmute!(ObjRef::NIL) { continue }
+                        write!(buf, "\to{} -> o{} [label=\"{}", id, r, name).unwrap();
+                        if fty == VRef { write!(buf, "[{}]", j).unwrap(); }
+                        buf.write("\"];\n");
+                    }
+                },
+                _ /* VLit */ => {}
+            }
+            i += 1;
+        }
+        next = objs.next(idx);
+    }
+    buf.write("}\n");
+}
+
 pub fn trace_objs(sequences: &Intern, objs: &Objects, start: ObjRef) {
     if trace!() {
         let mut tmp = Default::default();
-        dump_objs(&mut tmp, sequences, objs, start);
+        if trace!(DOT) {
+            dump_objs_dot(&mut tmp, sequences, objs, start);
+        } else {
+            dump_objs(&mut tmp, sequences, objs, start);
+        }
         trace!("{}", str::from_utf8(tmp.as_slice()).unwrap());
     }
 }
 
 /* ---- IR ------------------------------------------------------------------ */
 
-fn dump_debugsource(buf: &mut Bump, intern: &Intern, objs: &Objects, src: DebugSource) {
-    let op = objs[src.obj()].operator();
-    write!(buf, "{}{:?}", op.name(), src.obj()).unwrap();
-    match objs.get(src.obj()) {
-        ObjectRef::VAR(&VAR { name, .. })
-                | ObjectRef::TAB(&TAB { name, .. })
-                | ObjectRef::FUNC(&FUNC { name, .. }) => {
-            buf.push(b'(');
-            stringify(buf, intern, intern.get_slice(name), SequenceType::Pattern);
-            buf.push(b')');
-        },
-        ObjectRef::MOD(MOD { value, .. }) => {
-            buf.push(b'(');
-            for (i, &vset) in value.iter().enumerate() {
-                if i>0 { buf.push(b','); }
-                let VSET { var, .. } = objs[vset];
-                let VAR { name, .. } = objs[var];
-                stringify(buf, intern, intern.get_slice(name), SequenceType::Pattern);
-            }
-            buf.push(b')');
-        },
-        _ => {}
-    }
-    let flags = src.flags();
-    if flags.contains(DebugFlag::VALUE) {
-        buf.write(".value");
-    } else if (Operator::VAR|Operator::MOD).contains(op) {
-        buf.write(".avail");
-    }
-    if flags.contains(DebugFlag::INIT) {
-        buf.write(".init");
-    }
-}
-
 fn dump_ins(
     buf: &mut Bump,
     id: InsId,
@@ -152,7 +204,7 @@ fn dump_ins(
             },
             F(f) => {
                 write!(buf, "{:?}<", f).unwrap();
-                dump_debugsource(buf, intern, objs, funcs[f].source);
+                write_source(buf, intern, objs, funcs[f].source);
                 buf.push(b'>');
                 Ok(())
             },
@@ -190,7 +242,7 @@ fn dump_phis(buf: &mut Bump, func: &Func) {
 
 fn dump_funcheader(buf: &mut Bump, fid: FuncId, func: &Func, intern: &Intern, objs: &Objects) {
     write!(buf, "---------- FUNC {}<", {let i: u16 = zerocopy::transmute!(fid); i}).unwrap();
-    dump_debugsource(buf, intern, objs, func.source);
+    write_source(buf, intern, objs, func.source);
     buf.write("> ----------\n");
     dump_phis(buf, func);
 }
@@ -203,6 +255,76 @@ pub fn dump_ir(buf: &mut Bump, ir: &IR, intern: &Intern, objs: &Objects) {
     }
 }
 
+pub fn dump_kvalue(buf: &mut Bump, intern: &Intern, ins: Ins) {
+    match ins.opcode() {
+        Opcode::KINT => write!(buf, " {}", ins.bc() as i32).unwrap(),
+        Opcode::KINT64 => {
+            let data: BumpRef<Unalign<i64>> = zerocopy::transmute!(ins.bc());
+            write!(buf, " {}", intern.bump()[data].get()).unwrap();
+        },
+        Opcode::KFP64 => {
+            let data: BumpRef<Unalign<f64>> = zerocopy::transmute!(ins.bc());
+            write!(buf, " {}", intern.bump()[data].get()).unwrap();
+        },
+        _ => {}
+    }
+}
+
+// one cluster per function. value edges go from the input to the user (solid), control edges
+// from the instruction to its target (dashed). constants are shown with their value.
+pub fn dump_ir_dot(buf: &mut Bump, ir: &IR, intern: &Intern, objs: &Objects) {
+    use OperandData::*;
+    buf.write("digraph ir {\n\tnode [shape=box fontname=monospace];\n");
+    for (fid, func) in ir.funcs.pairs() {
+        let f: u16 = zerocopy::transmute!(fid);
+        write!(buf, "\tsubgraph cluster_f{} {{\n\t\tlabel=\"", f).unwrap();
+        let label = buf.as_slice::<u8>().len();
+        write!(buf, "FUNC {}<", f).unwrap();
+        write_source(buf, intern, objs, func.source);
+        buf.write(">\n");
+        dump_phis(buf, func);
+        dot_escape(buf, label);
+        write!(buf, "\";\n\t\tf{}_entry [shape=point];\n\t\tf{}_entry -> f{}_{:?} [style=dashed];\n",
+            f, f, f, func.entry).unwrap();
+        for (id, ins) in func.code.pairs() {
+            let opcode = ins.opcode();
+            write!(buf, "\t\tf{}_{:?} [label=\"", f, id).unwrap();
+            let label = buf.as_slice::<u8>().len();
+            write!(buf, "{:?} {} {}", id, ins.type_().name(), opcode.name()).unwrap();
+            if opcode.is_const() {
+                dump_kvalue(buf, intern, ins);
+            } else {
+                for op in ins.operands() {
+                    match op {
+                        V(_) | C(_) => {},
+                        F(callee) => {
+                            write!(buf, " {:?}<", callee).unwrap();
+                            write_source(buf, intern, objs, ir.funcs[callee].source);
+                            buf.push(b'>');
+                        },
+                        d => write!(buf, " {:?}", d).unwrap()
+                    }
+                }
+            }
+            dot_escape(buf, label);
+            buf.write(match opcode.is_control() {
+                true  => "\" style=bold];\n",
+                false => "\"];\n"
+            });
+            for op in ins.operands() {
+                match op {
+                    V(v) => write!(buf, "\t\tf{}_{:?} -> f{}_{:?};\n", f, v, f, id).unwrap(),
+                    C(c) => write!(buf, "\t\tf{}_{:?} -> f{}_{:?} [style=dashed color=blue];\n",
+                        f, id, f, c).unwrap(),
+                    _ => {}
+                }
+            }
+        }
+        buf.write("\t}\n");
+    }
+    buf.write("}\n");
+}
+
 pub fn dump_schedule(
     buf: &mut Bump,
     fid: FuncId,
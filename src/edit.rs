@@ -0,0 +1,147 @@
+//! Checked edits of the object graph.
+
+// hosts that generate models programmatically can change parsed objects directly instead of
+// writing source text and parsing it again. object references come from the host, so each edit
+// checks that it's given the right kind of object and that the result is something the parser
+// could have produced. on error, nothing is changed.
+//
+// new variables and expressions are created by parsing (see PARSE_VAR and PARSE_EXPR), edits only
+// rewire and adjust existing objects. an expression that an edit detaches stays in the graph
+// without a type or code.
+
+use core::fmt::{self, Display};
+
+use crate::intern::Intern;
+use crate::obj::{Obj, ObjRef, ObjectRef, Objects, Operator, EXPR, KINT, MOD, VAR, VGET};
+
+pub enum EditError {
+    NotObject,
+    Kind(&'static str),
+    Builtin,
+    NotInteger(f64),
+    NotOutput,
+    SelfReference
+}
+
+impl Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EditError::NotObject => f.write_str("not an object"),
+            EditError::Kind(what) => write!(f, "expected {}", what),
+            EditError::Builtin => f.write_str("builtin constants can't be edited"),
+            EditError::NotInteger(v) => write!(f, "integer constant can't hold {}", v),
+            EditError::NotOutput => f.write_str("variable is not returned by the model"),
+            EditError::SelfReference => f.write_str("model value reads its own output")
+        }
+    }
+}
+
+// host references are untrusted: check that `idx` starts an object of kind `op`.
+fn checkobj(objs: &Objects, idx: ObjRef, op: u8, what: &'static str) -> Result<(), EditError> {
+    if !objs.keys().any(|o| o == idx) {
+        return Err(EditError::NotObject);
+    }
+    match objs[idx].op == op {
+        true => Ok(()),
+        false => Err(EditError::Kind(what))
+    }
+}
+
+fn checkexpr(objs: &Objects, idx: ObjRef) -> Result<(), EditError> {
+    if !objs.keys().any(|o| o == idx) {
+        return Err(EditError::NotObject);
+    }
+    match Operator::is_expr_raw(objs[idx].op) {
+        true => Ok(()),
+        false => Err(EditError::Kind("expression"))
+    }
+}
+
+// does `expr` read `var` at the current index?
+fn readsself(objs: &Objects, expr: ObjRef, var: ObjRef<VAR>) -> bool {
+    if let ObjectRef::VGET(&VGET { var: v, ref idx, .. }) = objs.get(expr) {
+        if v == var && idx.is_empty() {
+            return true;
+        }
+    }
+    let raw = objs.get_raw(expr);
+    objs[expr].ref_params().any(|i| {
+        let o: ObjRef = zerocopy::transmute!(raw[i+1]);
+        Operator::is_expr_raw(objs[o].op) && readsself(objs, o, var)
+    })
+}
+
+// set the value of a numeric constant. integer constants stay integers.
+pub fn set_constant(
+    objs: &mut Objects,
+    intern: &mut Intern,
+    expr: ObjRef<EXPR>,
+    value: f64
+) -> Result<(), EditError> {
+    checkexpr(objs, expr.erase())?;
+    let raw: u32 = zerocopy::transmute!(expr);
+    let last: u32 = zerocopy::transmute!(ObjRef::GLOBAL);
+    if raw <= last {
+        return Err(EditError::Builtin);
+    }
+    let k: &mut KINT = &mut objs[expr.cast()];
+    match k.op {
+        Obj::KINT | Obj::KINT64 => {
+            if value.fract() != 0.0 || !(i64::MIN as f64..=i64::MAX as f64).contains(&value) {
+                return Err(EditError::NotInteger(value));
+            }
+            let v = value as i64;
+            match v == v as i32 as i64 {
+                true => {
+                    k.op = Obj::KINT;
+                    k.k = v as _;
+                },
+                false => {
+                    let r: u32 = zerocopy::transmute!(intern.intern(&v.to_ne_bytes()).to_bump());
+                    k.op = Obj::KINT64;
+                    k.k = r as _;
+                }
+            }
+        },
+        Obj::KFP64 => {
+            let r: u32 = zerocopy::transmute!(intern.intern(&value.to_ne_bytes()).to_bump());
+            k.k = r as _;
+        },
+        _ => return Err(EditError::Kind("numeric constant"))
+    }
+    Ok(())
+}
+
+// replace the expression `model` computes `var` with.
+pub fn set_model_value(
+    objs: &mut Objects,
+    model: ObjRef<MOD>,
+    var: ObjRef<VAR>,
+    value: ObjRef<EXPR>
+) -> Result<(), EditError> {
+    checkobj(objs, model.erase(), Obj::MOD, "model")?;
+    checkobj(objs, var.erase(), Obj::VAR, "variable")?;
+    checkexpr(objs, value.erase())?;
+    let Some(&vset) = objs[model].value.iter().find(|&&v| objs[v].var == var) else {
+        return Err(EditError::NotOutput);
+    };
+    if objs[vset].idx.is_empty() && readsself(objs, value.erase(), var) {
+        return Err(EditError::SelfReference);
+    }
+    objs[vset].value = value;
+    Ok(())
+}
+
+// replace the guard of `model`. nil removes it.
+pub fn set_model_guard(
+    objs: &mut Objects,
+    model: ObjRef<MOD>,
+    guard: ObjRef<EXPR>
+) -> Result<(), EditError> {
+    checkobj(objs, model.erase(), Obj::MOD, "model")?;
+    if !guard.is_nil() {
+        checkexpr(objs, guard.erase())?;
+    }
+    objs[model].guard = guard;
+    Ok(())
+}
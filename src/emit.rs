@@ -1,10 +1,12 @@
 //! IR -> Machine code pipeline.
 
-use core::mem::take;
+use core::mem::{offset_of, take};
+#[cfg(feature="threads")]
+use core::mem::replace;
 
 use alloc::vec::Vec;
 use cranelift_codegen::ir::condcodes::IntCC;
-use cranelift_codegen::ir::{AbiParam, AliasRegion, ExtFuncData, ExternalName, FuncRef, GlobalValue, GlobalValueData, InstBuilder, InstInserterBase, MemFlags, StackSlot, StackSlotData, StackSlotKind, UserExternalName, Value};
+use cranelift_codegen::ir::{AbiParam, AliasRegion, AtomicRmwOp, ExtFuncData, ExternalName, FuncRef, GlobalValue, GlobalValueData, InstBuilder, InstInserterBase, MemFlags, SourceLoc, StackSlot, StackSlotData, StackSlotKind, UserExternalName, Value};
 use cranelift_codegen::isa::{CallConv, OwnedTargetIsa};
 use cranelift_codegen::settings::Configurable;
 use cranelift_codegen::{FinalizedMachReloc, FinalizedRelocTarget};
@@ -13,18 +15,24 @@ use enumset::EnumSet;
 
 use crate::bitmap::BitMatrix;
 use crate::bump::{self, Aligned, Bump};
+use crate::callgraph::CallGraph;
 use crate::compile::{self, Ccx, Stage};
 use crate::controlflow::BlockId;
 use crate::dump::{dump_mcode, dump_schedule};
-use crate::image::Image;
-use crate::index::{self, IndexVec, InvalidValue};
-use crate::ir::{Chunk, Func, FuncId, FuncKind, Ins, InsId, Opcode, PhiId, Query, Type, IR};
+use crate::image::{Hooks, Image, ParEntry, ParSchedule, ProfCounter};
+use crate::index::{self, IndexSet, IndexVec, InvalidValue};
+use crate::ir::{Chunk, DebugFlag, Func, FuncId, FuncKind, Ins, InsId, Opcode, PhiId, Query, Type, IR};
 use crate::lang::{Lang, LangState};
 use crate::mcode::{MCode, MCodeData, MCodeOffset, Reloc, Sym};
 use crate::mem::{CursorA, SizeClass, Slot};
+use crate::obj::{ObjRef, EXPR};
+use crate::optimize::OptFlag;
+#[cfg(feature="threads")]
+use crate::parallel;
+use crate::peephole;
 use crate::schedule::{compute_schedule, Gcm};
 use crate::support::{emitsupport, NativeFunc, SuppFunc};
-use crate::trace::trace;
+use crate::trace::{trace, trace_span};
 use crate::translate::translate;
 use crate::typestate::{Absent, R, RW};
 
@@ -37,6 +45,7 @@ pub struct FuncBuilder {
     pub ctx: cranelift_codegen::Context,
     pub block: cranelift_codegen::ir::Block,
     pub supp: EnumSet<SuppFunc>, // stored here for borrowing reasons
+    pub srcloc: SourceLoc, // source expression of the instructions being built
 }
 
 // this is roughly the equivalent of
@@ -65,6 +74,16 @@ pub struct Emit {
     pub block: BlockId,
     pub fid: FuncId,
     pub idx: Value, // meaningful for chunks only
+    pub peephole: bool,
+    pub prof: *mut ProfCounter, // null when not profiling
+    pub profstart: Value, // clock at function entry
+    pub hooks: *mut Hooks, // null when not hooked
+    pub parallel: bool, // chunks may run concurrently, see parschedule
+    #[cfg(feature="threads")]
+    pub pending: Vec<(FuncId, cranelift_codegen::Context)>,
+    pub uses: IndexVec<InsId, u32>, // use counts, computed on the first switch of the function
+    pub switched: IndexSet<InsId>,  // IFs already merged into a switch
+    pub spans: IndexVec<InsId, ObjRef<EXPR>>, // source expression of each scheduled instruction
     // work arrays (TODO use ccx.tmp):
     pub tmp_val: Vec<Value>
 }
@@ -191,6 +210,9 @@ impl<'a> InstInserterBase<'a> for &'a mut FuncBuilder {
         inst: cranelift_codegen::ir::Inst
     ) -> &'a mut cranelift_codegen::ir::DataFlowGraph {
         self.ctx.func.layout.append_inst(inst, self.block);
+        if !self.srcloc.is_default() {
+            self.ctx.func.set_srcloc(inst, self.srcloc);
+        }
         &mut self.ctx.func.dfg
     }
 }
@@ -240,6 +262,7 @@ impl FuncBuilder {
 
     fn clear(&mut self) {
         self.ctx.clear();
+        self.srcloc = SourceLoc::default();
     }
 
 }
@@ -354,6 +377,7 @@ pub fn irt2cl(irt: Type) -> cranelift_codegen::ir::Type {
         F32 => types::F32,
         F64 => types::F64,
         B1  => types::I8,
+        F64X2 => types::F64X2,
         FX|LSV => unreachable!()
     }
 }
@@ -413,22 +437,98 @@ pub fn storeslot(
 ) {
     let ptr = slotptr(emit, vmctx, idx, scl, slot, type_);
     if type_ == Type::B1 {
-        let old = emit.fb.ins().load(irt2cl(Type::B1), MEM_VMCTX, ptr, 0);
         value = emit.fb.ins().ishl_imm(value, slot.bit() as i64);
+        if emit.parallel {
+            // chunks running on other threads may set bits in the same byte.
+            emit.fb.ins().atomic_rmw(irt2cl(Type::B1), MEM_VMCTX, AtomicRmwOp::Or, ptr, value);
+            return;
+        }
+        let old = emit.fb.ins().load(irt2cl(Type::B1), MEM_VMCTX, ptr, 0);
         value = emit.fb.ins().bor(value, old);
     }
     emit.fb.ins().store(MEM_VMCTX, value, ptr, 0);
 }
 
 pub fn collectargs(emit: &Emit, dest: &mut Bump<InsValue>, mut arg: InsId) {
-    while emit.code[arg].opcode() != Opcode::NOP {
-        debug_assert!(emit.code[arg].opcode() == Opcode::CARG);
+    // the list may end in ordering edges (MOVF) instead of the NOP, see lower::orderafter.
+    while emit.code[arg].opcode() == Opcode::CARG {
         let (ap, v) = emit.code[arg].decode_CARG();
         dest.push(emit.values[v]);
         arg = ap;
     }
 }
 
+fn profcounter(emit: &mut Emit) -> Value {
+    let fid: u16 = zerocopy::transmute!(emit.fid);
+    let ptr = emit.prof.wrapping_add(fid as usize);
+    emit.fb.ins().iconst(irt2cl(Type::PTR), ptr as i64)
+}
+
+fn profclock(emit: &mut Emit) -> Value {
+    let clock = emit.fb.importnative(NativeFunc::CLOCK);
+    let call = emit.fb.ins().call(clock, &[]);
+    emit.fb.ctx.func.dfg.inst_results(call)[0]
+}
+
+fn profadd(emit: &mut Emit, ptr: Value, offset: usize, value: Value) {
+    let old = emit.fb.ins().load(irt2cl(Type::I64), MemFlags::trusted(), ptr, offset as i32);
+    let new = emit.fb.ins().iadd(old, value);
+    emit.fb.ins().store(MemFlags::trusted(), new, ptr, offset as i32);
+}
+
+// count a switch case hit, see emitswitch.
+pub fn emitprofinc(emit: &mut Emit, ptr: *mut u64) {
+    let ptr = emit.fb.ins().iconst(irt2cl(Type::PTR), ptr as i64);
+    let one = emit.fb.ins().iconst(irt2cl(Type::I64), 1);
+    profadd(emit, ptr, 0, one);
+}
+
+// count the call and start the clock. must go in the entry block.
+fn emitprofenter(emit: &mut Emit) {
+    if emit.prof.is_null() { return }
+    let ptr = profcounter(emit);
+    let one = emit.fb.ins().iconst(irt2cl(Type::I64), 1);
+    profadd(emit, ptr, offset_of!(ProfCounter, calls), one);
+    emit.profstart = profclock(emit);
+}
+
+// add the cycles since entry. must go before each return.
+pub fn emitprofexit(emit: &mut Emit) {
+    if emit.prof.is_null() { return }
+    let now = profclock(emit);
+    let cycles = emit.fb.ins().isub(now, emit.profstart);
+    let ptr = profcounter(emit);
+    profadd(emit, ptr, offset_of!(ProfCounter, cycles), cycles);
+}
+
+// call a host hook with the function and its instance range.
+fn emithook(emit: &mut Emit, hook: NativeFunc) {
+    let fid: u16 = zerocopy::transmute!(emit.fid);
+    let hook = emit.fb.importnative(hook);
+    let hooks = emit.fb.ins().iconst(irt2cl(Type::PTR), emit.hooks as i64);
+    let fid = emit.fb.ins().iconst(irt2cl(Type::I32), fid as i64);
+    let (start, end) = match emit.idx == Value::reserved_value() {
+        true => {
+            let zero = emit.fb.ins().iconst(irt2cl(Type::I32), 0);
+            (zero, zero)
+        },
+        false => (emit.idx, emit.fb.ins().iadd_imm(emit.idx, 1))
+    };
+    emit.fb.ins().call(hook, &[hooks, fid, start, end]);
+}
+
+// must go in the entry block, after the chunk index is known.
+fn emithookenter(emit: &mut Emit) {
+    if emit.hooks.is_null() { return }
+    emithook(emit, NativeFunc::HOOKENTER);
+}
+
+// must go before each return.
+pub fn emithookexit(emit: &mut Emit) {
+    if emit.hooks.is_null() { return }
+    emithook(emit, NativeFunc::HOOKEXIT);
+}
+
 fn emithead(emit: &mut Emit, func: &Func) {
     match func.kind {
         FuncKind::User() => { /* NOP */ },
@@ -452,6 +552,8 @@ fn emithead(emit: &mut Emit, func: &Func) {
             let vmctx = emit.fb.vmctx();
             let one = emit.fb.ins().iconst(irt2cl(Type::B1), 1);
             storeslot(emit, vmctx, idx, scl, check, Type::B1, one);
+            emitprofenter(emit);
+            emithookenter(emit);
             let jarg = [idx];
             let jarg: &[Value] = match emit.blockparams[BlockId::START].is_empty() {
                 true => &[],
@@ -465,7 +567,13 @@ fn emithead(emit: &mut Emit, func: &Func) {
     }
 }
 
-fn emitreloc(mcode: &mut MCode, emit: &Emit, base: MCodeOffset, reloc: &FinalizedMachReloc) {
+fn emitreloc(
+    mcode: &mut MCode,
+    ctx: &cranelift_codegen::Context,
+    fid: FuncId,
+    base: MCodeOffset,
+    reloc: &FinalizedMachReloc
+) {
     let &FinalizedMachReloc { offset, kind, ref target, addend } = reloc;
     match target {
         &FinalizedRelocTarget::Func(ofs) =>
@@ -474,11 +582,11 @@ fn emitreloc(mcode: &mut MCode, emit: &Emit, base: MCodeOffset, reloc: &Finalize
                 add: addend as i32 + ofs as i32,
                 kind,
                 sym: Sym::Label,
-                which: {let fid: u16 = zerocopy::transmute!(emit.fid); fid as _}
+                which: {let fid: u16 = zerocopy::transmute!(fid); fid as _}
             }),
         &FinalizedRelocTarget::ExternalName(ExternalName::User(name)) => {
             let UserExternalName { namespace, index }
-                = emit.fb.ctx.func.params.user_named_funcs()[name];
+                = ctx.func.params.user_named_funcs()[name];
             mcode.relocs.push(Reloc {
                 at: base + offset,
                 add: addend as i32,
@@ -508,28 +616,53 @@ fn emitmcode(mcode: &mut MCode, buf: &[u8]) -> MCodeOffset {
     loc
 }
 
-fn compilefunc(emit: &mut Emit, mcode: &mut MCode) -> MCodeOffset {
+// finish the function before handing it to cranelift.
+fn prepfunc(emit: &mut Emit) {
     if let Some(frame) = &emit.frame {
         let slot = &mut emit.fb.ctx.func.sized_stack_slots[frame.slot];
         slot.size = frame.layout.ptr as _;
         slot.align_shift = frame.layout.align.ilog2() as _;
     }
+    if emit.peephole {
+        peephole::run(&mut emit.fb.ctx.func);
+    }
     if trace!(CLIF) {
         trace!("{}", emit.fb.ctx.func.display());
     }
-    emit.fb.ctx.compile(&*emit.isa, &mut Default::default()).unwrap();
-    let code = emit.fb.ctx.compiled_code().unwrap();
+}
+
+// copy compiled code and relocations to mcode.
+fn emitcompiled(
+    mcode: &mut MCode,
+    ctx: &cranelift_codegen::Context,
+    fid: FuncId
+) -> MCodeOffset {
+    let code = ctx.compiled_code().unwrap();
     let loc = emitmcode(mcode, code.code_buffer());
     for reloc in code.buffer.relocs() {
-        emitreloc(mcode, emit, loc, reloc);
+        emitreloc(mcode, ctx, fid, loc, reloc);
+    }
+    let base = ctx.func.params.base_srcloc();
+    for src in code.buffer.get_srclocs_sorted() {
+        let expr: u32 = src.loc.expand(base).bits();
+        mcode.srclocs.push((loc+src.start, loc+src.end, zerocopy::transmute!(expr)));
     }
     loc
 }
 
+fn compilefunc(emit: &mut Emit, mcode: &mut MCode) -> MCodeOffset {
+    prepfunc(emit);
+    emit.fb.ctx.compile(&*emit.isa, &mut Default::default()).unwrap();
+    emitcompiled(mcode, &emit.fb.ctx, emit.fid)
+}
+
 fn resetemit(emit: &mut Emit) {
     emit.fb.clear();
     emit.bump.clear();
     emit.frame = None;
+    emit.idx = Value::reserved_value();
+    emit.uses.clear();
+    emit.switched.clear();
 }
 
 fn emitirfunc(ecx: &mut Ecx, fid: FuncId) -> compile::Result {
@@ -544,6 +677,7 @@ fn emitirfunc(ecx: &mut Ecx, fid: FuncId) -> compile::Result {
         &mut emit.blockparams,
         &mut ecx.mark1
     );
+    emit.gcm.schedule.inst.map_spans(func, &mut emit.spans);
     if trace!(SCHEDULE) {
         let mut tmp = Default::default();
         dump_schedule(&mut tmp, fid, func, &emit.code, &emit.values, &emit.blockparams,
@@ -569,7 +703,17 @@ fn emitirfunc(ecx: &mut Ecx, fid: FuncId) -> compile::Result {
     }
     emit.fb.block = cranelift_codegen::ir::Block::from_u32(0);
     emit.block = BlockId::START;
+    if !matches!(func.kind, FuncKind::Chunk(_)) {
+        // chunks already have an entry block from emithead.
+        emitprofenter(emit);
+        emithookenter(emit);
+    }
     for id in index::iter_span(emit.code.end()) {
+        let expr = ecx.data.spans[id];
+        ecx.data.fb.srcloc = match expr.is_nil() {
+            true => SourceLoc::default(),
+            false => SourceLoc::new(zerocopy::transmute!(expr))
+        };
         translate(ecx, id)?;
         if ecx.data.code[id].opcode().is_control() {
             ecx.data.block += 1;
@@ -577,13 +721,46 @@ fn emitirfunc(ecx: &mut Ecx, fid: FuncId) -> compile::Result {
             ecx.data.fb.block = cranelift_codegen::ir::Block::from_u32(block as _);
         }
     }
-    let loc = compilefunc(&mut ecx.data, &mut ecx.mcode);
+    #[cfg(feature="threads")]
+    {
+        // compiled later, see compilepending.
+        prepfunc(&mut ecx.data);
+        let ctx = replace(&mut ecx.data.fb.ctx, cranelift_codegen::Context::new());
+        ecx.data.pending.push((fid, ctx));
+    }
+    #[cfg(not(feature="threads"))]
+    {
+        let loc = compilefunc(&mut ecx.data, &mut ecx.mcode);
+        placefunc(ecx, fid, loc);
+    }
+    Ok(())
+}
+
+// `loc..mcode end` is the code of `fid`.
+fn placefunc(ecx: &mut Ecx, fid: FuncId, loc: MCodeOffset) {
+    let end = ecx.mcode.code.end().ptr() as MCodeOffset;
+    ecx.mcode.lines.push((loc, end, ecx.ir.funcs[fid].source));
     let label = zerocopy::transmute!({let fid: u16 = zerocopy::transmute!(fid); fid as u32});
     ecx.mcode.labels[label] = loc;
     if let FuncKind::Query(Query { obj, .. }) = ecx.ir.funcs[fid].kind {
         ecx.objs[obj].mcode = loc;
     }
-    Ok(())
+}
+
+// translation needs the shared emit state, but instruction selection and register allocation
+// only need the function. run those on the thread pool, then place the code in queue order so
+// that the layout doesn't depend on the thread count.
+#[cfg(feature="threads")]
+fn compilepending(ecx: &mut Ecx) {
+    let mut pending = take(&mut ecx.data.pending);
+    let isa = &*ecx.data.isa;
+    parallel::for_each(&mut pending, || (), |_, (_, ctx)| {
+        ctx.compile(isa, &mut Default::default()).unwrap();
+    });
+    for (fid, ctx) in &pending {
+        let loc = emitcompiled(&mut ecx.mcode, ctx, *fid);
+        placefunc(ecx, *fid, loc);
+    }
 }
 
 fn emitsuppfunc(ecx: &mut Ecx, supp: SuppFunc) {
@@ -611,18 +788,184 @@ fn emitsuppfunc(ecx: &mut Ecx, supp: SuppFunc) {
     ecx.mcode.labels[label] = loc;
 }
 
+// code layout: queries are placed first, each followed by the chunks it calls (depth first, in
+// call order), so that the code executed by a query is mostly contiguous. functions not reachable
+// from any query come next, and init chunks, which run once per instance, go last.
+// supplementary functions (alloc, abort, ...) are emitted after all IR functions, so they end up
+// in the cold tail as well. within functions, cranelift moves blocks marked cold to the end.
+fn funcorder(ir: &IR) -> Vec<FuncId> {
+    let iscold = |f: &Func| f.source.flags().contains(DebugFlag::INIT);
+    let mut order = Vec::with_capacity(ir.funcs.raw.len());
+    let mut seen: IndexSet<FuncId> = Default::default();
+    let mut stack = Vec::new();
+    for (root, func) in ir.funcs.pairs() {
+        if !matches!(func.kind, FuncKind::Query(_)) { continue }
+        stack.push(root);
+        while let Some(fid) = stack.pop() {
+            let func = &ir.funcs[fid];
+            if iscold(func) || seen.test_and_set(fid) { continue }
+            order.push(fid);
+            let base = stack.len();
+            for (_, ins) in func.code.pairs() {
+                match ins.opcode() {
+                    Opcode::CALL => stack.push(ins.decode_CALL().1),
+                    Opcode::CALLC | Opcode::CALLCI => stack.push(ins.decode_CALLC().2),
+                    _ => {}
+                }
+            }
+            // reverse so that the first callee is popped first
+            stack[base..].reverse();
+        }
+    }
+    for cold in [false, true] {
+        for (fid, func) in ir.funcs.pairs() {
+            if iscold(func) == cold && !seen.test_and_set(fid) {
+                order.push(fid);
+            }
+        }
+    }
+    order
+}
+
 fn emitfuncs(ecx: &mut Ecx) -> compile::Result {
-    for id in index::iter_span(ecx.ir.funcs.end()) {
+    for id in funcorder(&ecx.ir) {
+        let _span = trace_span!("emit {:?}", id);
         emitirfunc(ecx, id)?;
     }
+    #[cfg(feature="threads")]
+    compilepending(ecx);
     let mut havesupp: EnumSet<SuppFunc> = EnumSet::empty();
     while let Some(supp) = ecx.data.fb.supp.difference(havesupp).iter().next() {
         havesupp |= supp;
         emitsuppfunc(ecx, supp);
     }
+    if ecx.data.parallel {
+        parschedule(ecx);
+    }
     Ok(())
 }
 
+/* ---- Parallel schedule --------------------------------------------------- */
+
+// with `Session::parallel`, a query can compute the chunks it may need ahead of time on a host
+// thread pool, see fhk_vmcallpar. a chunk qualifies if it's global, not recursive, doesn't call
+// into a language runtime, and all its callees qualify. its level is one more than the highest
+// level of its callees, so chunks of the same level never call each other, and each level can
+// run in parallel once the levels below it are done. the memo bits of chunks running at the
+// same time may share a byte, which is why parallel images set them atomically (see storeslot).
+
+const NOLEVEL: u32 = !0;
+
+fn parlevels(ir: &IR, cg: &CallGraph) -> IndexVec<FuncId, u32> {
+    let mut level: IndexVec<FuncId, u32> = Default::default();
+    level.raw.resize(ir.funcs.raw.len(), NOLEVEL);
+    for &f in cg.order() {
+        let func = &ir.funcs[f];
+        let FuncKind::Chunk(Chunk { scl: SizeClass::GLOBAL, .. }) = func.kind else { continue };
+        if cg.is_recursive(f) || func.code.pairs().any(|(_, ins)| ins.opcode().is_lang()) {
+            continue;
+        }
+        let l = cg.callees(f).iter()
+            .try_fold(0, |l, &g| (level[g] != NOLEVEL).then(|| l.max(level[g]+1)));
+        level[f] = l.unwrap_or(NOLEVEL);
+    }
+    level
+}
+
+// host entry for `chunk`, with the query signature. it computes the chunk unless it has already
+// started.
+fn emitparentry(ecx: &mut Ecx, chunk: FuncId) -> MCodeOffset {
+    let emit = &mut *ecx.data;
+    resetemit(emit);
+    emit.fid = chunk;
+    let FuncKind::Chunk(Chunk { scl, check, .. }) = ecx.ir.funcs[chunk].kind
+        else { unreachable!() };
+    // must match fhk_vmcall, see makesig.
+    let signature = &mut emit.fb.ctx.func.signature;
+    signature.call_conv = CallConv::SystemV;
+    signature.params.extend_from_slice(&[
+        AbiParam::new(irt2cl(Type::I32)),
+        AbiParam::new(irt2cl(Type::PTR))
+    ]);
+    let entry = emit.fb.newblock();
+    emit.fb.ctx.func.dfg.append_block_param(entry, irt2cl(Type::I32));
+    emit.fb.ctx.func.dfg.append_block_param(entry, irt2cl(Type::PTR));
+    emit.fb.block = entry;
+    let vmctx = emit.fb.vmctx();
+    let idx = emit.fb.ins().iconst(irt2cl(Type::I32), 0);
+    let bit = loadslot(emit, vmctx, idx, scl, check, Type::B1);
+    let call_block = emit.fb.newblock();
+    let ret_block = emit.fb.newblock();
+    emit.fb.ins().brif(bit, ret_block, &[], call_block, &[]);
+    emit.fb.block = call_block;
+    let funcref = emit.fb.importfunc(&ecx.ir, chunk);
+    emit.fb.ins().call(funcref, &[]);
+    emit.fb.ins().jump(ret_block, &[]);
+    emit.fb.block = ret_block;
+    emit.fb.ins().return_(&[]);
+    compilefunc(&mut ecx.data, &mut ecx.mcode)
+}
+
+fn parschedule(ecx: &mut Ecx) {
+    let mut cg = CallGraph::default();
+    cg.build(&ecx.ir);
+    let level = parlevels(&ecx.ir, &cg);
+    // chunks that each query reaches, by level.
+    let mut reach: Vec<(FuncId, Vec<(u32, FuncId)>)> = Vec::new();
+    let mut seen: IndexSet<FuncId> = Default::default();
+    let mut stack = Vec::new();
+    for (query, func) in ecx.ir.funcs.pairs() {
+        if !matches!(func.kind, FuncKind::Query(_)) { continue }
+        seen.clear();
+        let mut chunks = Vec::new();
+        stack.push(query);
+        while let Some(f) = stack.pop() {
+            for &g in cg.callees(f) {
+                if seen.test_and_set(g) { continue }
+                if level[g] != NOLEVEL {
+                    chunks.push((level[g], g));
+                }
+                stack.push(g);
+            }
+        }
+        if !chunks.is_empty() {
+            chunks.sort_by_key(|&(l, _)| l);
+            reach.push((query, chunks));
+        }
+    }
+    let mut stub: IndexVec<FuncId, MCodeOffset> = Default::default();
+    stub.raw.resize(ecx.ir.funcs.raw.len(), MCodeOffset::MAX);
+    let mut queries = Vec::with_capacity(reach.len());
+    let mut levels = Vec::new();
+    let mut entries = Vec::new();
+    for (query, chunks) in &reach {
+        let FuncKind::Query(Query { obj, .. }) = ecx.ir.funcs[*query].kind
+            else { unreachable!() };
+        let start = levels.len() as u32;
+        let mut prev = NOLEVEL;
+        for &(l, f) in chunks {
+            if l != prev {
+                levels.push(entries.len() as u32);
+                prev = l;
+            }
+            if stub[f] == MCodeOffset::MAX {
+                stub[f] = emitparentry(ecx, f);
+            }
+            let FuncKind::Chunk(Chunk { check, .. }) = ecx.ir.funcs[f].kind
+                else { unreachable!() };
+            entries.push(ParEntry { mcode: stub[f], check });
+        }
+        queries.push((ecx.objs[obj].mcode, start, levels.len() as u32));
+    }
+    levels.push(entries.len() as u32);
+    queries.sort_unstable_by_key(|&(mcode, _, _)| mcode);
+    ecx.mcode.par = Some(ParSchedule {
+        queries: queries.into(),
+        levels: levels.into(),
+        entries: entries.into()
+    });
+}
+
 impl Stage for Emit {
 
     fn new(ccx: &mut Ccx<Absent>) -> compile::Result<Self> {
@@ -635,7 +978,12 @@ impl Stage for Emit {
         let lang = LangState::new(ccx.erase(), langs)?;
         let mut flag_builder = cranelift_codegen::settings::builder();
         flag_builder.set("enable_pinned_reg", "true").unwrap();
-        flag_builder.set("opt_level", "speed").unwrap();
+        let opt_level = match (ccx.session.cgspeed, ccx.session.cgsize) {
+            (_, true) => "speed_and_size",
+            (true, false) => "speed",
+            (false, false) => "none"
+        };
+        flag_builder.set("opt_level", opt_level).unwrap();
         flag_builder.set("unwind_info", "false").unwrap();
         let isa = cranelift_native::builder()
             .unwrap()
@@ -648,6 +996,7 @@ impl Stage for Emit {
                 ctx: cranelift_codegen::Context::new(),
                 block: cranelift_codegen::ir::Block::reserved_value(),
                 supp: Default::default(),
+                srcloc: SourceLoc::default()
             },
             frame: None,
             gcm: Default::default(),
@@ -657,8 +1006,26 @@ impl Stage for Emit {
             blockparams: Default::default(),
             stack: Value::reserved_value(),
             idx: Value::reserved_value(),
+            peephole: ccx.session.flags.contains(OptFlag::PEEP),
+            prof: match ccx.session.profile {
+                true => ccx.mcode.prof.insert(
+                    core::iter::repeat_n(Default::default(), ccx.ir.funcs.raw.len()).collect()
+                ).as_mut_ptr(),
+                false => core::ptr::null_mut()
+            },
+            profstart: Value::reserved_value(),
+            hooks: match ccx.session.hooks {
+                true => &mut **ccx.mcode.hooks.insert(Default::default()),
+                false => core::ptr::null_mut()
+            },
+            parallel: ccx.session.parallel,
+            #[cfg(feature="threads")]
+            pending: Default::default(),
             block: BlockId::INVALID.into(),
             fid: FuncId::INVALID.into(),
+            uses: Default::default(),
+            switched: Default::default(),
+            spans: Default::default(),
             tmp_val: Default::default()
         })
     }
@@ -16,7 +16,15 @@ pub enum ErrorMessage {
     CapNameInTemplate,
     CapPosInBody,
     UndefCap,
-    BadImplicitTab
+    BadImplicitTab,
+    TooDeep,
+    GraphTooLarge,
+    BadData,
+    UndefHostFunc,
+    HostCallArity,
+    PiecewiseOrder,
+    Discontinuous,
+    LiteralRange
 }
 
 impl ErrorMessage {
@@ -31,7 +39,37 @@ impl ErrorMessage {
             CapNameInTemplate  => "named capture not allowed in templates",
             CapPosInBody       => "positional capture not allowed in macro body",
             UndefCap           => "undefined capture",
-            BadImplicitTab     => "implicit table not allowed here"
+            BadImplicitTab     => "implicit table not allowed here",
+            TooDeep            => "expression nested too deeply",
+            GraphTooLarge      => "object graph size limit exceeded",
+            BadData            => "invalid data block",
+            UndefHostFunc      => "undefined host function",
+            HostCallArity      => "wrong number of arguments or return values for host function",
+            PiecewiseOrder     => "piecewise breakpoints are not increasing",
+            Discontinuous      => "piecewise segments disagree at breakpoint",
+            LiteralRange       => "literal out of range for its type"
+        }
+    }
+
+    pub fn code(self) -> &'static str {
+        use ErrorMessage::*;
+        match self {
+            InvalidToken       => "E0010",
+            ExpectedValue      => "E0011",
+            ExpectedPrimitive  => "E0012",
+            ExpectedType       => "E0013",
+            CapNameInTemplate  => "E0014",
+            CapPosInBody       => "E0015",
+            UndefCap           => "E0016",
+            BadImplicitTab     => "E0017",
+            TooDeep            => "E0018",
+            GraphTooLarge      => "E0019",
+            BadData            => "E0020",
+            UndefHostFunc      => "E0021",
+            HostCallArity      => "E0022",
+            PiecewiseOrder     => "E0023",
+            Discontinuous      => "E0024",
+            LiteralRange       => "E0025"
         }
     }
 
@@ -41,6 +79,9 @@ impl CompileError for ErrorMessage {
     fn write(self, ccx: &mut Ccx<(), R, R>) {
         ccx.host.buf.write(self.str());
     }
+    fn code(&self) -> &'static str {
+        (*self).code()
+    }
 }
 
 impl CompileError for &CStr {
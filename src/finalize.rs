@@ -64,6 +64,10 @@ impl FinalizerBuilder {
 
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.cmd.is_empty()
+    }
+
     pub fn build(self) -> Finalizers {
         Finalizers {
             mem: Bump::copy_of(&self.mem),
@@ -74,6 +78,14 @@ impl FinalizerBuilder {
 
 }
 
+impl Finalizers {
+
+    pub fn is_empty(&self) -> bool {
+        self.cmd.is_empty()
+    }
+
+}
+
 impl Drop for Finalizers {
     fn drop(&mut self) {
         let mut cursor = Cursor::default();
@@ -0,0 +1,67 @@
+//! Guard page allocator for debugging.
+
+// each allocation gets its own mapping, with the object placed at the end so that the first byte
+// past it is on an inaccessible page:
+//
+//   +-------+--------+--------+---------+-------+
+//   | guard | poison | object | poison  | guard |
+//   +-------+--------+--------+---------+-------+
+//                             (< align)
+//
+// overflows fault at the offending access. underflows fault once they get past the poisoned
+// head, and `check` finds the writes that didn't. the object itself is filled with poison too,
+// so that reads of uninitialized state stand out.
+
+use core::ptr::null;
+
+use alloc::vec::Vec;
+use enumset::EnumSet;
+
+use crate::mmap::{Mmap, Prot, PAGE_SIZE};
+
+pub const POISON: u8 = 0xfd;
+
+struct Region {
+    map: Mmap,
+    start: usize, // object start in map
+    end: usize    // object end in map
+}
+
+#[derive(Default)]
+pub struct GuardAlloc {
+    regions: Vec<Region>
+}
+
+impl GuardAlloc {
+
+    pub fn alloc(&mut self, size: usize, align: usize) -> *mut u8 {
+        debug_assert!(align.is_power_of_two() && align <= PAGE_SIZE);
+        let pages = ((size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)).max(PAGE_SIZE);
+        let Some(mut map) = Mmap::new(pages + 2*PAGE_SIZE, Prot::Read | Prot::Write)
+            else { return core::ptr::null_mut() };
+        let tail = PAGE_SIZE + pages;
+        let start = (tail - size) & !(align - 1);
+        map.as_mut_slice()[PAGE_SIZE..tail].fill(POISON);
+        map.protect(0..PAGE_SIZE, EnumSet::empty());
+        map.protect(tail..tail+PAGE_SIZE, EnumSet::empty());
+        let ptr = unsafe { map.base().add(start) };
+        self.regions.push(Region { map, start, end: start+size });
+        ptr
+    }
+
+    // first byte of poisoned padding that has been overwritten, or null.
+    pub fn check(&self) -> *const u8 {
+        for &Region { ref map, start, end } in &self.regions {
+            let tail = map.size() - PAGE_SIZE;
+            let mem = map.base() as *const u8;
+            for ofs in (PAGE_SIZE..start).chain(end..tail) {
+                let p = unsafe { mem.add(ofs) };
+                if unsafe { *p } != POISON {
+                    return p;
+                }
+            }
+        }
+        null()
+    }
+
+}
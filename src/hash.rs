@@ -1,11 +1,100 @@
 //! Hashbrown & FxHash re-exports.
 
-use core::hash::{BuildHasher, Hash};
+use core::fmt::{Display, Formatter};
+use core::hash::{BuildHasher, Hash, Hasher};
+use core::sync::atomic::{AtomicU64, Ordering};
 
-use rustc_hash::FxBuildHasher;
+use hashbrown::HashTable;
+use rustc_hash::{FxBuildHasher, FxHasher};
 
 pub type HashMap<K, V> = hashbrown::HashMap<K, V, FxBuildHasher>;
 
+// seed for `fxhash`. zero (the default) gives deterministic hashes.
+// with the `std` feature, FHK_HASHSEED=random picks a random seed, FHK_HASHSEED=<number> uses
+// the number.
+// the seed must not change after any hash table is built, so it's set once before the first
+// graph is created.
+static SEED: AtomicU64 = AtomicU64::new(0);
+
+#[cfg(feature="std")]
+#[cold]
+pub fn init_seed() {
+    extern crate std;
+    use core::sync::atomic::AtomicBool;
+    static SEED_INIT: AtomicBool = AtomicBool::new(false);
+    if SEED_INIT.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    let seed = match std::env::var("FHK_HASHSEED") {
+        Ok(v) if v == "random" => std::collections::hash_map::RandomState::new().hash_one(0),
+        Ok(v) => v.parse().unwrap_or(0),
+        Err(_) => 0
+    };
+    SEED.store(seed, Ordering::Relaxed);
+}
+
+#[cfg(not(feature="std"))]
+pub fn init_seed() {}
+
+// hash for in-memory tables. may be seeded, so never persist it.
 pub fn fxhash<T: Hash>(v: T) -> u64 {
+    let mut hasher = FxHasher::with_seed(SEED.load(Ordering::Relaxed) as _);
+    v.hash(&mut hasher);
+    hasher.finish()
+}
+
+// hash that is the same across processes, regardless of the seed.
+pub fn stablehash<T: Hash>(v: T) -> u64 {
     FxBuildHasher::default().hash_one(v)
 }
+
+/* ---- Statistics ---------------------------------------------------------- */
+
+#[derive(Default)]
+pub struct HashStats {
+    pub len: usize,
+    pub capacity: usize,
+    pub collisions: usize, // entries whose full hash equals another entry's hash
+    pub max_group: usize,  // most entries sharing the same home bucket
+    pub avg_group: f64     // average entries per occupied home bucket
+}
+
+impl Display for HashStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "len {} cap {} load {:.2} collisions {} max group {} avg group {:.2}",
+            self.len,
+            self.capacity,
+            match self.capacity { 0 => 0.0, c => self.len as f64 / c as f64 },
+            self.collisions,
+            self.max_group,
+            self.avg_group
+        )
+    }
+}
+
+// home buckets are approximated as the low bits of the hash, with the bucket count rounded up
+// to a power of two like hashbrown does.
+pub fn table_stats<T>(table: &HashTable<T>, hash: impl Fn(&T) -> u64) -> HashStats {
+    let mut stats = HashStats {
+        len: table.len(),
+        capacity: table.capacity(),
+        ..Default::default()
+    };
+    if stats.len == 0 {
+        return stats;
+    }
+    let mask = (stats.capacity * 8 / 7).next_power_of_two() as u64 - 1;
+    let mut hashes: HashMap<u64, usize> = Default::default();
+    let mut groups: HashMap<u64, usize> = Default::default();
+    for v in table.iter() {
+        let h = hash(v);
+        *hashes.entry(h).or_default() += 1;
+        *groups.entry(h & mask).or_default() += 1;
+    }
+    stats.collisions = hashes.values().filter(|&&n| n > 1).sum();
+    stats.max_group = groups.values().copied().max().unwrap_or(0);
+    stats.avg_group = stats.len as f64 / groups.len() as f64;
+    stats
+}
@@ -1,21 +1,43 @@
 //! Lua host support.
 
-use core::ffi::{c_char, c_int, c_void};
+use core::ffi::{c_char, c_int, c_void, CStr};
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
 use core::u64;
 
 use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use enumset::EnumSet;
 
+use crate::analysis::{self, IrView};
+use crate::bindgen::{write_bindings, BindLang};
 use crate::bump::Bump;
+use crate::cache;
 use crate::compile::Ccx;
+use crate::cost::CostModel;
 use crate::data::{HOST_LUA, TENSOR_LUA};
-use crate::dump::dump_objs;
-use crate::image::{Image, Instance};
+use crate::dump::{dump_objs, dump_objs_dot};
+use crate::edit::{self, EditError};
+use crate::guard::GuardAlloc;
+use crate::hash::{self, stablehash};
+use crate::image::{self, HookFunc, Hooks, Image, Instance, ParEntry};
 use crate::intern::IRef;
-use crate::obj::{Obj, ObjRef, Operator, EXPR, QUERY, RESET, TAB};
-use crate::optimize::parse_optflags;
+use crate::ir;
+use crate::lang_Host::HostFunc;
+use crate::mcode::MCodeOffset;
+use crate::obj::{BinOp, Obj, ObjRef, ObjectRef, Operator, EXPR, MOD, QUERY, RESET, TAB, VAR};
+use crate::optimize::{parse_optflags, OptPass, PassStats};
 use crate::parse::{parse_expand_tab, parse_expand_var, parse_template, parse_toplevel_def, parse_toplevel_expr, ExpandResult};
 use crate::parser::{parse, pushtemplate, stringify, Parser, SequenceType};
+use crate::pgo;
+use crate::plugin;
+use crate::session::{Assume, Bound, IrPoint, Observer, OptLevel, Options};
+use crate::support::ABORT_MESSAGE;
+use crate::trace;
+use crate::typing::Primitive;
 
+#[cfg(not(feature="trace"))]
 use crate::image::fhk_vmcall_native as fhk_vmcall;
 use crate::FHK_VERSION_STRING;
 
@@ -33,10 +55,15 @@ unsafe extern "C-unwind" {
 type fhk_Graph = Ccx<Parser>;
 type fhk_Image = Image;
 type fhk_Instance = Instance;
+type fhk_Guard = GuardAlloc;
 type fhk_ObjRef<T=Obj> = ObjRef<T>;
 type fhk_SeqRef = IRef<[u8]>;
 type fhk_Result = i32;
 type fhk_Alloc = unsafe extern "C" fn(*mut c_void, usize, usize) -> *mut u8;
+// (ud, task, arg, num): run task(arg, i) for each i in 0..num, on any threads, and return when
+// all are done.
+type fhk_Pool = unsafe extern "C" fn(*mut c_void, unsafe extern "C" fn(*mut c_void, u32), *mut c_void, u32);
+type fhk_Observe = unsafe extern "C" fn(*mut c_void, *const u8, usize, *const u8, usize);
 
 #[derive(Default)]
 pub struct HostCtx {
@@ -66,7 +93,8 @@ impl HostInst {
 }
 
 extern "C" fn fhk_newgraph() -> *mut fhk_Graph {
-    let ccx = Box::leak(Box::new(Ccx::new(Default::default())));
+    hash::init_seed();
+    let ccx = Box::leak(Box::new(Ccx::new(Default::default(), Default::default())));
     // begin<Parse>() can't fail so this ceremony here is unnecessary but oh well.
     // it's only a couple lines longer than unwrap().
     if let Ok(parse) = ccx.begin() { return parse.leak() };
@@ -120,8 +148,10 @@ const PARSE_TEMPLATE: c_int = 2;
 const PARSE_TAB: c_int      = 3;
 const PARSE_VAR: c_int      = 4;
 const PARSE_CREATE: c_int   = 0x8; // flag to OR with PARSE_TAB or PARSE_VAR
+const PARSE_FALLBACK: c_int = 0x10; // flag to OR with PARSE_DEF
 fn doparse(G: &mut fhk_Graph, tab: fhk_ObjRef<TAB>, source: Source, what: c_int) -> fhk_Result {
-    parse(
+    let start: u32 = zerocopy::transmute!(G.objs.end());
+    let result = parse(
         G,
         match source { Source::String(s) => s, Source::Template(..) => &[] },
         |pcx| {
@@ -150,7 +180,18 @@ fn doparse(G: &mut fhk_Graph, tab: fhk_ObjRef<TAB>, source: Source, what: c_int)
                 _ => unreachable!()
             }
         }
-    ).unwrap_or(-1)
+    ).unwrap_or(-1);
+    if result >= 0 && (what & PARSE_FALLBACK) != 0 {
+        let models: Vec<ObjRef<MOD>> = G.objs.pairs()
+            .filter(|&(idx, obj)| {
+                let raw: u32 = zerocopy::transmute!(idx);
+                raw >= start && matches!(obj, ObjectRef::MOD(_))
+            })
+            .map(|(idx, _)| idx.cast())
+            .collect();
+        G.session.fallbacks.extend(models);
+    }
+    result
 }
 
 unsafe extern "C" fn fhk_parse(
@@ -204,13 +245,560 @@ unsafe extern "C" fn fhk_newreset(
     )
 }
 
-extern "C" fn fhk_dumpobjs(G: &mut fhk_Graph) {
+// fmt: 0 = text, 1 = dot
+extern "C" fn fhk_dumpobjs(G: &mut fhk_Graph, fmt: c_int) {
+    G.host.buf.clear();
+    match fmt {
+        0 => dump_objs(&mut G.host.buf, &G.intern, &G.objs, ObjRef::NIL),
+        _ => dump_objs_dot(&mut G.host.buf, &G.intern, &G.objs, ObjRef::NIL)
+    }
+}
+
+extern "C" fn fhk_hashstats(G: &mut fhk_Graph) {
+    G.host.buf.clear();
+    write!(G.host.buf, "intern {}\nobjs   {}", G.intern.hash_stats(), G.objs.lookup_stats()).unwrap();
+}
+
+// change settings through `Options`, so that conflicting combinations are rejected.
+fn setoptions(G: &mut fhk_Graph, f: impl FnOnce(Options) -> Options) -> fhk_Result {
+    match f(Options::current(&G.session, &G.pipeline)).apply(&mut G.session, &mut G.pipeline) {
+        Ok(()) => 0,
+        Err(e) => {
+            G.host.buf.clear();
+            write!(G.host.buf, "{}", e).unwrap();
+            -1
+        }
+    }
+}
+
+unsafe extern "C" fn fhk_optimize(G: &mut fhk_Graph, flags: *const c_char, len: usize) -> fhk_Result {
+    let flags = parse_optflags(unsafe { slice_from_raw_parts(flags as _, len) });
+    setoptions(G, |o| o.flags(flags))
+}
+
+// passes: comma-separated pass names, in order, NULL = keep current order.
+// maxiter: 0 = keep current value.
+// level: 0-3, or 's' for Os.
+extern "C" fn fhk_optlevel(G: &mut fhk_Graph, level: u8) -> fhk_Result {
+    let preset = match level {
+        b's' => Some(OptLevel::Os),
+        _ => OptLevel::from_u8(level)
+    };
+    match preset {
+        Some(level) => setoptions(G, |o| o.level(level)),
+        None => {
+            G.host.buf.clear();
+            write!(G.host.buf, "unknown optimization level: {}", level).unwrap();
+            -1
+        }
+    }
+}
+
+unsafe extern "C" fn fhk_optpasses(
+    G: &mut fhk_Graph,
+    passes: *const c_char,
+    len: usize,
+    maxiter: u32
+) -> fhk_Result {
+    let mut pipeline = G.pipeline.passes.clone();
+    if !passes.is_null() {
+        let passes: &[u8] = unsafe { slice_from_raw_parts(passes as _, len) };
+        pipeline.clear();
+        for name in passes.split(|&c| c == b',').filter(|n| !n.is_empty()) {
+            match OptPass::from_name(name) {
+                Some(pass) => pipeline.push(pass),
+                None => {
+                    G.host.buf.clear();
+                    write!(G.host.buf, "unknown pass: {}",
+                        core::str::from_utf8(name).unwrap_or("?")).unwrap();
+                    return -1;
+                }
+            }
+        }
+    }
+    let maxiter = if maxiter > 0 { maxiter } else { G.pipeline.max_iter };
+    setoptions(G, |o| o.passes(pipeline).max_iter(maxiter))
+}
+
+extern "C" fn fhk_optstats(G: &mut fhk_Graph) {
     G.host.buf.clear();
-    dump_objs(&mut G.host.buf, &G.intern, &G.objs, ObjRef::NIL);
+    for pass in EnumSet::<OptPass>::all() {
+        let PassStats { runs, removed, time } = *G.pipeline.stats(pass);
+        writeln!(G.host.buf, "{:-8} runs {:4} removed {:6} time {}us",
+            pass.name(), runs, removed, time/1000).unwrap();
+    }
 }
 
-unsafe extern "C" fn fhk_optimize(G: &mut fhk_Graph, flags: *const c_char, len: usize) {
-    G.flags = parse_optflags(unsafe { slice_from_raw_parts(flags as _, len) })
+// number of functions of the last compilation that depend on any of `objs`.
+unsafe extern "C" fn fhk_affected(G: &mut fhk_Graph, objs: *const fhk_ObjRef, num: usize) -> u32 {
+    G.deps.affected(unsafe { slice_from_raw_parts(objs, num) }, &mut Default::default()) as _
+}
+
+fn doedit(G: &mut fhk_Graph, result: Result<(), EditError>) -> fhk_Result {
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            G.host.buf.clear();
+            write!(G.host.buf, "{}", e).unwrap();
+            -1
+        }
+    }
+}
+
+extern "C" fn fhk_setconst(G: &mut fhk_Graph, expr: fhk_ObjRef<EXPR>, value: f64) -> fhk_Result {
+    let result = edit::set_constant(&mut G.objs, &mut G.intern, expr, value);
+    doedit(G, result)
+}
+
+extern "C" fn fhk_setvalue(
+    G: &mut fhk_Graph,
+    model: fhk_ObjRef<MOD>,
+    var: fhk_ObjRef<VAR>,
+    value: fhk_ObjRef<EXPR>
+) -> fhk_Result {
+    let result = edit::set_model_value(&mut G.objs, model, var, value);
+    doedit(G, result)
+}
+
+extern "C" fn fhk_setguard(
+    G: &mut fhk_Graph,
+    model: fhk_ObjRef<MOD>,
+    guard: fhk_ObjRef<EXPR>
+) -> fhk_Result {
+    let result = edit::set_model_guard(&mut G.objs, model, guard);
+    doedit(G, result)
+}
+
+// json: 0 = text, 1 = JSON array. clear: 1 = forget the diagnostics after rendering.
+extern "C" fn fhk_diagnostics(G: &mut fhk_Graph, json: c_int, clear: c_int) {
+    G.host.buf.clear();
+    match json != 0 {
+        true => G.diag.render_json(&mut G.host.buf),
+        false => G.diag.render(&mut G.host.buf)
+    }
+    if clear != 0 {
+        G.diag.clear();
+    }
+}
+
+// 0 = keep current value
+extern "C" fn fhk_parselimits(G: &mut fhk_Graph, depth: u32, words: u32) {
+    if depth > 0 { G.data.max_depth = depth; }
+    if words > 0 { G.data.max_words = words; }
+}
+
+// 0 keeps the current limit.
+extern "C" fn fhk_compilelimits(G: &mut fhk_Graph, mem: u64, ins: u32) {
+    if mem > 0 { G.session.maxmem = mem as _; }
+    if ins > 0 { G.session.maxins = ins; }
+}
+
+extern "C" fn fhk_decimalcomma(G: &mut fhk_Graph, on: c_int) {
+    G.data.decimal_comma = on != 0;
+}
+
+// fold: 0 = leave constant division by zero to trap at runtime, 1 = fold to `value`
+extern "C" fn fhk_divzero(G: &mut fhk_Graph, fold: c_int, value: i64) -> fhk_Result {
+    setoptions(G, |o| o.divzero(if fold != 0 { Some(value) } else { None }))
+}
+
+unsafe extern "C" fn fhk_costmodel(G: &mut fhk_Graph, name: *const c_char, len: usize) -> fhk_Result {
+    let name: &[u8] = unsafe { slice_from_raw_parts(name as _, len) };
+    match CostModel::from_name(name) {
+        Some(cost) => { G.session.cost = cost; 0 },
+        None => {
+            G.host.buf.clear();
+            write!(G.host.buf, "unknown cost model: {}",
+                core::str::from_utf8(name).unwrap_or("?")).unwrap();
+            -1
+        }
+    }
+}
+
+extern "C" fn fhk_switchmin(G: &mut fhk_Graph, min: u32) -> fhk_Result {
+    setoptions(G, |o| o.switchmin(min))
+}
+
+// negative tol = don't check
+extern "C" fn fhk_icheck(G: &mut fhk_Graph, tol: f64) -> fhk_Result {
+    setoptions(G, |o| o.icheck(if tol < 0.0 { None } else { Some(tol) }))
+}
+
+// negative eps = compare exactly
+extern "C" fn fhk_guardeps(G: &mut fhk_Graph, eps: f64) {
+    G.session.guardeps = if eps >= 0.0 { Some(eps) } else { None };
+}
+
+// NULL = don't write crash reports
+unsafe extern "C" fn fhk_crashdir(G: &mut fhk_Graph, path: *const c_char, len: usize) {
+    G.session.crashdir = match path.is_null() {
+        true => None,
+        false => Some(String::from_utf8_lossy(unsafe { slice_from_raw_parts(path as _, len) }).into())
+    };
+}
+
+extern "C" fn fhk_verify(G: &mut fhk_Graph, on: c_int) -> fhk_Result {
+    setoptions(G, |o| o.verify(on != 0))
+}
+
+// var op (other or k), where op: 0 = <, 1 = <=, 2 = >, 3 = >=, 4 = ==. other: 0 = compare to k.
+extern "C" fn fhk_assume(
+    G: &mut fhk_Graph,
+    var: fhk_ObjRef<VAR>,
+    op: c_int,
+    other: fhk_ObjRef<VAR>,
+    k: f64
+) -> fhk_Result {
+    G.host.buf.clear();
+    let isvar = |G: &fhk_Graph, v: ObjRef<VAR>| G.objs[v.erase()].op == Obj::VAR;
+    if !isvar(G, var) || !(other.is_nil() || isvar(G, other)) {
+        write!(G.host.buf, "assumption on a non-variable").unwrap();
+        return -1;
+    }
+    let right = match other.is_nil() {
+        true => Bound::K(k),
+        false if G.objs[other].tab == G.objs[var].tab => Bound::Var(other),
+        false => {
+            write!(G.host.buf, "assumption between variables of different tables").unwrap();
+            return -1;
+        }
+    };
+    let left = Bound::Var(var);
+    let assume = match op {
+        0 => Assume { left, op: BinOp::LT, right },
+        1 => Assume { left, op: BinOp::LE, right },
+        2 => Assume { left: right, op: BinOp::LT, right: left },
+        3 => Assume { left: right, op: BinOp::LE, right: left },
+        4 => Assume { left, op: BinOp::EQ, right },
+        _ => {
+            write!(G.host.buf, "invalid assumption operator: {}", op).unwrap();
+            return -1;
+        }
+    };
+    G.session.add_assume(assume);
+    0
+}
+
+extern "C" fn fhk_checkassume(G: &mut fhk_Graph, on: c_int) {
+    G.session.checkassume = on != 0;
+}
+
+extern "C" fn fhk_fastmath(G: &mut fhk_Graph, on: c_int) -> fhk_Result {
+    setoptions(G, |o| o.fastmath(on != 0))
+}
+
+// 0 = f64, 1 = f32
+extern "C" fn fhk_floattype(G: &mut fhk_Graph, single: c_int) {
+    G.session.float = match single {
+        0 => Primitive::F64,
+        _ => Primitive::F32
+    };
+}
+
+extern "C" fn fhk_profile(G: &mut fhk_Graph, on: c_int) -> fhk_Result {
+    setoptions(G, |o| o.profile(on != 0))
+}
+
+extern "C" fn fhk_hooks(G: &mut fhk_Graph, on: c_int) {
+    G.session.hooks = on != 0;
+}
+
+extern "C" fn fhk_parallel(G: &mut fhk_Graph, on: c_int) {
+    G.session.parallel = on != 0;
+}
+
+// returns the number of functions the plugin added, or -1 on error.
+unsafe extern "C" fn fhk_loadplugin(G: &mut fhk_Graph, path: *const c_char, len: usize) -> fhk_Result {
+    let path = unsafe { slice_from_raw_parts(path as *const u8, len) };
+    G.host.buf.clear();
+    match unsafe { plugin::load(&mut G.session, path) } {
+        Ok(num) => num as _,
+        Err(e) => {
+            write!(G.host.buf, "{}: {}", String::from_utf8_lossy(path), e).unwrap();
+            -1
+        }
+    }
+}
+
+// sig: return type followed by the parameter types, separated by spaces, eg. "f64 i32 f64".
+// func is called as `ret func(NULL, params...)`.
+unsafe extern "C" fn fhk_hostfunc(
+    G: &mut fhk_Graph,
+    name: *const c_char,
+    namelen: usize,
+    sig: *const c_char,
+    siglen: usize,
+    func: *const c_void,
+    pure: c_int
+) -> fhk_Result {
+    let name = unsafe { slice_from_raw_parts(name as *const u8, namelen) };
+    let sig: &[u8] = unsafe { slice_from_raw_parts(sig as _, siglen) };
+    G.host.buf.clear();
+    let types: Option<Vec<Primitive>> = sig.split(|&c| c == b' ')
+        .filter(|t| !t.is_empty())
+        .map(|t| Primitive::from_name(t).filter(|&p| p as u8 <= Primitive::U8 as u8))
+        .collect();
+    let Some((&ret, params)) = types.as_ref().and_then(|t| t.split_first()) else {
+        write!(G.host.buf, "invalid host function signature: {}", String::from_utf8_lossy(sig))
+            .unwrap();
+        return -1;
+    };
+    let mut f = unsafe { HostFunc::new(name, params, ret, func, core::ptr::null()) };
+    f.pure = pure != 0;
+    match G.add_hostfunc(f) {
+        Some(()) => 0,
+        None => {
+            write!(G.host.buf, "host function name taken: {}", String::from_utf8_lossy(name))
+                .unwrap();
+            -1
+        }
+    }
+}
+
+extern "C" fn fhk_audit(G: &mut fhk_Graph, on: c_int) {
+    G.session.audit = on != 0;
+}
+
+// json: 0 = text, 1 = JSON array.
+extern "C" fn fhk_auditlog(G: &mut fhk_Graph, json: c_int) {
+    G.host.buf.clear();
+    match json != 0 {
+        true => G.pipeline.audit.render_json(&mut G.host.buf),
+        false => G.pipeline.audit.render(&mut G.host.buf)
+    }
+}
+
+// which passes changed each function of the last compilation. json: 0 = text, 1 = JSON array.
+extern "C" fn fhk_passeffects(G: &mut fhk_Graph, json: c_int) {
+    G.host.buf.clear();
+    let audit = &G.pipeline.audit;
+    match json != 0 {
+        true => audit.render_effects_json(&mut G.host.buf, &G.intern, &G.objs),
+        false => audit.render_effects(&mut G.host.buf, &G.intern, &G.objs)
+    }
+}
+
+// IR of the last compilation as JSON, in the order it was scheduled for code generation.
+extern "C" fn fhk_irjson(G: &mut fhk_Graph) {
+    G.host.buf.clear();
+    analysis::write_json(&mut G.host.buf, IrView::new(&G.ir, &G.intern, &G.objs));
+}
+
+// points: comma-separated pipeline points, eg. "lower,fold,optimize".
+// func is called as `func(ud, point, plen, json, jlen)` with the IR as JSON (see fhk_irjson).
+unsafe extern "C" fn fhk_observe(
+    G: &mut fhk_Graph,
+    points: *const c_char,
+    len: usize,
+    func: fhk_Observe,
+    ud: *mut c_void
+) -> fhk_Result {
+    let points = unsafe { slice_from_raw_parts(points as *const u8, len) };
+    G.host.buf.clear();
+    let mut list = Vec::new();
+    for name in points.split(|&c| c == b',').filter(|p| !p.is_empty()) {
+        match IrPoint::from_name(name) {
+            Some(point) => list.push(point),
+            None => {
+                write!(G.host.buf, "unknown pipeline point: {}", String::from_utf8_lossy(name))
+                    .unwrap();
+                return -1;
+            }
+        }
+    }
+    G.session.add_observer(Observer {
+        points: list,
+        observe: Box::new(move |point, view| {
+            let mut buf: Bump = Default::default();
+            analysis::write_json(&mut buf, view);
+            let name = point.name();
+            let json: &[u8] = buf.as_slice();
+            unsafe { func(ud, name.as_ptr(), name.len(), json.as_ptr(), json.len()) }
+        })
+    });
+    0
+}
+
+// IR of the last compilation with instructions numbered independently of their ids.
+extern "C" fn fhk_ircanon(G: &mut fhk_Graph) {
+    G.host.buf.clear();
+    analysis::write_canonical(&mut G.host.buf, IrView::new(&G.ir, &G.intern, &G.objs));
+}
+
+unsafe extern "C" fn fhk_setprofile(G: &mut fhk_Graph, data: *const u8, len: usize) -> fhk_Result {
+    let data = unsafe { slice_from_raw_parts(data, len) };
+    match G.set_profile(data) {
+        Ok(()) => 0,
+        Err(()) => -1
+    }
+}
+
+extern "C" fn fhk_selfcheck(G: &mut fhk_Graph) -> c_int {
+    G.host.buf.clear();
+    match ir::selfcheck() {
+        Ok(()) => 1,
+        Err((op, msg)) => {
+            write!(G.host.buf, "{}: {}", op.name(), msg).unwrap();
+            0
+        }
+    }
+}
+
+// fmt: 0 = text, 1 = trace-event json
+unsafe extern "C" fn fhk_tracedump(G: &mut fhk_Graph, data: *const u8, len: usize, fmt: c_int) -> c_int {
+    let data = unsafe { slice_from_raw_parts(data, len) };
+    G.host.buf.clear();
+    let ok = match fmt {
+        0 => trace::write_text(&mut G.host.buf, data),
+        _ => trace::write_json(&mut G.host.buf, data)
+    };
+    ok as _
+}
+
+#[cfg(feature="trace")]
+unsafe extern "C" fn fhk_vmcall(vmctx: *mut Instance, result: *mut u8, mcode: *const u8) -> i32 {
+    let _span = crate::trace::trace_span!("vmcall {:p}", mcode);
+    unsafe { crate::image::fhk_vmcall_native(vmctx, result, mcode) }
+}
+
+struct VmcallMany {
+    insts: *const *mut Instance,
+    rets: *const *mut u8,
+    mcode: *const u8,
+    status: *mut i32
+}
+
+unsafe extern "C" fn vmcallmany_task(arg: *mut c_void, i: u32) {
+    unsafe {
+        let call = &*(arg as *const VmcallMany);
+        let i = i as usize;
+        *call.status.add(i) = fhk_vmcall(*call.insts.add(i), *call.rets.add(i), call.mcode);
+    }
+}
+
+// evaluate a query on `num` instances. instances don't share memory, so a pool can run them in
+// parallel, as long as the models don't call back into the (single-threaded) Lua state.
+// returns the number of failed calls; `status` tells which ones.
+unsafe extern "C" fn fhk_vmcallmany(
+    insts: *const *mut Instance,
+    rets: *const *mut u8,
+    num: u32,
+    mcode: *const u8,
+    status: *mut i32,
+    pool: Option<fhk_Pool>,
+    ud: *mut c_void
+) -> u32 {
+    let mut call = VmcallMany { insts, rets, mcode, status };
+    let arg = &raw mut call as *mut c_void;
+    match pool {
+        Some(pool) => unsafe { pool(ud, vmcallmany_task, arg, num) },
+        None => for i in 0..num { unsafe { vmcallmany_task(arg, i) } }
+    }
+    (0..num as usize).filter(|&i| unsafe { *status.add(i) } != 0).count() as _
+}
+
+struct VmcallPar {
+    inst: *mut Instance,
+    base: *const u8,
+    entries: *const ParEntry,
+    failed: *mut bool,
+    any: AtomicBool
+}
+
+unsafe extern "C" fn vmcallpar_task(arg: *mut c_void, i: u32) {
+    unsafe {
+        let call = &*(arg as *const VmcallPar);
+        let i = i as usize;
+        let mcode = call.base.add((*call.entries.add(i)).mcode as usize);
+        if image::vmcall_worker(call.inst, core::ptr::null_mut(), mcode) != 0 {
+            *call.failed.add(i) = true;
+            call.any.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+// evaluate a query on one instance. if the image was compiled with fhk_parallel, the chunks the
+// query may need are first computed level by level, running each level on the pool. the models
+// must not call back into the (single-threaded) Lua state, and the instance's allocator must be
+// thread-safe. a chunk that fails is left for the query, which computes it again if it needs it,
+// so the result and error are the same as with fhk_vmcall. returns 0 on success.
+unsafe extern "C" fn fhk_vmcallpar(
+    image: &fhk_Image,
+    inst: *mut Instance,
+    ret: *mut u8,
+    mcode: *const u8,
+    pool: Option<fhk_Pool>,
+    ud: *mut c_void
+) -> i32 {
+    if let (Some(par), Some(pool), true) = (&image.par, pool, image::PARALLEL_VMCALL) {
+        let base = image.mem.base() as *const u8;
+        let query = unsafe { mcode.offset_from(base) } as MCodeOffset;
+        let mut failed = Vec::new();
+        for level in par.levels(query) {
+            failed.clear();
+            failed.resize(level.len(), false);
+            let call = VmcallPar {
+                inst,
+                base,
+                entries: level.as_ptr(),
+                failed: failed.as_mut_ptr(),
+                any: AtomicBool::new(false)
+            };
+            unsafe { pool(ud, vmcallpar_task, &raw const call as *mut c_void, level.len() as _) };
+            if call.any.load(Ordering::Relaxed) {
+                // the chunk started, so its memo bit is set. clear it, so that the query
+                // doesn't read a result that was never written.
+                for (e, _) in level.iter().zip(&failed).filter(|&(_, &f)| f) {
+                    unsafe {
+                        *(inst as *mut u8).add(e.check.byte() as usize) &= !(1u8 << e.check.bit());
+                    }
+                }
+                break;
+            }
+        }
+    }
+    unsafe { fhk_vmcall(inst, ret, mcode) }
+}
+
+// hash of the object graph. compiling annotates the objects and adds query objects, so the host
+// takes this before the first compilation.
+extern "C" fn fhk_hashobjs(G: &fhk_Graph) -> u64 {
+    stablehash((G.objs.as_slice(), G.intern.bump().as_slice::<u8>()))
+}
+
+// hash of the settings that affect the compilation result.
+extern "C" fn fhk_hash(G: &fhk_Graph) -> u64 {
+    let s = &G.session;
+    stablehash((
+        s.flags.as_u32(),
+        G.pipeline.passes.iter().map(|&p| p as u8).collect::<Vec<_>>(),
+        G.pipeline.max_iter,
+        (s.icheck.map(f64::to_bits), s.divzero, s.switchmin, s.guardeps.map(f64::to_bits)),
+        (
+            s.assumes.iter().map(|a| {
+                let bound = |b| match b {
+                    Bound::Var(v) => (0u8, { let raw: u32 = zerocopy::transmute!(v); raw as u64 }),
+                    Bound::K(k) => (1u8, f64::to_bits(k))
+                };
+                (bound(a.left), a.op as u8, bound(a.right))
+            }).collect::<Vec<_>>(),
+            s.checkassume,
+            s.fallbacks.iter().map(|&m| { let raw: u32 = zerocopy::transmute!(m); raw })
+                .collect::<Vec<_>>()
+        ),
+        (s.inlinecost, s.cgspeed, s.cgsize, s.cost.name),
+        (s.verify, s.fastmath, s.float as u8, s.profile, s.hooks, s.parallel,
+            s.pgo.as_ref().map(|p| p.raw())),
+        s.hostfuncs.iter()
+            .map(|f| (&f.name, f.params.iter().map(|&p| p as u8).collect::<Vec<_>>(), f.ret as u8))
+            .collect::<Vec<_>>()
+    ))
+}
+
+// mix bytes into a hash from `fhk_hash`. the host uses this for the object graph snapshot, the
+// queries, and the files a graph depends on without containing them, such as plugins.
+unsafe extern "C" fn fhk_hashmix(h: u64, data: *const u8, len: usize) -> u64 {
+    stablehash((h, unsafe { slice_from_raw_parts(data, len) }))
 }
 
 unsafe extern "C" fn fhk_compile(G: &mut fhk_Graph, image: *mut *mut fhk_Image) -> fhk_Result {
@@ -224,14 +812,188 @@ unsafe extern "C" fn fhk_compile(G: &mut fhk_Graph, image: *mut *mut fhk_Image)
     }
 }
 
+// serialize the compiled image into the buffer. returns the length, or -1 if it can't be cached.
+extern "C" fn fhk_saveimage(G: &mut fhk_Graph, image: &fhk_Image, key: u64) -> i64 {
+    match cache::save(G, image, key) {
+        Some(data) => {
+            G.host.buf.clear();
+            G.host.buf.write(&data[..]);
+            data.len() as _
+        },
+        None => -1
+    }
+}
+
+// compile into a relocatable object in the buffer. returns the length, or -1 on error.
+unsafe extern "C" fn fhk_compileobj(G: &mut fhk_Graph, prefix: *const u8, len: usize) -> i64 {
+    let prefix = unsafe { core::str::from_utf8_unchecked(slice_from_raw_parts(prefix, len)) };
+    let result = G.begin().unwrap().ccx.compile_object(prefix);
+    match result {
+        Ok(data) => {
+            G.host.buf.clear();
+            G.host.buf.write(&data[..]);
+            data.len() as _
+        },
+        Err(()) => -1
+    }
+}
+
+// write typed bindings for the entry points of `fhk_compileobj` into the buffer.
+// lang: 0 = Rust, 1 = C header. must be called after compiling.
+unsafe extern "C" fn fhk_bindings(
+    G: &mut fhk_Graph,
+    prefix: *const u8,
+    len: usize,
+    lang: c_int
+) -> fhk_Result {
+    let prefix = unsafe { core::str::from_utf8_unchecked(slice_from_raw_parts(prefix, len)) };
+    let lang = match lang { 0 => BindLang::Rust, _ => BindLang::C };
+    G.host.buf.clear();
+    match write_bindings(&mut G.host.buf, &G.intern, &G.objs, prefix, lang) {
+        Ok(()) => 0,
+        Err(e) => {
+            G.host.buf.clear();
+            write!(G.host.buf, "{}", e).unwrap();
+            -1
+        }
+    }
+}
+
+unsafe extern "C" fn fhk_loadimage(
+    G: &mut fhk_Graph,
+    data: *const u8,
+    len: usize,
+    key: u64,
+    image: *mut *mut fhk_Image
+) -> fhk_Result {
+    let data = unsafe { slice_from_raw_parts(data, len) };
+    let result = G.begin().unwrap().ccx.compile_cached(data, key);
+    match result {
+        Ok(()) => {
+            unsafe { *image = Box::leak(Box::new(G.image.take().unwrap())); }
+            0
+        },
+        Err(()) => -1
+    }
+}
+
 extern "C" fn fhk_mcode(image: &fhk_Image) -> *const u8 {
     image.mem.base()
 }
 
+// describe the compiled code at `pc`, or null if it's not in the image.
+extern "C" fn fhk_srcloc(image: &fhk_Image, pc: *const u8) -> *const c_char {
+    match image.srcloc(pc) {
+        Some(text) => text.as_ptr() as _,
+        None => core::ptr::null()
+    }
+}
+
+// number of profile counters, or zero if the image wasn't compiled with profiling.
+extern "C" fn fhk_profnum(image: &fhk_Image) -> u32 {
+    match &image.profile {
+        Some(profile) => profile.counters.len() as _,
+        None => 0
+    }
+}
+
+extern "C" fn fhk_profname(image: &fhk_Image, idx: u32) -> *const c_char {
+    image.profile.as_ref().unwrap().name(idx as _).as_ptr() as _
+}
+
+// writes calls and cycles.
+unsafe extern "C" fn fhk_profread(image: &fhk_Image, idx: u32, out: *mut u64) {
+    let ctr = image.profile.as_ref().unwrap().read(idx as _);
+    unsafe {
+        *out = ctr.calls;
+        *out.add(1) = ctr.cycles;
+    }
+}
+
+// serialize the counters for `fhk_setprofile` into the buffer. returns the length, or -1 if the
+// image wasn't compiled with profiling.
+extern "C" fn fhk_saveprofile(G: &mut fhk_Graph, image: &fhk_Image) -> i64 {
+    match &image.profile {
+        Some(profile) => {
+            let data = pgo::save(profile);
+            G.host.buf.clear();
+            G.host.buf.write(&data[..]);
+            data.len() as _
+        },
+        None => -1
+    }
+}
+
+extern "C" fn fhk_profreset(image: &mut fhk_Image) {
+    if let Some(profile) = &mut image.profile {
+        profile.reset();
+    }
+}
+
+extern "C" fn fhk_profreport(image: &mut fhk_Image) -> *const c_char {
+    match &mut image.profile {
+        Some(profile) => profile.write_report().as_ptr() as _,
+        None => core::ptr::null()
+    }
+}
+
+// set the callbacks of a hooked image. returns -1 if the image wasn't compiled with hooks.
+extern "C" fn fhk_sethooks(
+    image: &mut fhk_Image,
+    enter: Option<HookFunc>,
+    exit: Option<HookFunc>,
+    ud: *mut c_void
+) -> c_int {
+    match &mut image.hooks {
+        Some(table) => {
+            *table.hooks = Hooks { enter, exit, ud };
+            0
+        },
+        None => -1
+    }
+}
+
+// describe the function a hook was called for.
+extern "C" fn fhk_hookname(image: &fhk_Image, func: u32) -> *const c_char {
+    image.hooks.as_ref().unwrap().name(func as _).as_ptr() as _
+}
+
+extern "C" fn fhk_sizereport(image: &mut fhk_Image) -> *const c_char {
+    image.write_sizereport().as_ptr() as _
+}
+
 extern "C" fn fhk_vmerr(instance: &fhk_Instance) -> *const c_char {
     instance.host.err as _
 }
 
+// 1 if the last error of `instance` is a query without a suitable model, 0 otherwise.
+extern "C" fn fhk_vmaborted(instance: &fhk_Instance) -> c_int {
+    let err = instance.host.err;
+    (!err.is_null() && unsafe { CStr::from_ptr(err) }.to_bytes() == ABORT_MESSAGE) as _
+}
+
+// nonzero if images run on the IR interpreter instead of machine code.
+extern "C" fn fhk_interp() -> c_int {
+    cfg!(feature="interp") as _
+}
+
+extern "C" fn fhk_newguard() -> *mut fhk_Guard {
+    Box::leak(Box::new(GuardAlloc::default()))
+}
+
+unsafe extern "C" fn fhk_destroyguard(guard: *mut fhk_Guard) {
+    drop(unsafe { Box::from_raw(guard) })
+}
+
+// fhk_Alloc with a fhk_Guard as udata
+unsafe extern "C" fn fhk_guardalloc(guard: *mut c_void, size: usize, align: usize) -> *mut u8 {
+    unsafe { &mut *(guard as *mut fhk_Guard) }.alloc(size, align)
+}
+
+extern "C" fn fhk_guardcheck(guard: &fhk_Guard) -> *const u8 {
+    guard.check()
+}
+
 unsafe extern "C" fn fhk_newinstance(
     image: &fhk_Image,
     alloc: fhk_Alloc,
@@ -266,8 +1028,12 @@ macro_rules! define_api {
 typedef struct fhk_Graph fhk_Graph;
 typedef struct fhk_Image fhk_Image;
 typedef struct fhk_Instance fhk_Instance;
+typedef struct fhk_Guard fhk_Guard;
 typedef union fhk_Obj { uint32_t raw; struct { uint8_t n; uint8_t op; uint8_t mark; uint8_t data; } obj; } fhk_Obj;
-typedef void *(fhk_Alloc)(void *, size_t, size_t);",
+typedef void *(fhk_Alloc)(void *, size_t, size_t);
+typedef void (fhk_Pool)(void *, void (*)(void *, uint32_t), void *, uint32_t);
+typedef void (fhk_Hook)(void *, uint32_t, uint32_t, uint32_t);
+typedef void (fhk_Observe)(void *, const uint8_t *, size_t, const uint8_t *, size_t);",
             stringify! {
                 typedef struct {
                     $($t)*
@@ -291,13 +1057,75 @@ define_api! {
     void (*fhk_getstr)(fhk_Graph *, uint32_t);
     int32_t (*fhk_newquery)(fhk_Graph *, int32_t, int32_t *, size_t);
     int32_t (*fhk_newreset)(fhk_Graph *, int32_t *, size_t);
-    void (*fhk_dumpobjs)(fhk_Graph *);
-    void (*fhk_optimize)(fhk_Graph *, const char *, size_t);
+    void (*fhk_dumpobjs)(fhk_Graph *, int);
+    void (*fhk_hashstats)(fhk_Graph *);
+    fhk_Result (*fhk_optimize)(fhk_Graph *, const char *, size_t);
+    fhk_Result (*fhk_optlevel)(fhk_Graph *, uint8_t);
+    fhk_Result (*fhk_optpasses)(fhk_Graph *, const char *, size_t, uint32_t);
+    void (*fhk_optstats)(fhk_Graph *);
+    void (*fhk_diagnostics)(fhk_Graph *, int, int);
+    uint32_t (*fhk_affected)(fhk_Graph *, int32_t *, size_t);
+    fhk_Result (*fhk_setconst)(fhk_Graph *, int32_t, double);
+    fhk_Result (*fhk_setvalue)(fhk_Graph *, int32_t, int32_t, int32_t);
+    fhk_Result (*fhk_setguard)(fhk_Graph *, int32_t, int32_t);
+    void (*fhk_parselimits)(fhk_Graph *, uint32_t, uint32_t);
+    void (*fhk_compilelimits)(fhk_Graph *, uint64_t, uint32_t);
+    void (*fhk_decimalcomma)(fhk_Graph *, int);
+    fhk_Result (*fhk_icheck)(fhk_Graph *, double);
+    void (*fhk_guardeps)(fhk_Graph *, double);
+    fhk_Result (*fhk_switchmin)(fhk_Graph *, uint32_t);
+    fhk_Result (*fhk_costmodel)(fhk_Graph *, const char *, size_t);
+    fhk_Result (*fhk_divzero)(fhk_Graph *, int, int64_t);
+    void (*fhk_crashdir)(fhk_Graph *, const char *, size_t);
+    fhk_Result (*fhk_verify)(fhk_Graph *, int);
+    fhk_Result (*fhk_assume)(fhk_Graph *, int32_t, int, int32_t, double);
+    void (*fhk_checkassume)(fhk_Graph *, int);
+    fhk_Result (*fhk_fastmath)(fhk_Graph *, int);
+    void (*fhk_floattype)(fhk_Graph *, int);
+    fhk_Result (*fhk_profile)(fhk_Graph *, int);
+    void (*fhk_hooks)(fhk_Graph *, int);
+    void (*fhk_parallel)(fhk_Graph *, int);
+    fhk_Result (*fhk_loadplugin)(fhk_Graph *, const char *, size_t);
+    fhk_Result (*fhk_hostfunc)(fhk_Graph *, const char *, size_t, const char *, size_t, void *, int);
+    void (*fhk_audit)(fhk_Graph *, int);
+    void (*fhk_auditlog)(fhk_Graph *, int);
+    void (*fhk_passeffects)(fhk_Graph *, int);
+    void (*fhk_irjson)(fhk_Graph *);
+    fhk_Result (*fhk_observe)(fhk_Graph *, const char *, size_t, fhk_Observe *, void *);
+    void (*fhk_ircanon)(fhk_Graph *);
+    int32_t (*fhk_setprofile)(fhk_Graph *, const uint8_t *, size_t);
+    uint64_t (*fhk_hash)(fhk_Graph *);
+    uint64_t (*fhk_hashobjs)(fhk_Graph *);
+    uint64_t (*fhk_hashmix)(uint64_t, const uint8_t *, size_t);
+    int (*fhk_selfcheck)(fhk_Graph *);
+    int (*fhk_tracedump)(fhk_Graph *, const uint8_t *, size_t, int);
     int32_t (*fhk_compile)(fhk_Graph *, fhk_Image **);
+    int64_t (*fhk_saveimage)(fhk_Graph *, fhk_Image *, uint64_t);
+    int64_t (*fhk_compileobj)(fhk_Graph *, const char *, size_t);
+    fhk_Result (*fhk_bindings)(fhk_Graph *, const char *, size_t, int);
+    int32_t (*fhk_loadimage)(fhk_Graph *, const uint8_t *, size_t, uint64_t, fhk_Image **);
     void *(*fhk_mcode)(fhk_Image *);
+    const char *(*fhk_srcloc)(fhk_Image *, uintptr_t);
+    uint32_t (*fhk_profnum)(fhk_Image *);
+    const char *(*fhk_profname)(fhk_Image *, uint32_t);
+    void (*fhk_profread)(fhk_Image *, uint32_t, uint64_t *);
+    int64_t (*fhk_saveprofile)(fhk_Graph *, fhk_Image *);
+    void (*fhk_profreset)(fhk_Image *);
+    const char *(*fhk_profreport)(fhk_Image *);
+    int (*fhk_sethooks)(fhk_Image *, fhk_Hook *, fhk_Hook *, void *);
+    const char *(*fhk_hookname)(fhk_Image *, uint32_t);
+    const char *(*fhk_sizereport)(fhk_Image *);
+    int (*fhk_interp)();
+    fhk_Guard *(*fhk_newguard)();
+    void (*fhk_destroyguard)(fhk_Guard *);
+    void *(*fhk_guardalloc)(void *, size_t, size_t);
+    uint8_t *(*fhk_guardcheck)(fhk_Guard *);
     fhk_Instance *(*fhk_newinstance)(fhk_Image *, fhk_Alloc *, void *, fhk_Instance *, uint64_t);
     int32_t (*fhk_vmcall)(fhk_Instance *, void *, uintptr_t);
+    uint32_t (*fhk_vmcallmany)(fhk_Instance **, void **, uint32_t, uintptr_t, int32_t *, fhk_Pool *, void *);
+    int32_t (*fhk_vmcallpar)(fhk_Image *, fhk_Instance *, void *, uintptr_t, fhk_Pool *, void *);
     char *(*fhk_vmerr)(fhk_Instance *);
+    int (*fhk_vmaborted)(fhk_Instance *);
 }
 
 #[unsafe(no_mangle)]
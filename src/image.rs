@@ -1,24 +1,153 @@
 //! Image and instance management.
 
 use core::arch::global_asm;
+use core::ffi::c_void;
 use core::marker::PhantomPinned;
 use core::mem::offset_of;
 use core::u64;
 
+use alloc::boxed::Box;
 use cfg_if::cfg_if;
 
+use crate::bump::Bump;
 use crate::finalize::Finalizers;
 use crate::host::HostInst;
-use crate::mem::{Breakpoints, Offset};
+use crate::ir::DebugSource;
+use crate::mcode::MCodeOffset;
+use crate::mem::{Breakpoints, Offset, Slot};
 use crate::mmap::Mmap;
+use crate::obj::ObjRef;
 
 pub struct Image {
     pub mem: Mmap,
     pub breakpoints: Breakpoints,
     pub fin: Finalizers,
+    pub srcmap: SrcMap,
+    pub codesize: CodeSize,
+    pub profile: Option<Profile>,
+    pub hooks: Option<HookTable>,
+    pub par: Option<ParSchedule>,
     pub size: Offset
 }
 
+// code range -> nul-terminated source description, for functions and the expressions inside
+// them. cached and interpreted images don't have one.
+#[derive(Default)]
+pub struct SrcMap {
+    pub ranges: Box<[(MCodeOffset, MCodeOffset, u32)]>, // start, end, offset in text
+    pub exprs: Box<[(MCodeOffset, MCodeOffset, u32)]>,  // same, but for expressions
+    pub text: Box<[u8]>
+}
+
+fn findrange(ranges: &[(MCodeOffset, MCodeOffset, u32)], ofs: MCodeOffset) -> Option<u32> {
+    let &(_, end, text) = ranges.get(ranges.partition_point(|&(start, _, _)| start <= ofs)
+        .checked_sub(1)?)?;
+    (ofs < end).then_some(text)
+}
+
+// emitted code bytes of each source object, largest first. every function of the object
+// (value, avail, init) counts towards it. cached and interpreted images don't have one.
+#[derive(Default)]
+pub struct CodeSize {
+    pub objs: Box<[SizeEntry]>,
+    pub text: Box<[u8]>,
+    pub report: Bump
+}
+
+#[derive(Clone, Copy)]
+pub struct SizeEntry {
+    pub obj: ObjRef,
+    pub bytes: u32,
+    pub name: u32, // nul-terminated description, offset in text
+    pub query: bool
+}
+
+// updated by profiled code, one per IR function.
+#[derive(Clone, Copy, Default)]
+#[repr(C)]
+pub struct ProfCounter {
+    pub calls: u64,
+    pub cycles: u64 // including callees
+}
+
+// hit counters of a switch, one per case.
+pub struct ProfSwitch {
+    pub source: DebugSource, // function containing the switch
+    pub keys: Box<[i64]>,
+    pub hits: Box<[u64]>
+}
+
+pub struct Profile {
+    pub counters: Box<[ProfCounter]>,
+    pub sources: Box<[DebugSource]>, // function of each counter
+    pub names: Box<[u32]>, // offset in text for each counter
+    pub text: Box<[u8]>,
+    pub switches: Box<[ProfSwitch]>,
+    pub report: Bump
+}
+
+// called by hooked code on entering and leaving each IR function. `start..end` is the instance
+// range the function computes: one instance for chunks, empty for queries.
+pub type HookFunc = unsafe extern "C" fn(ud: *mut c_void, func: u32, start: u32, end: u32);
+
+// the compiled code holds a pointer to this, so it must not move once emitted.
+#[repr(C)]
+pub struct Hooks {
+    pub enter: Option<HookFunc>,
+    pub exit: Option<HookFunc>,
+    pub ud: *mut c_void
+}
+
+impl Default for Hooks {
+    fn default() -> Self {
+        Self { enter: None, exit: None, ud: core::ptr::null_mut() }
+    }
+}
+
+pub struct HookTable {
+    pub hooks: Box<Hooks>,
+    pub names: Box<[u32]>, // offset in text for each IR function
+    pub text: Box<[u8]>
+}
+
+impl HookTable {
+
+    pub fn name(&self, func: usize) -> &[u8] {
+        let text = &self.text[self.names[func] as usize..];
+        &text[..text.iter().position(|&c| c == 0).unwrap()]
+    }
+
+}
+
+// chunks that queries can compute ahead on a thread pool, when compiled with
+// `Session::parallel`. entries of a level only call chunks of lower levels, so a level can run
+// in parallel once the levels below it are done.
+pub struct ParSchedule {
+    pub queries: Box<[(MCodeOffset, u32, u32)]>, // query code, range in `levels`, sorted by code
+    pub levels: Box<[u32]>, // start of each level in `entries`, followed by the end of the last
+    pub entries: Box<[ParEntry]>
+}
+
+#[derive(Clone, Copy)]
+pub struct ParEntry {
+    pub mcode: MCodeOffset, // stub with the query signature that computes the chunk
+    pub check: Slot         // set when the chunk starts
+}
+
+impl ParSchedule {
+
+    // levels of the query at `mcode`, lowest first.
+    pub fn levels(&self, mcode: MCodeOffset) -> impl Iterator<Item=&[ParEntry]> {
+        let (start, end) = match self.queries.binary_search_by_key(&mcode, |&(q, _, _)| q) {
+            Ok(i) => (self.queries[i].1, self.queries[i].2),
+            Err(_) => (0, 0)
+        };
+        (start as usize..end as usize)
+            .map(|l| &self.entries[self.levels[l] as usize..self.levels[l+1] as usize])
+    }
+
+}
+
 // note: the repr align is redundant here, but (regardless of fields), the compiled code expects
 // this to be aligned to 8.
 #[repr(align(8))]
@@ -40,6 +169,132 @@ pub struct DupHeader {
     pub next: Offset, // slot of next dup data
 }
 
+/* ---- Source mapping ------------------------------------------------------ */
+
+impl Image {
+
+    // describe the code at `pc`, eg. for reporting a fault address. this is the expression
+    // when the code generator knows it, otherwise the function.
+    pub fn srcloc(&self, pc: *const u8) -> Option<&[u8]> {
+        let ofs = (pc as usize).checked_sub(self.mem.base() as usize)?;
+        let ofs: MCodeOffset = ofs.try_into().ok()?;
+        let text = findrange(&self.srcmap.exprs, ofs)
+            .or_else(|| findrange(&self.srcmap.ranges, ofs))?;
+        let text = &self.srcmap.text[text as usize..];
+        Some(&text[..=text.iter().position(|&c| c == 0)?])
+    }
+
+    // nul-terminated table of code size per object, largest first. when profiled, also the calls
+    // and cycles of each object, and its share of the cycles spent in queries. cycles include
+    // callees, so the shares of nested objects overlap.
+    pub fn write_sizereport(&mut self) -> &[u8] {
+        use core::fmt::Write;
+        let mut report = core::mem::take(&mut self.codesize.report);
+        report.clear();
+        let codesize = &self.codesize;
+        let total: u32 = codesize.objs.iter().map(|e| e.bytes).sum();
+        let counters = |obj: ObjRef| -> ProfCounter {
+            let mut sum = ProfCounter::default();
+            if let Some(profile) = &self.profile {
+                for (i, src) in profile.sources.iter().enumerate() {
+                    if src.obj() == obj {
+                        let ProfCounter { calls, cycles } = profile.read(i);
+                        sum.calls += calls;
+                        sum.cycles += cycles;
+                    }
+                }
+            }
+            sum
+        };
+        let qcycles: u64 = codesize.objs.iter()
+            .filter(|e| e.query)
+            .map(|e| counters(e.obj).cycles)
+            .sum();
+        write!(report, "{:>10} {:>6}", "bytes", "code%").unwrap();
+        if self.profile.is_some() {
+            write!(report, " {:>12} {:>16} {:>6}", "calls", "cycles", "time%").unwrap();
+        }
+        report.write("  object\n");
+        for e in &codesize.objs {
+            write!(report, "{:>10} {:>6.1}", e.bytes, 100.0 * e.bytes as f64 / total as f64)
+                .unwrap();
+            if self.profile.is_some() {
+                let ProfCounter { calls, cycles } = counters(e.obj);
+                write!(report, " {:>12} {:>16}", calls, cycles).unwrap();
+                match qcycles {
+                    0 => write!(report, " {:>6}", "-"),
+                    _ => write!(report, " {:>6.1}", 100.0 * cycles as f64 / qcycles as f64)
+                }.unwrap();
+            }
+            report.write("  ");
+            let text = &codesize.text[e.name as usize..];
+            report.write(&text[..text.iter().position(|&c| c == 0).unwrap()]);
+            report.push(b'\n');
+        }
+        report.push(0u8);
+        self.codesize.report = report;
+        self.codesize.report.as_slice::<u8>()
+    }
+
+}
+
+/* ---- Profiling --------------------------------------------------------- */
+
+impl Profile {
+
+    // compiled code holds pointers into the counters, so all accesses go through raw pointers.
+    pub fn read(&self, idx: usize) -> ProfCounter {
+        unsafe { core::ptr::read_volatile(&raw const self.counters[idx]) }
+    }
+
+    pub fn reset(&mut self) {
+        for ctr in &mut self.counters {
+            unsafe { core::ptr::write_volatile(ctr, Default::default()) }
+        }
+        for switch in &mut self.switches {
+            for hits in &mut switch.hits {
+                unsafe { core::ptr::write_volatile(hits, 0) }
+            }
+        }
+    }
+
+    pub fn name(&self, idx: usize) -> &[u8] {
+        let text = &self.text[self.names[idx] as usize..];
+        &text[..text.iter().position(|&c| c == 0).unwrap()]
+    }
+
+    // nul-terminated table of called functions, most cycles first.
+    pub fn write_report(&mut self) -> &[u8] {
+        use core::fmt::Write;
+        let mut order: alloc::vec::Vec<usize> = (0..self.counters.len())
+            .filter(|&i| self.read(i).calls > 0)
+            .collect();
+        order.sort_by_key(|&i| core::cmp::Reverse(self.read(i).cycles));
+        let mut report = core::mem::take(&mut self.report);
+        report.clear();
+        write!(report, "{:>12} {:>16} {:>12}  function\n", "calls", "cycles", "cycles/call")
+            .unwrap();
+        for i in order {
+            let ProfCounter { calls, cycles } = self.read(i);
+            write!(report, "{:>12} {:>16} {:>12}  ", calls, cycles, cycles/calls).unwrap();
+            report.write(self.name(i));
+            report.push(b'\n');
+        }
+        report.push(0u8);
+        self.report = report;
+        self.report.as_slice::<u8>()
+    }
+
+}
+
+impl ProfSwitch {
+
+    pub fn read(&self, idx: usize) -> u64 {
+        unsafe { core::ptr::read_volatile(&raw const self.hits[idx]) }
+    }
+
+}
+
 /* ---- Instance creation --------------------------------------------------- */
 
 impl Image {
@@ -125,6 +380,7 @@ impl Image {
 #[cfg(all(target_arch="x86_64", unix))]
 global_asm!("
 .hidden fhk_vmcall
+.hidden fhk_vmcall_worker
 .hidden fhk_vmexit
 ");
 
@@ -132,6 +388,7 @@ global_asm!("
 global_asm!("
 .p2align 4
 .global fhk_vmcall
+.global fhk_vmcall_worker
 .global fhk_vmexit
 // (vmctx[rdi], result[rsi], mcode[rdx]) -> status[rax]
 fhk_vmcall:
@@ -157,23 +414,95 @@ fhk_vmcall:
     pop r12
     ret
 fhk_vmexit:
-    mov rsp, [r15+{vmctx_rsp}]          // restore stack
+    mov rdi, r15                        // rdi = vmctx
+    and rsp, -16                        // align stack for call
+    call {vmexit_sp}                    // rax = stack saved by the call being exited
+    mov rsp, rax                        // restore stack
     mov eax, 1                          // status = 1
     jmp 1b
+// (vmctx[rdi], result[rsi], mcode[rdx], sp[rcx]) -> status[rax]
+// same as fhk_vmcall, but saves the stack in `sp` instead of the instance, see vmcall_worker.
+fhk_vmcall_worker:
+    push r12
+    push r13
+    push r14
+    push r15
+    push rbx
+    push rbp
+    mov [rcx], rsp                      // save stack for fhk_vmexit
+    push rcx                            // align stack for call
+    mov r15, rdi                        // pinned reg = vmctx
+    xor rdi, rdi                        // idx = 0
+    call rdx                            // call mcode(idx, result)
+    pop rcx                             // realign stack
+    xor eax, eax                        // status = 0
+    jmp 1b
 ",
     vmctx_rsp = const offset_of!(Instance, sp),
+    vmexit_sp = sym fhk_vmexit_sp,
     // vmctx_scratchpad = const offset_of!(host::State, scratchpad)
 );
 
 #[allow(improper_ctypes)]
 unsafe extern "sysv64" {
     pub fn fhk_vmcall(vmctx: *mut Instance, result: *mut u8, mcode: *const u8) -> i32;
+    fn fhk_vmcall_worker(vmctx: *mut Instance, result: *mut u8, mcode: *const u8, sp: *mut *mut u8)
+        -> i32;
     #[cold]
     pub fn fhk_vmexit(vmctx: *mut Instance) -> !;
 }
 
+// worker calls can run on several threads in the same instance, so each saves its stack in its
+// own frame, and fhk_vmexit looks it up for the current thread.
+#[cfg(all(feature="std", target_arch="x86_64"))]
+mod worker {
+
+    extern crate std;
+
+    use core::cell::Cell;
+
+    std::thread_local! {
+        pub static SP: Cell<*mut *mut u8> = const { Cell::new(core::ptr::null_mut()) };
+    }
+
+}
+
+#[cfg(target_arch="x86_64")]
+unsafe extern "sysv64" fn fhk_vmexit_sp(vmctx: *const Instance) -> *mut u8 {
+    #[cfg(feature="std")]
+    {
+        let sp = worker::SP.get();
+        if !sp.is_null() {
+            return unsafe { *sp };
+        }
+    }
+    unsafe { (*vmctx).sp }
+}
+
+cfg_if! {
+    if #[cfg(all(feature="std", target_arch="x86_64", not(feature="interp")))] {
+        pub const PARALLEL_VMCALL: bool = true;
+        // like fhk_vmcall, but may run concurrently with other worker calls in the same instance.
+        pub unsafe fn vmcall_worker(vmctx: *mut Instance, result: *mut u8, mcode: *const u8) -> i32 {
+            let mut sp: *mut u8 = core::ptr::null_mut();
+            worker::SP.set(&raw mut sp);
+            let status = unsafe { fhk_vmcall_worker(vmctx, result, mcode, &raw mut sp) };
+            worker::SP.set(core::ptr::null_mut());
+            status
+        }
+    } else {
+        // worker calls need the per-thread exit stack, without it everything runs on the caller.
+        pub const PARALLEL_VMCALL: bool = false;
+        pub unsafe fn vmcall_worker(vmctx: *mut Instance, result: *mut u8, mcode: *const u8) -> i32 {
+            unsafe { fhk_vmcall_native(vmctx, result, mcode) }
+        }
+    }
+}
+
 cfg_if! {
-    if #[cfg(any(windows, all(target_os="macos", target_arch="aarch64")))] {
+    if #[cfg(feature="interp")] {
+        pub use crate::interp::fhk_vmcall as fhk_vmcall_native;
+    } else if #[cfg(any(windows, all(target_os="macos", target_arch="aarch64")))] {
         pub unsafe extern "C" fn fhk_vmcall_native(
             vmctx: *mut Instance,
             result: *mut u8,
@@ -8,7 +8,7 @@ use hashbrown::hash_table::Entry;
 use hashbrown::HashTable;
 
 use crate::bump::{self, Aligned, Bump, BumpRef, PackedSliceDst};
-use crate::hash::fxhash;
+use crate::hash::{fxhash, table_stats, HashStats};
 
 /*
  * +-------+------+
@@ -122,52 +122,53 @@ impl<T: ?Sized> IRef<T> {
 
 }
 
-unsafe fn bigsize(mut end: *const u8, mut raw_size: usize) -> usize {
-    let mut shift = 0;
+// bump[range], bounds checked only with the `checked` feature.
+unsafe fn slice(bump: &[u8], range: Range<usize>) -> &[u8] {
+    #[cfg(feature="checked")]
+    { &bump[range] }
+    #[cfg(not(feature="checked"))]
+    unsafe { bump.get_unchecked(range) }
+}
+
+unsafe fn bigsize(bump: &[u8], end: usize, raw_size: usize) -> usize {
     let mut size = 0;
-    loop {
-        size |= (unsafe { *end } as usize) << shift;
-        raw_size += 1;
-        if raw_size == 0x10 { return size }
-        end = unsafe { end.add(1) };
-        shift += 8;
+    for &b in unsafe { slice(bump, end..end+(0x10-raw_size)) }.iter().rev() {
+        size = (size << 8) | b as usize;
     }
+    size
 }
 
-unsafe fn bigbytes<'a>(data: *const u8, end: usize, raw_size: usize) -> &'a [u8] {
+unsafe fn bigbytes(bump: &[u8], end: usize, raw_size: usize) -> &[u8] {
     unsafe {
-        let end = data.add(end);
-        let size = bigsize(end, raw_size);
-        core::slice::from_raw_parts(end.sub(size), size)
+        let size = bigsize(bump, end, raw_size);
+        slice(bump, end-size..end)
     }
 }
 
-unsafe fn bytesmatch(data: *const u8, r: RawRef, bytes: &[u8]) -> bool {
+unsafe fn bytesmatch(bump: &[u8], r: RawRef, bytes: &[u8]) -> bool {
     let raw_size = r.raw_size() as usize;
     let end = r.end() as usize;
     if bytes.len() <= RawRef::MAX_SMALL as _ {
         // if `r` is big then the first comparison is always false
-        raw_size == bytes.len()
-            && bytes == unsafe { core::slice::from_raw_parts(data.add(end-raw_size), raw_size) }
+        raw_size == bytes.len() && bytes == unsafe { slice(bump, end-raw_size..end) }
     } else {
-        raw_size > RawRef::MAX_SMALL as _ && bytes == unsafe { bigbytes(data, end, raw_size) }
+        raw_size > RawRef::MAX_SMALL as _ && bytes == unsafe { bigbytes(bump, end, raw_size) }
     }
 }
 
 unsafe fn refmatch(bump: &[u8], r: RawRef, bytes: &[u8], align: u32) -> bool {
-    r.end() & (align - 1) == 0 && unsafe { bytesmatch(bump.as_ptr(), r, bytes) }
+    r.end() & (align - 1) == 0 && unsafe { bytesmatch(bump, r, bytes) }
 }
 
-unsafe fn refdata<'a>(data: *const u8, r: RawRef) -> &'a [u8] {
+unsafe fn refdata(bump: &[u8], r: RawRef) -> &[u8] {
     let raw_size = r.raw_size() as usize;
     let end = r.end() as usize;
-    let ep = unsafe { data.add(end) };
     let size = if raw_size <= RawRef::MAX_SMALL as _ {
         raw_size
     } else {
-        unsafe { bigsize(ep, raw_size) }
+        unsafe { bigsize(bump, end, raw_size) }
     };
-    unsafe { core::slice::from_raw_parts(ep.sub(size), size) }
+    unsafe { slice(bump, end-size..end) }
 }
 
 // safety: tab and bump must come from the same intern table
@@ -182,7 +183,7 @@ unsafe fn entry<'tab, 'short>(
         // note: this tests that the *end* is aligned, which works as long as size is a multiple
         // of alignment.
         |&r| unsafe { refmatch(bump, r, bytes, align) },
-        |&r| fxhash(unsafe { refdata(bump.as_ptr(), r) })
+        |&r| fxhash(unsafe { refdata(bump, r) })
     )
 }
 
@@ -223,7 +224,7 @@ fn reflen(data: &[u8], end: usize, raw_size: usize) -> usize {
         raw_size
     } else {
         assert!(end + (0x10 - raw_size) <= data.len());
-        unsafe { bigsize(data.as_ptr().add(end), raw_size) }
+        unsafe { bigsize(data, end, raw_size) }
     }
 }
 
@@ -262,6 +263,10 @@ impl Intern {
         ).map(|&r| IRef(r, PhantomData))
     }
 
+    pub fn intern_bytes(&mut self, bytes: &[u8], align: usize) -> IRef<[u8]> {
+        IRef(internbytes(self, bytes, align as _), PhantomData)
+    }
+
     pub fn intern_range(&mut self, range: Range<usize>) -> IRef<[u8]> {
         let Range { start, end } = range;
         let bytes = self.bump.as_slice();
@@ -295,6 +300,10 @@ impl Intern {
         &self.bump
     }
 
+    pub fn hash_stats(&self) -> HashStats {
+        table_stats(&self.tab, |&r| fxhash(unsafe { refdata(self.bump.as_slice(), r) }))
+    }
+
     // pub fn ref_to_bump<T>(&self, r: IRef<T>) -> BumpRef<T>
     //     where T: ?Sized + Aligned
     // {
@@ -0,0 +1,708 @@
+//! IR interpreter.
+
+// the interpreter replaces emit+link on platforms that don't allow generating machine code at
+// runtime. functions are scheduled exactly like for emit, and the scheduled code is written into
+// mcode.code as a flat, position independent program that is mapped read-only, so the host sees
+// the same image as with compiled code:
+//
+//   data | table | funcs... | (pad) | constant data (mcode.data)
+//
+// where `data` is the offset of the constant data, and `table` is the offset of the function
+// table (one u32 header offset for each function). each function is
+//
+//   FuncHeader | rets | (pad) | code | aux
+//
+// QUERY.mcode points to the query's header, and `fhk_vmcall` has the same signature and return
+// value as the asm version.
+//
+// language calls (LO* instructions) are compiled by each language's emitter and are not
+// supported here, and neither are the instructions only emitted code implements (TRET, CALL,
+// CONV). this means models that call Lua, C, R or host functions only run in builds without
+// `interp`. graphs that use them fail to compile with an error that says so, instead of failing
+// at runtime.
+
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::mem::take;
+
+use zerocopy::Unalign;
+
+use crate::bitmap::BitMatrix;
+use crate::bump::BumpRef;
+use crate::compile::{self, Ccx, CompileError, Stage};
+use crate::controlflow::BlockId;
+use crate::emit::InsValue;
+use crate::image::{Image, Instance};
+use crate::index::{self, IndexSlice, IndexVec};
+use crate::intern::IRef;
+use crate::ir::{Chunk, Func, FuncId, FuncKind, Ins, InsId, Opcode, PhiId, Query, Type};
+use crate::lang::Lang;
+use crate::mem::{Cursor, CursorA, CursorType, SizeClass, Slot};
+use crate::mmap::{Mmap, Prot};
+use crate::schedule::{compute_schedule, Gcm};
+use crate::support::{pow, rt_init, sqrt, DynSlot, ABORT_MESSAGE};
+use crate::typestate::{Absent, R, RW};
+
+#[derive(Default)]
+pub struct Interp {
+    gcm: Gcm,
+    code: IndexVec<InsId, Ins>,
+    values: IndexVec<InsId, InsValue>,
+    blockparams: BitMatrix<BlockId, PhiId>,
+    start: IndexVec<BlockId, InsId>,
+    aux: Vec<u64>
+}
+
+type Icx<'a> = Ccx<Interp, RW, R<'a>>;
+
+#[derive(zerocopy::FromBytes, zerocopy::IntoBytes, zerocopy::Immutable)]
+#[repr(C)]
+struct FuncHeader {
+    base: u32,   // offset of this header from program start
+    code: u32,   // offset of code from this header
+    ncode: u32,  // number of instructions
+    nphi: u32,   // number of phis
+    nret: u32,   // number of returns
+    param: u32,  // index parameter phi, or !0
+    flags: u32,  // FLAG_*
+    check: u32,  // check slot (chunks only)
+    frame: u32,  // frame size
+    falign: u32  // frame alignment
+}
+
+const FLAG_CHUNK: u32   = 0x1;
+const FLAG_DYNAMIC: u32 = 0x2;
+const FLAG_GLOBAL: u32  = 0x4;
+
+#[derive(Clone, Copy)]
+pub enum InterpError {
+    Lang(Lang),
+    Opcode(Opcode),
+    User
+}
+
+impl CompileError for InterpError {
+    fn write(self, ccx: &mut Ccx<(), R, R>) {
+        match self {
+            InterpError::Lang(lang) => {
+                ccx.host.buf.write(lang.name());
+                ccx.host.buf.write(" calls are not supported by the interpreter");
+            },
+            InterpError::Opcode(op) => {
+                write!(ccx.host.buf, "{} is not supported by the interpreter", op.name()).unwrap();
+            },
+            InterpError::User => {
+                ccx.host.buf.write("user functions are not supported by the interpreter");
+            }
+        }
+        ccx.host.buf.write(" (feature `interp`), they need a build that generates machine code");
+    }
+}
+
+// first thing in `func` the interpreter can't execute.
+fn unsupported(func: &Func) -> Option<InterpError> {
+    use Opcode::*;
+    if let FuncKind::User() = func.kind {
+        return Some(InterpError::User);
+    }
+    func.code.pairs().find_map(|(_, ins)| match ins.opcode() {
+        op if op.is_lang() => Some(InterpError::Lang(Lang::from_u8(ins.decode_L().lang))),
+        op @ (TRET | CALL | CONV) => Some(InterpError::Opcode(op)),
+        RES => match func.code.at(ins.decode_RES().0).opcode() {
+            CALLC | CALLCI => None,
+            op => Some(InterpError::Opcode(op))
+        },
+        _ => None
+    })
+}
+
+/* ---- Program ------------------------------------------------------------- */
+
+fn kvalue(ty: Type, k: i64) -> u64 {
+    match ty {
+        Type::F32 => (k as f32).to_bits() as _,
+        Type::F64 => (k as f64).to_bits(),
+        _ => sext(ty, k)
+    }
+}
+
+fn writefunc(icx: &mut Icx, fid: FuncId) {
+    let Interp { gcm, code, values, blockparams, start, aux } = &mut *icx.data;
+    let func = &icx.ir.funcs[fid];
+    compute_schedule(gcm, func, code, values, blockparams, &mut icx.mark1);
+    start.clear();
+    start.push(0.into());
+    for (id, ins) in code.pairs() {
+        if ins.opcode().is_control() {
+            start.push(id + 1);
+        }
+    }
+    let target = |ctr: InsId| -> u64 {
+        let pc: usize = start[values[ctr].block()].into();
+        pc as _
+    };
+    let mut frame = CursorA::default();
+    aux.clear();
+    for &ins in &code.raw {
+        use Opcode::*;
+        aux.push(match ins.opcode() {
+            JMP => target(ins.decode_JMP().1),
+            GOTO => target(ins.decode_GOTO()),
+            IF => {
+                let (_, tru, fal) = ins.decode_IF();
+                target(tru) | (target(fal) << 32)
+            },
+            KINT => kvalue(ins.type_(), ins.bc() as i32 as i64),
+            KINT64 => {
+                let k: BumpRef<Unalign<i64>> = zerocopy::transmute!(ins.bc());
+                kvalue(ins.type_(), icx.intern.bump()[k].get())
+            },
+            KFP64 => {
+                let k: BumpRef<Unalign<f64>> = zerocopy::transmute!(ins.bc());
+                let k = icx.intern.bump()[k].get();
+                match ins.type_() {
+                    Type::F32 => (k as f32).to_bits() as _,
+                    _ => k.to_bits()
+                }
+            },
+            KSTR => {
+                let k: IRef<[u8]> = zerocopy::transmute!(ins.bc());
+                icx.mcode.data.intern_bytes(icx.intern.get_slice(k), 8).to_bump().ptr() as _
+            },
+            BOX => {
+                // same layout as ins_box in translate.rs
+                let value = ins.decode_V();
+                match code[value].opcode() {
+                    CARG => {
+                        let ofs = frame.align(8);
+                        let mut args = value;
+                        while code[args].opcode() == CARG {
+                            let (next, value) = code[args].decode_CARG();
+                            frame.alloc_type(code[value].type_());
+                            args = next;
+                        }
+                        ofs as _
+                    },
+                    _ => frame.alloc_type(code[value].type_()) as _
+                }
+            },
+            ABOX => {
+                let (_, size, align) = ins.decode_ABOX();
+                frame.alloc(size as _, align as _) as _
+            },
+            CINIT => {
+                let (_, chunk) = ins.decode_CINIT();
+                let func = &icx.ir.funcs[chunk];
+                let FuncKind::Chunk(Chunk { scl, dynslots, .. }) = func.kind
+                    else { unreachable!() };
+                match scl.is_dynamic() {
+                    true => {
+                        let nret: usize = func.ret.into();
+                        // bitmap + one for each return
+                        (dynslots.ptr() as u64) | (((1 + nret) as u64) << 32)
+                    },
+                    false => 0
+                }
+            },
+            _ => 0
+        });
+    }
+    let nret: usize = func.ret.into();
+    let (flags, check) = match func.kind {
+        // rejected in `Interp::new`
+        FuncKind::User() => unreachable!(),
+        FuncKind::Query(_) => (0, Slot::default()),
+        FuncKind::Chunk(Chunk { scl, check, .. }) => (
+            FLAG_CHUNK
+                | if scl.is_dynamic() { FLAG_DYNAMIC } else { 0 }
+                | if scl == SizeClass::GLOBAL { FLAG_GLOBAL } else { 0 },
+            check
+        )
+    };
+    let mcode = &mut icx.mcode.code;
+    let head = mcode.push(FuncHeader {
+        base: 0,
+        code: 0,
+        ncode: code.raw.len() as _,
+        nphi: {let n: usize = func.phis.end().into(); n as _},
+        nret: nret as _,
+        param: match func.arg > func.ret {
+            true => {let p: usize = func.ret.into(); p as _},
+            false => !0
+        },
+        flags,
+        check: zerocopy::transmute!(check),
+        frame: frame.ptr as _,
+        falign: frame.align as _
+    });
+    for i in 0..nret {
+        let r: u32 = match func.kind {
+            FuncKind::Query(Query { offsets, .. }) => icx.perm[offsets.offset(i as _)],
+            FuncKind::Chunk(Chunk { slots, .. }) => zerocopy::transmute!(icx.perm[slots.offset(i as _)]),
+            FuncKind::User() => unreachable!()
+        };
+        mcode.push(r);
+    }
+    let ofs = mcode.write(&code.raw[..]).ptr();
+    mcode.write(&aux[..]);
+    let base = head.ptr();
+    mcode[head].base = base as _;
+    mcode[head].code = (ofs - base) as _;
+    icx.mcode.labels.raw.push(base as _);
+}
+
+fn writefuncs(icx: &mut Icx) {
+    for id in index::iter_span(icx.ir.funcs.end()) {
+        writefunc(icx, id);
+    }
+}
+
+impl Stage for Interp {
+
+    fn new(ccx: &mut Ccx<Absent>) -> compile::Result<Self> {
+        if let Some(e) = ccx.ir.funcs.raw.iter().find_map(unsupported) {
+            return ccx.error(e);
+        }
+        Ok(Default::default())
+    }
+
+    fn run(ccx: &mut Ccx<Self>) -> compile::Result {
+        let head = ccx.mcode.code.push([0u32; 2]);
+        ccx.freeze_ir(writefuncs);
+        let table = ccx.mcode.code.write(&ccx.mcode.labels.raw[..]).ptr();
+        ccx.mcode.code.align(8);
+        let data = ccx.mcode.code.end().ptr();
+        ccx.mcode.code[head] = [data as _, table as _];
+        for (fid, func) in ccx.ir.funcs.pairs() {
+            if let FuncKind::Query(Query { obj, .. }) = func.kind {
+                let fid: u16 = zerocopy::transmute!(fid);
+                ccx.objs[obj].mcode = ccx.mcode.labels.raw[fid as usize];
+            }
+        }
+        let code: &[u8] = ccx.mcode.code.as_slice();
+        let data: &[u8] = ccx.mcode.data.bump().as_slice();
+        // TODO this can really fail and should set an error insted of unwrapping
+        let mut mem = Mmap::new(code.len() + data.len(), Prot::Read | Prot::Write).unwrap();
+        let buf = mem.as_mut_slice();
+        buf[..code.len()].copy_from_slice(code);
+        buf[code.len()..].copy_from_slice(data);
+        mem.protect(0..code.len()+data.len(), Prot::Read.into());
+        ccx.image = Some(Image {
+            mem,
+            fin: take(&mut ccx.fin).build(),
+            srcmap: Default::default(),
+            codesize: Default::default(),
+            profile: None,
+            hooks: None,
+            par: None,
+            breakpoints: ccx.layout.breakpoints,
+            size: ccx.layout.size
+        });
+        Ok(())
+    }
+
+}
+
+/* ---- Values -------------------------------------------------------------- */
+
+// values are stored as u64. integers are sign extended, f32 is stored in the low 32 bits,
+// and b1 is 0 or 1.
+
+fn sext(ty: Type, v: i64) -> u64 {
+    (match ty {
+        Type::I8  => v as i8 as i64,
+        Type::I16 => v as i16 as i64,
+        Type::I32 => v as i32 as i64,
+        _ => v
+    }) as _
+}
+
+fn zext(ty: Type, v: u64) -> u64 {
+    match ty.size() {
+        1 => v as u8 as _,
+        2 => v as u16 as _,
+        4 => v as u32 as _,
+        _ => v
+    }
+}
+
+unsafe fn load(ptr: *const u8, ty: Type) -> u64 {
+    use Type::*;
+    unsafe {
+        match ty {
+            I8  => ptr.cast::<i8>().read() as i64 as _,
+            I16 => ptr.cast::<i16>().read_unaligned() as i64 as _,
+            I32 => ptr.cast::<i32>().read_unaligned() as i64 as _,
+            F32 => ptr.cast::<u32>().read_unaligned() as _,
+            B1  => ptr.read() as _,
+            PTR | I64 | F64 => ptr.cast::<u64>().read_unaligned(),
+            FX | LSV | F64X2 => unreachable!()
+        }
+    }
+}
+
+unsafe fn store(ptr: *mut u8, ty: Type, v: u64) {
+    unsafe {
+        match ty.size() {
+            1 => ptr.write(v as _),
+            2 => ptr.cast::<u16>().write_unaligned(v as _),
+            4 => ptr.cast::<u32>().write_unaligned(v as _),
+            8 => ptr.cast::<u64>().write_unaligned(v),
+            _ => unreachable!()
+        }
+    }
+}
+
+// returns None on integer division by zero.
+fn arith(op: Opcode, ty: Type, a: u64, b: u64) -> Option<u64> {
+    use Opcode::*;
+    macro_rules! fp {
+        ($t:ty, $bits:ty) => {{
+            let a = <$t>::from_bits(a as $bits);
+            let b = <$t>::from_bits(b as $bits);
+            (match op {
+                ADD => a+b,
+                SUB => a-b,
+                MUL => a*b,
+                DIV => a/b,
+                _ => unreachable!()
+            }).to_bits() as u64
+        }};
+    }
+    Some(match ty {
+        Type::F32 => fp!(f32, u32),
+        Type::F64 => fp!(f64, u64),
+        _ => {
+            let (x, y) = (a as i64, b as i64);
+            let v = match op {
+                ADD  => x.wrapping_add(y),
+                SUB  => x.wrapping_sub(y),
+                MUL  => x.wrapping_mul(y),
+                DIV  => x.wrapping_div(if y == 0 { return None } else { y }),
+                UDIV => (zext(ty, a) / if y == 0 { return None } else { zext(ty, b) }) as _,
+                USHR => (zext(ty, a) >> (b & (8*ty.size() as u64 - 1))) as _,
+                _ => unreachable!()
+            };
+            sext(ty, v)
+        }
+    })
+}
+
+fn cmp(op: Opcode, ty: Type, a: u64, b: u64) -> bool {
+    use Opcode::*;
+    match ty {
+        Type::F32 | Type::F64 => {
+            let (x, y) = match ty {
+                Type::F32 => (f32::from_bits(a as _) as f64, f32::from_bits(b as _) as f64),
+                _ => (f64::from_bits(a), f64::from_bits(b))
+            };
+            match op {
+                EQ  => x == y,
+                NE  => x != y,
+                LT  => x < y,
+                LE  => x <= y,
+                ULT => !(x >= y),
+                ULE => !(x > y),
+                _   => unreachable!()
+            }
+        },
+        Type::I8 | Type::I16 | Type::I32 | Type::I64 => {
+            let (x, y) = (a as i64, b as i64);
+            match op {
+                EQ  => x == y,
+                NE  => x != y,
+                LT  => x < y,
+                LE  => x <= y,
+                ULT => zext(ty, a) < zext(ty, b),
+                ULE => zext(ty, a) <= zext(ty, b),
+                _   => unreachable!()
+            }
+        },
+        _ => unreachable!()
+    }
+}
+
+/* ---- Execution ----------------------------------------------------------- */
+
+struct Vm {
+    base: *const u8,
+    data: *const u8,
+    vmctx: *mut Instance
+}
+
+impl Vm {
+
+    unsafe fn func(&self, fid: FuncId) -> &FuncHeader {
+        let fid: u16 = zerocopy::transmute!(fid);
+        unsafe {
+            let [_, table] = *self.base.cast::<[u32; 2]>();
+            let ofs = *self.base.add(table as usize).cast::<u32>().add(fid as usize);
+            &*self.base.add(ofs as usize).cast()
+        }
+    }
+
+    unsafe fn slotptr(&self, idx: u64, dynamic: bool, slot: Slot, ty: Type) -> *mut u8 {
+        unsafe {
+            let base = self.vmctx.cast::<u8>().add(slot.byte() as usize);
+            let base = match dynamic {
+                true => *base.cast::<*mut u8>(),
+                false => base
+            };
+            base.offset(idx as i64 as isize * ty.size() as isize)
+        }
+    }
+
+    unsafe fn loadslot(&self, idx: u64, dynamic: bool, slot: Slot, ty: Type) -> u64 {
+        unsafe {
+            let value = load(self.slotptr(idx, dynamic, slot, ty), ty);
+            match ty {
+                Type::B1 => (value >> slot.bit()) & 1,
+                _ => value
+            }
+        }
+    }
+
+    unsafe fn storeslot(&self, idx: u64, dynamic: bool, slot: Slot, ty: Type, mut value: u64) {
+        unsafe {
+            let ptr = self.slotptr(idx, dynamic, slot, ty);
+            if ty == Type::B1 {
+                value = (value << slot.bit()) | ptr.read() as u64;
+            }
+            store(ptr, ty, value);
+        }
+    }
+
+    #[cold]
+    fn fail(&self, err: &[u8]) -> Result<(), ()> {
+        unsafe { (*self.vmctx).host.set_error(err); }
+        Err(())
+    }
+
+    unsafe fn exec(&self, func: &FuncHeader, idx: u64, result: *mut u8) -> Result<(), ()> {
+        use Opcode::*;
+        let head = func as *const FuncHeader as *const u8;
+        let n = func.ncode as usize;
+        let (rets, code, aux) = unsafe {(
+            core::slice::from_raw_parts(head.add(size_of::<FuncHeader>()).cast::<u32>(),
+                func.nret as _),
+            IndexSlice::<InsId, Ins>::from_raw(core::slice::from_raw_parts(
+                    head.add(func.code as usize).cast(), n)),
+            IndexSlice::<InsId, u64>::from_raw(core::slice::from_raw_parts(
+                    head.add(func.code as usize + n*size_of::<Ins>()).cast(), n))
+        )};
+        let dynamic = func.flags & FLAG_DYNAMIC != 0;
+        if func.flags & FLAG_CHUNK != 0 {
+            let check: Slot = zerocopy::transmute!(func.check);
+            unsafe { self.storeslot(idx, dynamic, check, Type::B1, 1) }
+        }
+        let mut values: IndexVec<InsId, u64> = Default::default();
+        values.raw.resize(n, 0);
+        let mut phis: IndexVec<PhiId, u64> = Default::default();
+        phis.raw.resize(func.nphi as _, 0);
+        if func.param != !0 {
+            phis.raw[func.param as usize] = idx;
+        }
+        // frame for BOX/ABOX. it's over-allocated so that it can be aligned to any alignment.
+        let mut framebuf: Vec<u64> = Vec::new();
+        framebuf.resize((func.frame + func.falign).div_ceil(8) as _, 0);
+        let fp = {
+            let p = framebuf.as_mut_ptr() as usize;
+            ((p + func.falign as usize - 1) & !(func.falign as usize - 1)) as *mut u8
+        };
+        let mut pc: InsId = 0.into();
+        loop {
+            let ins = code[pc];
+            let mut next = pc + 1;
+            let value = match ins.opcode() {
+                NOP | KREF | CARG => 0,
+                JMP => {
+                    let (value, _, phi) = ins.decode_JMP();
+                    let ty = code[value].type_();
+                    if ty != Type::FX {
+                        let v = values[value];
+                        let p: usize = phi.into();
+                        if p < rets.len() {
+                            match func.flags & FLAG_CHUNK {
+                                0 => unsafe { store(result.add(rets[p] as usize), ty, v) },
+                                _ => {
+                                    let slot: Slot = zerocopy::transmute!(rets[p]);
+                                    unsafe { self.storeslot(idx, dynamic, slot, ty, v) }
+                                }
+                            }
+                        }
+                        phis[phi] = v;
+                    }
+                    next = (aux[pc] as usize).into();
+                    0
+                },
+                GOTO => {
+                    next = (aux[pc] as usize).into();
+                    0
+                },
+                IF => {
+                    let (cond, _, _) = ins.decode_IF();
+                    let target = match values[cond] {
+                        0 => aux[pc] >> 32,
+                        _ => aux[pc] & 0xffffffff
+                    };
+                    next = (target as usize).into();
+                    0
+                },
+                RET => return Ok(()),
+                // rejected in `Interp::new`
+                TRET | CALL | CONV => unreachable!(),
+                // execution of this instruction is always a compiler bug.
+                UB => return self.fail(b"undefined behavior"),
+                ABORT => return self.fail(ABORT_MESSAGE),
+                PHI => match ins.type_() {
+                    Type::FX => 0,
+                    _ => phis[ins.decode_PHI().1]
+                },
+                KINT | KINT64 | KFP64 => aux[pc],
+                KSTR => unsafe { self.data.add(aux[pc] as usize) as _ },
+                MOV | MOVB | MOVF => values[ins.decode_V()],
+                ADD | SUB | MUL | DIV | UDIV | USHR => {
+                    let (left, right) = ins.decode_VV();
+                    match arith(ins.opcode(), ins.type_(), values[left], values[right]) {
+                        Some(v) => v,
+                        None => return self.fail(b"integer division by zero")
+                    }
+                },
+                POW => {
+                    let (left, right) = ins.decode_VV();
+                    let v = unsafe {
+                        pow(f64::from_bits(values[left]), f64::from_bits(values[right]))
+                    };
+                    v.to_bits()
+                },
+                NEG => {
+                    let v = values[ins.decode_V()];
+                    match ins.type_() {
+                        Type::F32 => v ^ 0x80000000,
+                        Type::F64 => v ^ (1 << 63),
+                        Type::B1 => v ^ 1,
+                        ty => sext(ty, (v as i64).wrapping_neg())
+                    }
+                },
+                SQRT => {
+                    let v = values[ins.decode_V()];
+                    match ins.type_() {
+                        Type::F32 => unsafe { (sqrt(f32::from_bits(v as _) as _) as f32).to_bits() as _ },
+                        _ => unsafe { sqrt(f64::from_bits(v)).to_bits() }
+                    }
+                },
+                ADDP => {
+                    let (left, right) = ins.decode_VV();
+                    values[left].wrapping_add(values[right])
+                },
+                EQ | NE | LT | LE | ULT | ULE => {
+                    let (left, right) = ins.decode_VV();
+                    cmp(ins.opcode(), code[left].type_(), values[left], values[right]) as _
+                },
+                SELECT => {
+                    let (cond, tru, fal) = ins.decode_SELECT();
+                    match values[cond] {
+                        0 => values[fal],
+                        _ => values[tru]
+                    }
+                },
+                ALLOC => {
+                    let (size, align) = ins.decode_VV();
+                    unsafe {
+                        (*self.vmctx).host.alloc(values[size] as _, values[align] as _) as _
+                    }
+                },
+                STORE => {
+                    let (ptr, value) = ins.decode_VV();
+                    unsafe { store(values[ptr] as _, code[value].type_(), values[value]) }
+                    0
+                },
+                LOAD => unsafe { load(values[ins.decode_V()] as _, ins.type_()) },
+                BOX => {
+                    let value = ins.decode_V();
+                    match code[value].opcode() {
+                        CARG => {
+                            let mut cursor = Cursor { ptr: aux[pc] as _ };
+                            let mut args = value;
+                            while code[args].opcode() == CARG {
+                                let (next, value) = code[args].decode_CARG();
+                                let ty = code[value].type_();
+                                let p = cursor.alloc_type(ty);
+                                unsafe { store(fp.add(p), ty, values[value]) }
+                                args = next;
+                            }
+                        },
+                        _ => unsafe {
+                            store(fp.add(aux[pc] as _), code[value].type_(), values[value])
+                        }
+                    }
+                    aux[pc]
+                },
+                ABOX => aux[pc],
+                BREF => fp as u64 + values[ins.decode_V()],
+                CALLC | CALLCI => {
+                    let (i, _, chunk) = ins.decode_CALLC();
+                    let callee = unsafe { self.func(chunk) };
+                    let i = values[i];
+                    let cdyn = callee.flags & FLAG_DYNAMIC != 0;
+                    let check: Slot = zerocopy::transmute!(callee.check);
+                    if unsafe { self.loadslot(i, cdyn, check, Type::B1) } == 0 {
+                        let i = match callee.flags & FLAG_GLOBAL { 0 => i, _ => 0 };
+                        unsafe { self.exec(callee, i, core::ptr::null_mut())? }
+                    }
+                    0
+                },
+                RES => match ins.type_() {
+                    Type::FX => 0,
+                    ty => {
+                        let (call, phi) = ins.decode_RES();
+                        let (i, _, chunk) = match code[call].opcode() {
+                            CALLC | CALLCI => code[call].decode_CALLC(),
+                            _ => unreachable!()
+                        };
+                        let callee = unsafe { self.func(chunk) };
+                        let p: usize = phi.into();
+                        let slot: Slot = zerocopy::transmute!(unsafe {
+                            *(callee as *const FuncHeader as *const u8)
+                                .add(size_of::<FuncHeader>()).cast::<u32>().add(p)
+                        });
+                        unsafe { self.loadslot(values[i], callee.flags & FLAG_DYNAMIC != 0, slot, ty) }
+                    }
+                },
+                CINIT => {
+                    if aux[pc] != 0 {
+                        let (size, _) = ins.decode_CINIT();
+                        unsafe {
+                            rt_init(
+                                &mut *self.vmctx,
+                                self.data.add(aux[pc] as u32 as usize).cast::<DynSlot>(),
+                                (aux[pc] >> 32) as _,
+                                values[size] as _
+                            );
+                        }
+                    }
+                    0
+                },
+                // lowering doesn't vectorize when interpreting.
+                SPLAT | VSUM => unreachable!(),
+                LO | LOV | LOVV | LOVX | LOX | LOXX => unreachable!()
+            };
+            values[pc] = value;
+            pc = next;
+        }
+    }
+
+}
+
+pub unsafe extern "C" fn fhk_vmcall(vmctx: *mut Instance, result: *mut u8, mcode: *const u8) -> i32 {
+    unsafe {
+        let func = &*mcode.cast::<FuncHeader>();
+        let base = mcode.sub(func.base as usize);
+        let [data, _] = *base.cast::<[u32; 2]>();
+        let vm = Vm { base, data: base.add(data as usize), vmctx };
+        match vm.exec(func, 0, result) {
+            Ok(()) => 0,
+            Err(()) => 1
+        }
+    }
+}
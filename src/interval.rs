@@ -0,0 +1,382 @@
+//! Interval evaluation of IR.
+
+// this is a debugging tool for checking that the optimizer doesn't change results.
+// before optimization each function is evaluated over interval inputs, and after optimization
+// the same function is evaluated again. any return interval that escaped the original interval
+// by more than the tolerance is reported as an error.
+//
+// the evaluator is deliberately simple:
+//   * parameters take the full range of their type
+//   * anything it can't reason about (loads, calls, language instructions) is the full range
+//   * an IF whose condition can't be decided evaluates both arms, and the return intervals are
+//     joined over all paths that return. paths that end in UB or ABORT return nothing.
+//   * a tail call (TRET) or too many steps abort the evaluation of that function
+
+use core::fmt::Write;
+
+use alloc::vec::Vec;
+use zerocopy::Unalign;
+
+use crate::bump::BumpRef;
+use crate::compile::{self, Ccx, CompileError};
+use crate::index::{self, IndexVec};
+use crate::intern::Intern;
+use crate::ir::{DebugSource, Func, Ins, InsId, Opcode, PhiId, Type, IR};
+use crate::optimize::Optimize;
+use crate::symbol::{write_source, Symbols};
+use crate::trace::trace;
+use crate::typestate::R;
+
+// bail out if the paths of the function take more than this many control instructions in total.
+const MAX_STEPS: usize = 10000;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Interval {
+    pub lo: f64,
+    pub hi: f64
+}
+
+impl Interval {
+
+    pub const TOP: Self = Self { lo: f64::NEG_INFINITY, hi: f64::INFINITY };
+    pub const BOOL: Self = Self { lo: 0.0, hi: 1.0 };
+
+    pub fn new(lo: f64, hi: f64) -> Self {
+        match lo.is_nan() || hi.is_nan() {
+            true  => Self::TOP,
+            false => Self { lo, hi }
+        }
+    }
+
+    pub fn point(value: f64) -> Self {
+        Self::new(value, value)
+    }
+
+    pub fn of_type(ty: Type) -> Self {
+        match ty {
+            Type::I8  => Self::new(i8::MIN as _, i8::MAX as _),
+            Type::I16 => Self::new(i16::MIN as _, i16::MAX as _),
+            Type::I32 => Self::new(i32::MIN as _, i32::MAX as _),
+            Type::I64 => Self::new(i64::MIN as _, i64::MAX as _),
+            Type::B1  => Self::BOOL,
+            _ => Self::TOP
+        }
+    }
+
+    pub fn contains(self, value: f64) -> bool {
+        self.lo <= value && value <= self.hi
+    }
+
+    fn corners(self, other: Self, f: impl Fn(f64, f64) -> f64) -> Self {
+        let c = [f(self.lo, other.lo), f(self.lo, other.hi), f(self.hi, other.lo),
+            f(self.hi, other.hi)];
+        if c.iter().any(|x| x.is_nan()) {
+            return Self::TOP;
+        }
+        Self::new(
+            c.iter().fold(f64::INFINITY, |m, &x| m.min(x)),
+            c.iter().fold(f64::NEG_INFINITY, |m, &x| m.max(x))
+        )
+    }
+
+    fn add(self, other: Self) -> Self {
+        Self::new(self.lo + other.lo, self.hi + other.hi)
+    }
+
+    fn sub(self, other: Self) -> Self {
+        Self::new(self.lo - other.hi, self.hi - other.lo)
+    }
+
+    fn mul(self, other: Self) -> Self {
+        // 0*inf is 0 here: the infinite bound is never attained
+        self.corners(other, |a, b| if a == 0.0 || b == 0.0 { 0.0 } else { a*b })
+    }
+
+    fn div(self, other: Self) -> Self {
+        match other.contains(0.0) {
+            true  => Self::TOP,
+            false => self.corners(other, |a, b| a/b)
+        }
+    }
+
+    fn neg(self) -> Self {
+        Self::new(-self.hi, -self.lo)
+    }
+
+    fn pow(self, other: Self) -> Self {
+        if other.lo == other.hi && other.lo == (other.lo as i32) as f64 {
+            let n = other.lo;
+            if n < 0.0 && self.contains(0.0) {
+                return Self::TOP;
+            }
+            let (x, y) = (self.lo.powf(n), self.hi.powf(n));
+            if n > 0.0 && (n as i32) % 2 == 0 && self.lo < 0.0 && self.hi > 0.0 {
+                return Self::new(0.0, x.max(y));
+            }
+            return Self::new(x.min(y), x.max(y));
+        }
+        match self.lo > 0.0 {
+            true  => self.corners(other, |a, b| a.powf(b)),
+            false => Self::TOP
+        }
+    }
+
+    fn trunc(self) -> Self {
+        fn t(x: f64) -> f64 {
+            match x > i64::MIN as f64 && x < i64::MAX as f64 {
+                true  => (x as i64) as f64,
+                false => x
+            }
+        }
+        Self::new(t(self.lo), t(self.hi))
+    }
+
+    fn lt(self, other: Self) -> Self {
+        if self.hi < other.lo { Self::point(1.0) }
+        else if self.lo >= other.hi { Self::point(0.0) }
+        else { Self::BOOL }
+    }
+
+    fn le(self, other: Self) -> Self {
+        if self.hi <= other.lo { Self::point(1.0) }
+        else if self.lo > other.hi { Self::point(0.0) }
+        else { Self::BOOL }
+    }
+
+    fn eq(self, other: Self) -> Self {
+        if self.lo == self.hi && other.lo == other.hi && self.lo == other.lo { Self::point(1.0) }
+        else if self.hi < other.lo || other.hi < self.lo { Self::point(0.0) }
+        else { Self::BOOL }
+    }
+
+    fn not(self) -> Self {
+        Self::new(1.0 - self.hi, 1.0 - self.lo)
+    }
+
+    // does `self` escape `orig` by more than the tolerance?
+    pub fn widens(self, orig: Self, tol: f64) -> bool {
+        fn slack(x: f64, tol: f64) -> f64 {
+            tol * if x < -1.0 { -x } else if x > 1.0 { x } else { 1.0 }
+        }
+        self.lo < orig.lo - slack(orig.lo, tol) || self.hi > orig.hi + slack(orig.hi, tol)
+    }
+
+}
+
+#[derive(Default)]
+struct Eval {
+    value: IndexVec<InsId, Option<Interval>>,
+    phi: IndexVec<PhiId, Interval>,
+    forks: Vec<(InsId, usize)>, // (branch, start of its phis in `saved`), last in first out
+    saved: Vec<Interval>
+}
+
+fn kvalue(intern: &Intern, ins: Ins) -> f64 {
+    use Opcode::*;
+    match ins.opcode() {
+        KINT => ins.bc() as i32 as _,
+        KINT64 => {
+            let data: BumpRef<Unalign<i64>> = zerocopy::transmute!(ins.bc());
+            intern.bump()[data].get() as _
+        },
+        _ /* KFP64 */ => {
+            let data: BumpRef<Unalign<f64>> = zerocopy::transmute!(ins.bc());
+            intern.bump()[data].get()
+        }
+    }
+}
+
+fn evalins(eval: &mut Eval, intern: &Intern, func: &Func, id: InsId) -> Interval {
+    use Opcode::*;
+    if let Some(v) = eval.value[id] {
+        return v;
+    }
+    let ins = func.code.at(id);
+    let ty = ins.type_();
+    let v = match ins.opcode() {
+        KINT|KINT64|KFP64 => Interval::point(kvalue(intern, ins)),
+        PHI => {
+            let (_, phi) = ins.decode_PHI();
+            eval.phi[phi]
+        },
+        MOV|MOVB|MOVF => evalins(eval, intern, func, ins.decode_V()),
+        CONV if ty.is_int() => evalins(eval, intern, func, ins.decode_V()).trunc(),
+        CONV if ty.is_fp() => evalins(eval, intern, func, ins.decode_V()),
+        ADD|SUB|MUL|DIV|UDIV|USHR|POW|EQ|NE|LT|LE|ULT|ULE => {
+            let (a, b) = ins.decode_VV();
+            let a = evalins(eval, intern, func, a);
+            let b = evalins(eval, intern, func, b);
+            match ins.opcode() {
+                ADD => a.add(b),
+                SUB => a.sub(b),
+                MUL => a.mul(b),
+                DIV|UDIV if ty.is_int() => a.div(b).trunc(),
+                DIV|UDIV => a.div(b),
+                USHR if a.lo >= 0.0 && b.lo == b.hi && b.lo < 64.0 =>
+                    a.div(Interval::point((1u64 << b.lo as u32) as f64)).trunc(),
+                USHR => Interval::of_type(ty),
+                POW => a.pow(b),
+                EQ => a.eq(b),
+                NE => a.eq(b).not(),
+                LT => a.lt(b),
+                LE => a.le(b),
+                ULT if a.lo >= 0.0 && b.lo >= 0.0 => a.lt(b),
+                ULE if a.lo >= 0.0 && b.lo >= 0.0 => a.le(b),
+                _ => Interval::BOOL
+            }
+        },
+        SELECT => {
+            let (_, tru, fal) = ins.decode_SELECT();
+            let tru = evalins(eval, intern, func, tru);
+            let fal = evalins(eval, intern, func, fal);
+            Interval::new(tru.lo.min(fal.lo), tru.hi.max(fal.hi))
+        },
+        NEG if ty == Type::B1 => evalins(eval, intern, func, ins.decode_V()).not(),
+        NEG => evalins(eval, intern, func, ins.decode_V()).neg(),
+        _ => Interval::of_type(ty)
+    };
+    eval.value[id] = Some(v);
+    v
+}
+
+// on success, pushes return values to `out` and returns true.
+fn evalfunc(eval: &mut Eval, intern: &Intern, func: &Func, out: &mut Vec<Interval>) -> bool {
+    use Opcode::*;
+    eval.value.clear();
+    eval.value.raw.resize(func.code.end().into(), None);
+    eval.phi.clear();
+    for phi in index::iter_span(func.phis.end()) {
+        eval.phi.push(Interval::of_type(func.phis.at(phi).type_));
+    }
+    eval.forks.clear();
+    eval.saved.clear();
+    let start = out.len();
+    let mut returned = false;
+    let mut ctr = func.entry;
+    let mut steps = 0;
+    loop {
+        if steps == MAX_STEPS {
+            out.truncate(start);
+            return false
+        }
+        steps += 1;
+        let ins = func.code.at(ctr);
+        match ins.opcode() {
+            JMP => {
+                let (value, dest, phi) = ins.decode_JMP();
+                eval.phi[phi] = evalins(eval, intern, func, value);
+                eval.value.raw.fill(None);
+                ctr = dest;
+                continue
+            },
+            GOTO => { ctr = ins.decode_GOTO(); continue },
+            IF => {
+                let (cond, tru, fal) = ins.decode_IF();
+                let cond = evalins(eval, intern, func, cond);
+                if cond == Interval::point(0.0) {
+                    ctr = fal;
+                } else if !cond.contains(0.0) {
+                    ctr = tru;
+                } else {
+                    eval.forks.push((fal, eval.saved.len()));
+                    eval.saved.extend_from_slice(&eval.phi.raw);
+                    ctr = tru;
+                }
+                continue
+            },
+            RET if returned => {
+                for (o, phi) in out[start..].iter_mut().zip(func.returns()) {
+                    let v = eval.phi[phi];
+                    *o = Interval::new(o.lo.min(v.lo), o.hi.max(v.hi));
+                }
+            },
+            RET => {
+                out.extend(func.returns().map(|phi| eval.phi[phi]));
+                returned = true;
+            },
+            UB|ABORT => {},
+            _ /* TRET */ => {
+                out.truncate(start);
+                return false
+            }
+        }
+        // this path is done, continue from the last undecided branch.
+        let Some((fal, base)) = eval.forks.pop() else { return returned };
+        eval.phi.raw.copy_from_slice(&eval.saved[base..]);
+        eval.saved.truncate(base);
+        eval.value.raw.fill(None);
+        ctr = fal;
+    }
+}
+
+#[derive(Default)]
+pub struct IntervalCheck {
+    eval: Eval,
+    funcs: Vec<(DebugSource, usize, usize)>, // source, start..end in `values`
+    values: Vec<Interval>
+}
+
+impl IntervalCheck {
+
+    pub fn snapshot(&mut self, ir: &IR, intern: &Intern) {
+        self.funcs.clear();
+        self.values.clear();
+        for func in &ir.funcs.raw {
+            let start = self.values.len();
+            if evalfunc(&mut self.eval, intern, func, &mut self.values) {
+                self.funcs.push((func.source, start, self.values.len()));
+            } else {
+                self.values.truncate(start);
+            }
+        }
+    }
+
+}
+
+struct IntervalError {
+    src: DebugSource,
+    ret: usize,
+    orig: Interval,
+    new: Interval
+}
+
+impl CompileError for IntervalError {
+    fn write(self, ccx: &mut Ccx<(), R, R>) {
+        ccx.host.buf.write("interval check failed: ");
+        write_source(&mut ccx.host.buf, &ccx.intern, &ccx.objs, self.src);
+        write!(
+            ccx.host.buf,
+            " return {} widened from [{}, {}] to [{}, {}]",
+            self.ret, self.orig.lo, self.orig.hi, self.new.lo, self.new.hi
+        ).unwrap();
+    }
+}
+
+pub fn check(ccx: &mut Ccx<Optimize>, tol: f64) -> compile::Result {
+    let icheck = &mut ccx.data.icheck;
+    let symbols = Symbols::new(&ccx.ir);
+    let mut new = Vec::new();
+    let mut err = None;
+    'funcs: for &(src, start, end) in &icheck.funcs {
+        for &fid in symbols.funcs(src.obj()) {
+            let func = &ccx.ir.funcs[fid];
+            if func.source != src { continue }
+            new.clear();
+            if !evalfunc(&mut icheck.eval, &ccx.intern, func, &mut new) || new.len() != end-start {
+                continue
+            }
+            for (ret, (&o, &n)) in icheck.values[start..end].iter().zip(new.iter()).enumerate() {
+                trace!(OPTIMIZE "ICHECK {:?} return {}: [{}, {}] -> [{}, {}]", fid, ret, o.lo,
+                    o.hi, n.lo, n.hi);
+                if n.widens(o, tol) {
+                    err = Some(IntervalError { src, ret, orig: o, new: n });
+                    break 'funcs
+                }
+            }
+        }
+    }
+    match err {
+        Some(e) => ccx.error(e),
+        None => Ok(())
+    }
+}
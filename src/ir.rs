@@ -5,7 +5,9 @@ use core::marker::PhantomData;
 use core::mem::transmute;
 use core::ops::Range;
 use core::slice;
-use enumset::{EnumSet, EnumSetType};
+
+use alloc::vec::Vec;
+use enumset::{enum_set, EnumSet, EnumSetType};
 
 use crate::bump::BumpRef;
 use crate::foreach_lang;
@@ -13,7 +15,7 @@ use crate::index::{index, IndexValueVec, IndexVec, InvalidValue};
 use crate::lang::Lang;
 use crate::mcode::MCodeData;
 use crate::mem::{Offset, ResetId, ResetSet, SizeClass, Slot};
-use crate::obj::{ObjRef, QUERY};
+use crate::obj::{ObjRef, EXPR, QUERY};
 use crate::support::DynSlot;
 
 index!(pub struct FuncId(u16) invalid(!0) debug("f{}"));
@@ -43,9 +45,9 @@ macro_rules! define_types {
             };
 
             pub fn size(self) -> usize {
-                // 4 bits per variant (fits at most 16 types)
-                let magic = $( (($size as u64) << (4 * Type::$name as usize)) )|*;
-                ((magic >> (4 * self as usize)) & 0xf) as usize
+                // 5 bits per variant (fits at most 12 types)
+                let magic = $( (($size as u64) << (5 * Type::$name as usize)) )|*;
+                ((magic >> (5 * self as usize)) & 0x1f) as usize
             }
 
         }
@@ -87,6 +89,7 @@ define_types! {
     F32  4;
     F64  8;
     B1   1;
+    F64X2 16; // vectorized loops only
 }
 
 impl Type {
@@ -112,6 +115,10 @@ impl Type {
         (I8|I16|I32|I64).contains(self)
     }
 
+    pub fn is_vector(self) -> bool {
+        self == Type::F64X2
+    }
+
 }
 
 /* ---- Opcodes ------------------------------------------------------------- */
@@ -191,6 +198,14 @@ macro_rules! define_operands {
             }
         }
 
+        impl OperandData {
+            fn bits(self) -> u64 {
+                match self {
+                    $(OperandData::$name(v) => OperandBits::bits(v)),*
+                }
+            }
+        }
+
         impl Debug for OperandData {
             fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
                 match *self {
@@ -270,6 +285,7 @@ macro_rules! define_opcodes {
         $name:ident $(.$type:ident)?
         $($mode:ident)*
         $(, $decoder:ident)?
+        $(| $($flag:ident)*)?
         ;
     )*) => {
 
@@ -294,6 +310,9 @@ macro_rules! define_opcodes {
             };
             const INPUTS_V: &'static [u8] = &[$(count_operands(Opcode::$name, Operand::V) as _),*];
             const INPUTS_C: &'static [u8] = &[$(count_operands(Opcode::$name, Operand::C) as _),*];
+            const FLAGS: &'static [EnumSet<OpFlag>] = &[
+                $(enum_set!($($(OpFlag::$flag)|*)?)),*
+            ];
         }
 
         impl Ins {
@@ -306,11 +325,33 @@ macro_rules! define_opcodes {
                 );
                 decode_fn!(pub fn $($decoder $name)? -> $($mode)*);
             )*
+
+            // operands returned by the opcode's own decoder, encoded back into instruction bits.
+            fn selfcheck_decoder(self) -> Option<u64> {
+                match self.opcode() {
+                    $($( Opcode::$name => Some(EncodeOperands::encode(self.$decoder())), )?)*
+                    _ => None
+                }
+            }
         }
 
     };
 }
 
+// opcode properties. passes should check these instead of matching on opcode sets, so that a
+// new opcode only needs an entry in the table below.
+#[derive(EnumSetType)]
+pub enum OpFlag {
+    CTRL,  // control instruction
+    PIN,   // pinned to its control instruction, not moved by the scheduler
+    NOCSE, // each instance is distinct, never deduplicated
+    K,     // constant
+    LANG,  // language-specific instruction
+    COMM,  // commutative in its two value inputs
+    ARITH, // value inputs have the same type as the result
+    CMP,   // value inputs have the same type, result is B1
+}
+
 // NOTE:
 // * value inputs (V) must be placed first
 // * control inputs (C) must be placed right after value inputs
@@ -320,68 +361,75 @@ define_opcodes! {
 
 /* -- Control instructions ---------- */
 
-    JMP.FX    V C P, decode_JMP;  // value dest phi
-    GOTO.FX   C,     decode_GOTO;
-    IF.FX     V C C, decode_IF;   // cond tru fal
-    RET.FX;
-    TRET.FX   V F;                // args func
-    UB.FX;
-    ABORT.FX; // (add reason/message here if needed)
+    JMP.FX    V C P, decode_JMP   | CTRL;        // value dest phi
+    GOTO.FX   C,     decode_GOTO  | CTRL;
+    IF.FX     V C C, decode_IF    | CTRL;        // cond tru fal
+    RET.FX                        | CTRL;
+    TRET.FX   V F                 | CTRL;        // args func
+    UB.FX                         | CTRL;
+    ABORT.FX                      | CTRL;        // (add reason/message here if needed)
 
 /* -- Data instructions --------------*/
 
-    PHI       C P,   decode_PHI;  // control id
+    PHI       C P,   decode_PHI   | PIN;         // control id
 
-    KINT      XX;
-    KINT64    XX;
-    KFP64     XX;
-    KSTR      XX;
-    KREF.LSV  XX;
+    KINT      XX                  | K;
+    KINT64    XX                  | K;
+    KFP64     XX                  | K;
+    KSTR      XX                  | K;
+    KREF.LSV  XX                  | K;
 
     MOV       V;
     MOVB      V;
-    MOVF      V V;
+    MOVF      V V;                               // value fx  (value, ordered after fx)
     CONV      V X;
 
-    ADD       V V;
-    SUB       V V;
-    MUL       V V;
-    DIV       V V;
-    UDIV      V V;
-    POW       V V;
-    NEG       V;
+    ADD       V V                 | COMM ARITH;
+    SUB       V V                 | ARITH;
+    MUL       V V                 | COMM ARITH;
+    DIV       V V                 | ARITH;
+    UDIV      V V                 | ARITH;
+    USHR      V V                 | ARITH;
+    POW       V V                 | ARITH;
+    NEG       V                   | ARITH;
+    SQRT      V                   | ARITH;
+
+    SPLAT     V;                                 // scalar (-> vector)
+    VSUM      V;                                 // vector (-> scalar sum of lanes)
 
     ADDP.PTR  V V;
 
-    EQ.B1     V V;
-    NE.B1     V V;
-    LT.B1     V V;
-    LE.B1     V V;
-    ULT.B1    V V;
-    ULE.B1    V V;
+    EQ.B1     V V                 | COMM CMP;
+    NE.B1     V V                 | COMM CMP;
+    LT.B1     V V                 | CMP;
+    LE.B1     V V                 | CMP;
+    ULT.B1    V V                 | CMP;
+    ULE.B1    V V                 | CMP;
 
-    ALLOC.PTR V V C;                   // size align control
-    STORE.FX  V V;                     // ptr value
-    LOAD      V;                       // ptr
+    SELECT    V V V, decode_SELECT;              // cond tru fal
+
+    ALLOC.PTR V V C               | PIN NOCSE;   // size align control
+    STORE.FX  V V;                               // ptr value
+    LOAD      V;                                 // ptr
 
     BOX.LSV   V;
-    ABOX.LSV  C X X, decode_ABOX;      // control size align
+    ABOX.LSV  C X X, decode_ABOX  | PIN NOCSE;   // control size align
     BREF.PTR  V;
 
-    CALL.FX   V F,   decode_CALL;      // args func
-    CALLC.FX  V V F;                   // idx fx chunk  (NOT inlineable)
-    CALLCI.FX V V F;                   // idx fx chunk  (inlineable)
-    CARG.LSV  V V,   decode_CARG;      // arg next
-    RES       V P,   decode_RES;       // call phi
+    CALL.FX   V F,   decode_CALL;                // args func
+    CALLC.FX  V V F;                             // idx fx chunk  (NOT inlineable)
+    CALLCI.FX V V F;                             // idx fx chunk  (inlineable)
+    CARG.LSV  V V,   decode_CARG;                // arg next
+    RES       V P,   decode_RES;                 // call phi
 
-    CINIT.FX  V F,   decode_CINIT;     // size chunk
+    CINIT.FX  V F,   decode_CINIT;               // size chunk
 
-    LO;
-    LOV       V L,   decode_LOV;
-    LOVV      V V L, decode_LOVV;
-    LOVX      V X L, decode_LOVX;
-    LOX       L X,   decode_LOX;
-    LOXX      L XX,  decode_LOXX;
+    LO                            | LANG;
+    LOV       V L,   decode_LOV   | LANG;
+    LOVV      V V L, decode_LOVV  | LANG;
+    LOVX      V X L, decode_LOVX  | LANG;
+    LOX       L X,   decode_LOX   | LANG;
+    LOXX      L XX,  decode_LOXX  | LANG;
 
 }
 
@@ -397,9 +445,12 @@ impl Opcode {
             ..Self::OPERANDS_OFS[self as usize+1] as usize]
     }
 
+    pub fn flags(self) -> EnumSet<OpFlag> {
+        Self::FLAGS[self as usize]
+    }
+
     pub fn is_control(self) -> bool {
-        use Opcode::*;
-        (JMP|GOTO|IF|RET|TRET|UB|ABORT).contains(self)
+        self.flags().contains(OpFlag::CTRL)
     }
 
     pub fn is_data(self) -> bool {
@@ -407,23 +458,23 @@ impl Opcode {
     }
 
     pub fn is_lang(self) -> bool {
-        use Opcode::*;
-        (LO|LOV|LOVV|LOVX|LOX|LOXX).contains(self)
+        self.flags().contains(OpFlag::LANG)
     }
 
     pub fn is_pinned(self) -> bool {
-        use Opcode::*;
-        (PHI|ALLOC|ABOX).contains(self)
+        self.flags().contains(OpFlag::PIN)
     }
 
     pub fn is_cse(self) -> bool {
-        use Opcode::*;
-        !(ALLOC|ABOX).contains(self)
+        !self.flags().contains(OpFlag::NOCSE)
     }
 
     pub fn is_const(self) -> bool {
-        use Opcode::*;
-        (KINT|KINT64|KFP64|KSTR|KREF).contains(self)
+        self.flags().contains(OpFlag::K)
+    }
+
+    pub fn is_commutative(self) -> bool {
+        self.flags().contains(OpFlag::COMM)
     }
 
     pub fn num_v(self) -> usize {
@@ -458,11 +509,14 @@ const fn count_operands(opcode: Opcode, opr: Operand) -> usize {
 /* ---- Instructions -------------------------------------------------------- */
 
 /*
- * +--------+--------+--------+--------+-------+--------+
- * | 63..48 | 47..32 | 31..16 | 15..12 | 11..8 |  7..0  |
- * +--------+--------+--------+--------+-------+--------+
- * |   C    |    B   |    A   |  type  | ----- | opcode |
- * +--------+--------+--------+--------+-------+--------+
+ * +--------+--------+--------+--------+-------+----+--------+
+ * | 63..48 | 47..32 | 31..16 | 15..12 | 11..9 |  8 |  7..0  |
+ * +--------+--------+--------+--------+-------+----+--------+
+ * |   C    |    B   |    A   |  type  | ----- | fx | opcode |
+ * +--------+--------+--------+--------+-------+----+--------+
+ *
+ * fx: instruction has side effects that the optimizer can't see (eg. a foreign call that
+ *     wasn't annotated pure), and must not be deduplicated.
  *
  * NOTE: do not derive FromBytes. opcode and type must always be valid.
  */
@@ -491,6 +545,14 @@ impl Ins {
         Self((self.0 & !0xff) | (opcode as u64))
     }
 
+    pub const fn is_effect(self) -> bool {
+        self.0 & 0x100 != 0
+    }
+
+    pub const fn set_effect(self) -> Self {
+        Self(self.0 | 0x100)
+    }
+
     pub const fn type_(self) -> Type {
         unsafe { transmute(((self.0 as u16) >> 12) as u8) }
     }
@@ -642,6 +704,174 @@ impl Debug for Ins {
     }
 }
 
+/* ---- Self check ---------------------------------------------------------- */
+
+trait OperandBits: Copy {
+    const WIDTH: u32;
+    fn bits(self) -> u64;
+}
+
+macro_rules! operand_bits {
+    ($($type:ty: $raw:ty;)*) => {
+        $(
+            impl OperandBits for $type {
+                const WIDTH: u32 = 8*size_of::<$raw>() as u32;
+                fn bits(self) -> u64 {
+                    let raw: $raw = zerocopy::transmute!(self);
+                    raw as _
+                }
+            }
+        )*
+    };
+}
+
+operand_bits! {
+    InsId: u16;
+    PhiId: u16;
+    FuncId: u16;
+    LangOp: u16;
+    u16: u16;
+    u32: u32;
+}
+
+trait EncodeOperands {
+    fn encode(self) -> u64;
+}
+
+impl<A: OperandBits> EncodeOperands for A {
+    fn encode(self) -> u64 {
+        self.bits() << 16
+    }
+}
+
+impl<A: OperandBits, B: OperandBits> EncodeOperands for (A, B) {
+    fn encode(self) -> u64 {
+        (self.0.bits() << 16) | (self.1.bits() << (16+A::WIDTH))
+    }
+}
+
+impl<A: OperandBits, B: OperandBits, C: OperandBits> EncodeOperands for (A, B, C) {
+    fn encode(self) -> u64 {
+        (self.0.bits() << 16) | (self.1.bits() << (16+A::WIDTH))
+            | (self.2.bits() << (16+A::WIDTH+B::WIDTH))
+    }
+}
+
+fn selfcheck_opcode(op: Opcode) -> Result<(), &'static str> {
+    let operands = op.operands();
+    // layout: XX is always in bc, so it must be last, with at most one operand before it.
+    if operands.len() > 3 {
+        return Err("too many operands");
+    }
+    if let Some(i) = operands.iter().position(|&o| o == Operand::XX) {
+        if i > 1 || i+1 != operands.len() {
+            return Err("32-bit operand not in bc");
+        }
+    }
+    let (nv, nc) = (op.num_v(), op.num_c());
+    if operands[..nv].iter().any(|&o| o != Operand::V)
+        || operands[nv..nv+nc].iter().any(|&o| o != Operand::C)
+    {
+        return Err("value and control inputs must come first");
+    }
+    let flags = op.flags();
+    if flags.contains(OpFlag::CTRL) && !(flags & !OpFlag::CTRL).is_empty() {
+        return Err("control instruction with data flags");
+    }
+    if flags.contains(OpFlag::COMM) && nv != 2 {
+        return Err("commutative opcode without two value inputs");
+    }
+    if flags.contains(OpFlag::CMP) && nv != 2 {
+        return Err("comparison without two value inputs");
+    }
+    if flags.contains(OpFlag::K) && nv+nc > 0 {
+        return Err("constant with inputs");
+    }
+    // fill each slot with a distinct value and read it back through every accessor.
+    let abc = [0x1011, 0x2022, 0x3033];
+    let ins = Ins::new(op, Type::FX).set_abc(abc);
+    let mut expected = [0u64; 3];
+    for (i,&o) in operands.iter().enumerate() {
+        expected[i] = match o {
+            Operand::XX => ((abc[2] as u64) << 16) | abc[1] as u64,
+            _ => abc[i] as u64
+        };
+    }
+    if ins.operands().map(OperandData::bits).ne(expected[..operands.len()].iter().cloned()) {
+        return Err("operands() mismatch");
+    }
+    if ins.inputs().iter().map(|v| v.bits()).ne(expected[..nv].iter().cloned()) {
+        return Err("inputs() mismatch");
+    }
+    if ins.controls().iter().map(|v| v.bits()).ne(expected[nv..nv+nc].iter().cloned()) {
+        return Err("controls() mismatch");
+    }
+    let width: u32 = operands.iter().map(|&o| if o == Operand::XX { 32 } else { 16 }).sum();
+    let used = ins.0 & (((1 << width) - 1) << 16);
+    if nc > 0 && ins.decode_C() != ins.controls()[0] {
+        return Err("decode_C() mismatch");
+    }
+    if nv >= 1 && ins.decode_V() != ins.inputs()[0] {
+        return Err("decode_V() mismatch");
+    }
+    if nv >= 2 && ins.decode_VV() != (ins.inputs()[0], ins.inputs()[1]) {
+        return Err("decode_VV() mismatch");
+    }
+    if let Some(i) = operands.iter().position(|&o| o == Operand::L) {
+        if expected[i] != ins.decode_L().bits() {
+            return Err("decode_L() mismatch");
+        }
+    }
+    if (Opcode::CALLC | Opcode::CALLCI).contains(op)
+        && ins.decode_CALLC().encode() != used
+    {
+        return Err("decode_CALLC() mismatch");
+    }
+    if let Some(bits) = ins.selfcheck_decoder() {
+        if bits != used {
+            return Err("named decoder mismatch");
+        }
+    }
+    // writes through the mutable accessors must land in the right operand and leave the
+    // opcode and type alone.
+    let mut mins = ins;
+    let flip = |v: InsId| -> InsId { zerocopy::transmute!(!(v.bits() as u16)) };
+    for v in mins.inputs_and_controls_mut() { *v = flip(*v); }
+    for e in &mut expected[..nv+nc] { *e = !*e & 0xffff; }
+    let mut mins2 = ins;
+    for v in mins2.inputs_mut() { *v = flip(*v); }
+    for v in mins2.controls_mut() { *v = flip(*v); }
+    if mins != mins2 {
+        return Err("inputs_mut()/controls_mut() disagree with inputs_and_controls_mut()");
+    }
+    let has_phi = operands.contains(&Operand::P);
+    match mins.phi_mut() {
+        Some(phi) if has_phi => {
+            let i = operands.iter().position(|&o| o == Operand::P).unwrap();
+            *phi = zerocopy::transmute!(!(phi.bits() as u16));
+            expected[i] = !expected[i] & 0xffff;
+        },
+        None if !has_phi => {},
+        _ => return Err("phi_mut() mismatch")
+    }
+    if mins.opcode() != op || mins.type_() != Type::FX {
+        return Err("mutable accessor clobbered opcode or type");
+    }
+    if mins.operands().map(OperandData::bits).ne(expected[..operands.len()].iter().cloned()) {
+        return Err("mutable accessor wrote to the wrong operand");
+    }
+    Ok(())
+}
+
+// check that the operand encoding of every opcode round-trips through its accessors.
+// encoding bugs otherwise show up as very confusing optimizer behavior.
+pub fn selfcheck() -> Result<(), (Opcode, &'static str)> {
+    for op in EnumSet::<Opcode>::all() {
+        selfcheck_opcode(op).map_err(|e| (op, e))?;
+    }
+    Ok(())
+}
+
 /* ---- Instruction matching ------------------------------------------------ */
 
 // instruction matching mini-language:
@@ -649,6 +879,18 @@ impl Debug for Ins {
 //   `const`               matches constants
 //   number                matches small integer constants (KINT)
 //   (opcode [pattern]*)   matches instructions
+//   (opcode:ty [pattern]*) matches instructions of type `ty` (`int`, `fp`, or a `Type` name)
+//   {x}                   matches anything and binds the operand's InsId to `x`
+//   {x: pattern}          matches `pattern` and binds the operand's InsId to `x`
+// bound variables must be declared `mut` by the caller, or use `ins_match!`, which declares them
+// and returns them if the pattern matches:
+//   ins_match!(code, ins, |x, k| ADD:int {x} {k: const}) -> Option<(InsId, InsId)>
+
+macro_rules! type_matches {
+    ($type:expr; int) => { $type.is_int() };
+    ($type:expr; fp) => { $type.is_fp() };
+    ($type:expr; $ty:ident) => { $type == $crate::ir::Type::$ty };
+}
 
 macro_rules! value_matches {
     ($code:expr, $value:expr; _) => {
@@ -669,26 +911,68 @@ macro_rules! value_matches {
             $crate::ir::ins_matches!($code, ins; $($t)*)
         }
     };
+    ($code:expr, $value:expr; {$x:ident}) => {
+        {
+            $x = zerocopy::transmute!($value);
+            true
+        }
+    };
+    ($code:expr, $value:expr; {$x:ident : $p:tt}) => {
+        $crate::ir::value_matches!($code, $value; $p) && {
+            $x = zerocopy::transmute!($value);
+            true
+        }
+    };
 }
 
 macro_rules! ins_matches {
+    ($code:expr, $ins:expr; _) => {
+        true
+    };
+    ($code:expr, $ins:expr; _ : $ty:tt $($rest:tt)*) => {
+        $crate::ir::type_matches!($ins.type_(); $ty)
+            && $crate::ir::ins_matches!($code, $ins; _ $($rest)*)
+    };
     ($code:expr, $ins:expr; _ $a:tt $($b:tt $($c:tt)? )? ) => {
         $crate::ir::value_matches!($code, $ins.a(); $a)
             $( && $crate::ir::value_matches!($code, $ins.b(); $b)
                 $( && $crate::ir::value_matches!($code, $ins.c(); $c) )?)?
     };
-    ($code:expr, $ins:expr; $opcode:tt $( $a:tt $($rest:tt)* )? ) => {
+    ($code:expr, $ins:expr; $opcode:tt $(: $ty:tt)? $( $a:tt $($rest:tt)* )? ) => {
         {
             #[allow(unused_imports)]
             use $crate::ir::Opcode::*;
             let opcode: enumset::EnumSet<$crate::ir::Opcode> = $opcode.into();
             opcode.contains($ins.opcode())
+                $( && $crate::ir::type_matches!($ins.type_(); $ty) )?
                 $( && $crate::ir::ins_matches!($code, $ins; _ $a $($rest)*) )?
         }
     };
 }
 
-pub(crate) use {ins_matches, value_matches};
+macro_rules! ins_match {
+    (@decl $($x:ident)+) => {
+        $(
+            #[allow(unused_mut)]
+            let mut $x: $crate::ir::InsId =
+                <$crate::ir::InsId as $crate::index::InvalidValue>::INVALID.into();
+        )+
+    };
+    ($code:expr, $ins:expr, |$x:ident| $($p:tt)*) => {
+        {
+            $crate::ir::ins_match!(@decl $x);
+            if $crate::ir::ins_matches!($code, $ins; $($p)*) { Some($x) } else { None }
+        }
+    };
+    ($code:expr, $ins:expr, |$($x:ident),+| $($p:tt)*) => {
+        {
+            $crate::ir::ins_match!(@decl $($x)+);
+            if $crate::ir::ins_matches!($code, $ins; $($p)*) { Some(($($x),+)) } else { None }
+        }
+    };
+}
+
+pub(crate) use {ins_match, ins_matches, type_matches, value_matches};
 
 /* ---- IR ------------------------------------------------------------------ */
 
@@ -729,7 +1013,7 @@ pub enum DebugFlag {
 // +--------+-------+------+
 // | objref | value | init |
 // +--------+-------+------+
-#[derive(Clone, Copy, Default)]
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
 pub struct DebugSource(u32);
 
 pub struct Func {
@@ -740,7 +1024,10 @@ pub struct Func {
     pub phis: IndexValueVec<PhiId, Phi>,
     pub kind: FuncKind,
     pub reset: ResetSet,
-    pub source: DebugSource
+    pub source: DebugSource,
+    // source expression of the instructions, sorted by instruction: instructions from `.0` up
+    // to the next entry were lowered from `.1`. NIL if not known.
+    pub spans: Vec<(InsId, ObjRef<EXPR>)>
 }
 
 #[derive(Default)]
@@ -767,7 +1054,16 @@ impl Func {
             ret: 0.into(),
             arg: 0.into(),
             reset: ResetSet::default() | ResetId::GLOBAL,
-            source
+            source,
+            spans: Default::default()
+        }
+    }
+
+    // source expression of `id`, see `Func::spans`.
+    pub fn span(&self, id: InsId) -> ObjRef<EXPR> {
+        match self.spans.partition_point(|&(start, _)| start <= id) {
+            0 => ObjRef::NIL.cast(),
+            i => self.spans[i-1].1
         }
     }
 
@@ -825,6 +1121,15 @@ impl DebugSource {
         unsafe { EnumSet::from_repr_unchecked((self.0 & 3) as _) }
     }
 
+    // for serialization
+    pub fn raw(self) -> u32 {
+        self.0
+    }
+
+    pub fn from_raw(raw: u32) -> Self {
+        Self(raw)
+    }
+
 }
 
 pub struct Returns;
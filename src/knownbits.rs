@@ -0,0 +1,213 @@
+//! Known-bits analysis of integer IR values.
+
+// for each bit of an integer value, the analysis tracks whether it's known to be zero, known to
+// be one, or unknown. it's computed on demand, looking through a bounded number of instructions,
+// so that fold can ask about any value without a separate pass.
+//
+// bits above the width of the type are never known.
+
+use zerocopy::Unalign;
+
+use crate::bump::BumpRef;
+use crate::index::IndexVec;
+use crate::intern::Intern;
+use crate::ir::{Ins, InsId, Opcode, Type};
+
+// how many instructions deep to look. the result is only ever less precise when this is hit.
+const MAX_DEPTH: u32 = 6;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct KnownBits {
+    pub zero: u64,
+    pub one: u64
+}
+
+fn width(ty: Type) -> u32 {
+    8 * ty.size() as u32
+}
+
+fn mask(ty: Type) -> u64 {
+    !0 >> (64 - width(ty))
+}
+
+impl KnownBits {
+
+    pub const UNKNOWN: Self = Self { zero: 0, one: 0 };
+
+    pub fn constant(ty: Type, value: u64) -> Self {
+        let m = mask(ty);
+        Self { zero: !value & m, one: value & m }
+    }
+
+    pub fn is_constant(self, ty: Type) -> bool {
+        (self.zero | self.one) == mask(ty)
+    }
+
+    // (known) value as a sign-extended integer.
+    pub fn value(self, ty: Type) -> i64 {
+        let shift = 64 - width(ty);
+        ((self.one << shift) as i64) >> shift
+    }
+
+    // bits known to be the same in both
+    fn common(self, other: Self) -> Self {
+        Self { zero: self.zero & other.zero, one: self.one & other.one }
+    }
+
+    pub fn trailing_zeros(self) -> u32 {
+        self.zero.trailing_ones()
+    }
+
+    // leading zeros within the width of `ty`
+    pub fn leading_zeros(self, ty: Type) -> u32 {
+        (self.zero << (64 - width(ty))).leading_ones()
+    }
+
+    pub fn umin(self) -> u64 {
+        self.one
+    }
+
+    pub fn umax(self, ty: Type) -> u64 {
+        !self.zero & mask(ty)
+    }
+
+    pub fn smin(self, ty: Type) -> i64 {
+        let sign = 1 << (width(ty) - 1);
+        let v = match self.zero & sign {
+            0 => self.one | sign,
+            _ => self.one
+        };
+        Self { zero: 0, one: v }.value(ty)
+    }
+
+    pub fn smax(self, ty: Type) -> i64 {
+        let sign = 1 << (width(ty) - 1);
+        let v = match self.one & sign {
+            0 => self.umax(ty) & !sign,
+            _ => self.umax(ty)
+        };
+        Self { zero: 0, one: v }.value(ty)
+    }
+
+    // the low `n` bits are zero
+    fn low_zeros(ty: Type, n: u32) -> Self {
+        Self { zero: mask(ty) & !(!0u64).checked_shl(n).unwrap_or(0), one: 0 }
+    }
+
+    // the high `n` bits (within the type) are zero
+    fn high_zeros(ty: Type, n: u32) -> Self {
+        let n = n.min(width(ty));
+        Self { zero: mask(ty) & !(mask(ty) >> n), one: 0 }
+    }
+
+}
+
+// a+b+carry
+fn add(ty: Type, a: KnownBits, b: KnownBits, carry: bool) -> KnownBits {
+    let m = mask(ty);
+    let maxsum = (!a.zero & m).wrapping_add(!b.zero & m).wrapping_add(carry as u64);
+    let minsum = a.one.wrapping_add(b.one).wrapping_add(carry as u64);
+    let carry_zero = !(maxsum ^ a.zero ^ b.zero);
+    let carry_one = minsum ^ a.one ^ b.one;
+    let known = (a.zero | a.one) & (b.zero | b.one) & (carry_zero | carry_one) & m;
+    KnownBits { zero: !maxsum & known, one: minsum & known }
+}
+
+fn not(ty: Type, a: KnownBits) -> KnownBits {
+    KnownBits { zero: a.one, one: a.zero & mask(ty) }
+}
+
+fn kint(intern: &Intern, ins: Ins) -> u64 {
+    match ins.opcode() {
+        Opcode::KINT => ins.bc() as i32 as i64 as _,
+        Opcode::KINT64 => {
+            let data: BumpRef<Unalign<i64>> = zerocopy::transmute!(ins.bc());
+            intern.bump()[data].get() as _
+        },
+        _ => unreachable!()
+    }
+}
+
+fn known(code: &IndexVec<InsId, Ins>, intern: &Intern, ins: Ins, depth: u32) -> KnownBits {
+    use Opcode::*;
+    let ty = ins.type_();
+    if !ty.is_int() || depth > MAX_DEPTH {
+        return KnownBits::UNKNOWN;
+    }
+    let input = |id: InsId| known(code, intern, code[id], depth+1);
+    match ins.opcode() {
+        KINT|KINT64 => KnownBits::constant(ty, kint(intern, ins)),
+        MOV => input(ins.decode_V()),
+        ADD => {
+            let (a, b) = ins.decode_VV();
+            add(ty, input(a), input(b), false)
+        },
+        SUB => {
+            let (a, b) = ins.decode_VV();
+            add(ty, input(a), not(ty, input(b)), true)
+        },
+        NEG => add(ty, KnownBits::constant(ty, 0), not(ty, input(ins.decode_V())), true),
+        MUL => {
+            let (a, b) = ins.decode_VV();
+            let (a, b) = (input(a), input(b));
+            if a.is_constant(ty) && b.is_constant(ty) {
+                return KnownBits::constant(ty, a.one.wrapping_mul(b.one));
+            }
+            KnownBits::low_zeros(ty, a.trailing_zeros() + b.trailing_zeros())
+        },
+        USHR => {
+            let (a, b) = ins.decode_VV();
+            let (a, b) = (input(a), input(b));
+            if b.is_constant(ty) && b.one < width(ty) as u64 {
+                let k = b.one as u32;
+                let m = mask(ty);
+                return KnownBits { zero: ((a.zero >> k) | !(m >> k)) & m, one: a.one >> k };
+            }
+            KnownBits::high_zeros(ty, a.leading_zeros(ty))
+        },
+        UDIV => {
+            let (a, _) = ins.decode_VV();
+            KnownBits::high_zeros(ty, input(a).leading_zeros(ty))
+        },
+        SELECT => {
+            let (_, a, b) = ins.decode_SELECT();
+            input(a).common(input(b))
+        },
+        _ => KnownBits::UNKNOWN
+    }
+}
+
+// known bits of `ins`, whose inputs are in `code`. `ins` itself doesn't need to be in `code`.
+pub fn knownbits(code: &IndexVec<InsId, Ins>, intern: &Intern, ins: Ins) -> KnownBits {
+    known(code, intern, ins, 0)
+}
+
+// integer comparison `a op b` decided by the known bits of the operands, if possible.
+pub fn knowncmp(
+    code: &IndexVec<InsId, Ins>,
+    intern: &Intern,
+    op: Opcode,
+    a: InsId,
+    b: InsId
+) -> Option<bool> {
+    use Opcode::*;
+    let ty = code[a].type_();
+    let (a, b) = (knownbits(code, intern, code[a]), knownbits(code, intern, code[b]));
+    if a == KnownBits::UNKNOWN && b == KnownBits::UNKNOWN {
+        return None;
+    }
+    let differ = ((a.one & b.zero) | (a.zero & b.one)) != 0;
+    match op {
+        EQ if differ => Some(false),
+        NE if differ => Some(true),
+        ULT if a.umax(ty) < b.umin() => Some(true),
+        ULT if a.umin() >= b.umax(ty) => Some(false),
+        ULE if a.umax(ty) <= b.umin() => Some(true),
+        ULE if a.umin() > b.umax(ty) => Some(false),
+        LT if a.smax(ty) < b.smin(ty) => Some(true),
+        LT if a.smin(ty) >= b.smax(ty) => Some(false),
+        LE if a.smax(ty) <= b.smin(ty) => Some(true),
+        LE if a.smin(ty) > b.smax(ty) => Some(false),
+        _ => None
+    }
+}
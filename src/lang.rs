@@ -1,16 +1,18 @@
 //! Language support.
 
-use core::iter::zip;
-use core::mem::MaybeUninit;
-use core::ptr::NonNull;
+#[cfg(not(feature="checked"))]
+use core::{iter::zip, mem::MaybeUninit, ptr::NonNull};
 
+#[cfg(not(feature="checked"))]
 use alloc::boxed::Box;
+#[cfg(feature="checked")]
+use alloc::vec::Vec;
 use enumset::EnumSet;
 
 use crate::compile::{self, Ccx};
 use crate::emit::{Ecx, Emit, InsValue};
 use crate::foreach_lang;
-use crate::ir::{Func, InsId};
+use crate::ir::{Func, Ins, InsId};
 use crate::lower::CLcx;
 use crate::obj::{ObjRef, CALLX};
 use crate::parser::Pcx;
@@ -22,6 +24,17 @@ pub trait Language: Sized {
     fn emit(ecx: &mut Ecx, id: InsId, lop: u8) -> compile::Result<InsValue>;
     #[allow(unused_variables)]
     fn finish_emit(self, ccx: &mut Ccx<Emit>) -> compile::Result { Ok(()) }
+    // evaluate a pure call at compile time. this is only asked for calls with constant inputs
+    // and a single scalar output. returning None leaves the call to `lower`.
+    #[allow(unused_variables)]
+    fn fold(lcx: &mut CLcx, obj: ObjRef<CALLX>, inputs: &[Ins]) -> Option<Ins> { None }
+}
+
+// implemented for each language state type by `define_langs`.
+pub trait LangType: Language {
+    const LANG: Lang;
+    // safety: `any` must hold a `Self`
+    unsafe fn downcast(any: &mut AnyLang) -> &mut Self;
 }
 
 macro_rules! define_langs {
@@ -57,26 +70,79 @@ macro_rules! define_langs {
                 }
             }
 
+            // every builtin language, and whether it's compiled into this build.
+            pub const BUILTIN: &[(&str, bool)] = &[
+                $(
+                    (stringify!($name), {
+                        #[allow(unused_mut)]
+                        let mut on = false;
+                        $(#[$($meta)*])?
+                        { on = true; }
+                        on
+                    }),
+                )*
+            ];
+
         }
 
+        #[cfg(not(feature="checked"))]
         #[repr(C)]
-        union AnyLang {
+        pub union AnyLang {
             $(
                 $(#[$($meta)*])?
                 $name: core::mem::ManuallyDrop<$crate::$module::$name>,
             )*
         }
 
-        impl LangState {
+        #[cfg(feature="checked")]
+        pub enum AnyLang {
             $(
                 $(#[$($meta)*])?
-                #[allow(dead_code)]
-                pub fn $name(&mut self) -> &mut $crate::$module::$name {
-                    unsafe { &mut self.get_mut(Lang::$name).$name }
-                }
+                $name($crate::$module::$name),
             )*
         }
 
+        $(
+            $(#[$($meta)*])?
+            #[cfg(feature="checked")]
+            impl From<$crate::$module::$name> for AnyLang {
+                fn from(l: $crate::$module::$name) -> Self {
+                    Self::$name(l)
+                }
+            }
+        )*
+
+        #[cfg(feature="checked")]
+        impl AnyLang {
+            fn finish(self, ccx: &mut Ccx<Emit>) -> compile::Result {
+                match self {
+                    $(
+                        $(#[$($meta)*])?
+                        Self::$name(l) => l.finish_emit(ccx),
+                    )*
+                }
+            }
+        }
+
+        $(
+            $(#[$($meta)*])?
+            impl LangType for $crate::$module::$name {
+                const LANG: Lang = Lang::$name;
+                #[cfg(not(feature="checked"))]
+                unsafe fn downcast(any: &mut AnyLang) -> &mut Self {
+                    unsafe { &mut any.$name }
+                }
+                #[cfg(feature="checked")]
+                unsafe fn downcast(any: &mut AnyLang) -> &mut Self {
+                    match any {
+                        AnyLang::$name(l) => l,
+                        #[allow(unreachable_patterns)]
+                        _ => unreachable!()
+                    }
+                }
+            }
+        )*
+
         macro_rules! dispatch {
             ($discrim:expr, $lang:ty => $value:expr) => {
                 match $discrim {
@@ -119,14 +185,22 @@ impl Lang {
         dispatch!(self, Lang => Lang::emit(ecx, id, lop))
     }
 
+    pub fn fold(self, lcx: &mut CLcx, obj: ObjRef<CALLX>, inputs: &[Ins]) -> Option<Ins> {
+        dispatch!(self, Lang => Lang::fold(lcx, obj, inputs))
+    }
+
 }
 
+/* ---- Language state ------------------------------------------------------ */
+
 // note: this intentionally does *not* implement Drop. you "drop" it by calling `finish`.
+#[cfg(not(feature="checked"))]
 pub struct LangState {
     present: EnumSet<Lang>,
     data: NonNull<AnyLang>
 }
 
+#[cfg(not(feature="checked"))]
 impl Default for LangState {
     fn default() -> Self {
         Self {
@@ -136,6 +210,7 @@ impl Default for LangState {
     }
 }
 
+#[cfg(not(feature="checked"))]
 impl LangState {
 
     pub fn new(ccx: &mut Ccx, langs: EnumSet<Lang>) -> compile::Result<Self> {
@@ -180,3 +255,50 @@ impl LangState {
     }
 
 }
+
+// safe version of the above for running under miri and sanitizers.
+#[cfg(feature="checked")]
+#[derive(Default)]
+pub struct LangState {
+    present: EnumSet<Lang>,
+    data: Vec<AnyLang>
+}
+
+#[cfg(feature="checked")]
+impl LangState {
+
+    pub fn new(ccx: &mut Ccx, langs: EnumSet<Lang>) -> compile::Result<Self> {
+        let mut data = Vec::with_capacity(langs.len());
+        for lang in langs {
+            dispatch!(lang, Lang => data.push(Lang::begin_emit(ccx)?.into()));
+        }
+        Ok(Self { present: langs, data })
+    }
+
+    pub fn finish(self, ccx: &mut Ccx<Emit>) -> compile::Result {
+        let mut result = Ok(());
+        for l in self.data {
+            result = result.and(l.finish(ccx));
+        }
+        result
+    }
+
+    fn get_mut(&mut self, lang: Lang) -> &mut AnyLang {
+        assert!(self.present.contains(lang));
+        let idx = (self.present.as_u64_truncated() & ((1 << lang as u8) - 1)).count_ones();
+        &mut self.data[idx as usize]
+    }
+
+}
+
+impl LangState {
+
+    pub fn get<L: LangType>(&mut self) -> Option<&mut L> {
+        match self.present.contains(L::LANG) {
+            // safety: present languages are stored in their own slot
+            true => Some(unsafe { L::downcast(self.get_mut(L::LANG)) }),
+            false => None
+        }
+    }
+
+}
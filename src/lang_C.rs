@@ -2,17 +2,22 @@
 
 use core::cmp::{max, Ordering};
 use core::iter::{repeat_n, zip};
+use core::mem::transmute;
 
+use alloc::ffi::CString;
+use alloc::vec::Vec;
 use cranelift_codegen::ir::{AbiParam, InstBuilder, Signature};
 use enumset::EnumSetType;
+use zerocopy::Unalign;
 
 use crate::bitmap::BitmapWord;
 use crate::bump::{BumpPtr, BumpRef, BumpVec};
 use crate::compile::{self, Ccx};
-use crate::emit::{cast_values, collectargs, irt2cl, Ecx, InsValue, NATIVE_CALLCONV};
+use crate::dl::{self, LibBox};
+use crate::emit::{cast_values, collectargs, irt2cl, Ecx, Emit, InsValue, NATIVE_CALLCONV};
 use crate::index::InvalidValue;
-use crate::intern::IRef;
-use crate::ir::{Func, Ins, InsId, LangOp, Type};
+use crate::intern::{IRef, Intern};
+use crate::ir::{Func, Ins, InsId, LangOp, Opcode, Type};
 use crate::lang::{Lang, Language};
 use crate::lex::Token;
 use crate::lower::CLcx;
@@ -26,7 +31,10 @@ const LOP_CCALL: u8 = 1;
 const LOP_CRES:  u8 = 2;
 
 #[derive(Default)]
-pub struct C;
+pub struct C {
+    // libraries opened for symbol calls, by name. empty name is the running process.
+    libs: Vec<(IRef<[u8]>, LibBox)>
+}
 
 macro_rules! define_primitives {
     ($($name:ident $irt:ident $($cname:literal)*;)*) => {
@@ -563,7 +571,8 @@ fn collect_call(pcx: &mut Pcx, ps: &ParseState) -> ObjRef<CALLX> {
     };
     // TODO: nonscalar out parameters need annotations
     pcx.objs.push_args(
-        CALLX::new(Lang::C as _, ObjRef::NIL, zerocopy::transmute!(call)),
+        CALLX::new(Lang::C as _, ObjRef::NIL, zerocopy::transmute!(call), CALLX::FX_ANY,
+            ObjRef::NIL.cast()),
         ps.inputs.as_slice(&pcx.tmp)
     )
 }
@@ -726,23 +735,127 @@ fn lower_call(
     outbase
 }
 
+/* ---- Folding ------------------------------------------------------------- */
+
+fn kfpvalue(intern: &Intern, ins: Ins) -> Option<f64> {
+    match ins.opcode() {
+        Opcode::KINT => Some(ins.bc() as i32 as _),
+        Opcode::KINT64 => {
+            let data: BumpRef<Unalign<i64>> = zerocopy::transmute!(ins.bc());
+            Some(intern.bump()[data].get() as _)
+        },
+        Opcode::KFP64 => {
+            let data: BumpRef<Unalign<f64>> = zerocopy::transmute!(ins.bc());
+            Some(intern.bump()[data].get())
+        },
+        _ => None
+    }
+}
+
+// pure calls of `double f(double, ...)` symbols are evaluated here, anything else is left
+// for runtime.
+fn fold_call(lcx: &mut CLcx, obj: ObjRef<CALLX>, inputs: &[Ins]) -> Option<Ins> {
+    let call: BumpRef<Call> = zerocopy::transmute!(lcx.objs[obj].func);
+    let call = &lcx.perm[call];
+    let func = &lcx.intern[call.func];
+    if func.what != CFunc::SYM || call.nstore > 0 || call.size > 0 || call.nout != 1
+        || func.ret.to_ir() != Type::F64 || lcx.perm[call.outputs].tag != Output::RETURN
+    {
+        return None;
+    }
+    let argv = &lcx.perm[call.args..call.args.offset(call.narg as _)];
+    let mut args = [0.0; 3];
+    if argv.len() > args.len() {
+        return None;
+    }
+    for (a, (&v, &ct)) in zip(&mut args, zip(argv, lcx.intern.get_slice(func.args))) {
+        let (idx, tag) = v.unpack();
+        if tag != TAG_INPUT || ct.to_ir() != Type::F64 {
+            return None;
+        }
+        *a = kfpvalue(&lcx.intern, inputs[idx as usize])?;
+    }
+    let &CDynFunc { lib, sym, .. } = &lcx.intern[call.func.cast()];
+    let lib = match lcx.intern.get_slice(lib) {
+        [] => dl::this()?,
+        name => dl::open(CString::new(name).ok()?.as_bytes_with_nul())?
+    };
+    let ptr = lib.sym(&CString::new(lcx.intern.get_slice(sym)).ok()?);
+    if ptr.is_null() {
+        return None;
+    }
+    let value = unsafe {
+        match argv.len() {
+            0 => transmute::<_, extern "C" fn() -> f64>(ptr)(),
+            1 => transmute::<_, extern "C" fn(f64) -> f64>(ptr)(args[0]),
+            2 => transmute::<_, extern "C" fn(f64, f64) -> f64>(ptr)(args[0], args[1]),
+            _ => transmute::<_, extern "C" fn(f64, f64, f64) -> f64>(ptr)(args[0], args[1], args[2])
+        }
+    };
+    Some(Ins::KFP64(Type::F64,
+        zerocopy::transmute!(lcx.intern.intern(&value.to_ne_bytes()).to_bump())))
+}
+
 /* ---- Emitting ------------------------------------------------------------ */
 
-fn emit_call(ecx: &mut Ecx, id: InsId) -> InsValue {
+fn loadlib<'a>(
+    libs: &'a mut Vec<(IRef<[u8]>, LibBox)>,
+    name: IRef<[u8]>,
+    bytes: &[u8]
+) -> Option<&'a LibBox> {
+    let idx = match libs.iter().position(|&(n,_)| n == name) {
+        Some(idx) => idx,
+        None => {
+            let lib = match bytes.is_empty() {
+                true => dl::this()?,
+                false => dl::open(CString::new(bytes).ok()?.as_bytes_with_nul())?
+            };
+            libs.push((name, lib));
+            libs.len()-1
+        }
+    };
+    Some(&libs[idx].1)
+}
+
+fn loadsym(ecx: &mut Ecx, func: IRef<CDynFunc>) -> compile::Result<i64> {
     let emit = &mut *ecx.data;
-    let (mut args, cf) = emit.code[id].decode_VV();
-    let func: &CFunc = &ecx.intern[zerocopy::transmute!(emit.code[cf].bc())];
-    let ptr = match func.what {
+    let &CDynFunc { lib, sym, .. } = &ecx.intern[func];
+    let libname = ecx.intern.get_slice(lib);
+    let symname = ecx.intern.get_slice(sym);
+    let Some(l) = loadlib(&mut emit.lang.get::<C>().unwrap().libs, lib, libname) else {
+        ecx.host.buf.write(b"failed to load library: ");
+        ecx.host.buf.write(libname);
+        return Err(());
+    };
+    let ptr = match CString::new(symname) {
+        Ok(s) => l.sym(&s),
+        Err(_) => core::ptr::null_mut()
+    };
+    if ptr.is_null() {
+        ecx.host.buf.write(b"symbol not found: ");
+        ecx.host.buf.write(symname);
+        return Err(());
+    }
+    Ok(ptr as i64)
+}
+
+fn emit_call(ecx: &mut Ecx, id: InsId) -> compile::Result<InsValue> {
+    ecx.mcode.hostptr = true;
+    let (mut args, cf) = ecx.data.code[id].decode_VV();
+    let fref: IRef<CFunc> = zerocopy::transmute!(ecx.data.code[cf].bc());
+    let ptr = match ecx.intern[fref].what {
         CFunc::PTR => {
-            let (ap, ptr) = emit.code[args].decode_CARG();
+            let (ap, ptr) = ecx.data.code[args].decode_CARG();
             args = ap;
-            ptr
+            ecx.data.values[ptr].value()
         },
         _ /* SYM */ => {
-            // TODO: load dynamic sym
-            todo!()
+            let ptr = loadsym(ecx, fref.cast())?;
+            ecx.data.fb.ins().iconst(irt2cl(Type::PTR), ptr)
         }
     };
+    let emit = &mut *ecx.data;
+    let func = &ecx.intern[fref];
     let mut sig = Signature::new(NATIVE_CALLCONV);
     sig.params.extend(
         ecx.intern.get_slice(func.args)
@@ -754,9 +867,9 @@ fn emit_call(ecx: &mut Ecx, id: InsId) -> InsValue {
     let argv = ecx.tmp.align_for::<InsValue>();
     let argbase = argv.end();
     collectargs(emit, argv, args);
-    InsValue::from_cl_inst(
-        emit.fb.ins().call_indirect(sig, emit.values[ptr].value(), cast_values(&argv[argbase..]))
-    )
+    Ok(InsValue::from_cl_inst(
+        emit.fb.ins().call_indirect(sig, ptr, cast_values(&argv[argbase..]))
+    ))
 }
 
 fn emit_res(ecx: &mut Ecx, id: InsId) -> InsValue {
@@ -815,12 +928,25 @@ impl Language for C {
         Ok(Default::default())
     }
 
+    fn fold(lcx: &mut CLcx, obj: ObjRef<CALLX>, inputs: &[Ins]) -> Option<Ins> {
+        fold_call(lcx, obj, inputs)
+    }
+
     fn emit(ecx: &mut Ecx, id: InsId, lop: u8) -> compile::Result<InsValue> {
-        Ok(match lop {
+        match lop {
             LOP_CCALL => emit_call(ecx, id),
-            LOP_CRES  => emit_res(ecx, id),
+            LOP_CRES  => Ok(emit_res(ecx, id)),
             _ => unreachable!()
-        })
+        }
+    }
+
+    fn finish_emit(self, ccx: &mut Ccx<Emit>) -> compile::Result {
+        // compiled code holds raw symbol addresses, keep the libraries loaded as long as the
+        // image lives.
+        for (_, lib) in self.libs {
+            ccx.fin.push(lib);
+        }
+        Ok(())
     }
 
 }
@@ -0,0 +1,278 @@
+//! Host functions.
+//!
+//! Native functions registered by the embedder. In a model they are called either like builtin
+//! functions, `f(x, y)`, or explicitly, `call Host["f"] (x, y)`.
+
+use core::any::Any;
+use core::ffi::c_void;
+
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use cranelift_codegen::ir::{AbiParam, InstBuilder, Signature};
+
+use crate::bump::BumpRef;
+use crate::compile::{self, Ccx};
+use crate::emit::{cast_values, collectargs, irt2cl, Ecx, Emit, InsValue, NATIVE_CALLCONV};
+use crate::err::ErrorMessage;
+use crate::intern::IRef;
+use crate::ir::{Func, Ins, InsId, LangOp, Type};
+use crate::lang::{Lang, Language};
+use crate::lex::Token;
+use crate::lower::CLcx;
+use crate::obj::{ObjRef, CALLX, EXPR, TPRI};
+use crate::parse::parse_expr;
+use crate::parser::{check, consume, next, require, syntaxerr, Pcx};
+use crate::typing::Primitive;
+
+pub struct HostFunc {
+    pub name: Box<[u8]>,
+    pub params: Box<[Primitive]>,
+    pub ret: Primitive,
+    // called as `ret func(data, params...)` with the native calling convention
+    pub func: *const c_void,
+    pub data: *const c_void,
+    // owner of `data`, moved to the image finalizers so that it outlives the compiled code
+    pub keep: Option<Rc<dyn Any>>,
+    // calls without an effect annotation are pure
+    pub pure: bool
+}
+
+impl HostFunc {
+
+    // safety: `func` must have the signature described by `params` and `ret`, and `data` must
+    // stay valid as long as any image that calls it.
+    pub unsafe fn new(
+        name: &[u8],
+        params: &[Primitive],
+        ret: Primitive,
+        func: *const c_void,
+        data: *const c_void
+    ) -> Self {
+        Self {
+            name: name.into(),
+            params: params.into(),
+            ret,
+            func,
+            data,
+            keep: None,
+            pure: false
+        }
+    }
+
+    // a rust closure, called through a trampoline that gets the closure as `data`.
+    pub fn closure<F, Args>(name: &[u8], f: F) -> Self
+        where F: HostClosure<Args>
+    {
+        let f = Rc::new(f);
+        Self {
+            name: name.into(),
+            params: F::PARAMS.into(),
+            ret: F::RET,
+            func: F::trampoline(),
+            data: Rc::as_ptr(&f).cast(),
+            keep: Some(f),
+            pure: false
+        }
+    }
+
+}
+
+pub trait HostValue: Copy + 'static {
+    const PRI: Primitive;
+}
+
+macro_rules! host_value {
+    ($($ty:ty => $pri:ident;)*) => {
+        $( impl HostValue for $ty { const PRI: Primitive = Primitive::$pri; } )*
+    };
+}
+
+host_value! {
+    f64 => F64;
+    f32 => F32;
+    i64 => I64;
+    i32 => I32;
+    i16 => I16;
+    i8  => I8;
+    u64 => U64;
+    u32 => U32;
+    u16 => U16;
+    u8  => U8;
+}
+
+// implemented for rust closures taking up to 4 scalar parameters.
+pub trait HostClosure<Args>: 'static {
+    const PARAMS: &'static [Primitive];
+    const RET: Primitive;
+    fn trampoline() -> *const c_void;
+}
+
+macro_rules! host_closure {
+    ($($a:ident)*) => {
+        impl<F, R, $($a),*> HostClosure<($($a,)*)> for F
+            where F: Fn($($a),*) -> R + 'static,
+                  R: HostValue,
+                  $($a: HostValue),*
+        {
+            const PARAMS: &'static [Primitive] = &[$(<$a as HostValue>::PRI),*];
+            const RET: Primitive = R::PRI;
+            fn trampoline() -> *const c_void {
+                #[allow(non_snake_case)]
+                extern "C" fn call<F, R, $($a),*>(data: *const c_void, $($a: $a),*) -> R
+                    where F: Fn($($a),*) -> R
+                {
+                    unsafe { (*data.cast::<F>())($($a),*) }
+                }
+                call::<F, R, $($a),*> as *const c_void
+            }
+        }
+    };
+}
+
+host_closure!();
+host_closure!(A);
+host_closure!(A B);
+host_closure!(A B C);
+host_closure!(A B C D);
+
+#[derive(Default)]
+pub struct Host;
+
+const LOP_CALL: u8 = 0;
+const LOP_RES: u8 = 1;
+
+/* ---- Parsing ------------------------------------------------------------- */
+
+// args are on pcx.tmp starting from `base`.
+pub fn newcall(pcx: &mut Pcx, idx: usize, base: BumpRef<u8>) -> compile::Result<ObjRef<CALLX>> {
+    let args: &[ObjRef<EXPR>] = &pcx.tmp[base.cast_up()..];
+    let func = &pcx.session.hostfuncs[idx];
+    if args.len() != func.params.len() {
+        return syntaxerr(pcx, ErrorMessage::HostCallArity);
+    }
+    for (&arg, &pri) in args.iter().zip(&func.params) {
+        if pcx.objs[arg].ann.is_nil() {
+            let ann = pcx.objs.push(TPRI::new(pri as _)).erase();
+            pcx.objs[arg].ann = ann;
+        }
+    }
+    let ann = pcx.objs.push(TPRI::new(func.ret as _)).erase();
+    let fx = match func.pure {
+        true => CALLX::FX_PURE,
+        false => CALLX::FX_ANY
+    };
+    Ok(pcx.objs.push_args(
+        CALLX::new(Lang::Host as _, ann, idx as _, fx, ObjRef::NIL.cast()),
+        &pcx.tmp[base.cast_up()..]
+    ))
+}
+
+fn parse_call(pcx: &mut Pcx, n: usize) -> compile::Result<ObjRef<CALLX>> {
+    consume(pcx, Token::LBracket)?;
+    require(pcx, Token::Literal)?;
+    let name: IRef<[u8]> = zerocopy::transmute!(pcx.data.tdata);
+    let name = pcx.intern.get_slice(name);
+    let Some(idx) = pcx.session.hostfuncs.iter().position(|f| &*f.name == name) else {
+        return syntaxerr(pcx, ErrorMessage::UndefHostFunc);
+    };
+    next(pcx)?;
+    consume(pcx, Token::RBracket)?;
+    if n != 1 {
+        return syntaxerr(pcx, ErrorMessage::HostCallArity);
+    }
+    consume(pcx, Token::LParen)?;
+    let base = pcx.tmp.end();
+    while pcx.data.token != Token::RParen {
+        let arg = parse_expr(pcx)?;
+        pcx.tmp.push(arg);
+        if !check(pcx, Token::Comma)? { break }
+    }
+    consume(pcx, Token::RParen)?;
+    let call = newcall(pcx, idx, base);
+    pcx.tmp.truncate(base);
+    call
+}
+
+/* ---- Lowering ------------------------------------------------------------ */
+
+// FX  LOVX args (idx: LOP_CALL)
+// VAL LOV  call (LOP_RES)
+fn lower_call(lcx: &mut CLcx, obj: ObjRef<CALLX>, func: &Func, inputs: &[InsId]) -> InsId {
+    let idx = lcx.objs[obj].func;
+    let ret = lcx.session.hostfuncs[idx as usize].ret;
+    let mut args = func.code.push(Ins::NOP(Type::LSV));
+    for &input in inputs.iter().rev() {
+        args = func.code.push(Ins::CARG(args, input));
+    }
+    let call = func.code.push(Ins::LOVX(Type::FX, args, idx as _, LangOp::Host(LOP_CALL)));
+    func.code.push(Ins::LOV(ret.to_ir(), call, LangOp::Host(LOP_RES)))
+}
+
+/* ---- Emitting ------------------------------------------------------------ */
+
+fn emit_call(ecx: &mut Ecx, id: InsId) -> InsValue {
+    ecx.mcode.hostptr = true;
+    let emit = &mut *ecx.data;
+    let (args, idx, _) = emit.code[id].decode_LOVX();
+    let func = &ecx.session.hostfuncs[idx as usize];
+    let mut sig = Signature::new(NATIVE_CALLCONV);
+    sig.params.push(AbiParam::new(irt2cl(Type::PTR)));
+    sig.params.extend(func.params.iter().map(|p| AbiParam::new(irt2cl(p.to_ir()))));
+    sig.returns.push(AbiParam::new(irt2cl(func.ret.to_ir())));
+    let sig = emit.fb.ctx.func.import_signature(sig);
+    let ptr = emit.fb.ins().iconst(irt2cl(Type::PTR), func.func as i64);
+    let data = emit.fb.ins().iconst(irt2cl(Type::PTR), func.data as i64);
+    let argv = ecx.tmp.align_for::<InsValue>();
+    let argbase = argv.end();
+    argv.push(InsValue::from_value(data));
+    collectargs(emit, argv, args);
+    InsValue::from_cl_inst(emit.fb.ins().call_indirect(sig, ptr, cast_values(&argv[argbase..])))
+}
+
+fn emit_res(ecx: &mut Ecx, id: InsId) -> InsValue {
+    let emit = &mut *ecx.data;
+    let call = emit.code[id].decode_V();
+    let inst = emit.values[call].cl_inst();
+    InsValue::from_value(emit.fb.ctx.func.dfg.inst_results(inst)[0])
+}
+
+/* -------------------------------------------------------------------------- */
+
+impl Language for Host {
+
+    fn parse(pcx: &mut Pcx, n: usize) -> compile::Result<ObjRef<CALLX>> {
+        parse_call(pcx, n)
+    }
+
+    fn lower(
+        lcx: &mut CLcx,
+        _ctr: InsId,
+        obj: ObjRef<CALLX>,
+        func: &Func,
+        inputs: &[InsId]
+    ) -> InsId {
+        lower_call(lcx, obj, func, inputs)
+    }
+
+    fn begin_emit(_: &mut Ccx) -> compile::Result<Self> {
+        Ok(Host)
+    }
+
+    fn emit(ecx: &mut Ecx, id: InsId, lop: u8) -> compile::Result<InsValue> {
+        Ok(match lop {
+            LOP_CALL => emit_call(ecx, id),
+            LOP_RES  => emit_res(ecx, id),
+            _ => unreachable!()
+        })
+    }
+
+    fn finish_emit(self, ccx: &mut Ccx<Emit>) -> compile::Result {
+        for func in &ccx.session.hostfuncs {
+            if let Some(keep) = &func.keep {
+                ccx.fin.push(keep.clone());
+            }
+        }
+        Ok(())
+    }
+
+}
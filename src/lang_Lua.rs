@@ -283,7 +283,11 @@ fn collect_call(pcx: &mut Pcx, ps: &ParseState, n: usize) -> ObjRef<CALLX> {
     } else {
         ObjRef::NIL
     };
-    pcx.objs.push_args(CALLX::new(Lang::Lua as _, ann, zerocopy::transmute!(ps.lf)), inputs)
+    pcx.objs.push_args(
+        CALLX::new(Lang::Lua as _, ann, zerocopy::transmute!(ps.lf), CALLX::FX_ANY,
+            ObjRef::NIL.cast()),
+        inputs
+    )
 }
 
 /* ---- Lowering ------------------------------------------------------------ */
@@ -394,7 +398,7 @@ unsafe fn makejumptab(ccx: &mut Ccx, lib: &LuaLib, L: *mut lua_State) -> compile
                     lib.lua_createtable(L, 0, 0); // inputs
                     lib.lua_createtable(L, 0, 0); // insid -> input idx
                     let mut n_in = 0;
-                    while func.code.at(args).opcode() != Opcode::NOP {
+                    while func.code.at(args).opcode() == Opcode::CARG {
                         let (next, value) = func.code.at(args).decode_CARG();
                         lib.lua_rawgeti(L, -1, {let idx: u16 = zerocopy::transmute!(value); idx as _});
                         if lib.lua_type(L, -1) == LUA_TNIL {
@@ -458,14 +462,14 @@ unsafe fn makejumptab(ccx: &mut Ccx, lib: &LuaLib, L: *mut lua_State) -> compile
 fn emitcall(ecx: &mut Ecx, id: InsId) -> InsValue {
     let emit = &mut *ecx.data;
     let (mut args, jump, _) = emit.code[id].decode_LOVX();
-    let &mut Lua { L, ref lib, base } = emit.lang.Lua();
+    let &mut Lua { L, ref lib, base } = emit.lang.get::<Lua>().unwrap();
     unsafe {
         lib.lua_rawgeti(L, STACK_FUNCS, jump as _);
         lib.lua_getfield(L, -1, c"inputs".as_ptr());
     }
     let base = emit.fb.ins().iconst(irt2cl(Type::PTR), base as i64);
     let mut idx = 0;
-    while emit.code[args].opcode() != Opcode::NOP {
+    while emit.code[args].opcode() == Opcode::CARG {
         idx += 1;
         let (next, value) = emit.code[args].decode_CARG();
         let ofs = unsafe {
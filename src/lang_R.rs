@@ -228,7 +228,8 @@ fn parse_call(pcx: &mut Pcx) -> compile::Result<ObjRef<CALLX>> {
     }
     consume(pcx, Token::RParen)?;
     let callx = pcx.objs.push_args(
-        CALLX::new(Lang::R as _, ObjRef::NIL, zerocopy::transmute!(rf)),
+        CALLX::new(Lang::R as _, ObjRef::NIL, zerocopy::transmute!(rf), CALLX::FX_ANY,
+            ObjRef::NIL.cast()),
         &pcx.tmp[base.cast_up()..]
     );
     pcx.tmp.truncate(base);
@@ -371,7 +372,7 @@ fn optstring(lib: &LibR, s: &[u8]) -> SEXP {
 fn emit_call(ecx: &mut Ecx, id: InsId) -> compile::Result<InsValue> {
     let emit = &mut *ecx.data;
     let (mut args, rf) = emit.code[id].decode_VV();
-    let &mut R { ref lib, loader, rt } = emit.lang.R();
+    let &mut R { ref lib, loader, rt } = emit.lang.get::<R>().unwrap();
     let fun = {
         let rf: &RFunc = &ecx.intern[zerocopy::transmute!(emit.code[rf].bc())];
         let mut err = 0;
@@ -394,7 +395,7 @@ fn emit_call(ecx: &mut Ecx, id: InsId) -> compile::Result<InsValue> {
     // layout `Call` by hand because zerocopy doesn't let us derive the traits. sigh.
     ecx.tmp.push(Unalign::<usize>::new(fun.0.as_ptr() as _));
     let info = ecx.tmp.push([0u8, 0u8]); // narg, nret
-    while emit.code[args].opcode() != Opcode::NOP {
+    while emit.code[args].opcode() == Opcode::CARG {
         let (next, value) = emit.code[args].decode_CARG();
         match emit.code[value].decode_L().op {
             LOP_INPUT => narg += 1,
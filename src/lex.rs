@@ -1,15 +1,18 @@
 //! Lexical analysis
 
+use alloc::vec::Vec;
 use core::str;
 
 use enumset::EnumSetType;
 use logos::{Logos, Skip};
 
 use crate::compile;
+use crate::diag::Span;
 use crate::err::ErrorMessage;
 use crate::parser::{syntaxerr, Pcx};
+use crate::typing::Primitive;
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 pub struct SourceLocation {
     pub line: u32,
     pub col: u32
@@ -29,6 +32,17 @@ fn lex_newline(lex: &mut logos::Lexer<'_, Token>) -> Skip {
     Skip
 }
 
+// data blocks may span multiple lines
+fn lex_data(lex: &mut logos::Lexer<'_, Token>) {
+    let start = lex.span().start;
+    for (i, &c) in lex.slice().iter().enumerate() {
+        if c == b'\n' {
+            lex.extras.line += 1;
+            lex.extras.col = (start+i) as _;
+        }
+    }
+}
+
 #[derive(Logos, EnumSetType, Debug)]
 #[logos(extras=SourceLocation)]
 #[logos(source=[u8])]
@@ -44,12 +58,13 @@ pub enum Token {
     Int,     // data = signed int literal
     Int64,   // data = intern kref8
     Fp64,    // data = intern kref8
+    Typed,   // data = intern ref: primitive (u8) + value (i64 or f64)
 
-    #[regex(r"[[:alpha:]_][[:word:]]*")]
+    #[regex(r"[\p{L}_][\p{L}\p{M}\p{N}_]*")]
     #[regex(r"`([^`]*)`")]
     Ident,   // data = intern ref
 
-    #[regex(r"\$[[:alpha:]_][[:word:]]*")]
+    #[regex(r"\$[\p{L}_][\p{L}\p{M}\p{N}_]*")]
     #[regex(r"\$`([^`]*)`")]
     CapName, // data = intern ref
 
@@ -62,6 +77,9 @@ pub enum Token {
     #[regex(r#""([^"]*)""#)]
     Literal, // data = intern ref
 
+    #[regex(r"\[\|[^|]*\|\]", lex_data)]
+    Data,    // data = intern ref (block contents)
+
     /* ---- symbols and keywords ------------------------------------------------ */
 
     // binary operators. ORDER BINOP.
@@ -107,16 +125,26 @@ pub enum Token {
     #[token("out")]       Out,
     #[token("let")]       Let,
     #[token("in")]        In,
+    #[token("if")]        If,
+    #[token("then")]      Then,
+    #[token("else")]      Else,
+    #[token("eager")]     Eager,
     #[token("true")]      True,
     #[token("false")]     False,
 
     /* ---- pseudo tokens ------------------------------------------------------- */
 
-    #[regex(r"0x[[:digit:]a-fA-F]+")]
+    #[regex(r"0x[[:xdigit:]_]+(?:[iu](?:8|16|32|64))?")]
     NumHex,
 
+    #[regex(r"0b[01_]+(?:[iu](?:8|16|32|64))?")]
+    NumBin,
+
+    #[regex(r"@[[:digit:]]{4}-[[:digit:]]{2}-[[:digit:]]{2}")]
+    Date,
+
     #[token("inf")]
-    #[regex(r"(?:(?:[[:digit:]]+(?:\.[[:digit:]]*)?)|(?:\.[[:digit:]]+))(?:[eE]-?[[:digit:]+])?")]
+    #[regex(r"(?:(?:[[:digit:]][[:digit:]_]*(?:\.[[:digit:]_]*)?)|(?:\.[[:digit:]][[:digit:]_]*))(?:[eE][-+]?[[:digit:]]+)?(?:[fiu](?:8|16|32|64))?")]
     Num,
 
     #[token("\n", lex_newline)]
@@ -134,7 +162,7 @@ pub enum Token {
 impl Token {
 
     pub fn has_data(self) -> bool {
-        (self as u8) <= (Self::Literal as u8)
+        (self as u8) <= (Self::Data as u8)
     }
 
     pub fn is_binop(self) -> bool {
@@ -179,17 +207,22 @@ impl Token {
             Out        => "out",
             Let        => "let",
             In         => "in",
+            If         => "if",
+            Then       => "then",
+            Else       => "else",
+            Eager      => "eager",
             And        => "and",
             Or         => "or",
             Not        => "not",
             True       => "true",
             False      => "false",
             Newline    => "\n",
-            Num | NumHex | Int | Int64 | Fp64 => "<num>",
+            Num | NumHex | NumBin | Int | Int64 | Fp64 | Typed | Date => "<num>",
             Ident      => "<ident>",
             CapName | CapPos => "<capture>",
             Scope      => "<scope>",
             Literal    => "<literal>",
+            Data       => "<data>",
             Eof        => "<eof>",
             OpThis     => "$$",
             OpLiteralBoundary => "\"",
@@ -199,12 +232,54 @@ impl Token {
 
 }
 
+// canonical forms for letters written as base letter + combining mark (NFD).
+// only the letters that actually show up in names are handled, not full NFC.
+const COMPOSE: &[(u8, [u8; 2], [u8; 2])] = &[
+    // base, combining mark, composed
+    (b'a', [0xcc, 0x88], [0xc3, 0xa4]), // ä
+    (b'o', [0xcc, 0x88], [0xc3, 0xb6]), // ö
+    (b'u', [0xcc, 0x88], [0xc3, 0xbc]), // ü
+    (b'A', [0xcc, 0x88], [0xc3, 0x84]), // Ä
+    (b'O', [0xcc, 0x88], [0xc3, 0x96]), // Ö
+    (b'U', [0xcc, 0x88], [0xc3, 0x9c]), // Ü
+    (b'a', [0xcc, 0x8a], [0xc3, 0xa5]), // å
+    (b'A', [0xcc, 0x8a], [0xc3, 0x85]), // Å
+];
+
+fn normalize(id: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(id.len());
+    let mut i = 0;
+    while let Some(&c) = id.get(i) {
+        match COMPOSE.iter().find(|(b, m, _)| *b == c && id.get(i+1..i+3) == Some(m)) {
+            Some((_, _, composed)) => { out.extend_from_slice(composed); i += 3; },
+            None => { out.push(c); i += 1; }
+        }
+    }
+    out
+}
+
 fn internid(pcx: &mut Pcx, ofs: usize) {
     let mut id = &pcx.data.lex.slice()[ofs..];
     if id.get(0).cloned() == Some('`' as _) {
         id = &id[1..id.len()-1];
     }
-    pcx.data.tdata = zerocopy::transmute!(pcx.intern.intern(id));
+    pcx.data.tdata = zerocopy::transmute!(match id.is_ascii() {
+        true  => pcx.intern.intern(id),
+        false => pcx.intern.intern(&normalize(id)[..])
+    });
+}
+
+// decimal comma mode: `1,5` is the number 1.5. a comma directly after an integer literal
+// and directly followed by a digit is a decimal separator, so argument lists must put a space
+// after the comma (`f(1, 5)`). returns the length of the fraction including the comma.
+fn decimalcomma(num: &[u8], rest: &[u8]) -> usize {
+    if !num.iter().all(u8::is_ascii_digit) || rest.get(0) != Some(&b',') {
+        return 0;
+    }
+    match rest[1..].iter().take_while(|c| c.is_ascii_digit()).count() {
+        0 => 0,
+        n => n+1
+    }
 }
 
 fn internint(pcx: &mut Pcx, v: i64) -> Token {
@@ -226,6 +301,71 @@ fn internfloat(pcx: &mut Pcx, v: f64) -> Token {
     }
 }
 
+// split a numeric literal into digits without `_` separators and the type suffix.
+// returns None for an unknown suffix (f8, f16).
+fn splitnum(s: &[u8], suffix: &[u8]) -> Option<(Vec<u8>, Option<Primitive>)> {
+    let start = match s.get(..2) { Some(b"0x"|b"0b") => 2, _ => 0 };
+    let split = s[start..].iter().position(|c| suffix.contains(c)).map(|p| start+p);
+    let (num, sfx) = s.split_at(split.unwrap_or(s.len()));
+    let pri = match split {
+        Some(_) => Some(Primitive::from_name(sfx)?),
+        None => None
+    };
+    Some((num.iter().copied().filter(|&c| c != b'_').collect(), pri))
+}
+
+// days since 1970-01-01 of a proleptic gregorian date.
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y-1 } else { y };
+    let era = (if y >= 0 { y } else { y-399 }) / 400;
+    let yoe = y - era*400;
+    let doy = (153*(if m > 2 { m-3 } else { m+9 }) + 2)/5 + d-1;
+    let doe = yoe*365 + yoe/4 - yoe/100 + doy;
+    era*146097 + doe - 719468
+}
+
+// @yyyy-mm-dd -> i32 days since 1970-01-01
+fn parsedate(s: &[u8]) -> Option<i64> {
+    // safety: pattern accepts only valid utf8
+    let num = |r: core::ops::Range<usize>| unsafe { str::from_utf8_unchecked(&s[r]) }.parse::<i64>().ok();
+    let (y, m, d) = (num(1..5)?, num(6..8)?, num(9..11)?);
+    let mdays = match m {
+        2 if y%4 == 0 && (y%100 != 0 || y%400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        1..=12 => 31,
+        _ => return None
+    };
+    if d < 1 || d > mdays { return None }
+    Some(days_from_civil(y, m, d))
+}
+
+// largest value of a literal with the integer type suffix `pri`, none if `pri` isn't an integer
+// type. literals are never negative, negation is an operator.
+fn intmax(pri: Primitive) -> Option<u64> {
+    use Primitive::*;
+    Some(match pri {
+        I8  => i8::MAX as _,  U8  => u8::MAX as _,
+        I16 => i16::MAX as _, U16 => u16::MAX as _,
+        I32 => i32::MAX as _, U32 => u32::MAX as _,
+        I64 => i64::MAX as _, U64 => u64::MAX,
+        _ => return None
+    })
+}
+
+fn interntyped(pcx: &mut Pcx, pri: Primitive, value: [u8; 8]) -> Token {
+    let mut data = [0; 9];
+    data[0] = pri as u8;
+    data[1..].copy_from_slice(&value);
+    pcx.data.tdata = zerocopy::transmute!(pcx.intern.intern(&data[..]));
+    Token::Typed
+}
+
+// inverse of `interntyped`
+pub fn typedvalue(data: &[u8]) -> (Primitive, [u8; 8]) {
+    (Primitive::from_u8(data[0]), data[1..9].try_into().unwrap())
+}
+
 pub fn next(pcx: &mut Pcx) -> compile::Result<Token> {
     let parser = &mut *pcx.data;
     let mut token = match parser.lex.next() {
@@ -234,22 +374,82 @@ pub fn next(pcx: &mut Pcx) -> compile::Result<Token> {
         None            => return Ok(Token::Eof)
     };
     match token {
-        Token::NumHex => {
-            let v = i64::from_str_radix(
-                // safety: pattern accepts only valid utf8
-                unsafe { str::from_utf8_unchecked(&parser.lex.slice()[2..]) },
-                16
-            ).unwrap();
-            token = internint(pcx, v);
+        Token::NumHex | Token::NumBin => {
+            let Some((num, pri)) = splitnum(parser.lex.slice(), b"iu")
+                else { return syntaxerr(pcx, ErrorMessage::InvalidToken) };
+            let radix = match token { Token::NumHex => 16, _ => 2 };
+            // safety: pattern accepts only valid utf8
+            let v = u64::from_str_radix(unsafe { str::from_utf8_unchecked(&num[2..]) }, radix);
+            token = match (v, pri) {
+                (Ok(v), None) => internint(pcx, v as _),
+                (Ok(v), Some(pri)) => match intmax(pri) {
+                    Some(max) if v <= max => interntyped(pcx, pri, v.to_ne_bytes()),
+                    Some(_) => return syntaxerr(pcx, ErrorMessage::LiteralRange),
+                    None => return syntaxerr(pcx, ErrorMessage::InvalidToken)
+                },
+                _ => return syntaxerr(pcx, ErrorMessage::InvalidToken)
+            };
         },
-        Token::Num => {
+        Token::Date => {
+            let Some(days) = parsedate(parser.lex.slice())
+                else { return syntaxerr(pcx, ErrorMessage::InvalidToken) };
+            token = interntyped(pcx, Primitive::I32, days.to_ne_bytes());
+        },
+        Token::Num if parser.lex.slice().iter().any(|&c| matches!(c, b'_'|b'i'|b'u'|b'f'))
+            && parser.lex.slice() != b"inf" =>
+        {
+            let Some((num, pri)) = splitnum(parser.lex.slice(), b"fiu")
+                else { return syntaxerr(pcx, ErrorMessage::InvalidToken) };
             // safety: pattern accepts only valid utf8
-            let slice = unsafe { str::from_utf8_unchecked(parser.lex.slice()) };
-            token = match slice.parse() {
-                Ok(v) => internfloat(pcx, v),
-                _     => internint(pcx, slice.parse().unwrap())
+            let num = unsafe { str::from_utf8_unchecked(&num) };
+            token = match pri {
+                None => match num.parse() {
+                    Ok(v) => internfloat(pcx, v),
+                    _     => internint(pcx, num.parse().unwrap())
+                },
+                Some(pri @ (Primitive::F32|Primitive::F64)) => {
+                    let v: f64 = num.parse().unwrap();
+                    if pri == Primitive::F32 && v.is_finite() && (v as f32).is_infinite() {
+                        return syntaxerr(pcx, ErrorMessage::LiteralRange);
+                    }
+                    interntyped(pcx, pri, v.to_ne_bytes())
+                },
+                Some(pri) => {
+                    let Some(max) = intmax(pri)
+                        else { return syntaxerr(pcx, ErrorMessage::InvalidToken) };
+                    match num.parse::<u64>().ok()
+                        .or_else(|| num.parse::<f64>().ok()
+                            .filter(|&v| v.fract() == 0.0 && v < u64::MAX as f64)
+                            .map(|v| v as u64))
+                    {
+                        Some(v) if v <= max => interntyped(pcx, pri, v.to_ne_bytes()),
+                        Some(_) => return syntaxerr(pcx, ErrorMessage::LiteralRange),
+                        None => return syntaxerr(pcx, ErrorMessage::InvalidToken)
+                    }
+                }
             };
         },
+        Token::Num => {
+            let frac = match parser.decimal_comma {
+                true  => decimalcomma(parser.lex.slice(), parser.lex.remainder()),
+                false => 0
+            };
+            if frac > 0 {
+                let mut num: Vec<u8> = parser.lex.slice().into();
+                num.push(b'.');
+                num.extend_from_slice(&parser.lex.remainder()[1..frac]);
+                parser.lex.bump(frac);
+                // safety: digits and a dot are valid utf8
+                token = internfloat(pcx, unsafe { str::from_utf8_unchecked(&num) }.parse().unwrap());
+            } else {
+                // safety: pattern accepts only valid utf8
+                let slice = unsafe { str::from_utf8_unchecked(parser.lex.slice()) };
+                token = match slice.parse() {
+                    Ok(v) => internfloat(pcx, v),
+                    _     => internint(pcx, slice.parse().unwrap())
+                };
+            }
+        },
         Token::Ident => {
             internid(pcx, 0);
         },
@@ -274,6 +474,10 @@ pub fn next(pcx: &mut Pcx) -> compile::Result<Token> {
             let s = parser.lex.slice();
             parser.tdata = zerocopy::transmute!(pcx.intern.intern(&s[1..s.len()-1]));
         },
+        Token::Data => {
+            let s = parser.lex.slice();
+            parser.tdata = zerocopy::transmute!(pcx.intern.intern(&s[2..s.len()-2]));
+        },
         _ => {}
     }
     Ok(token)
@@ -286,3 +490,9 @@ pub fn loc(lex: &logos::Lexer<'_,  Token>) -> SourceLocation {
         col: lex.span().start as u32 - linestart.col
     }
 }
+
+pub fn span(lex: &logos::Lexer<'_, Token>) -> Span {
+    let SourceLocation { line, col } = loc(lex);
+    let span = lex.span();
+    Span { start: span.start as _, end: span.end as _, line, col }
+}
@@ -3,36 +3,61 @@
 
 extern crate alloc;
 
+mod analysis;
+mod aot;
 mod array;
+mod audit;
+mod bindgen;
 mod bitmap;
 mod bump;
+mod cache;
+mod callgraph;
 mod compile;
 mod concat;
 mod controlflow;
+mod cost;
+mod crash;
 mod data;
+mod deps;
+mod diag;
 mod dl;
 mod dump;
+mod edit;
 mod emit;
 mod err;
 mod finalize;
 mod graph;
+mod guard;
 mod hash;
 mod image;
 mod index;
 mod intern;
+#[cfg(feature="interp")]
+mod interp;
+mod interval;
 mod ir;
+mod knownbits;
 mod layout;
 mod lex;
+mod limits;
 mod link;
 mod lower;
 mod mcode;
 mod mem;
 mod mmap;
 mod obj;
+#[cfg(feature="threads")]
+mod parallel;
 mod parse;
 mod parser;
+mod peephole;
+mod pgo;
+mod plugin;
+mod remap;
 mod schedule;
+mod session;
 mod support;
+mod symbol;
 mod trace;
 mod translate;
 mod typeinfer;
@@ -42,9 +67,15 @@ mod zerocopy_union;
 
 /* ---- Optimizer ----------------------------------------------------------- */
 
+mod opt_cfg;
 mod opt_control;
 mod opt_fold;
 mod opt_inline;
+mod opt_mem;
+mod opt_merge;
+mod opt_range;
+mod opt_sig;
+mod opt_verify;
 mod optimize;
 
 /* ---- Host support -------------------------------------------------------- */
@@ -63,6 +94,7 @@ macro_rules! foreach_lang {
             #[cfg(feature="lang-C")]   lang_C::C;
             #[cfg(feature="lang-Lua")] lang_Lua::Lua;
             #[cfg(feature="lang-R")]   lang_R::R;
+                                       lang_Host::Host;
         }
     };
 }
@@ -84,7 +116,11 @@ foreach_lang!(define_lang_mods);
 
 pub const FHK_VERSION_STRING: &[u8] = &concat::concat_slices!(u8;
     match option_env!("FHK_GITHASH") { Some(v) => v.as_bytes(), None => b"(unknown version)" },
+    #[cfg(feature="checked")]  b" checked",
     #[cfg(feature="host-Lua")] b" Lua",
+    #[cfg(feature="interp")]   b" interp",
+    #[cfg(feature="std")]      b" std",
+    #[cfg(feature="threads")]  b" threads",
     b" [",
     #[cfg(feature="lang-C")]   b" C",
     #[cfg(feature="lang-Lua")] b" Lua",
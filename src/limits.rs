@@ -0,0 +1,97 @@
+//! Compiler resource limits.
+
+// services that compile untrusted models can cap how large a compilation may grow, on top of the
+// parser's nesting and object graph limits:
+//
+//   * `Session::maxmem`: bytes of objects and interned data, checked before type inference.
+//   * `Session::maxins`: IR instructions over all functions. lowering emits IR in proportion to
+//     the object graph, which `maxmem` already bounds, so lowering is checked once it's done.
+//     inlining is what can blow up the IR, so the inliner stops inlining calls at the limit.
+//     the check after optimization catches whatever other passes add.
+//
+// the error lists the biggest contributors, so that whoever wrote the model knows what to cut.
+
+use core::fmt::Write;
+
+use alloc::vec::Vec;
+
+use crate::compile::{self, Ccx, CompileError};
+use crate::intern::Intern;
+use crate::ir::IR;
+use crate::obj::Objects;
+use crate::symbol::{write_objdesc, write_source};
+use crate::typestate::{Absent, R};
+
+// number of contributors listed in the error
+const TOP: usize = 3;
+
+#[derive(Clone, Copy)]
+pub enum LimitError {
+    Memory(usize), // bytes used
+    Ins(usize)     // instructions
+}
+
+fn objbytes(objs: &Objects) -> usize {
+    objs.as_slice().len() * 4
+}
+
+fn internbytes(intern: &Intern) -> usize {
+    intern.bump().as_slice::<u8>().len()
+}
+
+pub fn numins(ir: &IR) -> usize {
+    ir.funcs.raw.iter().map(|f| { let n: usize = f.code.end().into(); n }).sum()
+}
+
+impl CompileError for LimitError {
+    fn write(self, ccx: &mut Ccx<(), R, R>) {
+        match self {
+            LimitError::Memory(used) => {
+                write!(ccx.host.buf, "model too large: {} bytes (limit {}), objects {}, interned {}",
+                    used, ccx.session.maxmem, objbytes(&ccx.objs), internbytes(&ccx.intern)).unwrap();
+                let mut objs: Vec<_> = ccx.objs.keys().map(|o| (ccx.objs.get_raw(o).len(), o))
+                    .collect();
+                objs.sort_unstable_by(|a, b| b.0.cmp(&a.0));
+                ccx.host.buf.write("; largest objects:");
+                for &(n, o) in objs.iter().take(TOP) {
+                    ccx.host.buf.push(b' ');
+                    write_objdesc(&mut ccx.host.buf, &ccx.intern, &ccx.objs, o);
+                    write!(ccx.host.buf, " ({} bytes)", n*4).unwrap();
+                }
+            },
+            LimitError::Ins(used) => {
+                write!(ccx.host.buf, "model too large: {} IR instructions (limit {})", used,
+                    ccx.session.maxins).unwrap();
+                let mut funcs: Vec<_> = ccx.ir.funcs.raw.iter()
+                    .map(|f| { let n: usize = f.code.end().into(); (n, f.source) })
+                    .collect();
+                funcs.sort_unstable_by(|a, b| b.0.cmp(&a.0));
+                ccx.host.buf.write("; largest functions:");
+                for &(n, source) in funcs.iter().take(TOP) {
+                    ccx.host.buf.push(b' ');
+                    write_source(&mut ccx.host.buf, &ccx.intern, &ccx.objs, source);
+                    write!(ccx.host.buf, " ({})", n).unwrap();
+                }
+            }
+        }
+    }
+    fn code(&self) -> &'static str {
+        "E0301"
+    }
+}
+
+pub fn check_memory(ccx: &mut Ccx<Absent>) -> compile::Result {
+    let used = objbytes(&ccx.objs) + internbytes(&ccx.intern);
+    match ccx.session.maxmem > 0 && used > ccx.session.maxmem {
+        true => ccx.error(LimitError::Memory(used)),
+        false => Ok(())
+    }
+}
+
+pub fn check_ins(ccx: &mut Ccx<Absent>) -> compile::Result {
+    let used = numins(&ccx.ir);
+    match ccx.session.maxins > 0 && used > ccx.session.maxins as usize {
+        true => ccx.error(LimitError::Ins(used)),
+        false => Ok(())
+    }
+}
@@ -4,9 +4,11 @@ use core::mem::take;
 
 use crate::compile::{self, Ccx, Stage};
 use crate::image::Image;
-use crate::mcode::{MCode, Reloc, Sym};
+use crate::index::IndexSlice;
+use crate::mcode::{Label, MCodeOffset, Reloc, Sym};
 use crate::mmap::{Mmap, Prot};
 use crate::support::NativeFunc;
+use crate::symbol::{build_codesize, build_hooks, build_profile, build_srcmap};
 use crate::trace::trace;
 use crate::typestate::Absent;
 
@@ -32,19 +34,22 @@ unsafe fn doreloc(kind: cranelift_codegen::binemit::Reloc, at: *mut u8, what: *c
     }
 }
 
-fn link(mcode: &MCode) -> compile::Result<Mmap> {
-    let code: &[u8] = mcode.code.as_slice();
-    let data: &[u8] = mcode.data.bump().as_slice();
+pub fn link(
+    code: &[u8],
+    data: &[u8],
+    relocs: &[Reloc],
+    labels: &IndexSlice<Label, MCodeOffset>
+) -> compile::Result<Mmap> {
     // TODO this can really fail and should set an error insted of unwrapping
     let mut map = Mmap::new(code.len() + data.len(), Prot::Read | Prot::Write).unwrap();
     let mem = map.as_mut_slice();
     mem[..code.len()].copy_from_slice(code);
     mem[code.len()..].copy_from_slice(data);
     let mem = mem.as_mut_ptr();
-    for &Reloc { at, add, kind, sym, which } in &mcode.relocs {
+    for &Reloc { at, add, kind, sym, which } in relocs {
         let base = match sym {
             Sym::Data   => unsafe { mem.add(code.len() + which as usize) },
-            Sym::Label  => unsafe { mem.add(mcode.labels[zerocopy::transmute!(which)] as usize) },
+            Sym::Label  => unsafe { mem.add(labels[zerocopy::transmute!(which)] as usize) },
             Sym::Native => NativeFunc::from_u8(which as _).ptr().cast()
         };
         unsafe {
@@ -68,7 +73,7 @@ fn link(mcode: &MCode) -> compile::Result<Mmap> {
     );
     if trace!(LINK) {
         // TODO: move disassembly here too
-        for (label, &ofs) in mcode.labels.pairs() {
+        for (label, &ofs) in labels.pairs() {
             trace!(LINK "{:?} {:?}", unsafe { mem.add(ofs as _) }, label);
         }
     }
@@ -85,10 +90,20 @@ impl Stage for Link {
         // ensure start of data ( = end of code) is aligned
         // put code first so that final label addresses can be calculated from map base.
         ccx.mcode.align_code();
-        let mem = link(&ccx.mcode)?;
+        let mcode = &ccx.mcode;
+        let mem = link(mcode.code.as_slice(), mcode.data.bump().as_slice(), &mcode.relocs,
+            &mcode.labels)?;
         ccx.image = Some(Image {
             mem,
             fin: take(&mut ccx.fin).build(),
+            srcmap: build_srcmap(ccx),
+            codesize: build_codesize(ccx),
+            profile: take(&mut ccx.mcode.prof).map(|ctr| {
+                let switches = take(&mut ccx.mcode.profswitch);
+                build_profile(ccx, ctr, switches)
+            }),
+            hooks: take(&mut ccx.mcode.hooks).map(|hooks| build_hooks(ccx, hooks)),
+            par: take(&mut ccx.mcode.par),
             breakpoints: ccx.layout.breakpoints,
             size: ccx.layout.size
         });
@@ -1,22 +1,29 @@
 //! Graph -> IR.
 
 use core::cmp:: min;
+use core::fmt::Write;
 use core::iter::{repeat_n, zip};
-use core::mem::{replace, swap};
+use core::mem::{replace, swap, take};
 
 use alloc::vec::Vec;
 use enumset::EnumSet;
 
 use crate::bitmap::BitMatrix;
 use crate::bump::{self, Bump, BumpRef};
-use crate::compile::{self, Ccx, Stage};
-use crate::dump::dump_ir;
+use crate::callgraph::CallGraph;
+use crate::compile::{self, Ccx, CompileError, Stage};
+use crate::deps::Deps;
+use crate::diag::Severity;
+use crate::dump::{dump_ir, dump_ir_dot};
 use crate::hash::HashMap;
-use crate::index::{IndexOption, InvalidValue};
+use crate::index::{self, IndexOption, IndexSet, InvalidValue};
 use crate::ir::{self, Chunk, DebugFlag, DebugSource, Func, FuncId, FuncKind, Ins, InsId, Opcode, Phi, PhiId, Query, SignatureBuilder, Type, IR};
 use crate::lang::Lang;
 use crate::mem::{Offset, ResetId, ResetSet, SizeClass};
-use crate::obj::{cast_args, cast_args_raw, obj_index_of, BinOp, Intrinsic, Obj, ObjRef, ObjectRef, Objects, Operator, BINOP, CALLX, CAT, DIM, EXPR, FLAT, GET, INTR, KFP64, KINT, KINT64, KSTR, LEN, LOAD, MOD, NEW, QUERY, RESET, SPEC, SPLAT, TAB, TPRI, TTEN, TTUP, VAR, VGET, VSET};
+use crate::obj::{cast_args, cast_args_raw, obj_index_of, BinOp, Intrinsic, Obj, ObjRef, ObjectRef, Objects, Operator, BINOP, CALLX, CAT, DIM, EXPR, FLAT, GET, INTR, KDATA, KFP64, KINT, KINT64, KSTR, LEN, LOAD, MOD, NEW, QUERY, RESET, SPEC, SPLAT, TAB, TPRI, TTEN, TTUP, VAR, VGET, VSET};
+use crate::optimize::OptFlag;
+use crate::session::{Bound, IrPoint};
+use crate::symbol::write_source;
 use crate::trace::trace;
 use crate::typestate::{Absent, Access, R, RW};
 use crate::typing::{Primitive, IRT_IDX};
@@ -182,6 +189,36 @@ pub struct Lower<O=RW, F=RW> {
     // current function:
     func: Access<Func, F>,
     tab: BumpRef<Tab>,
+    // (block, effect) of the last effectful foreign call in the current function
+    lastfx: Option<(InsId, InsId)>,
+    // model guard being lowered, NIL outside guards
+    guard: ObjRef<EXPR>,
+    // guards already warned about exact float equality
+    guardwarn: Vec<ObjRef<EXPR>>,
+    // expression being lowered and the spans of the current function, see `Func::spans`
+    span: ObjRef<EXPR>,
+    spans: Vec<(InsId, ObjRef<EXPR>)>,
+    err: Option<ShapeError>
+}
+
+// tensor operands of an elementwise operator with mismatching constant lengths.
+struct ShapeError {
+    axis: u8,
+    left: i64,
+    right: i64
+}
+
+impl CompileError for ShapeError {
+    fn write(self, ccx: &mut Ccx<(), R, R>) {
+        write!(
+            ccx.host.buf,
+            "shape mismatch in elementwise operation: axis {} has lengths {} and {}",
+            self.axis, self.left, self.right
+        ).unwrap();
+    }
+    fn code(&self) -> &'static str {
+        "E0201"
+    }
 }
 
 // for emit*
@@ -470,9 +507,13 @@ fn collectobjs(ctx: &mut Ccx<Lower>) {
         }
     }
     // pass 2: allocate objs and functions (note: depends on objs being in topo order)
+    // fallback models are created last so that their setters come last in each variable.
     ctx.freeze_graph(|ctx| {
         let objs = Access::borrow(&ctx.objs);
-        for (idx, obj) in objs.pairs() {
+        let fallbacks = ctx.session.fallbacks.clone();
+        let nonfallback = objs.pairs().filter(|&(idx, _)| !fallbacks.contains(&idx.cast()));
+        let last = fallbacks.iter().map(|&m| (m.erase(), objs.get(m.erase())));
+        for (idx, obj) in nonfallback.chain(last) {
             let bp = ctx.data.bump.end().cast_up();
             match obj {
                 ObjectRef::TAB(tab)   => createtab(ctx, idx.cast(), tab),
@@ -605,6 +646,16 @@ fn swapctr(func: &Func, ctr: &mut InsId, ins: Ins, new: InsId) {
     *ctr = new;
 }
 
+// effect ordering across blocks. `to` (a branch or loop body entered from `from`, or the merge
+// or exit after it) is dominated by `from`, so calls at `to` can be ordered after the last call
+// at `from`. calls inside a branch or loop don't dominate the merge, so they don't carry over.
+fn carryfx(fx: Option<(InsId, InsId)>, from: InsId, to: InsId) -> Option<(InsId, InsId)> {
+    match fx {
+        Some((ctr, fx)) if ctr == from => Some((to, fx)),
+        _ => None
+    }
+}
+
 /* ---- Loops --------------------------------------------------------------- */
 
 struct LoopState {
@@ -1078,6 +1129,8 @@ fn emitsplatloop(
 //   * emititer()     emit an iterator into a given loop
 //   * emitcheck()    emit a test for whether the value is computable or not
 
+// elementwise AND/OR. both operands are already computed here, scalars go through emitlazylogic.
+//
 // AND:
 //      IF left ->ri ->fal
 // ri:  JMP right merge
@@ -1099,6 +1152,87 @@ fn emitlogic(func: &Func, ctr: &mut InsId, left: InsId, right: InsId, op: BinOp)
     func.code.push(Ins::PHI(Type::B1, merge, phi))
 }
 
+// emit `expr` in a branch starting at `ctr`, and jump to `merge` passing its value in `phis`.
+// values computed here don't dominate the merge (or the other branch), so they are dropped from
+// the expression cache afterwards.
+fn emitbranch(
+    lcx: &mut Lcx,
+    mut ctr: InsId,
+    expr: ObjRef<EXPR>,
+    merge: InsId,
+    phis: PhiId,
+    num: usize
+) {
+    let mark = lcx.data.func.code.end();
+    let value = emitvalue(lcx, &mut ctr, expr);
+    let func = &lcx.data.func;
+    let last = num as isize - 1;
+    let mut jmp = Ins::JMP(value + last, merge, phis + last);
+    for i in (0..last).rev() {
+        let next = func.code.push(jmp);
+        jmp = Ins::JMP(value + i, next, phis + i);
+    }
+    func.code.set(ctr, jmp);
+    lcx.data.expr.retain(|_, ins| *ins < mark);
+}
+
+// scalar AND/OR, where the right operand is only evaluated if it decides the result:
+//      IF left ->ri ->k    (AND)
+//      IF left ->k ->ri    (OR)
+// ri:  ... JMP right merge
+// k:   JMP (KINT 0 or 1) merge
+fn emitlazylogic(
+    lcx: &mut Lcx,
+    ctr: &mut InsId,
+    left: InsId,
+    right: ObjRef<EXPR>,
+    op: BinOp
+) -> InsId {
+    let [ri, merge] = areserve(&lcx.data.func);
+    let phi = lcx.data.func.phis.push(Phi::new(Type::B1));
+    let fx = lcx.data.lastfx;
+    lcx.data.lastfx = carryfx(fx, *ctr, ri);
+    emitbranch(lcx, ri, right, merge, phi, 1);
+    lcx.data.lastfx = carryfx(fx, *ctr, merge);
+    let func = &lcx.data.func;
+    let kbool = func.code.push(Ins::KINT(Type::B1, (op == BinOp::OR) as _));
+    let k = func.code.push(Ins::JMP(kbool, merge, phi));
+    let (tru, fal) = match op {
+        BinOp::OR => (k, ri),
+        _ => (ri, k)
+    };
+    swapctr(func, ctr, Ins::IF(left, tru, fal), merge);
+    func.code.push(Ins::PHI(Type::B1, merge, phi))
+}
+
+// if c then a else b: only the taken branch is evaluated.
+//        IF c ->tru ->fal
+// tru:   ... JMP a merge
+// fal:   ... JMP b merge
+// merge: PHI
+fn emitif(lcx: &mut Lcx, ctr: &mut InsId, ann: ObjRef, args: &[ObjRef<EXPR>]) -> InsId {
+    let &[cond, tru, fal] = args else { unreachable!() };
+    let cv = emitvalue(lcx, ctr, cond);
+    let base = lcx.tmp.end();
+    let deco = decomposition(&lcx.objs, ann, &mut lcx.tmp);
+    let num = deco.len();
+    let phis = lcx.data.func.phis.extend(deco.iter().map(|&ty| Phi::new(ty)));
+    lcx.tmp.truncate(base);
+    let [ctru, cfal, merge] = areserve(&lcx.data.func);
+    let fx = lcx.data.lastfx;
+    lcx.data.lastfx = carryfx(fx, *ctr, ctru);
+    emitbranch(lcx, ctru, tru, merge, phis, num);
+    lcx.data.lastfx = carryfx(fx, *ctr, cfal);
+    emitbranch(lcx, cfal, fal, merge, phis, num);
+    lcx.data.lastfx = carryfx(fx, *ctr, merge);
+    let func = &lcx.data.func;
+    swapctr(func, ctr, Ins::IF(cv, ctru, cfal), merge);
+    func.code.extend(
+        (0..num as isize)
+        .map(|i| Ins::PHI(func.phis.at(phis+i).type_, merge, phis+i))
+    )
+}
+
 fn emitcmp(func: &Func, left: InsId, right: InsId, op: BinOp, ty: Primitive) -> InsId {
     let opcode = match (op, ty.is_unsigned()) {
         (BinOp::LT, true)  => Opcode::ULT,
@@ -1125,19 +1259,96 @@ fn emitscalarbinop(
     use BinOp::*;
     let irt = ty.to_ir();
     match op {
-        OR|AND => emitlogic(&lcx.data.func, ctr, left, right, op),
+        OR|AND => {
+            let ctr0 = *ctr;
+            let value = emitlogic(&lcx.data.func, ctr, left, right, op);
+            lcx.data.lastfx = carryfx(lcx.data.lastfx, ctr0, *ctr);
+            value
+        },
         ADD   => lcx.data.func.code.push(Ins::ADD(irt, left, right)),
         SUB   => lcx.data.func.code.push(Ins::SUB(irt, left, right)),
         MUL   => lcx.data.func.code.push(Ins::MUL(irt, left, right)),
         DIV if ty.is_unsigned() => lcx.data.func.code.push(Ins::UDIV(irt, left, right)),
         DIV   => lcx.data.func.code.push(Ins::DIV(irt, left, right)),
         POW   => lcx.data.func.code.push(Ins::POW(irt, left, right)),
+        EQ|NE if irt.is_fp() && !lcx.data.guard.is_nil() => emitguardeq(lcx, op, irt, left, right),
         EQ    => lcx.data.func.code.push(Ins::EQ(left, right)),
         NE    => lcx.data.func.code.push(Ins::NE(left, right)),
         LT|LE => emitcmp(&lcx.data.func, left, right, op, ty)
     }
 }
 
+// float `=` and `!=` in a model guard. exact equality makes model selection flip on rounding
+// noise, so with `Session::guardeps` set, a = b is |a-b| <= eps, and without it the comparison
+// stays exact but gets a warning (once per guard).
+fn emitguardeq(lcx: &mut Lcx, op: BinOp, irt: Type, left: InsId, right: InsId) -> InsId {
+    let Some(eps) = lcx.session.guardeps else {
+        let guard = lcx.data.guard;
+        if !lcx.data.guardwarn.contains(&guard) {
+            lcx.data.guardwarn.push(guard);
+            lcx.host.buf.clear();
+            lcx.host.buf.write(b"exact float equality in the guard of ");
+            write_source(&mut lcx.host.buf, &lcx.intern, &lcx.objs, lcx.data.func.source);
+            lcx.host.buf.write(b"\nnote: set a tolerance with guardeps");
+            lcx.diag.push("W0202", Severity::Warning, None, lcx.host.buf.as_slice());
+        }
+        return lcx.data.func.code.push(match op {
+            BinOp::EQ => Ins::EQ(left, right),
+            _ => Ins::NE(left, right)
+        });
+    };
+    let func = &lcx.data.func;
+    let diff = func.code.push(Ins::SUB(irt, left, right));
+    let neg = func.code.push(Ins::NEG(irt, diff));
+    let lt = func.code.push(Ins::LT(diff, neg));
+    let abs = func.code.push(Ins::SELECT(irt, lt, neg, diff));
+    let keps = func.code.push(Ins::KFP64(irt, zerocopy::transmute!(eps)));
+    let eq = func.code.push(Ins::LE(abs, keps));
+    match op {
+        BinOp::EQ => eq,
+        _ => func.code.push(Ins::NEG(Type::B1, eq))
+    }
+}
+
+fn kintvalue(func: &Func, mut ins: InsId) -> Option<i64> {
+    loop {
+        let i = func.code.at(ins);
+        match i.opcode() {
+            Opcode::MOV => ins = zerocopy::transmute!(i.a()),
+            Opcode::KINT => return Some(i.bc() as i32 as _),
+            _ => return None
+        }
+    }
+}
+
+// tensor operands must have equal shapes. lengths known at lowering time are checked here,
+// the rest are checked at runtime.
+fn emitshapecheck(
+    lcx: &mut Lcx,
+    ctr: &mut InsId,
+    left: ObjRef<EXPR>,
+    right: ObjRef<EXPR>,
+    dim: u8
+) {
+    let ls = emitshape(lcx, ctr, left);
+    let rs = emitshape(lcx, ctr, right);
+    let mut fail = None;
+    for axis in 0..dim {
+        let (l, r) = (ls + axis as isize, rs + axis as isize);
+        match (kintvalue(&lcx.data.func, l), kintvalue(&lcx.data.func, r)) {
+            (Some(a), Some(b)) if a == b => continue,
+            (Some(left), Some(right)) => {
+                lcx.data.err.get_or_insert(ShapeError { axis, left, right });
+                return;
+            },
+            _ => {}
+        }
+        let fail = *fail.get_or_insert_with(|| lcx.data.func.code.push(Ins::ABORT()));
+        let eq = lcx.data.func.code.push(Ins::EQ(l, r));
+        emitjumpifnot(&lcx.data.func, ctr, eq, fail);
+    }
+}
+
 fn broadcastbinop(
     lcx: &mut Lcx,
     loop_: &mut LoopState,
@@ -1146,6 +1357,12 @@ fn broadcastbinop(
     left: ObjRef<EXPR>,
     right: ObjRef<EXPR>
 ) -> InsId {
+    let objs = Access::borrow(&lcx.objs);
+    if let (ObjectRef::TTEN(&TTEN { dim, .. }), Obj::TTEN)
+        = (objs.get(objs[left].ann), objs[objs[right].ann].op)
+    {
+        emitshapecheck(lcx, &mut loop_.head, left, right, dim);
+    }
     let lhs = emitbroadcast(lcx, loop_, left);
     let rhs = emitbroadcast(lcx, loop_, right);
     match lcx.objs.get(ty) {
@@ -1176,6 +1393,7 @@ fn emitscalarintrinsic(
     let func = &lcx.data.func;
     match f {
         UNM|NOT => func.code.push(Ins::NEG(ty, argv[0])),
+        SELECT  => func.code.push(Ins::SELECT(ty, argv[0], argv[1], argv[2])),
         EXP     => todo!(), // ir intrinsic call?
         LOG     => todo!(), // ir intrinsic call?
         CONV    => todo!(),
@@ -1183,15 +1401,81 @@ fn emitscalarintrinsic(
     }
 }
 
+// f64 tensor data that emitvalue gives without collecting it into a new array.
+fn isdensef64(lcx: &Lcx, expr: ObjRef<EXPR>) -> bool {
+    let objs = &lcx.objs;
+    let ObjectRef::TTEN(&TTEN { elem, .. }) = objs.get(objs[expr].ann) else { return false };
+    match objs.get(elem) {
+        ObjectRef::TPRI(&TPRI { ty, .. }) if Primitive::from_u8(ty).to_ir() == Type::F64 => {},
+        _ => return false
+    }
+    if !(objs[expr].mark == EXPR_ONE && isiterable(lcx, expr)) {
+        // emititer would materialize it anyway
+        return true;
+    }
+    match objs.get(expr.erase()) {
+        ObjectRef::LOAD(_) => true,
+        ObjectRef::VGET(&VGET { ann, var, .. }) => ann == objs[var].ann,
+        _ => false
+    }
+}
+
+// sum of dense f64 data, two lanes at a time:
+//   acc = splat 0
+//   for j in 0..len/2: acc += data[2j..2j+2]
+//   sum = acc[0] + acc[1]
+//   for i in 2*(len/2)..len: sum += data[i]
+fn emitvsum(lcx: &mut Lcx, ctr: &mut InsId, arg: ObjRef<EXPR>) -> InsId {
+    let value = emitvalue(lcx, ctr, arg);
+    let ctr0 = *ctr;
+    let ty = &lcx.objs[lcx.objs[arg].ann.cast::<TTEN>()];
+    let shape = extractshape(&lcx.objs, value, ty);
+    let func = &lcx.data.func;
+    let len = emitshapelen(func, shape, ty.dim as _);
+    let two = func.code.push(Ins::KINT(IRT_IDX, 2));
+    let nvec = func.code.push(Ins::UDIV(IRT_IDX, len, two));
+    let zero = func.code.push(Ins::KINT(Type::F64, 0));
+    let vzero = func.code.push(Ins::SPLAT(Type::F64X2, zero));
+    let mut vreduce = newreducety(func, [Type::F64X2], vzero);
+    let izero = func.code.push(Ins::KINT(IRT_IDX, 0));
+    let j = emitrangeloop(func, &mut vreduce.loop_, IRT_IDX, izero, nvec);
+    let vptr = emitarrayptr(func, value, j, Type::F64X2);
+    let velem = func.code.push(Ins::LOAD(Type::F64X2, vptr));
+    let vnext = func.code.push(Ins::ADD(Type::F64X2, vreduce.value, velem));
+    swapctr(func, ctr, vreduce.start, vreduce.loop_.out);
+    let vacc = closereduce(func, &vreduce, vnext);
+    let vsum = func.code.push(Ins::VSUM(Type::F64, vacc));
+    let tail = func.code.push(Ins::MUL(IRT_IDX, nvec, two));
+    let mut reduce = newreducety(func, [Type::F64], vsum);
+    let i = emitrangeloop(func, &mut reduce.loop_, IRT_IDX, tail, len);
+    let ptr = emitarrayptr(func, value, i, Type::F64);
+    let elem = func.code.push(Ins::LOAD(Type::F64, ptr));
+    let next = func.code.push(Ins::ADD(Type::F64, reduce.value, elem));
+    swapctr(func, ctr, reduce.start, reduce.loop_.out);
+    let sum = closereduce(func, &reduce, next);
+    lcx.data.lastfx = carryfx(lcx.data.lastfx, ctr0, *ctr);
+    sum
+}
+
+// vectorized sums add even and odd elements separately, and combine the lanes at the end.
 fn emitsum(lcx: &mut Lcx, ctr: &mut InsId, arg: ObjRef<EXPR>, ty: Type) -> InsId {
     if isscalarann(&lcx.objs, arg.erase()) {
         return emitvalue(lcx, ctr, arg);
     }
+    // the interpreter doesn't do vector types.
+    if !cfg!(feature="interp") && ty == Type::F64 && lcx.session.flags.contains(OptFlag::VECTOR)
+        && isdensef64(lcx, arg)
+    {
+        return emitvsum(lcx, ctr, arg);
+    }
     let zero = lcx.data.func.code.push(Ins::KINT(ty, 0));
     let mut reduce = newreducety(&lcx.data.func, [ty], zero);
+    let (ctr0, fx) = (*ctr, lcx.data.lastfx);
+    lcx.data.lastfx = carryfx(fx, ctr0, reduce.loop_.body);
     let elem = emititer(lcx, &mut reduce.loop_, arg);
     let next = lcx.data.func.code.push(Ins::ADD(ty, reduce.value, elem));
     swapctr(&lcx.data.func, ctr, reduce.start, reduce.loop_.out);
+    lcx.data.lastfx = carryfx(fx, ctr0, *ctr);
     closereduce(&lcx.data.func, &reduce, next)
 }
 
@@ -1203,6 +1487,8 @@ fn emitanyall(lcx: &mut Lcx, ctr: &mut InsId, arg: ObjRef<EXPR>, f: Intrinsic) -
     let out = lcx.data.func.code.push(Ins::JMP(default, merge, resphi));
     let found = lcx.data.func.code.push(Ins::JMP(notdefault, merge, resphi));
     let mut loop_ = LoopState { head: *ctr, tail, body, out };
+    let fx = lcx.data.lastfx;
+    lcx.data.lastfx = carryfx(fx, *ctr, body);
     let value = emititer(lcx, &mut loop_, arg);
     lcx.data.func.code.set(loop_.body, if f == Intrinsic::ALL {
         Ins::IF(value, tail, found)
@@ -1211,6 +1497,7 @@ fn emitanyall(lcx: &mut Lcx, ctr: &mut InsId, arg: ObjRef<EXPR>, f: Intrinsic) -
     });
     lcx.data.func.code.set(loop_.tail, Ins::GOTO(body));
     lcx.data.func.code.set(loop_.head, Ins::GOTO(body));
+    lcx.data.lastfx = carryfx(fx, *ctr, merge);
     *ctr = merge;
     lcx.data.func.code.push(Ins::PHI(Type::B1, merge, resphi))
 }
@@ -1265,6 +1552,51 @@ fn broadcastintrinsic(
     v
 }
 
+// pure call with constant inputs: ask the language to evaluate it now.
+fn foldcallx(
+    lcx: &mut CLcx,
+    lang: Lang,
+    callx: ObjRef<CALLX>,
+    func: &Func,
+    inputs: &[InsId]
+) -> Option<InsId> {
+    let &CALLX { fx, reads, ann, .. } = &lcx.objs[callx];
+    if fx != CALLX::FX_PURE || !reads.is_nil() {
+        return None;
+    }
+    let ObjectRef::TPRI(&TPRI { ty, .. }) = lcx.objs.get(lcx.objs.totype(ann)) else { return None };
+    let mut k = Vec::with_capacity(inputs.len());
+    for &input in inputs {
+        let ins = func.code.at(input);
+        if !ins.opcode().is_const() { return None }
+        k.push(ins);
+    }
+    let ins = lang.fold(lcx, callx, &k)?;
+    debug_assert!(ins.opcode().is_const() && ins.type_() == Primitive::from_u8(ty).to_ir());
+    Some(func.code.push(ins))
+}
+
+// make the language call `call` depend on `after`. the edge goes at the end of the call's argument
+// list, which every call has, even one without inputs.
+fn orderafter(func: &Func, call: InsId, after: InsId) {
+    let mut args: InsId = zerocopy::transmute!(func.code.at(call).a());
+    while func.code.at(args).opcode() == Opcode::CARG {
+        (args, _) = func.code.at(args).decode_CARG();
+    }
+    let end = func.code.at(args);
+    let moved = func.code.push(end);
+    func.code.set(args, Ins::MOVF(end.type_(), moved, after));
+}
+
+// side-effecting calls of one evaluation run in source order within an instance, and instances
+// run in ascending index order. the order within an instance is enforced here: each `writes` call
+// gets an ordering edge (MOVF) to the last effectful call that dominates it, so the optimizer can't
+// reorder or merge them. calls in different branches of a conditional aren't ordered against
+// each other, only one of them runs anyway. instances are ordered by the loops that compute them.
+// `reads(...)` calls get an ordering edge to each variable they read instead, so they see whatever
+// the calls computing those variables did.
+// nothing else is needed: fold CSE skips effects, and the scheduler places every instruction at its
+// uses, it doesn't hoist anything out of loops.
 fn emitcallx(lcx: &mut Lcx, ctr: &mut InsId, callx: ObjRef<CALLX>) -> InsId {
     let objs = Access::borrow(&lcx.objs);
     let base = lcx.data.tmp_ins.len();
@@ -1272,15 +1604,53 @@ fn emitcallx(lcx: &mut Lcx, ctr: &mut InsId, callx: ObjRef<CALLX>) -> InsId {
         let value = emitvalue(lcx, ctr, input);
         lcx.data.tmp_ins.push(value);
     }
+    let &CALLX { fx, reads, .. } = &objs[callx];
+    let mut after = Vec::new();
+    if !reads.is_nil() {
+        for &read in &objs[reads].fields {
+            after.push(emitvalue(lcx, ctr, read));
+        }
+    }
+    // order after the previous effectful call, if it's in the same block or carried over
+    // from a dominating block (see carryfx).
+    if fx == CALLX::FX_WRITES {
+        if let Some((fxctr, last)) = lcx.data.lastfx {
+            if fxctr == *ctr {
+                after.push(last);
+            }
+        }
+    }
     let lang = Lang::from_u8(objs[callx].lang);
+    let start = lcx.data.func.code.end();
     let value = {
         // safety: this casts (ignoring newtype wrappers):
         //   &mut Ccx<Lower> -> &mut Ccx<UnsafeCell<Lower>>
         let lcx: &mut CLcx = unsafe { core::mem::transmute(&mut *lcx) };
         let lower = Access::borrow(&lcx.data);
-        lang.lower(lcx, *ctr, callx, &lower.func, &lower.tmp_ins[base..])
+        let inputs = &lower.tmp_ins[base..];
+        match foldcallx(lcx, lang, callx, &lower.func, inputs) {
+            Some(value) => value,
+            None => lang.lower(lcx, *ctr, callx, &lower.func, inputs)
+        }
     };
     lcx.data.tmp_ins.truncate(base);
+    if fx == CALLX::FX_WRITES || fx == CALLX::FX_IDEMPOTENT || !after.is_empty() {
+        // the call itself is the effect-typed language instruction
+        for id in index::iter_range(start..lcx.data.func.code.end()) {
+            let ins = lcx.data.func.code.at(id);
+            if ins.type_() == Type::FX && ins.opcode().is_lang() {
+                for &a in &after {
+                    orderafter(&lcx.data.func, id, a);
+                }
+                if fx == CALLX::FX_WRITES {
+                    lcx.data.func.code.set(id, ins.set_effect());
+                }
+                if fx == CALLX::FX_WRITES || fx == CALLX::FX_IDEMPOTENT {
+                    lcx.data.lastfx = Some((*ctr, id));
+                }
+            }
+        }
+    }
     value
 }
 
@@ -1305,10 +1675,13 @@ fn emitcat(lcx: &mut Lcx, ctr: &mut InsId, cat: &CAT) -> InsId {
         let stores = match objs[e].op {
             Obj::SPLAT => {
                 let mut reduce = newreducefx(&lcx.data.func, ds as _);
+                let (ctr0, fx) = (*ctr, lcx.data.lastfx);
+                lcx.data.lastfx = carryfx(fx, ctr0, reduce.loop_.body);
                 let value = emititer(lcx, &mut reduce.loop_, objs[e.cast::<SPLAT>()].value);
                 let next = emitreducestore(&lcx.data.func, &mut reduce, ptrs, esizes, value,
                     Some(idxs + i as isize).into());
                 swapctr(&lcx.data.func, ctr, reduce.start, reduce.loop_.out);
+                lcx.data.lastfx = carryfx(fx, ctr0, *ctr);
                 closereduce(&lcx.data.func, &reduce, next)
             },
             _ => {
@@ -1346,7 +1719,91 @@ fn emitvget1(lcx: &mut Lcx, ctr: &mut InsId, vget: &VGET) -> InsId {
     let var = vardata(&lcx.data.objs, vget.var);
     let inline = isdisjointidx(&lcx.data.bump[lcx.data.tab],
         &lcx.data.bump[lcx.data.bump[var].tab], &vget.idx);
-    emitvarload(lcx, var, i, inline)
+    let value = emitvarload(lcx, var, i, inline);
+    match lcx.session.assumes.iter().any(|a| a.mentions(vget.var)) {
+        true => emitassume(lcx, ctr, vget.var, value, i, inline),
+        false => value
+    }
+}
+
+fn emitbound(
+    lcx: &mut Lcx,
+    bound: Bound,
+    var: ObjRef<VAR>,
+    value: InsId,
+    idx: InsId,
+    inline: bool,
+    ty: Primitive,
+    round: fn(f64) -> f64
+) -> Option<InsId> {
+    let irt = ty.to_ir();
+    Some(match bound {
+        Bound::Var(v) if v == var => value,
+        Bound::Var(v) => {
+            if lcx.objs[v].tab != lcx.objs[var].tab || lcx.objs[v].ann != lcx.objs[var].ann {
+                return None;
+            }
+            emitvarload(lcx, vardata(&lcx.data.objs, v), idx, inline)
+        },
+        Bound::K(k) if irt.is_fp() => lcx.data.func.code.push(
+            Ins::KFP64(irt, zerocopy::transmute!(k))),
+        Bound::K(k) => {
+            let k = round(k) as i64;
+            lcx.data.func.code.push(match k == k as i32 as i64 {
+                true => Ins::KINT(irt, k as _),
+                false => Ins::KINT64(irt,
+                    zerocopy::transmute!(lcx.intern.intern(&k.to_ne_bytes()).to_bump()))
+            })
+        }
+    })
+}
+
+// host-declared invariants on `var`. the value is checked against each one, and a failed check
+// jumps to UB, so range analysis can use the dominating edge to eliminate later checks. with
+// `checkassume` the check jumps to ABORT instead. `var = k` also replaces the value with `k`
+// when it's not checked.
+fn emitassume(
+    lcx: &mut Lcx,
+    ctr: &mut InsId,
+    var: ObjRef<VAR>,
+    mut value: InsId,
+    idx: InsId,
+    inline: bool
+) -> InsId {
+    let ObjectRef::TPRI(&TPRI { ty, .. }) = lcx.objs.get(lcx.objs[var].ann) else { return value };
+    let ty = Primitive::from_u8(ty);
+    let check = lcx.session.checkassume;
+    let ctr0 = *ctr;
+    let mut fail = None;
+    for i in 0..lcx.session.assumes.len() {
+        let assume = lcx.session.assumes[i];
+        if !assume.mentions(var) { continue }
+        // integer bounds are rounded inward: x < 2.5 is x < 3, 2.5 < x is 2 < x.
+        let (lround, rround): (fn(f64) -> f64, fn(f64) -> f64) = match assume.op {
+            BinOp::LT => (f64::floor, f64::ceil),
+            _ => (f64::ceil, f64::floor)
+        };
+        let (Some(left), Some(right)) = (
+            emitbound(lcx, assume.left, var, value, idx, inline, ty, lround),
+            emitbound(lcx, assume.right, var, value, idx, inline, ty, rround)
+        ) else { continue };
+        if assume.op == BinOp::EQ && !check {
+            if let Bound::K(_) = assume.right { value = right; }
+            if let Bound::K(_) = assume.left { value = left; }
+            continue;
+        }
+        let cond = match assume.op {
+            BinOp::EQ => lcx.data.func.code.push(Ins::EQ(left, right)),
+            op => emitcmp(&lcx.data.func, left, right, op, ty)
+        };
+        let fail = *fail.get_or_insert_with(|| lcx.data.func.code.push(match check {
+            true => Ins::ABORT(),
+            false => Ins::UB()
+        }));
+        emitjumpifnot(&lcx.data.func, ctr, cond, fail);
+    }
+    lcx.data.lastfx = carryfx(lcx.data.lastfx, ctr0, *ctr);
+    value
 }
 
 fn computeshape(lcx: &mut Lcx, ctr: &mut InsId, expr: ObjRef<EXPR>) -> InsId {
@@ -1382,7 +1839,7 @@ fn computeshape(lcx: &mut Lcx, ctr: &mut InsId, expr: ObjRef<EXPR>) -> InsId {
         },
         ObjectRef::IDX(_) => todo!(),
         ObjectRef::BINOP(&BINOP { left, right, .. }) => {
-            // TODO: this should really insert an assertion that both shapes indeed are equal.
+            // if both are tensors, their shapes are checked equal in broadcastbinop.
             let n = match objs[objs[left].ann].op {
                 Obj::TPRI => right,
                 _ /* TTEN */ => left
@@ -1464,9 +1921,12 @@ fn closecollect(
 fn materializecollect(lcx: &mut Lcx, ctr: &mut InsId, expr: ObjRef<EXPR>, cty: &TTEN) -> InsId {
     let shape = computeshape(lcx, ctr, expr);
     let mut collect = newcollect(lcx, cty, shape);
+    let (ctr0, fx) = (*ctr, lcx.data.lastfx);
+    lcx.data.lastfx = carryfx(fx, ctr0, collect.reduce.loop_.body);
     let value = emititer(lcx, &mut collect.reduce.loop_, expr);
     let (out, result, start) = closecollect(lcx, collect, value);
     swapctr(&lcx.data.func, ctr, start, out);
+    lcx.data.lastfx = carryfx(fx, ctr0, *ctr);
     result
 }
 
@@ -2185,6 +2645,8 @@ fn computevalue(lcx: &mut Lcx, ctr: &mut InsId, expr: ObjRef<EXPR>) -> InsId {
         ObjectRef::GET(o) => emitget(lcx, ctr, o),
         ObjectRef::CALLX(_) => emitcallx(lcx, ctr, expr.cast()),
         ObjectRef::CAT(cat) => emitcat(lcx, ctr, cat),
+        ObjectRef::INTR(&INTR { func, ref args, .. }) if func == Intrinsic::IF as _
+            => emitif(lcx, ctr, ann, args),
         o => match objs.get(ann) {
             ObjectRef::TPRI(&TPRI { ty, .. }) => /* scalar value */ {
                 let ty = Primitive::from_u8(ty).to_ir();
@@ -2195,8 +2657,8 @@ fn computevalue(lcx: &mut Lcx, ctr: &mut InsId, expr: ObjRef<EXPR>) -> InsId {
                         Ins::KINT64(ty, zerocopy::transmute!(k))),
                     ObjectRef::KFP64(&KFP64 { k, .. }) => lcx.data.func.code.push(
                         Ins::KFP64(ty, zerocopy::transmute!(k))),
-                    ObjectRef::KSTR(&KSTR { k, .. }) => lcx.data.func.code.push(
-                        Ins::KSTR(ty, zerocopy::transmute!(k))),
+                    ObjectRef::KSTR(&KSTR { k, .. }) | ObjectRef::KDATA(&KDATA { k, .. }) =>
+                        lcx.data.func.code.push(Ins::KSTR(ty, zerocopy::transmute!(k))),
                     ObjectRef::DIM(&DIM { axis, .. }) => {
                         debug_assert!(ty == IRT_IDX);
                         let source = lcx.data.bump[lcx.data.tab].axes.len();
@@ -2206,6 +2668,12 @@ fn computevalue(lcx: &mut Lcx, ctr: &mut InsId, expr: ObjRef<EXPR>) -> InsId {
                         emitshape(lcx, ctr, value) + axis as isize,
                     ObjectRef::VGET(o) => emitvget1(lcx, ctr, o),
                     ObjectRef::IDX(_) => todo!(),
+                    ObjectRef::BINOP(&BINOP { binop, left, right, .. })
+                        if (BinOp::AND | BinOp::OR).contains(BinOp::from_u8(binop)) =>
+                    {
+                        let lhs = emitvalue(lcx, ctr, left);
+                        emitlazylogic(lcx, ctr, lhs, right, BinOp::from_u8(binop))
+                    },
                     ObjectRef::BINOP(&BINOP { binop, left, right, .. }) => {
                         let lhs = emitvalue(lcx, ctr, left);
                         let rhs = emitvalue(lcx, ctr, right);
@@ -2261,6 +2729,13 @@ fn computevalue(lcx: &mut Lcx, ctr: &mut InsId, expr: ObjRef<EXPR>) -> InsId {
 }
 
 fn itervalue(lcx: &mut Lcx, loop_: &mut LoopState, expr: ObjRef<EXPR>) -> InsId {
+    let outer = enterspan(lcx, expr);
+    let value = itervalue1(lcx, loop_, expr);
+    enterspan(lcx, outer);
+    value
+}
+
+fn itervalue1(lcx: &mut Lcx, loop_: &mut LoopState, expr: ObjRef<EXPR>) -> InsId {
     let objs = Access::borrow(&lcx.objs);
     match objs.get(expr.erase()) {
         ObjectRef::VGET(vget @ &VGET { ann, var, ref idx, .. }) => {
@@ -2285,6 +2760,11 @@ fn itervalue(lcx: &mut Lcx, loop_: &mut LoopState, expr: ObjRef<EXPR>) -> InsId
         ObjectRef::INTR(&INTR { ann, func, ref args, .. }) => {
             debug_assert!(lcx.objs[ann].op == Obj::TTEN);
             let func = Intrinsic::from_u8(func);
+            if func == Intrinsic::SELECT {
+                let dim = lcx.objs[ann.cast::<TTEN>()].dim;
+                emitshapecheck(lcx, &mut loop_.head, args[0], args[1], dim);
+                emitshapecheck(lcx, &mut loop_.head, args[0], args[2], dim);
+            }
             if func.is_broadcast() {
                 broadcastintrinsic(lcx, loop_, func, args, lcx.objs[ann.cast::<TTEN>()].elem)
             } else {
@@ -2306,6 +2786,21 @@ fn itervalue(lcx: &mut Lcx, loop_: &mut LoopState, expr: ObjRef<EXPR>) -> InsId
     }
 }
 
+// attribute the instructions emitted from here on to `expr`, if it has a source location.
+// returns the expression to restore when done with `expr`.
+fn enterspan(lcx: &mut Lcx, expr: ObjRef<EXPR>) -> ObjRef<EXPR> {
+    let prev = lcx.data.span;
+    if expr != prev && (expr.is_nil() || lcx.srclocs.contains_key(&expr)) {
+        lcx.data.span = expr;
+        let at = lcx.data.func.code.end();
+        match lcx.data.spans.last_mut() {
+            Some(last) if last.0 == at => last.1 = expr,
+            _ => lcx.data.spans.push((at, expr))
+        }
+    }
+    prev
+}
+
 fn emitvalue(lcx: &mut Lcx, ctr: &mut InsId, expr: ObjRef<EXPR>) -> InsId {
     // this is saved here even if it only has one reference, because for non-iterable objects
     // the value may be used multiple times per reference, eg. when a caller does
@@ -2313,7 +2808,9 @@ fn emitvalue(lcx: &mut Lcx, ctr: &mut InsId, expr: ObjRef<EXPR>) -> InsId {
     if let Some(&ins) = lcx.data.expr.get(&expr) {
         return ins;
     }
+    let outer = enterspan(lcx, expr);
     let ins = computevalue(lcx, ctr, expr);
+    enterspan(lcx, outer);
     lcx.data.expr.insert_unique_unchecked(expr, ins);
     ins
 }
@@ -2689,7 +3186,9 @@ fn emitvararms(lcx: &mut Lcx, var: BumpRef<Var>) {
             VSet::SIMPLE => {
                 if !model.guard.is_nil() {
                     emitcheck(lcx, &mut ctr, model.guard.erase(), next);
+                    lcx.data.guard = model.guard;
                     let cond = emitvalue(lcx, &mut ctr, model.guard);
+                    lcx.data.guard = ObjRef::NIL.cast();
                     emitjumpifnot(&lcx.data.func, &mut ctr, cond, next);
                 }
                 emitcheck(lcx, &mut ctr, lcx.objs[vset.obj].value.erase(), next);
@@ -2836,7 +3335,9 @@ fn emitmodavail(lcx: &mut Lcx, model: BumpRef<Mod>) {
     let jfal = lcx.data.func.code.push(Ins::JMP(kfal, ret, 0.into()));
     if !model.guard.is_nil() {
         emitcheck(lcx, &mut ctr, model.guard.erase(), jfal);
+        lcx.data.guard = model.guard;
         let cond = emitvalue(lcx, &mut ctr, model.guard);
+        lcx.data.guard = ObjRef::NIL.cast();
         emitjumpifnot(&lcx.data.func, &mut ctr, cond, jfal);
     }
     for setter in &model.value {
@@ -2903,6 +3404,8 @@ fn emittemplate(lcx: &mut Ccx<Lower<R, RW>, R>, id: FuncId, template: Template)
     swap(&mut *lcx.data.func, &mut lcx.ir.funcs[id]);
     debug_assert!(lcx.data.func.code.is_empty());
     lcx.data.expr.clear();
+    lcx.data.lastfx = None;
+    lcx.data.span = ObjRef::NIL.cast();
     // start:
     lcx.data.func.entry = INS_ENTRY;
     reserve(&lcx.data.func, 1);
@@ -2928,6 +3431,7 @@ fn emittemplate(lcx: &mut Ccx<Lower<R, RW>, R>, id: FuncId, template: Template)
             Query(query) => emitquery(lcx, query)
         }
     }
+    lcx.data.func.spans = take(&mut lcx.data.spans);
     swap(&mut *lcx.data.func, &mut lcx.ir.funcs[id]);
 }
 
@@ -3100,6 +3604,87 @@ fn computereset(ccx: &mut Ccx<Lower, R>) {
     }
 }
 
+/* ---- Recursion ----------------------------------------------------------- */
+
+// does the call evaluate the callee for the same index as the caller?
+// a chunk is marked computed only after it returns, so a cycle of these calls never terminates
+// once entered. recursion over other indices is fine: it ends when it reaches a computed index.
+fn issameindex(ir: &IR, caller: &Func, ins: Ins) -> bool {
+    let (mut idx, _, f) = ins.decode_CALLC();
+    let (FuncKind::Chunk(a), FuncKind::Chunk(b)) = (&caller.kind, &ir.funcs[f].kind) else {
+        return false
+    };
+    if b.scl == SizeClass::GLOBAL { return true }
+    if a.scl != b.scl { return false }
+    loop {
+        let ins = caller.code.at(idx);
+        match ins.opcode() {
+            Opcode::MOV => idx = zerocopy::transmute!(ins.a()),
+            Opcode::PHI => {
+                let (_, phi) = ins.decode_PHI();
+                return phi >= caller.ret && phi < caller.arg;
+            },
+            _ => return false
+        }
+    }
+}
+
+fn visitcycles(
+    ir: &IR,
+    cg: &CallGraph,
+    path: &mut Vec<FuncId>,
+    done: &mut IndexSet<FuncId>,
+    cycles: &mut Vec<Vec<FuncId>>,
+    f: FuncId
+) {
+    if let Some(i) = path.iter().position(|&g| g == f) {
+        if !cycles.iter().any(|c| c[..] == path[i..]) {
+            cycles.push(path[i..].to_vec());
+        }
+        return;
+    }
+    if done.contains(f) { return }
+    path.push(f);
+    let func = &ir.funcs[f];
+    for (_, ins) in func.code.pairs() {
+        if (Opcode::CALLC|Opcode::CALLCI).contains(ins.opcode()) {
+            let (_, _, g) = ins.decode_CALLC();
+            if cg.scc(g) == cg.scc(f) && issameindex(ir, func, ins) {
+                visitcycles(ir, cg, path, done, cycles, g);
+            }
+        }
+    }
+    path.pop();
+    done.insert(f);
+}
+
+// warn about definitions that depend on themselves for the same index.
+// this is a warning rather than an error because a condition (eg. a model guard) may break
+// the cycle before it is entered.
+fn checkrecursion(ccx: &mut Ccx<Lower>) {
+    let mut cg = CallGraph::default();
+    cg.build(&ccx.ir);
+    let mut path = Vec::new();
+    let mut done: IndexSet<FuncId> = Default::default();
+    let mut cycles = Vec::new();
+    for f in index::iter_span(ccx.ir.funcs.end()) {
+        if cg.is_recursive(f) {
+            visitcycles(&ccx.ir, &cg, &mut path, &mut done, &mut cycles, f);
+        }
+    }
+    for cycle in cycles {
+        let buf = &mut ccx.host.buf;
+        buf.clear();
+        write_source(buf, &ccx.intern, &ccx.objs, ccx.ir.funcs[cycle[0]].source);
+        write!(buf, " depends on itself for the same index\ncycle: ").unwrap();
+        for (i, &f) in cycle.iter().chain(&cycle[..1]).enumerate() {
+            if i > 0 { write!(buf, " -> ").unwrap(); }
+            write_source(buf, &ccx.intern, &ccx.objs, ccx.ir.funcs[f].source);
+        }
+        ccx.diag.push("W0201", Severity::Warning, None, ccx.host.buf.as_slice());
+    }
+}
+
 impl Stage for Lower {
 
     fn new(_: &mut Ccx<Absent>) -> compile::Result<Self> {
@@ -3112,17 +3697,33 @@ impl Stage for Lower {
             tmp_ty: Default::default(),
             func: Access::new(Func::new(FuncKind::User(),
                 DebugSource::new(ObjRef::NIL, EnumSet::empty()))),
-            tab: BumpRef::zero()
+            tab: BumpRef::zero(),
+            lastfx: None,
+            guard: ObjRef::NIL.cast(),
+            guardwarn: Default::default(),
+            span: ObjRef::NIL.cast(),
+            spans: Default::default(),
+            err: None
         })
     }
 
     fn run(ccx: &mut Ccx<Lower>) -> compile::Result {
         collectobjs(ccx);
         emitobjs(unsafe { core::mem::transmute(&mut *ccx) });
+        if let Some(e) = ccx.data.err.take() {
+            return ccx.error(e);
+        }
         ccx.freeze_graph(computereset);
+        ccx.deps = Deps::collect(&ccx.ir);
+        checkrecursion(ccx);
+        ccx.session.observe(IrPoint::Lower, &ccx.ir, &ccx.intern, &ccx.objs);
         if trace!(LOWER) {
             let mut tmp = Default::default();
-            dump_ir(&mut tmp, &ccx.ir, &ccx.intern, &ccx.objs);
+            if trace!(DOT) {
+                dump_ir_dot(&mut tmp, &ccx.ir, &ccx.intern, &ccx.objs);
+            } else {
+                dump_ir(&mut tmp, &ccx.ir, &ccx.intern, &ccx.objs);
+            }
             trace!("{}", core::str::from_utf8(tmp.as_slice()).unwrap());
         }
         Ok(())
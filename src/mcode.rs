@@ -1,11 +1,15 @@
 //! Machine code & relocs.
 
+use alloc::boxed::Box;
 use alloc::vec::Vec;
 use enumset::EnumSetType;
 
 use crate::bump::{Bump, BumpRef};
+use crate::image::{Hooks, ParSchedule, ProfCounter, ProfSwitch};
 use crate::index::{index, IndexVec};
 use crate::intern::Intern;
+use crate::ir::DebugSource;
+use crate::obj::{ObjRef, EXPR};
 use crate::support::NativeFunc;
 
 // cranelift uses:
@@ -44,7 +48,20 @@ pub struct MCode {
     pub data: Intern,
     pub code: Bump,
     pub relocs: Vec<Reloc>,
-    pub labels: IndexVec<Label, MCodeOffset>
+    pub labels: IndexVec<Label, MCodeOffset>,
+    // code range of each IR function, for mapping fault addresses back to the source
+    pub lines: Vec<(MCodeOffset, MCodeOffset, DebugSource)>,
+    // code range of each source expression, where the code generator kept track of it
+    pub srclocs: Vec<(MCodeOffset, MCodeOffset, ObjRef<EXPR>)>,
+    // call counters of each IR function, when compiled with profiling
+    pub prof: Option<Box<[ProfCounter]>>,
+    pub profswitch: Vec<ProfSwitch>,
+    // host callbacks, when compiled with hooks
+    pub hooks: Option<Box<Hooks>>,
+    // chunks queries can compute ahead, when compiled with `Session::parallel`
+    pub par: Option<ParSchedule>,
+    // the code calls host function pointers, which are only valid in this process
+    pub hostptr: bool
 }
 
 impl Sym {
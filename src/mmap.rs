@@ -5,6 +5,8 @@ use core::ops::Range;
 
 use enumset::{EnumSet, EnumSetType};
 
+pub const PAGE_SIZE: usize = 4096;
+
 #[derive(EnumSetType)]
 pub enum Prot {
     Read,
@@ -37,6 +39,10 @@ impl Mmap {
         self.base.cast()
     }
 
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
     // note: this function is safe because you can't go out of bounds,
     // but it will segfault if you try to write without PROT_WRITE.
     pub fn as_mut_slice(&mut self) -> &mut [u8] {
@@ -65,14 +71,12 @@ mod target {
 
     use enumset::EnumSet;
 
-    use super::{Mmap, Prot};
+    use super::{Mmap, Prot, PAGE_SIZE};
 
     fn prot2flags(prot: EnumSet<Prot>) -> c_int {
         prot.as_u8_truncated() as _
     }
 
-    const PAGE_SIZE: usize = 4096;
-
     // TODO: return io::Result here when it's available on no_std.
     pub fn map(size: usize, prot: EnumSet<Prot>) -> Option<Mmap> {
         match unsafe {
@@ -129,12 +133,15 @@ mod target {
     const MEM_RESERVE: u32 = 0x00002000;
     const MEM_RELEASE: u32 = 0x00008000;
 
+    const PAGE_NOACCESS: u32 = 0x01;
     const PAGE_READONLY: u32 = 0x02;
     const PAGE_READWRITE: u32 = 0x04;
     const PAGE_EXECUTE_READ: u32 = 0x20;
 
     fn prot2flags(prot: EnumSet<Prot>) -> u32 {
-        if prot == Prot::Read {
+        if prot.is_empty() {
+            PAGE_NOACCESS
+        } else if prot == Prot::Read {
             PAGE_READONLY
         } else if prot == Prot::Read | Prot::Write {
             PAGE_READWRITE
@@ -14,7 +14,7 @@ use zerocopy::Unalign;
 
 use crate::bump::{self, Aligned, Bump, BumpRef};
 use crate::compile::Ccx;
-use crate::hash::fxhash;
+use crate::hash::{fxhash, table_stats, HashStats};
 use crate::intern::IRef;
 use crate::mcode::MCodeOffset;
 use crate::typing::Primitive;
@@ -306,6 +306,7 @@ define_ops! {
     KINT64      { ann: ObjRef/*TY*/, k: BumpRef<Unalign<i64>> };
     KFP64       { ann: ObjRef/*TY*/, k: BumpRef<Unalign<f64>> };
     KSTR        { ann: ObjRef/*TY*/, k: IRef<[u8]> };
+    KDATA       { ann: ObjRef/*TY*/, k: IRef<[u8]> }; // pointer to constant data
     DIM.axis    { ann: ObjRef/*TPRI.IDX*/ };
     LEN.axis    { ann: ObjRef/*TPRI.IDX*/, value: ObjRef<EXPR> };
     TUPLE       { ann: ObjRef/*TY*/ } fields: [ObjRef<EXPR>];
@@ -319,7 +320,7 @@ define_ops! {
     GET.idx     { ann: ObjRef/*TY*/, value: ObjRef<EXPR> };
     FREF        { ann: ObjRef/*TY*/, func: ObjRef/*FUNC|FNI*/ };
     CALL        { ann: ObjRef/*TY*/, func: ObjRef<EXPR> } args: [ObjRef<EXPR>];
-    CALLX.lang  { ann: ObjRef/*TY*/, func: u32 } inputs: [ObjRef<EXPR>];
+    CALLX.lang  { ann: ObjRef/*TY*/, func: u32, fx: u32, reads: ObjRef<TUPLE> } inputs: [ObjRef<EXPR>];
 }
 
 define_ops!(@struct (EXPR ann:ObjRef;) (data));
@@ -435,6 +436,8 @@ define_intrinsics! {
     ALL     b"all";
     CONV    b"conv";
     REP     b"rep";
+    IF;      // if c then a else b (lazy)
+    SELECT;  // if eager c then a else b
 }
 
 impl Intrinsic {
@@ -447,7 +450,7 @@ impl Intrinsic {
 
      pub fn is_broadcast(self) -> bool {
          use Intrinsic::*;
-         (UNM|NOT|EXP|LOG|CONV).contains(self)
+         (UNM|NOT|EXP|LOG|CONV|SELECT).contains(self)
      }
 
 }
@@ -457,6 +460,14 @@ impl SPEC {
     pub const SLURP: u8 = 1;
 }
 
+// `reads` lists the variables a pure call depends on: the call is ordered after computing them.
+impl CALLX {
+    pub const FX_ANY: u32        = 0; // unannotated: may be deduplicated, isn't ordered
+    pub const FX_PURE: u32       = 1; // `pure` or `reads(...)`: may also be folded at compile time
+    pub const FX_WRITES: u32     = 2; // `writes`: runs in source order, never deduplicated
+    pub const FX_IDEMPOTENT: u32 = 3; // `idempotent`: has side effects, may be deduplicated
+}
+
 // TODO: put fieldtype and fieldname in separate arrays so this can just be implemented as
 // enumerate+filter on the fieldtype array
 pub struct RefParamIter {
@@ -571,6 +582,15 @@ impl Objects {
         self[self[tab].shape].fields.len()
     }
 
+    pub fn as_slice(&self) -> &[u32] {
+        self.bump.as_slice()
+    }
+
+    pub fn lookup_stats(&self) -> HashStats {
+        let data = self.bump.as_slice();
+        table_stats(&self.lookup, |idx| fxhash(lookupkey(data, idx.raw.cast::<u32>().index())))
+    }
+
     pub fn as_mut_ptr(&mut self) -> *mut u32 {
         self.bump.as_mut_slice().as_mut_ptr()
     }
@@ -600,6 +620,7 @@ impl Objects {
             (KINT64(a),KINT64(b)) => a.k == b.k,
             (KFP64(a), KFP64(b))  => a.k == b.k,
             (KSTR(a),  KSTR(b))   => a.k == b.k,
+            (KDATA(a), KDATA(b))  => a.k == b.k,
             (DIM(a),   DIM(b))    => a.axis == b.axis,
             (TUPLE(a), TUPLE(b))  => self.allequal(cast_args(&a.fields), cast_args(&b.fields)),
             (VGET(a),  VGET(b))   => a.var == b.var
@@ -618,7 +639,11 @@ impl Objects {
             (GET(a),   GET(b))    => a.idx == b.idx && self.equal(a.value.erase(), b.value.erase()),
             (FREF(_),  FREF(_))   => todo!(),
             (CALL(_),  CALL(_))   => todo!(),
-            (CALLX(_), CALLX(_))  => todo!(),
+            // calls with side effects that must all run are never equal.
+            (CALLX(a), CALLX(b))  => ao.data == bo.data && a.func == b.func && a.fx == b.fx
+                && a.fx != CALLX::FX_WRITES
+                && self.equal(a.reads.erase(), b.reads.erase())
+                && self.allequal(cast_args(&a.inputs), cast_args(&b.inputs)),
             _ => false
         }
     }
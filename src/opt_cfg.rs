@@ -0,0 +1,148 @@
+//! Control flow graph simplification.
+
+// this pass cleans up the control flow left behind by fold, SCCP and switch elimination:
+//   (1) unreachable control instructions, and instructions pinned to them, are replaced with NOPs
+//   (2) PHI reads in a block whose only precedessor is a JMP setting the PHI are replaced with
+//       the jumped value, and JMPs setting a PHI that is never read are replaced with GOTOs
+//   (3) a GOTO is merged to its successor if it has no pins, or if it's the only precedessor of
+//       its successor
+// (2) and (3) are repeated until neither changes anything, so that whole chains are merged in one
+// run instead of one block per optimizer iteration.
+
+use crate::graph::Graph;
+use crate::index::{self, IndexSet, IndexSlice};
+use crate::ir::{Func, FuncId, Ins, InsId, Opcode, PhiId};
+use crate::optimize::FuncScratch;
+use crate::trace::trace;
+
+/* ---- Unreachable code ---------------------------------------------------- */
+
+fn markreachable(code: &IndexSlice<InsId, Ins>, reachable: &mut IndexSet<InsId>, id: InsId) {
+    if reachable.test_and_set(id) {
+        return
+    }
+    for &c in code[id].controls() {
+        markreachable(code, reachable, c);
+    }
+}
+
+fn removeunreachable(code: &mut IndexSlice<InsId, Ins>, reachable: &IndexSet<InsId>) {
+    for (id, ins) in code.pairs_mut() {
+        let op = ins.opcode();
+        if op.is_control() && !reachable.contains(id) {
+            trace!(OPTIMIZE "CFG remove unreachable {:?} {:?}", id, *ins);
+            *ins = Ins::NOP_FX;
+        } else if op.is_pinned() && !reachable.contains(ins.decode_C()) {
+            // keep the type so that the (equally dead) users still typecheck.
+            trace!(OPTIMIZE "CFG remove unreachable {:?} {:?}", id, *ins);
+            *ins = Ins::NOP(ins.type_());
+        }
+    }
+}
+
+/* ---- PHI ----------------------------------------------------------------- */
+
+// the only precedessor of `id`, if it has exactly one.
+fn singlepred(code: &IndexSlice<InsId, Ins>, dfg: &Graph<InsId>, id: InsId) -> Option<InsId> {
+    let mut preds = dfg.uses(id).iter().cloned().filter(|&u| code[u].opcode().is_control());
+    match (preds.next(), preds.next()) {
+        (Some(pred), None) => Some(pred),
+        _ => None
+    }
+}
+
+fn phi_run(
+    code: &mut IndexSlice<InsId, Ins>,
+    dfg: &mut Graph<InsId>,
+    isread: &mut IndexSet<PhiId>,
+    entry: InsId,
+    arg: PhiId
+) -> bool {
+    let mut changed = false;
+    // (2a) single-precedessor reads
+    for id in index::iter_span(code.end()) {
+        // the entry block is also entered by the call.
+        if id == entry || !code[id].opcode().is_control() { continue }
+        let Some(pred) = singlepred(code, dfg, id) else { continue };
+        if pred == id || code[pred].opcode() != Opcode::JMP { continue }
+        let (value, _, phi) = code[pred].decode_JMP();
+        let mut u = 0;
+        while let Some(&user) = dfg.uses(id).get(u) {
+            let uins = code[user];
+            if uins.opcode() == Opcode::PHI && uins.decode_PHI().1 == phi {
+                trace!(OPTIMIZE "CFG patch read {:?} {:?}  =>  {:?}", user, uins, value);
+                code[user] = uins.set_opcode(Opcode::MOV).set_a(zerocopy::transmute!(value));
+                dfg.replace_input(user, id, value);
+                changed = true;
+            } else {
+                u += 1;
+            }
+        }
+    }
+    // (2b) unread writes. return and argument phis are read outside the function.
+    isread.clear();
+    for ins in &code.raw {
+        if ins.opcode() == Opcode::PHI {
+            isread.insert(ins.decode_PHI().1);
+        }
+    }
+    for (id, ins) in code.pairs_mut() {
+        if ins.opcode() != Opcode::JMP { continue }
+        let (value, dest, phi) = ins.decode_JMP();
+        if phi >= arg && !isread.contains(phi) {
+            trace!(OPTIMIZE "CFG patch jump {:?} {:?}", id, *ins);
+            *ins = Ins::GOTO(dest);
+            dfg.remove_input(id, value);
+            changed = true;
+        }
+    }
+    changed
+}
+
+/* ---- GOTO ---------------------------------------------------------------- */
+
+fn goto_run(code: &mut IndexSlice<InsId, Ins>, dfg: &mut Graph<InsId>, entry: InsId) -> bool {
+    let mut changed = false;
+    for id in index::iter_span(code.end()) {
+        let ins = code[id];
+        if ins.opcode() != Opcode::GOTO { continue }
+        let succ = ins.decode_GOTO();
+        if succ == id { continue }
+        let haspins = dfg.uses(id).iter().any(|&u| code[u].opcode().is_pinned());
+        // pins can't move to the entry block because it's also entered by the call.
+        if haspins && (succ == entry || singlepred(code, dfg, succ) != Some(id)) { continue }
+        trace!(OPTIMIZE "CFG merge {:?} {:?}", id, ins);
+        // leave the successor in `a` so that the entry can be found if it's merged.
+        code[id] = ins.set_opcode(Opcode::NOP);
+        dfg.remove_input(id, succ);
+        while let Some(&user) = dfg.uses(id).get(0) {
+            for c in code[user].controls_mut() { if *c == id { *c = succ; } }
+            dfg.replace_input(user, id, succ);
+        }
+        changed = true;
+    }
+    changed
+}
+
+/* -------------------------------------------------------------------------- */
+
+pub fn run(fs: &mut FuncScratch, func: &mut Func, fid: FuncId) {
+    trace!(OPTIMIZE "CFG {:?}", fid);
+    let code = func.code.inner_mut();
+    fs.mark1.clear();
+    markreachable(code, &mut fs.mark1, func.entry);
+    removeunreachable(code, &fs.mark1);
+    let dfg = &mut fs.cf.dfg;
+    dfg.clear(code.end());
+    for (id, ins) in code.pairs() {
+        dfg.add_inputs(id, ins.inputs_and_controls());
+    }
+    loop {
+        let changed = phi_run(code, dfg, &mut fs.phi_mark, func.entry, func.arg)
+            | goto_run(code, dfg, func.entry);
+        if !changed { break }
+    }
+    while code[func.entry].opcode() == Opcode::NOP {
+        func.entry = zerocopy::transmute!(code[func.entry].a());
+    }
+}
@@ -9,13 +9,15 @@
 
 use core::iter::zip;
 
+use enumset::EnumSet;
+
 use crate::bitmap::BitmapWord;
 use crate::bump::{Bump, BumpRef};
 use crate::controlflow::BlockId;
 use crate::graph::{Graph, GraphPtr};
 use crate::index::{self, index, IndexArray, IndexOption, IndexSet, IndexSlice, IndexVec};
-use crate::ir::{ins_matches, FuncId, Ins, InsId, Opcode, Phi, PhiId, Type};
-use crate::optimize::{Ocx, OptFlag};
+use crate::ir::{ins_match, ins_matches, Func, FuncId, Ins, InsId, Opcode, Phi, PhiId, Type};
+use crate::optimize::{FuncScratch, OptFlag};
 use crate::trace::trace;
 use crate::zerocopy_union::zerocopy_union;
 
@@ -87,10 +89,10 @@ fn switch_run(
         if pin != 1 { /* failed (1a) or (1c) */ continue }
         let ins = code[ctr];
         // check (1a)
-        let (phiv, isbool) = if ins_matches!(code, ins; IF (PHI)) {
-            (ins.decode_V(), true)
-        } else if ins_matches!(code, ins; IF ((EQ|NE) (PHI) const)) {
-            (code[ins.decode_V()].decode_V(), false)
+        let (phiv, isbool) = if let Some(phiv) = ins_match!(code, ins, |phiv| IF {phiv: (PHI)}) {
+            (phiv, true)
+        } else if let Some(phiv) = ins_match!(code, ins, |phiv| IF ((EQ|NE) {phiv: (PHI)} const)) {
+            (phiv, false)
         } else {
             continue
         };
@@ -750,45 +752,43 @@ fn scandata(
     }
 }
 
-pub fn run(ocx: &mut Ocx, fid: FuncId) {
+pub fn run(fs: &mut FuncScratch, flags: EnumSet<OptFlag>, func: &mut Func, fid: FuncId) {
     trace!(OPTIMIZE "CONTROL {:?}", fid);
-    let opt = &mut *ocx.data;
-    let func = &mut ocx.ir.funcs[fid];
     let code = func.code.inner_mut();
-    let base = ocx.tmp.end();
-    let (insdata_ptr, _) = ocx.tmp.reserve_dst::<IndexSlice<InsId, InsData>>(code.raw.len());
-    let (phi_ptr, _) = ocx.tmp.reserve_dst::<IndexSlice<PhiId, PhiData>>(func.phis.end().into());
-    let (fixins_ptr, _) = ocx.tmp.reserve::<IndexArray<FixId, InsId, MAX_CCPFIX>>();
-    let blocks = ocx.tmp.align_for::<BlockData>();
+    let base = fs.tmp.end();
+    let (insdata_ptr, _) = fs.tmp.reserve_dst::<IndexSlice<InsId, InsData>>(code.raw.len());
+    let (phi_ptr, _) = fs.tmp.reserve_dst::<IndexSlice<PhiId, PhiData>>(func.phis.end().into());
+    let (fixins_ptr, _) = fs.tmp.reserve::<IndexArray<FixId, InsId, MAX_CCPFIX>>();
+    let blocks = fs.tmp.align_for::<BlockData>();
     let blocks_start = blocks.end();
-    ocx.mark1.clear();
-    scancontrol(blocks, blocks_start, code, insdata_ptr, &mut ocx.mark1, func.entry);
+    fs.mark1.clear();
+    scancontrol(blocks, blocks_start, code, insdata_ptr, &mut fs.mark1, func.entry);
     let blocks_num = blocks.end().index() - blocks_start.index();
-    let (ccp_ptr, _) = ocx.tmp.reserve_dst::<IndexSlice<BlockId, CCPBlock>>(blocks_num);
-    let (data, blocks_ccp) = ocx.tmp.get_dst_mut_split(ccp_ptr, blocks_num);
+    let (ccp_ptr, _) = fs.tmp.reserve_dst::<IndexSlice<BlockId, CCPBlock>>(blocks_num);
+    let (data, blocks_ccp) = fs.tmp.get_dst_mut_split(ccp_ptr, blocks_num);
     let (data, blocks) = data.get_dst_mut_split(blocks_start.cast(), blocks_num);
     let (data, fix_ins) = data.get_mut_split(fixins_ptr);
     let (data, phi_data) = data.get_dst_mut_split(phi_ptr, func.phis.end().into());
     let ins_data = data.get_dst_mut(insdata_ptr, code.raw.len());
-    scandata(code, blocks, &ocx.mark1, ins_data, &mut opt.cf.dfg);
-    if ocx.flags.contains(OptFlag::SWITCH) {
-        switch_run(code, blocks, &mut ocx.mark1, &mut ocx.mark2, &mut opt.cf.dfg);
+    scandata(code, blocks, &fs.mark1, ins_data, &mut fs.cf.dfg);
+    if flags.contains(OptFlag::SWITCH) {
+        switch_run(code, blocks, &mut fs.mark1, &mut fs.mark2, &mut fs.cf.dfg);
     }
-    if ocx.flags.contains(OptFlag::LOOP) {
-        loop_run(code, blocks, &mut ocx.mark1, &mut opt.phi_mark, &mut opt.cf.dfg);
+    if flags.contains(OptFlag::LOOP) {
+        loop_run(code, blocks, &mut fs.mark1, &mut fs.phi_mark, &mut fs.cf.dfg);
     }
-    if ocx.flags.contains(OptFlag::PHI) {
-        phi_run(code, func.phis.inner_mut(), blocks, &mut opt.cf.dfg, phi_data, func.arg);
+    if flags.contains(OptFlag::PHI) {
+        phi_run(code, func.phis.inner_mut(), blocks, &mut fs.cf.dfg, phi_data, func.arg);
     }
-    if ocx.flags.contains(OptFlag::GOTO) {
-        goto_run(code, blocks, ins_data, &mut opt.cf.dfg);
+    if flags.contains(OptFlag::GOTO) {
+        goto_run(code, blocks, ins_data, &mut fs.cf.dfg);
         while code[func.entry].opcode() == Opcode::NOP {
             func.entry = zerocopy::transmute!(code[func.entry].a());
         }
     }
-    if ocx.flags.contains(OptFlag::CCP) {
+    if flags.contains(OptFlag::CCP) {
         // CCP must run last because it adds new instructions and doesn't maintain dfg.
-        ccp_run(code, blocks, blocks_ccp, ins_data, fix_ins, &mut ocx.mark1, &mut opt.cf.dfg);
+        ccp_run(code, blocks, blocks_ccp, ins_data, fix_ins, &mut fs.mark1, &mut fs.cf.dfg);
     }
-    ocx.tmp.truncate(base);
+    fs.tmp.truncate(base);
 }
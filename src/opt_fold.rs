@@ -7,12 +7,15 @@ use hashbrown::hash_table::Entry;
 use hashbrown::HashTable;
 use zerocopy::Unalign;
 
+use crate::audit::Decision;
 use crate::bump::BumpRef;
 use crate::compile::Ccx;
-use crate::hash::fxhash;
+use crate::hash::{fxhash, table_stats};
 use crate::index::{IndexOption, IndexVec};
-use crate::ir::{ins_matches, Func, FuncId, Ins, InsId, Opcode, Type};
-use crate::optimize::{FuncPass, Ocx, Optimize};
+use crate::ir::{ins_match, ins_matches, Func, FuncId, Ins, InsId, Opcode, Type};
+use crate::knownbits::{knownbits, knowncmp};
+use crate::optimize::{FuncPass, Ocx, OptPass, Optimize};
+use crate::trace::trace;
 use crate::typestate::{Absent, Access, R};
 
 #[derive(Default)]
@@ -23,9 +26,9 @@ pub struct Fold {
     code: IndexVec<InsId, Ins>
 }
 
-type Fcx<'a, 'b> = Ccx<Optimize, R<'a>, R<'b>>;
+pub type Fcx<'a, 'b> = Ccx<Optimize, R<'a>, R<'b>>;
 
-enum FoldStatus {
+pub enum FoldStatus {
     Done(Ins),
     Again(Ins),
     New(InsId),
@@ -80,16 +83,61 @@ fn newkfp(fcx: &mut Fcx, ty: Type, value: f64) -> Ins {
     }
 }
 
-fn foldintarith(op: Opcode, left: i64, right: i64) -> i64 {
+// sign-extend the low bits of `v` to the size of `ty`
+fn wrapint(ty: Type, v: i64) -> i64 {
+    match ty.size() {
+        1 => v as i8 as _,
+        2 => v as i16 as _,
+        4 => v as i32 as _,
+        _ => v
+    }
+}
+
+// zero-extend the low bits of `v` to the size of `ty`
+fn wrapuint(ty: Type, v: i64) -> u64 {
+    match ty.size() {
+        1 => v as u8 as _,
+        2 => v as u16 as _,
+        4 => v as u32 as _,
+        _ => v as _
+    }
+}
+
+// integer arithmetic wraps around at the size of the type, and shift amounts are masked to the
+// width of the type, same as the emitted code.
+enum IntFold {
+    Value(i64),
+    DivZero, // division by zero, including 0^-n
+    Trap     // traps at runtime for another reason: MIN/-1 overflows
+}
+
+fn foldintarith(op: Opcode, ty: Type, left: i64, right: i64) -> IntFold {
     use Opcode::*;
-    match op {
-        ADD  => left + right,
-        SUB  => left - right,
-        MUL  => left * right,
-        DIV  => left / right,
-        UDIV => ((left as u64) / (right as u64)) as _,
+    let (left, right) = (wrapint(ty, left), wrapint(ty, right));
+    let v = match op {
+        ADD  => left.wrapping_add(right),
+        SUB  => left.wrapping_sub(right),
+        MUL  => left.wrapping_mul(right),
+        DIV if right == 0 => return IntFold::DivZero,
+        DIV if right == -1 && left == wrapint(ty, 1 << (8*ty.size() as u32 - 1)) =>
+            return IntFold::Trap,
+        DIV  => left.wrapping_div(right),
+        UDIV => match wrapuint(ty, left).checked_div(wrapuint(ty, right)) {
+            Some(v) => v as _,
+            None => return IntFold::DivZero
+        },
+        USHR => (wrapuint(ty, left) >> (right as u32 & (8*ty.size() as u32 - 1))) as _,
+        POW if right >= 0 => left.wrapping_pow(right.min(u32::MAX as _) as _),
+        // x^-n truncates to zero unless |x|=1
+        POW  => match left {
+            0 => return IntFold::DivZero,
+            1 => 1,
+            -1 => if right & 1 == 0 { 1 } else { -1 },
+            _ => 0
+        },
         _    => unreachable!()
-    }
+    };
+    IntFold::Value(wrapint(ty, v))
 }
 
 fn foldfparith(op: Opcode, left: f64, right: f64) -> f64 {
@@ -128,6 +176,70 @@ fn foldfpcmp(op: Opcode, left: f64, right: f64) -> bool {
     }
 }
 
+// max exponent for x^n -> multiplication chain
+const MAX_POWI: i32 = 16;
+
+fn ispow2(k: f64) -> bool {
+    let bits = k.to_bits();
+    let exp = (bits >> 52) & 0x7ff;
+    bits & ((1 << 52) - 1) == 0 && exp != 0 && exp != 0x7ff
+}
+
+// push a new instruction, or return an existing equivalent one
+pub fn emit(fcx: &mut Fcx, ins: Ins) -> InsId {
+    let opt = &mut *fcx.data;
+    if !ins.opcode().is_cse() || ins.is_effect() {
+        return opt.fold.code.push(ins);
+    }
+    match opt.fold.cse_map.entry(
+        fxhash(ins),
+        |idx| opt.fold.code[*idx] == ins,
+        |idx| fxhash(opt.fold.code[*idx])
+    ) {
+        Entry::Occupied(e) => *e.get(),
+        Entry::Vacant(e) => {
+            let id = opt.fold.code.push(ins);
+            e.insert(id);
+            id
+        }
+    }
+}
+
+// shortest addition chains for 2..=MAX_POWI, excluding the leading 1.
+// each element is a sum of two (not necessarily distinct) earlier elements.
+const ADDCHAIN: [&[u8]; MAX_POWI as usize - 1] = [
+    &[2],
+    &[2, 3],
+    &[2, 4],
+    &[2, 4, 5],
+    &[2, 3, 6],
+    &[2, 4, 6, 7],
+    &[2, 4, 8],
+    &[2, 4, 8, 9],
+    &[2, 4, 5, 10],
+    &[2, 4, 5, 10, 11],
+    &[2, 3, 6, 12],
+    &[2, 4, 6, 12, 13],
+    &[2, 3, 6, 7, 14],
+    &[2, 3, 6, 12, 15],
+    &[2, 4, 8, 16]
+];
+
+// x^n by multiplication along the addition chain of n
+fn powi(fcx: &mut Fcx, ty: Type, x: InsId, n: u32) -> InsId {
+    let chain = ADDCHAIN[n as usize - 2];
+    let mut pows = [(1, x); MAX_POWI as usize];
+    for (i, &e) in chain.iter().enumerate() {
+        let (a, b) = pows[..=i].iter()
+            .find_map(|&(ea, a)| pows[..=i].iter()
+                .find(|&&(eb, _)| ea + eb == e)
+                .map(|&(_, b)| (a, b)))
+            .unwrap();
+        pows[i+1] = (e, emit(fcx, Ins::MUL(ty, a, b)));
+    }
+    pows[chain.len()].1
+}
+
 fn fold(fcx: &mut Fcx, mut ins: Ins) -> FoldStatus {
     use Opcode::*;
     let opt = &mut *fcx.data;
@@ -137,7 +249,7 @@ fn fold(fcx: &mut Fcx, mut ins: Ins) -> FoldStatus {
     match op {
 
         // fold constant arithmetic
-        ADD|SUB|MUL|DIV|UDIV|POW if m!(const const) => {
+        ADD|SUB|MUL|DIV|UDIV|USHR|POW if m!(const const) => {
             let (left, right) = ins.decode_VV();
             let left = code[left];
             let right = code[right];
@@ -148,7 +260,12 @@ fn fold(fcx: &mut Fcx, mut ins: Ins) -> FoldStatus {
                 ins = newkfp(fcx, ty, foldfparith(op, kfpvalue(fcx, left), kfpvalue(fcx, right)));
             } else {
                 debug_assert!(ty.is_int());
-                ins = newkint(fcx, ty, foldintarith(op, kintvalue(fcx, left), kintvalue(fcx, right)));
+                let value = foldintarith(op, ty, kintvalue(fcx, left), kintvalue(fcx, right));
+                ins = match (value, fcx.session.divzero) {
+                    (IntFold::Value(v), _) | (IntFold::DivZero, Some(v)) => newkint(fcx, ty, v),
+                    // leave it for the runtime trap
+                    _ => ins
+                };
             }
             FoldStatus::Done(ins)
         },
@@ -175,10 +292,13 @@ fn fold(fcx: &mut Fcx, mut ins: Ins) -> FoldStatus {
             FoldStatus::Done(Ins::KINT(Type::B1, (EQ|LE|ULE).contains(op) as _))
         },
 
+        // x<x is false for floats too, since any comparison with nan is false
+        LT if ins.a() == ins.b() => FoldStatus::Done(Ins::KINT(Type::B1, 0)),
+
         // sort commutative operands:
         // * constants go to right
         // * non-constants are sorted by insid
-        ADD|MUL|EQ|NE if m!(const _) || (ins.a() > ins.b() && !m!(_ const)) => {
+        _ if op.is_commutative() && (m!(const _) || (ins.a() > ins.b() && !m!(_ const))) => {
             ins.inputs_mut().swap(0, 1);
             FoldStatus::Again(ins)
         },
@@ -198,10 +318,142 @@ fn fold(fcx: &mut Fcx, mut ins: Ins) -> FoldStatus {
         POW if m!(1) || m!(_ 0) => FoldStatus::Done(Ins::KINT(ins.type_(), 1)),
         POW if m!(_ 1) => FoldStatus::New(ins.decode_V()),
 
-        // x^2 = x*x
-        // TODO: more generally, fold x^n = x*x*...*x (this requires a small refactoring because
-        // we need to produce multiple instructions)
-        POW if m!(_ 2) => FoldStatus::Again(Ins::MUL(ins.type_(), ins.decode_V(), ins.decode_V())),
+        // x^n = x*x*...*x for small integer n, if the multiplications are cheaper than pow
+        POW if m!(_ (KINT)) && (2..=MAX_POWI).contains(&(code.raw[ins.b() as usize].bc() as i32)) => {
+            let ty = ins.type_();
+            let (x, n) = ins.decode_VV();
+            let n = code[n].bc() as u32;
+            let cost = fcx.session.cost;
+            if ADDCHAIN[n as usize - 2].len() as u32 * cost.latency(MUL, ty) > cost.latency(POW, ty) {
+                return FoldStatus::Done(ins);
+            }
+            FoldStatus::New(powi(fcx, ty, x, n))
+        },
+
+        // x^0.5 = sqrt(x), except for x=-0 and x=-inf
+        POW if fcx.session.fastmath && ins.type_().is_fp() && m!(_ (KFP64)) => {
+            let (x, k) = ins.decode_VV();
+            let k = code[k];
+            if kfpvalue(fcx, k) != 0.5 {
+                return FoldStatus::Done(ins);
+            }
+            FoldStatus::Done(Ins::SQRT(ins.type_(), x))
+        },
+
+        // integer identities (not valid for floats because of nans and infs):
+        //   x-x = 0
+        //   x/x = 1, if x is known to be nonzero, or division by zero folds to one anyway.
+        //         otherwise the runtime trap for x=0 must stay, same as for 0/x below.
+        SUB if ins.a() == ins.b() && ins.type_().is_int() => {
+            FoldStatus::Done(Ins::KINT(ins.type_(), 0))
+        },
+        DIV|UDIV if ins.a() == ins.b() && ins.type_().is_int()
+            && (fcx.session.divzero == Some(1)
+                || knownbits(code, &fcx.intern, code[ins.decode_VV().0]).one != 0) =>
+        {
+            FoldStatus::Done(Ins::KINT(ins.type_(), 1))
+        },
+
+        // 0/x = 0 for integers, if division by zero folds to zero anyway.
+        // otherwise the runtime trap for x=0 must stay.
+        DIV|UDIV if m!(0 _) && ins.type_().is_int() && fcx.session.divzero == Some(0) => {
+            FoldStatus::Done(Ins::KINT(ins.type_(), 0))
+        },
+
+        // 0-x = -x for integers (not for floats: 0-0 = +0, but -0 = -0)
+        SUB if m!(0 _) && ins.type_().is_int() => {
+            FoldStatus::Again(Ins::NEG(ins.type_(), zerocopy::transmute!(ins.b())))
+        },
+
+        // x-(-y) = x+y
+        SUB if m!(_ (NEG _)) => {
+            let (x, y) = ins_match!(code, ins, |x, y| _ {x} (_ {y})).unwrap();
+            FoldStatus::Again(Ins::ADD(ins.type_(), x, y))
+        },
+
+        // x+(-y) = x-y
+        // (-x)+y = y-x
+        ADD if m!(_ (NEG _)) => {
+            let (x, y) = ins_match!(code, ins, |x, y| _ {x} (_ {y})).unwrap();
+            FoldStatus::Again(Ins::SUB(ins.type_(), x, y))
+        },
+        ADD if m!((NEG _) _) => {
+            let (x, y) = ins_match!(code, ins, |x, y| _ (_ {x}) {y}).unwrap();
+            FoldStatus::Again(Ins::SUB(ins.type_(), y, x))
+        },
+
+        // x*(-1) = -x, for floats too: the product only flips the sign.
+        // (x/(-1) for floats is rewritten to this by the x/2^k rule)
+        MUL if m!(_ const) && match code.raw[ins.b() as usize] {
+            k if ins.type_().is_fp() => kfpvalue(fcx, k) == -1.0,
+            k => k == Ins::KINT(ins.type_(), -1i32 as _)
+        } => {
+            FoldStatus::Again(Ins::NEG(ins.type_(), ins.decode_V()))
+        },
+
+        // x-k = x+(-k) for integers, so that constant chains only need to handle ADD
+        SUB if m!(_ const) && ins.type_().is_int() => {
+            let (x, k) = ins.decode_VV();
+            let ty = ins.type_();
+            let k = code[k];
+            let k = newkint(fcx, ty, wrapint(ty, kintvalue(fcx, k).wrapping_neg()));
+            let k = emit(fcx, k);
+            FoldStatus::Again(Ins::ADD(ty, x, k))
+        },
+
+        // reassociate integer constant chains:
+        //   (x+a)+b = x+(a+b)
+        //   (x*a)*b = x*(a*b)
+        ADD|MUL if m!(:int ((ADD|MUL) _ const) const) && code.raw[ins.a() as usize].opcode() == op => {
+            let (x, a, b) = ins_match!(code, ins, |x, a, b| _ (_ {x} {a}) {b}).unwrap();
+            let (a, b) = (code[a], code[b]);
+            let ty = ins.type_();
+            let IntFold::Value(k) = foldintarith(op, ty, kintvalue(fcx, a), kintvalue(fcx, b))
+                else { unreachable!() };
+            let k = newkint(fcx, ty, k);
+            let k = emit(fcx, k);
+            FoldStatus::Again(ins.set_b(zerocopy::transmute!(k)).set_a(zerocopy::transmute!(x)))
+        },
+
+        // x/2^k = x>>k for unsigned integers
+        UDIV if m!(_ const) => {
+            let (x, k) = ins.decode_VV();
+            let ty = ins.type_();
+            let k = code[k];
+            let k = kintvalue(fcx, k) as u64;
+            if !k.is_power_of_two() {
+                return FoldStatus::Done(ins);
+            }
+            let k = emit(fcx, Ins::KINT(ty, k.trailing_zeros()));
+            FoldStatus::Done(Ins::USHR(ty, x, k))
+        },
+
+        // x/2^k = x*2^-k for floats (exact, since 2^-k is exactly representable).
+        // other constants only with fastmath, and only if the target divides slower.
+        DIV if m!(_ const) && ins.type_().is_fp() => {
+            let (x, k) = ins.decode_VV();
+            let ty = ins.type_();
+            let k = code[k];
+            let k = kfpvalue(fcx, k);
+            let cost = fcx.session.cost;
+            let reciprocal = fcx.session.fastmath && k != 0.0 && k.is_finite()
+                && cost.latency(DIV, ty) > cost.latency(MUL, ty);
+            if !(ispow2(k) || reciprocal) {
+                return FoldStatus::Done(ins);
+            }
+            let k = newkfp(fcx, ty, 1.0 / k);
+            let k = emit(fcx, k);
+            FoldStatus::Again(Ins::MUL(ty, x, k))
+        },
+
+        // -(-x) = x
+        NEG if m!((NEG _)) => FoldStatus::New(ins_match!(code, ins, |x| _ (_ {x})).unwrap()),
+
+        // -(x-y) = y-x for integers (not for floats: -(x-x) = -0, but x-x = +0)
+        NEG if m!((SUB _ _)) && ins.type_().is_int() => {
+            let (x, y) = ins_match!(code, ins, |x, y| _ (_ {x} {y})).unwrap();
+            FoldStatus::Again(Ins::SUB(ins.type_(), y, x))
+        },
 
         // fold constant negation
         NEG if m!(const) => {
@@ -209,7 +461,8 @@ fn fold(fcx: &mut Fcx, mut ins: Ins) -> FoldStatus {
             let ty = ins.type_();
             FoldStatus::Done(match ty {
                 Type::F32|Type::F64 => newkfp(fcx, ty, -kfpvalue(fcx, operand)),
-                Type::I8|Type::I16|Type::I32|Type::I64 => newkint(fcx, ty, -kintvalue(fcx, operand)),
+                Type::I8|Type::I16|Type::I32|Type::I64 =>
+                    newkint(fcx, ty, wrapint(ty, kintvalue(fcx, operand).wrapping_neg())),
                 Type::B1 => Ins::KINT(Type::B1, (operand == Ins::KINT(Type::B1, 0)) as _),
                 _ => unreachable!()
             })
@@ -227,6 +480,15 @@ fn fold(fcx: &mut Fcx, mut ins: Ins) -> FoldStatus {
         //     FoldStatus::Old(ins.decode_V())
         // },
 
+        // select with constant condition
+        SELECT if m!(const) => {
+            let (cond, tru, fal) = ins.decode_SELECT();
+            FoldStatus::New(if code[cond] == Ins::KINT(Type::B1, 0) { fal } else { tru })
+        },
+
+        // select between equal values
+        SELECT if ins.b() == ins.c() => FoldStatus::New(zerocopy::transmute!(ins.b())),
+
         // eliminate constant IF
         IF if m!(const) => {
             let (cond, left, right) = ins.decode_IF();
@@ -242,22 +504,45 @@ fn fold(fcx: &mut Fcx, mut ins: Ins) -> FoldStatus {
 
         // TODO: canonicalize IF (NE) tru fal -> IF (EQ) fal tru
 
+        // integer comparison decided by known bits, eg. 2*x = 1 or (x>>60) < 16
+        EQ|NE|LT|LE|ULT|ULE if code[ins.decode_V()].type_().is_int() => {
+            let (a, b) = ins.decode_VV();
+            FoldStatus::Done(match knowncmp(code, &fcx.intern, op, a, b) {
+                Some(v) => Ins::KINT(Type::B1, v as _),
+                None => ins
+            })
+        },
+
+        // integer arithmetic with all result bits known, eg. (16*x)*16 = 0 for I8.
+        // (not UDIV: that would drop the trap for division by zero)
+        ADD|SUB|MUL|USHR|NEG if ins.type_().is_int() => {
+            let ty = ins.type_();
+            let known = knownbits(code, &fcx.intern, ins);
+            if !known.is_constant(ty) {
+                return FoldStatus::Done(ins);
+            }
+            FoldStatus::Done(newkint(fcx, ty, known.value(ty)))
+        },
+
         _ => FoldStatus::Done(ins)
     }
 }
 
-fn visit(fcx: &mut Fcx, func: &Func, id: InsId) -> InsId {
+fn visit(fcx: &mut Fcx, func: &Func, fid: FuncId, id: InsId) -> InsId {
     if let Some(new) = fcx.data.fold.old_new[id].unpack() {
         return new;
     }
     let mut ins = func.code.at(id);
     for input in ins.inputs_mut() {
-        *input = visit(fcx, func, *input);
+        *input = visit(fcx, func, fid, *input);
     }
+    // with auditing on, log everything that isn't just copied over.
+    let orig = ins;
+    let mut changed = false;
     let new = loop {
         match fold(fcx, ins) {
-            FoldStatus::Again(xins) => ins = xins,
-            FoldStatus::New(id) => break id,
+            FoldStatus::Again(xins) => { ins = xins; changed = true },
+            FoldStatus::New(id) => { changed = true; break id },
             // FoldStatus::Old(id) => {
             //     // this cannot cause infinite recursion, because the frontend will never
             //     // generate an infinite loop, so an instruction can never unconditionally
@@ -268,31 +553,39 @@ fn visit(fcx: &mut Fcx, func: &Func, id: InsId) -> InsId {
                 if ins.opcode().is_control() {
                     fcx.data.fold.next.extend(ins.controls());
                 }
-                break match ins.opcode().is_cse() {
-                    true => {
-                        let opt = &mut *fcx.data;
-                        match opt.fold.cse_map.entry(
-                            fxhash(ins),
-                            |idx| opt.fold.code[*idx] == ins,
-                            |idx| fxhash(opt.fold.code[*idx])
-                        ) {
-                            Entry::Occupied(e) => *e.get(),
-                            Entry::Vacant(e) => {
-                                let id = opt.fold.code.push(ins);
-                                e.insert(id);
-                                id
-                            }
-                        }
-                    },
-                    false => fcx.data.fold.code.push(ins)
-                }
+                let end = fcx.data.fold.code.end();
+                let new = emit(fcx, ins);
+                changed |= ins != orig || new < end;
+                break new
             }
         }
     };
     fcx.data.fold.old_new[id] = Some(new).into();
+    if fcx.session.audit && changed {
+        audit(fcx, func, fid, orig, id, new);
+    }
     new
 }
 
+#[cold]
+fn audit(fcx: &mut Fcx, func: &Func, fid: FuncId, orig: Ins, before: InsId, after: InsId) {
+    let rule = if fcx.data.fold.code[after].opcode().is_const() && !orig.opcode().is_const() {
+        "const"
+    } else if fcx.data.fold.code[after] == orig {
+        "cse"
+    } else {
+        "simplify"
+    };
+    fcx.pipeline.audit.push(Decision {
+        pass: OptPass::FOLD,
+        func: fid,
+        source: func.source.obj(),
+        rule,
+        before,
+        after
+    });
+}
+
 fn fixup(fold: &mut Fold) {
     for ins in &mut fold.code.raw {
         for ctrl in ins.controls_mut() {
@@ -318,9 +611,11 @@ impl FuncPass for Fold {
             fcx.data.fold.code.clear();
             fcx.data.fold.next.push_back(func.entry);
             while let Some(id) = fcx.data.fold.next.pop_front() {
-                visit(fcx, func, id);
+                visit(fcx, func, fid, id);
             }
             fixup(&mut fcx.data.fold);
+            trace!(OPTIMIZE "FOLD {:?} cse {}", fid, table_stats(&fcx.data.fold.cse_map,
+                |&idx| fxhash(fcx.data.fold.code[idx])));
         });
         let func = &mut ocx.ir.funcs[fid];
         func.entry = ocx.data.fold.old_new[func.entry].unwrap();
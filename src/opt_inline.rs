@@ -8,9 +8,12 @@ use alloc::vec::Vec;
 use crate::bump::BumpRef;
 use crate::compile::Ccx;
 use crate::controlflow::{dom, BlockId, ControlFlow, InstanceMap};
-use crate::index::{self, IndexOption, IndexSet, IndexSlice, IndexVec};
+use crate::index::{self, IndexSet, IndexSlice, IndexVec};
 use crate::ir::{FuncId, FuncKind, Ins, InsId, Opcode, IR};
-use crate::optimize::{Ocx, Pass};
+use crate::limits;
+use crate::obj::{ObjRef, EXPR};
+use crate::optimize::{Ocx, Optimize, Pass};
+use crate::remap::FuncMap;
 use crate::trace::trace;
 use crate::typestate::Absent;
 
@@ -38,8 +41,8 @@ macro_rules! define_costs {
 define_costs! {
     NOP | JMP | GOTO | UB | ABORT | PHI | KINT | KINT64 | KFP64 | KSTR | KREF
        | MOV | MOVB | MOVF | CONV | ABOX | BREF | CARG | RES | RET | TRET => 0,
-    ADD | SUB | MUL | DIV | UDIV | NEG | ADDP | EQ | NE | LT | LE | ULT | ULE
-        | STORE | LOAD | BOX | IF => 1,
+    ADD | SUB | MUL | DIV | UDIV | USHR | NEG | SQRT | ADDP | EQ | NE | LT | LE | ULT | ULE
+        | SELECT | STORE | LOAD | BOX | IF | SPLAT | VSUM => 1,
     // TODO: CALL cost should depend on called function
     POW | ALLOC | CALL | CALLC | CALLCI => 5,
     CINIT | LO | LOV | LOVV | LOVX | LOX | LOXX => 255
@@ -47,10 +50,10 @@ define_costs! {
 
 const LOOP_COST: u32 = 255;
 
-// TODO: this should be configurable.
 // note: this cannot be higher than L* costs.
 const FUNC_COST: u32 = 100;
-const USE_COST: u32 = 50;
+// functions that the profile says are hot are inlined more eagerly.
+const HOT_USE_FACTOR: u32 = 4;
 
 fn execcost(op: Opcode) -> u32 {
     OP_COST[op as usize] as _
@@ -85,7 +88,9 @@ pub struct Inline {
     func: IndexVec<FuncId, FuncData>,
     control: ControlFlow,
     inst: InstanceMap,
-    actions: Vec<Action>
+    actions: Vec<Action>,
+    spans: IndexVec<InsId, ObjRef<EXPR>>,
+    size: usize // instructions over all functions, counting the ones inlined so far
 }
 
 // mark 1: visited
@@ -210,6 +215,13 @@ fn inlinecalls(ccx: &mut Ocx, fid: FuncId, calls: Range<BumpRef<InsId>>, cost: &
     let func = &ccx.ir.funcs[fid];
     let mut code = func.code.take_inner();
     inl.control.map_all(&mut code, &inl.inst);
+    inl.inst.map_spans(func, &mut inl.spans);
+    let mut spans: Vec<(InsId, ObjRef<EXPR>)> = Vec::new();
+    for (id, &expr) in inl.spans.pairs() {
+        if spans.last().is_none_or(|&(_, e)| e != expr) {
+            spans.push((id, expr));
+        }
+    }
     let mut phi_base = 0usize;
     let mut dest: InsId = 0.into();
     for action in &inl.actions {
@@ -230,6 +242,8 @@ fn inlinecalls(ccx: &mut Ocx, fid: FuncId, calls: Range<BumpRef<InsId>>, cost: &
                 let other = &ccx.ir.funcs[fu];
                 let entry = other.entry + ins_base as isize;
                 func.phis.extend(other.phis.pairs().map(|(_,p)|p));
+                spans.push((ins_base.into(), ObjRef::NIL.cast()));
+                spans.extend(other.spans.iter().map(|&(start, e)| (start + ins_base as isize, e)));
                 code[at] = match other.params() {
                     r if r.is_empty() => Ins::GOTO(entry),
                     Range { start, end } => {
@@ -270,6 +284,7 @@ fn inlinecalls(ccx: &mut Ocx, fid: FuncId, calls: Range<BumpRef<InsId>>, cost: &
         }
     }
     func.code.replace_inner(code);
+    ccx.ir.funcs[fid].spans = spans;
 }
 
 fn visitinline(ccx: &mut Ocx, fid: FuncId) -> InlineState {
@@ -280,10 +295,10 @@ fn visitinline(ccx: &mut Ocx, fid: FuncId) -> InlineState {
         },
         InlineState::Yes | InlineState::No => return fd.state,
         InlineState::Working => {
-            // no inlining for recursive functions.
+            // recursive call. the function is not inlined anywhere (see below), so this call
+            // stays too.
             // (TODO?: there's room here for a heuristic to decide which function to disable
             //         inlining for in a recursive call chain)
-            fd.state = InlineState::No;
             return InlineState::No;
         }
     }
@@ -302,7 +317,21 @@ fn visitinline(ccx: &mut Ocx, fid: FuncId) -> InlineState {
     let mut call = base.cast_up::<InsId>();
     while call < end {
         let (_, _, f) = ccx.ir.funcs[fid].code.at(ccx.tmp[call]).decode_CALLC();
-        match visitinline(ccx, f) {
+        let mut state = visitinline(ccx, f);
+        if let InlineState::Yes = state {
+            let n: usize = ccx.ir.funcs[f].code.end().into();
+            let inl = &mut ccx.data.inline;
+            if ccx.session.maxins > 0 && inl.size + n > ccx.session.maxins as usize {
+                // inlining would go over the instruction limit. keep the call, and the callee
+                // with it.
+                trace!(OPTIMIZE "inline: {:?} over instruction limit", f);
+                inl.func[f].state = InlineState::No;
+                state = InlineState::No;
+            } else {
+                inl.size += n;
+            }
+        }
+        match state {
             InlineState::Yes => {
                 call = call.offset(1);
             },
@@ -320,7 +349,12 @@ fn visitinline(ccx: &mut Ocx, fid: FuncId) -> InlineState {
         inlinecalls(ccx, fid, start..end, &mut cost);
     }
     ccx.tmp.truncate(base);
+    let recursive = ccx.data.callgraph.is_recursive(fid);
     let fd = &mut ccx.data.inline.func[fid];
+    if recursive {
+        // no inlining for recursive functions.
+        fd.state = InlineState::No;
+    }
     match fd.state {
         InlineState::Working => {
             let func = &mut ccx.ir.funcs[fid];
@@ -334,13 +368,17 @@ fn visitinline(ccx: &mut Ocx, fid: FuncId) -> InlineState {
             }
             // if all callers are CALLCI:
             //   old cost: cost * callers
-            //   new cost: USE_COST * callers + (cost + FUNC_COST) * 1
+            //   new cost: inlinecost * callers + (cost + FUNC_COST) * 1
             // if some callers are CALLC:
             //   callers = huge,
             // and this effectively reduces to
-            //   cost <= USE_COST
+            //   cost <= inlinecost
+            let usecost = match &ccx.session.pgo {
+                Some(pgo) if pgo.is_hot(func.source) => ccx.session.inlinecost * HOT_USE_FACTOR,
+                _ => ccx.session.inlinecost
+            };
             let total = (cost as u64)*(fd.callers as u64);
-            let thres = (USE_COST as u64)*(fd.callers as u64) + (cost as u64) + (FUNC_COST as u64);
+            let thres = (usecost as u64)*(fd.callers as u64) + (cost as u64) + (FUNC_COST as u64);
             trace!(OPTIMIZE "inline: {:?} cost={} thres={} inline={}", fid, total, thres, total<=thres);
             fd.state = match total <= thres {
                 true => InlineState::Yes,
@@ -356,53 +394,13 @@ fn visitinline(ccx: &mut Ocx, fid: FuncId) -> InlineState {
     fd.state
 }
 
-fn sweepdead(
-    ir: &mut IR,
-    fda: &IndexSlice<FuncId, FuncData>,
-    work: &mut IndexSlice<FuncId, IndexOption<FuncId>>
-) {
-    for (f,(fd,w)) in zip(&ir.funcs.raw, zip(&fda.raw, &mut work.raw)) {
-        *w = (!matches!((&f.kind, fd),
-            (FuncKind::Chunk(_), FuncData {state:InlineState::Yes|InlineState::Undetermined,..})
-        )).then_some(0.into()).into();
-    }
-    let mut left = 0.into();
-    let mut right = ir.funcs.end();
-    while left < right {
-        while left < right && work[left].is_some() {
-            work[left] = Some(left).into();
-            left += 1;
-        }
-        while left < right && work[right-1].is_none() {
-            ir.funcs.raw.pop();
-            right -= 1;
-        }
-        if left < right {
-            debug_assert!(work[left].is_none() && work[right-1].is_some());
-            ir.funcs.raw.swap_remove(left.into());
-            work[right-1] = Some(left).into();
-            right -= 1;
-            left += 1;
-        }
-    }
-    if right < work.end() {
-        for func in &mut ir.funcs.raw {
-            for ins in &mut func.code.inner_mut().raw {
-                match ins.opcode() {
-                    Opcode::CALLC|Opcode::CALLCI => {
-                        // at this point all ir functions are live, and live functions do
-                        // not call dead functions.
-                        debug_assert!(work[zerocopy::transmute!(ins.c())].is_some());
-                        *ins = ins.set_c(zerocopy::transmute!(work[zerocopy::transmute!(ins.c())]));
-                    },
-                    Opcode::CINIT => match work[zerocopy::transmute!(ins.b())].unpack() {
-                        Some(f) => *ins = ins.set_b(zerocopy::transmute!(f)),
-                        None => *ins = Ins::NOP_FX
-                    },
-                    _ => {}
-                }
-            }
-        }
+// drop chunks that were inlined into every caller or that no query reaches.
+// at this point live functions do not call dead functions.
+fn sweepdead(ir: &mut IR, fda: &IndexSlice<FuncId, FuncData>, funcmap: &mut FuncMap) {
+    let dead = funcmap.build(ir.funcs.end(), |f| !matches!((&ir.funcs[f].kind, &fda[f]),
+        (FuncKind::Chunk(_), FuncData {state:InlineState::Yes|InlineState::Undetermined,..})));
+    if dead > 0 {
+        funcmap.apply(ir, |f| f);
     }
 }
 
@@ -414,8 +412,10 @@ impl Pass for Inline {
 
     fn run(ccx: &mut Ocx) {
         debug_assert!(!ccx.ir.funcs.is_empty());
+        ccx.data.callgraph.build(&ccx.ir);
         ccx.data.inline.func.clear();
         ccx.data.inline.func.raw.resize(ccx.ir.funcs.raw.len(), FuncData::default());
+        ccx.data.inline.size = limits::numins(&ccx.ir);
         for id in index::iter_span(ccx.ir.funcs.end()) {
             if let FuncKind::Query(_) = ccx.ir.funcs[id].kind {
                 visitcallers(&ccx.ir, &mut ccx.data.inline, id);
@@ -426,10 +426,8 @@ impl Pass for Inline {
                 visitinline(ccx, id);
             }
         }
-        let base = ccx.tmp.end();
-        let (_, work) = ccx.tmp.reserve_dst(ccx.ir.funcs.raw.len());
-        sweepdead(&mut ccx.ir, &ccx.data.inline.func, work);
-        ccx.tmp.truncate(base);
+        let Optimize { inline, funcmap, .. } = &mut *ccx.data;
+        sweepdead(&mut ccx.ir, &inline.func, funcmap);
     }
 
 }
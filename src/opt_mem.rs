@@ -0,0 +1,82 @@
+//! Load forwarding + dead store elimination.
+
+// memory operations are ordered only through data dependencies: a load that must see a store
+// takes its pointer through a MOVF of the store's fx value, and the same goes for stores.
+// this pass matches the following patterns, where p is the same instruction in both places:
+//
+//   load forwarding:
+//     s = STORE p v
+//     m = MOVF p s
+//     LOAD m          ->  MOV v      (when the types match)
+//
+//   dead store elimination:
+//     s = STORE p v
+//     m = MOVF p s
+//     STORE m w       ->  STORE p w  (when s and m have no other uses and v, w have the same size)
+//
+// the rewritten instructions are left for fold to remove.
+
+use crate::index::{self, IndexVec};
+use crate::ir::{Func, FuncId, Ins, InsId, Opcode};
+use crate::trace::trace;
+
+#[derive(Default)]
+pub struct MemOpt {
+    uses: IndexVec<InsId, u16>
+}
+
+// if `ptr` is MOVF p s where s is a STORE to p, returns (p, s)
+fn storeptr(code: &IndexVec<InsId, Ins>, ptr: InsId) -> Option<(InsId, InsId)> {
+    let movf = code[ptr];
+    if movf.opcode() != Opcode::MOVF {
+        return None;
+    }
+    let (p, s) = movf.decode_VV();
+    let store = code[s];
+    match store.opcode() == Opcode::STORE && store.decode_V() == p {
+        true  => Some((p, s)),
+        false => None
+    }
+}
+
+fn countuses(uses: &mut IndexVec<InsId, u16>, code: &IndexVec<InsId, Ins>) {
+    uses.clear();
+    uses.raw.resize(code.raw.len(), 0);
+    for ins in &code.raw {
+        for &input in ins.inputs() {
+            uses[input] = uses[input].saturating_add(1);
+        }
+    }
+}
+
+pub fn run(opt: &mut MemOpt, func: &mut Func, fid: FuncId) {
+    trace!(OPTIMIZE "MEM {:?}", fid);
+    let uses = &mut opt.uses;
+    let code = func.code.inner_mut();
+    countuses(uses, code);
+    for id in index::iter_span(code.end()) {
+        let ins = code[id];
+        match ins.opcode() {
+            Opcode::LOAD => {
+                let Some((_, s)) = storeptr(code, ins.decode_V()) else { continue };
+                let (_, value) = code[s].decode_VV();
+                if code[value].type_() == ins.type_() {
+                    trace!(OPTIMIZE "MEM forward {:?} -> {:?}", id, value);
+                    code[id] = Ins::MOV(ins.type_(), value);
+                }
+            },
+            Opcode::STORE => {
+                let (ptr, value) = ins.decode_VV();
+                let Some((p, s)) = storeptr(code, ptr) else { continue };
+                let (_, old) = code[s].decode_VV();
+                if uses[s] == 1 && uses[ptr] == 1
+                    && code[old].type_().size() == code[value].type_().size()
+                {
+                    trace!(OPTIMIZE "MEM dead store {:?}", s);
+                    code[id] = Ins::STORE(p, value);
+                }
+            },
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,116 @@
+//! Identical function merging.
+
+// specialization produces lots of chunk functions with identical bodies. two chunk functions
+// with the same code, signature, size class and reset set compute the same values, so every call
+// to one can call the other instead, and the duplicate is dropped.
+//
+// fold numbers instructions in visiting order, so structurally identical functions end up with
+// identical code and it's enough to compare instructions directly. functions are visited
+// bottom-up in the call graph and calls to merged callees compare equal, so callers that only
+// differ by which duplicate they call are merged in the same run.
+//
+// every function operand is redirected to the kept chunk, see `FuncMap::apply` for CINITs.
+
+use hashbrown::hash_table::Entry;
+use hashbrown::HashTable;
+
+use crate::callgraph::CallGraph;
+use crate::compile::Ccx;
+use crate::hash::fxhash;
+use crate::index::{self, IndexSlice, IndexVec};
+use crate::ir::{Func, FuncId, FuncKind, Ins, Opcode};
+use crate::optimize::{Ocx, Optimize, Pass};
+use crate::trace::trace;
+use crate::typestate::Absent;
+
+#[derive(Default)]
+pub struct Merge {
+    map: HashTable<FuncId>,
+    canon: IndexVec<FuncId, FuncId>
+}
+
+// calls to merged callees compare equal. only callees in earlier components are canonicalized,
+// because their canon is final by the time `scc` is visited.
+fn canonins(ins: Ins, canon: &IndexSlice<FuncId, FuncId>, cg: &CallGraph, scc: u32) -> Ins {
+    if (Opcode::CALLC|Opcode::CALLCI).contains(ins.opcode()) {
+        let (_, _, f) = ins.decode_CALLC();
+        if cg.scc(f) < scc {
+            return ins.set_c(zerocopy::transmute!(canon[f]));
+        }
+    }
+    ins
+}
+
+fn hashfunc(func: &Func, fid: FuncId, canon: &IndexSlice<FuncId, FuncId>, cg: &CallGraph) -> u64 {
+    let scc = cg.scc(fid);
+    let mut h = fxhash((func.entry, func.ret, func.arg, func.code.end(), func.phis.end()));
+    for (_, ins) in func.code.pairs() {
+        h = fxhash((h, canonins(ins, canon, cg, scc)));
+    }
+    h
+}
+
+fn samefunc(
+    funcs: &IndexSlice<FuncId, Func>,
+    fa: FuncId,
+    fb: FuncId,
+    canon: &IndexSlice<FuncId, FuncId>,
+    cg: &CallGraph
+) -> bool {
+    let (a, b) = (&funcs[fa], &funcs[fb]);
+    let (sa, sb) = (cg.scc(fa), cg.scc(fb));
+    let (FuncKind::Chunk(ca), FuncKind::Chunk(cb)) = (&a.kind, &b.kind) else { return false };
+    ca.scl == cb.scl
+        && a.reset == b.reset
+        && a.entry == b.entry
+        && a.ret == b.ret
+        && a.arg == b.arg
+        && a.phis.end() == b.phis.end()
+        && a.code.end() == b.code.end()
+        && a.phis.pairs().zip(b.phis.pairs()).all(|((_,x),(_,y))| x.type_ == y.type_)
+        && a.code.pairs().zip(b.code.pairs())
+            .all(|((_,x),(_,y))| canonins(x, canon, cg, sa) == canonins(y, canon, cg, sb))
+}
+
+impl Pass for Merge {
+
+    fn new(_: &mut Ccx<Absent>) -> Self {
+        Default::default()
+    }
+
+    fn run(ccx: &mut Ocx) {
+        let Optimize { merge, callgraph, funcmap, .. } = &mut *ccx.data;
+        let ir = &mut ccx.ir;
+        callgraph.build(ir);
+        let cg = &*callgraph;
+        merge.map.clear();
+        merge.canon.clear();
+        merge.canon.raw.extend(index::iter_span(ir.funcs.end()));
+        let mut merged = 0;
+        for &fid in cg.order() {
+            if !matches!(ir.funcs[fid].kind, FuncKind::Chunk(_)) {
+                continue;
+            }
+            let funcs = &ir.funcs;
+            let canon = &merge.canon;
+            match merge.map.entry(
+                hashfunc(&funcs[fid], fid, canon, cg),
+                |&f| samefunc(funcs, f, fid, canon, cg),
+                |&f| hashfunc(&funcs[f], f, canon, cg)
+            ) {
+                Entry::Occupied(e) => {
+                    trace!(OPTIMIZE "MERGE {:?} -> {:?}", fid, *e.get());
+                    merge.canon[fid] = *e.get();
+                    merged += 1;
+                },
+                Entry::Vacant(e) => { e.insert(fid); }
+            }
+        }
+        if merged > 0 {
+            let canon = &merge.canon;
+            funcmap.build(ir.funcs.end(), |f| canon[f] == f);
+            funcmap.apply(ir, |f| canon[f]);
+        }
+    }
+
+}
@@ -0,0 +1,450 @@
+//! Range analysis and redundant check elimination.
+
+// this pass replaces IFs on integer comparisons that always go the same way with GOTOs.
+// a comparison is decided either by
+//   (1) the value ranges of its operands, propagated through arithmetic and PHIs, or
+//   (2) a comparison that is known to hold because the IF is dominated by an edge of another IF.
+//       a PHI satisfies a comparison against a loop-invariant bound if every value jumped to it
+//       satisfies it at the JMP. this is what proves that a loop counter stays below the bound
+//       checked by the loop head and tail.
+// anything that can't be proven is left alone. the unreachable blocks are removed by the next cfg
+// pass.
+
+use alloc::vec::Vec;
+
+use crate::audit::{AuditLog, Decision};
+use crate::controlflow::{BlockId, Structure};
+use crate::index::{IndexSlice, IndexVec};
+use crate::intern::Intern;
+use crate::ir::{Func, FuncId, Ins, InsId, Opcode, PhiId, Type};
+use crate::knownbits::knownbits;
+use crate::optimize::{FuncScratch, OptPass};
+use crate::trace::trace;
+
+// ranges that haven't converged after this many sweeps over the function are forgotten.
+const MAX_SWEEPS: usize = 16;
+
+// PHI ranges that still grow after this many sweeps are widened to the range of their type.
+const WIDEN_SWEEPS: usize = 2;
+
+// how many PHIs deep to look when proving a comparison.
+const MAX_PHI_DEPTH: u32 = 4;
+
+// how many instructions deep to look when checking that an operand is pure.
+const MAX_PURE_DEPTH: u32 = 8;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct Range {
+    lo: i64,
+    hi: i64
+}
+
+impl Range {
+
+    const EMPTY: Self = Self { lo: i64::MAX, hi: i64::MIN };
+
+    fn of_type(ty: Type) -> Self {
+        match ty {
+            Type::I8  => Self { lo: i8::MIN as _, hi: i8::MAX as _ },
+            Type::I16 => Self { lo: i16::MIN as _, hi: i16::MAX as _ },
+            Type::I32 => Self { lo: i32::MIN as _, hi: i32::MAX as _ },
+            _         => Self { lo: i64::MIN, hi: i64::MAX }
+        }
+    }
+
+    fn is_empty(self) -> bool {
+        self.lo > self.hi
+    }
+
+    fn union(self, other: Self) -> Self {
+        Self { lo: self.lo.min(other.lo), hi: self.hi.max(other.hi) }
+    }
+
+    // [lo, hi] if it fits in `ty`, otherwise the result may have wrapped.
+    fn exact(ty: Type, lo: i128, hi: i128) -> Self {
+        let t = Self::of_type(ty);
+        match lo >= t.lo as i128 && hi <= t.hi as i128 {
+            true  => Self { lo: lo as _, hi: hi as _ },
+            false => t
+        }
+    }
+
+}
+
+// `a op b` holds in the blocks dominated by `block`.
+#[derive(Clone, Copy)]
+struct Fact {
+    op: Opcode,
+    a: InsId,
+    b: InsId,
+    block: BlockId
+}
+
+#[derive(Default)]
+pub struct RangeOpt {
+    ranges: IndexVec<InsId, Range>,
+    phis: IndexVec<PhiId, Range>,
+    facts: Vec<Fact>
+}
+
+struct Rcx<'a> {
+    code: &'a IndexSlice<InsId, Ins>,
+    structure: &'a Structure,
+    ranges: &'a IndexSlice<InsId, Range>,
+    facts: &'a [Fact],
+    arg: PhiId
+}
+
+/* ---- Ranges -------------------------------------------------------------- */
+
+fn eval(
+    code: &IndexVec<InsId, Ins>,
+    intern: &Intern,
+    ranges: &IndexSlice<InsId, Range>,
+    phis: &IndexSlice<PhiId, Range>,
+    arg: PhiId,
+    ins: Ins
+) -> Range {
+    use Opcode::*;
+    let ty = ins.type_();
+    if !ty.is_int() {
+        return Range::of_type(ty);
+    }
+    match ins.opcode() {
+        MOV => ranges[ins.decode_V()],
+        PHI => {
+            let (_, phi) = ins.decode_PHI();
+            match phi < arg {
+                true  => Range::of_type(ty),
+                false => phis[phi]
+            }
+        },
+        op @ (ADD|SUB|MUL) => {
+            let (a, b) = ins.decode_VV();
+            let (a, b) = (ranges[a], ranges[b]);
+            if a.is_empty() || b.is_empty() {
+                return Range::EMPTY;
+            }
+            let (alo, ahi, blo, bhi) = (a.lo as i128, a.hi as i128, b.lo as i128, b.hi as i128);
+            match op {
+                ADD => Range::exact(ty, alo+blo, ahi+bhi),
+                SUB => Range::exact(ty, alo-bhi, ahi-blo),
+                _ => {
+                    let p = [alo*blo, alo*bhi, ahi*blo, ahi*bhi];
+                    Range::exact(ty, *p.iter().min().unwrap(), *p.iter().max().unwrap())
+                }
+            }
+        },
+        NEG => {
+            let a = ranges[ins.decode_V()];
+            match a.is_empty() {
+                true  => Range::EMPTY,
+                false => Range::exact(ty, -(a.hi as i128), -(a.lo as i128))
+            }
+        },
+        SELECT => {
+            let (_, a, b) = ins.decode_SELECT();
+            ranges[a].union(ranges[b])
+        },
+        _ => {
+            let k = knownbits(code, intern, ins);
+            Range { lo: k.smin(ty), hi: k.smax(ty) }
+        }
+    }
+}
+
+// returns false if the ranges didn't converge.
+fn propagate(ro: &mut RangeOpt, code: &IndexVec<InsId, Ins>, intern: &Intern, arg: PhiId) -> bool {
+    for sweep in 0..MAX_SWEEPS {
+        let mut changed = false;
+        for (id, ins) in code.pairs() {
+            match ins.opcode() {
+                Opcode::JMP => {
+                    let (value, _, phi) = ins.decode_JMP();
+                    let old = ro.phis[phi];
+                    let mut new = old.union(ro.ranges[value]);
+                    if new == old { continue }
+                    if sweep >= WIDEN_SWEEPS && !old.is_empty() {
+                        let t = Range::of_type(code[value].type_());
+                        if new.lo < old.lo { new.lo = t.lo; }
+                        if new.hi > old.hi { new.hi = t.hi; }
+                    }
+                    ro.phis[phi] = new;
+                    changed = true;
+                },
+                op if op.is_control() => {},
+                _ => {
+                    let new = eval(code, intern, &ro.ranges, &ro.phis, arg, *ins);
+                    if new != ro.ranges[id] {
+                        ro.ranges[id] = new;
+                        changed = true;
+                    }
+                }
+            }
+        }
+        if !changed { return true }
+    }
+    false
+}
+
+fn decideranges(op: Opcode, a: Range, b: Range) -> Option<bool> {
+    use Opcode::*;
+    if a.is_empty() || b.is_empty() {
+        return None;
+    }
+    // unsigned comparisons of non-negative values are signed comparisons.
+    let op = match op {
+        ULT if a.lo >= 0 && b.lo >= 0 => LT,
+        ULE if a.lo >= 0 && b.lo >= 0 => LE,
+        op => op
+    };
+    match op {
+        LT if a.hi < b.lo => Some(true),
+        LT if a.lo >= b.hi => Some(false),
+        LE if a.hi <= b.lo => Some(true),
+        LE if a.lo > b.hi => Some(false),
+        EQ if a.lo == a.hi && b.lo == b.hi && a.lo == b.lo => Some(true),
+        EQ if a.hi < b.lo || b.hi < a.lo => Some(false),
+        NE if a.lo == a.hi && b.lo == b.hi && a.lo == b.lo => Some(false),
+        NE if a.hi < b.lo || b.hi < a.lo => Some(true),
+        _ => None
+    }
+}
+
+/* ---- Facts --------------------------------------------------------------- */
+
+fn negate(op: Opcode, a: InsId, b: InsId) -> (Opcode, InsId, InsId) {
+    use Opcode::*;
+    match op {
+        EQ  => (NE, a, b),
+        NE  => (EQ, a, b),
+        LT  => (LE, b, a),
+        LE  => (LT, b, a),
+        ULT => (ULE, b, a),
+        _ /* ULE */ => (ULT, b, a)
+    }
+}
+
+// is `id` computed from its inputs only? if `invariant` is set, PHIs other than arguments are
+// rejected, so that the value is the same everywhere in the function.
+fn ispure(code: &IndexSlice<InsId, Ins>, arg: PhiId, id: InsId, invariant: bool, depth: u32) -> bool {
+    use Opcode::*;
+    let ins = code[id];
+    match ins.opcode() {
+        PHI => !invariant || ins.decode_PHI().1 < arg,
+        LOAD | CALL | ALLOC | ABOX => false,
+        op if op.is_lang() => false,
+        _ if depth >= MAX_PURE_DEPTH => false,
+        _ => ins.inputs().iter().all(|&i| ispure(code, arg, i, invariant, depth+1))
+    }
+}
+
+fn collectfacts(
+    ro: &mut RangeOpt,
+    fs: &mut FuncScratch,
+    code: &IndexSlice<InsId, Ins>,
+    structure: &Structure,
+    entry: InsId,
+    arg: PhiId
+) {
+    use Opcode::*;
+    // mark1 = at least one precedessor, mark2 = more than one.
+    // the entry is also entered by the call, so it starts with one.
+    fs.mark1.clear();
+    fs.mark2.clear();
+    fs.mark1.insert(entry);
+    for ins in &code.raw {
+        if ins.opcode().is_control() {
+            for &c in ins.controls() {
+                if fs.mark1.test_and_set(c) {
+                    fs.mark2.insert(c);
+                }
+            }
+        }
+    }
+    ro.facts.clear();
+    for (id, ins) in code.pairs() {
+        if ins.opcode() != IF || structure.block(id).is_none() { continue }
+        let (cond, tru, fal) = ins.decode_IF();
+        let cins = code[cond];
+        if !matches!(cins.opcode(), EQ|NE|LT|LE|ULT|ULE) { continue }
+        let (a, b) = cins.decode_VV();
+        if tru == fal
+            || !code[a].type_().is_int()
+            || !ispure(code, arg, a, false, 0)
+            || !ispure(code, arg, b, false, 0)
+        {
+            continue
+        }
+        let (nop, na, nb) = negate(cins.opcode(), a, b);
+        for (target, op, a, b) in [(tru, cins.opcode(), a, b), (fal, nop, na, nb)] {
+            if fs.mark2.contains(target) { continue }
+            if let Some(block) = structure.block(target) {
+                ro.facts.push(Fact { op, a, b, block });
+            }
+        }
+    }
+}
+
+impl<'a> Rcx<'a> {
+
+    // Some(true) if a < b, Some(false) if a <= b, None if unknown (signed).
+    fn below(&self, a: InsId, b: InsId) -> Option<bool> {
+        if a == b {
+            return Some(false);
+        }
+        let bins = self.code[b];
+        if bins.opcode() != Opcode::ADD {
+            return None;
+        }
+        let (x, y) = bins.decode_VV();
+        let c = match (x == a, y == a) {
+            (true, _) => y,
+            (_, true) => x,
+            _ => return None
+        };
+        let (ra, rc) = (self.ranges[a], self.ranges[c]);
+        if ra.is_empty() || rc.is_empty() || rc.lo < 0 {
+            return None;
+        }
+        // b = a+c must not wrap.
+        if ra.hi as i128 + rc.hi as i128 > Range::of_type(bins.type_()).hi as i128 {
+            return None;
+        }
+        Some(rc.lo > 0)
+    }
+
+    // does `fact` imply `a op b`?
+    fn implies(&self, fact: &Fact, op: Opcode, a: InsId, b: InsId) -> bool {
+        use Opcode::*;
+        let same = fact.a == a && fact.b == b;
+        let swapped = fact.a == b && fact.b == a;
+        match (fact.op, op) {
+            (EQ, EQ) => same || swapped,
+            (EQ, LE|ULE) => same || swapped,
+            (NE, NE) => same || swapped,
+            (LT|ULT, NE) => same || swapped,
+            (ULT, ULT) | (ULT|ULE, ULE) => same,
+            (LT|LE, LT|LE) => {
+                // a <= fact.a op fact.b <= b
+                let (Some(sa), Some(sb)) = (self.below(a, fact.a), self.below(fact.b, b)) else {
+                    return false
+                };
+                op == LE || fact.op == LT || sa || sb
+            },
+            _ => false
+        }
+    }
+
+    fn holds(&self, op: Opcode, a: InsId, b: InsId, at: BlockId, depth: u32) -> bool {
+        use Opcode::*;
+        if a == b && matches!(op, EQ|LE|ULE) {
+            return true;
+        }
+        if self.facts.iter().any(|f| self.structure.dominates(f.block, at)
+            && self.implies(f, op, a, b))
+        {
+            return true;
+        }
+        // every value jumped to the phi satisfies the comparison.
+        let ains = self.code[a];
+        if depth >= MAX_PHI_DEPTH
+            || ains.opcode() != PHI
+            || !matches!(op, LT|LE|ULT|ULE)
+            || !ispure(self.code, self.arg, b, true, 0)
+        {
+            return false;
+        }
+        let (_, phi) = ains.decode_PHI();
+        if phi < self.arg {
+            return false;
+        }
+        let mut any = false;
+        for (id, ins) in self.code.pairs() {
+            if ins.opcode() != JMP { continue }
+            let (value, _, p) = ins.decode_JMP();
+            if p != phi { continue }
+            let Some(block) = self.structure.block(id) else { continue };
+            if self.decide(op, value, b, block, depth+1) != Some(true) {
+                return false;
+            }
+            any = true;
+        }
+        any
+    }
+
+    fn decide(&self, op: Opcode, a: InsId, b: InsId, at: BlockId, depth: u32) -> Option<bool> {
+        if let Some(r) = decideranges(op, self.ranges[a], self.ranges[b]) {
+            return Some(r);
+        }
+        if self.holds(op, a, b, at, depth) {
+            return Some(true);
+        }
+        let (nop, na, nb) = negate(op, a, b);
+        if self.holds(nop, na, nb, at, depth) {
+            return Some(false);
+        }
+        None
+    }
+
+}
+
+/* -------------------------------------------------------------------------- */
+
+pub fn run(
+    fs: &mut FuncScratch,
+    structure: &Structure,
+    intern: &Intern,
+    func: &mut Func,
+    fid: FuncId,
+    audit: Option<&mut AuditLog>
+) {
+    use Opcode::*;
+    trace!(OPTIMIZE "RANGE {:?}", fid);
+    let mut ro = core::mem::take(&mut fs.range);
+    let code = func.code.inner_mut();
+    ro.ranges.raw.clear();
+    ro.ranges.raw.resize(code.raw.len(), Range::EMPTY);
+    ro.phis.raw.clear();
+    ro.phis.raw.resize(func.phis.end().into(), Range::EMPTY);
+    if !propagate(&mut ro, code, intern, func.arg) {
+        // fall back to type ranges, facts can still decide comparisons.
+        for (id, ins) in code.pairs() {
+            ro.ranges[id] = Range::of_type(ins.type_());
+        }
+    }
+    collectfacts(&mut ro, fs, code, structure, func.entry, func.arg);
+    let mut decided: Vec<(InsId, InsId)> = Vec::new();
+    {
+        let rcx = Rcx { code, structure, ranges: &ro.ranges, facts: &ro.facts, arg: func.arg };
+        for (id, ins) in code.pairs() {
+            if ins.opcode() != IF { continue }
+            let Some(block) = structure.block(id) else { continue };
+            let (cond, tru, fal) = ins.decode_IF();
+            let cins = code[cond];
+            if !matches!(cins.opcode(), EQ|NE|LT|LE|ULT|ULE) { continue }
+            let (a, b) = cins.decode_VV();
+            if !code[a].type_().is_int() { continue }
+            if let Some(r) = rcx.decide(cins.opcode(), a, b, block, 0) {
+                decided.push((id, if r { tru } else { fal }));
+            }
+        }
+    }
+    if let Some(audit) = audit {
+        for &(id, _) in &decided {
+            audit.push(Decision {
+                pass: OptPass::RANGE,
+                func: fid,
+                source: func.source.obj(),
+                rule: "decide",
+                before: id,
+                after: id
+            });
+        }
+    }
+    for (id, target) in decided {
+        trace!(OPTIMIZE "RANGE decide {:?} {:?}  =>  {:?}", id, code[id], target);
+        code[id] = Ins::GOTO(target);
+    }
+    fs.range = ro;
+}
@@ -0,0 +1,135 @@
+//! Unused return value elimination.
+
+// a chunk return is dead when no RES reads it and the chunk doesn't read it back through a PHI.
+// dead returns are dropped from the signature: JMPs to them become GOTOs, the remaining phis
+// are renumbered, and RES phi indices in callers are updated to match. this runs before layout,
+// so dropped returns also don't get a slot.
+//
+// parameters are not touched: chunks only take the instance index, which CALLC always passes,
+// and query signatures are fixed by the query.
+
+use alloc::vec::Vec;
+
+use crate::compile::Ccx;
+use crate::index::{self, IndexOption, IndexVec};
+use crate::ir::{FuncId, FuncKind, Ins, Opcode, PhiId, IR};
+use crate::optimize::{Ocx, Pass};
+use crate::trace::trace;
+use crate::typestate::Absent;
+
+#[derive(Default)]
+pub struct Signature {
+    base: IndexVec<FuncId, usize>,    // start of the function's returns in `ret`
+    ret: Vec<IndexOption<PhiId>>,     // new return phi, or none if dead
+    phis: IndexVec<PhiId, IndexOption<PhiId>>
+}
+
+fn markused(ir: &IR, base: &IndexVec<FuncId, usize>, used: &mut [bool]) {
+    for (fid, func) in ir.funcs.pairs() {
+        let own = matches!(func.kind, FuncKind::Chunk(_));
+        for (_, ins) in func.code.pairs() {
+            match ins.opcode() {
+                Opcode::RES => {
+                    let (call, phi) = ins.decode_RES();
+                    let call = func.code.at(call);
+                    if (Opcode::CALLC|Opcode::CALLCI).contains(call.opcode()) {
+                        let (_, _, f) = call.decode_CALLC();
+                        let phi: usize = phi.into();
+                        used[base[f]+phi] = true;
+                    }
+                },
+                Opcode::PHI if own => {
+                    let (_, phi) = ins.decode_PHI();
+                    if phi < func.ret {
+                        let phi: usize = phi.into();
+                        used[base[fid]+phi] = true;
+                    }
+                },
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Pass for Signature {
+
+    fn new(_: &mut Ccx<Absent>) -> Self {
+        Default::default()
+    }
+
+    fn run(ccx: &mut Ocx) {
+        let sig = &mut ccx.data.sig;
+        let ir = &mut ccx.ir;
+        sig.base.clear();
+        sig.ret.clear();
+        let mut used = Vec::new();
+        for func in &ir.funcs.raw {
+            sig.base.push(used.len());
+            let nret: usize = func.ret.into();
+            used.extend(core::iter::repeat_n(!matches!(func.kind, FuncKind::Chunk(_)), nret));
+        }
+        markused(ir, &sig.base, &mut used);
+        if used.iter().all(|&u| u) {
+            return;
+        }
+        for (fid, func) in ir.funcs.pairs_mut() {
+            let base = sig.base[fid];
+            let nret: usize = func.ret.into();
+            sig.phis.clear();
+            let mut next: PhiId = 0.into();
+            for phi in index::iter_span(func.phis.end()) {
+                let i: usize = phi.into();
+                let live = phi >= func.ret || used[base+i];
+                sig.phis.push(match live {
+                    true => { let p = next; next += 1; Some(p) },
+                    false => None
+                }.into());
+            }
+            sig.ret.extend(sig.phis.raw[..nret].iter().copied());
+            if next == func.phis.end() {
+                continue;
+            }
+            trace!(OPTIMIZE "SIG {:?} drop {} returns", fid,
+                sig.phis.raw[..nret].iter().filter(|p| p.is_none()).count());
+            let mut phi: PhiId = 0.into();
+            func.phis.inner_mut().raw.retain(|_| {
+                let keep = sig.phis[phi].is_some();
+                phi += 1;
+                keep
+            });
+            let nparam: usize = { let arg: usize = func.arg.into(); arg - nret };
+            func.ret = sig.phis.raw[..nret].iter().filter(|p| p.is_some()).count().into();
+            func.arg = func.ret + nparam as isize;
+            for ins in &mut func.code.inner_mut().raw {
+                match ins.opcode() {
+                    Opcode::JMP => {
+                        let (_, dest, phi) = ins.decode_JMP();
+                        match sig.phis[phi].unpack() {
+                            Some(p) => *ins.phi_mut().unwrap() = p,
+                            None => *ins = Ins::GOTO(dest)
+                        }
+                    },
+                    Opcode::PHI => {
+                        let p = ins.phi_mut().unwrap();
+                        *p = sig.phis[*p].unwrap();
+                    },
+                    _ => {}
+                }
+            }
+        }
+        for func in &mut ir.funcs.raw {
+            let code = func.code.inner_mut();
+            for id in index::iter_span(code.end()) {
+                let ins = code[id];
+                if ins.opcode() != Opcode::RES { continue }
+                let (call, phi) = ins.decode_RES();
+                let call = code[call];
+                if !(Opcode::CALLC|Opcode::CALLCI).contains(call.opcode()) { continue }
+                let (_, _, f) = call.decode_CALLC();
+                let phi: usize = phi.into();
+                *code[id].phi_mut().unwrap() = sig.ret[sig.base[f]+phi].unwrap();
+            }
+        }
+    }
+
+}
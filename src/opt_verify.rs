@@ -0,0 +1,170 @@
+//! IR verifier.
+
+// checks structural invariants that the optimizer passes rely on but don't check themselves:
+//   * every instruction and phi reference is in bounds,
+//   * value operands refer to data instructions and control operands to control instructions,
+//   * PHI and JMP types match the phi, and RES types match the callee's return,
+//   * arithmetic and comparison operands have matching types, IF conditions are B1,
+//   * values of pinned instructions are only used in blocks dominated by their own block.
+// this is a debugging aid. it's only run when `Ccx::verify` is set, before the first pass and
+// after every pass after that.
+
+use core::fmt::Write;
+
+use crate::compile::{Ccx, CompileError};
+use crate::controlflow::Structure;
+use crate::index::IndexSlice;
+use crate::ir::{DebugSource, Func, FuncId, Ins, InsId, OpFlag, Opcode, OperandData, Type, IR};
+use crate::optimize::{OptPass, StructureCache};
+use crate::symbol::write_source;
+use crate::typestate::R;
+
+pub struct VerifyError {
+    pass: Option<OptPass>, // pass that broke the invariant, none if it was broken on entry
+    func: FuncId,
+    source: DebugSource,
+    ins: InsId,
+    what: &'static str
+}
+
+impl CompileError for VerifyError {
+    fn write(self, ccx: &mut Ccx<(), R, R>) {
+        ccx.host.buf.write("invalid IR ");
+        match self.pass {
+            Some(pass) => write!(ccx.host.buf, "after {}", pass.name()).unwrap(),
+            None => ccx.host.buf.write("before optimization")
+        }
+        write!(ccx.host.buf, ": {:?} (", self.func).unwrap();
+        write_source(&mut ccx.host.buf, &ccx.intern, &ccx.objs, self.source);
+        let ins: u16 = zerocopy::transmute!(self.ins);
+        write!(ccx.host.buf, ") ins {}: {}", ins, self.what).unwrap();
+    }
+}
+
+fn verifyins(
+    funcs: &IndexSlice<FuncId, Func>,
+    func: &Func,
+    ins: Ins
+) -> Result<(), &'static str> {
+    use Opcode::*;
+    let code = &func.code;
+    let opcode = ins.opcode();
+    for op in ins.operands() {
+        match op {
+            OperandData::V(v) if v >= code.end() => return Err("value operand out of bounds"),
+            OperandData::V(v) if code.at(v).opcode().is_control()
+                => return Err("control instruction used as value"),
+            OperandData::C(c) if c >= code.end() => return Err("control operand out of bounds"),
+            OperandData::C(c) if code.at(c).opcode().is_data()
+                => return Err("data instruction used as control"),
+            OperandData::P(p) if p >= func.phis.end() => return Err("phi out of bounds"),
+            OperandData::F(f) if f >= funcs.end() => return Err("function out of bounds"),
+            _ => {}
+        }
+    }
+    let ty = ins.type_();
+    let vty = |v: InsId| code.at(v).type_();
+    match opcode {
+        PHI => {
+            let (_, phi) = ins.decode_PHI();
+            if func.phis.at(phi).type_ != ty { return Err("PHI type differs from phi") }
+        },
+        JMP => {
+            let (value, _, phi) = ins.decode_JMP();
+            if func.phis.at(phi).type_ != vty(value) {
+                return Err("JMP value type differs from phi");
+            }
+        },
+        IF => {
+            let (cond, _, _) = ins.decode_IF();
+            if vty(cond) != Type::B1 { return Err("IF condition is not B1") }
+        },
+        RES => {
+            let (call, phi) = ins.decode_RES();
+            let call = code.at(call);
+            if (CALLC|CALLCI).contains(call.opcode()) {
+                let (_, _, f) = call.decode_CALLC();
+                let callee = &funcs[f];
+                if phi >= callee.ret { return Err("RES of a non-returned phi") }
+                if callee.phis.at(phi).type_ != ty {
+                    return Err("RES type differs from callee return");
+                }
+            }
+        },
+        _ if opcode.flags().contains(OpFlag::ARITH) => {
+            if ins.inputs().iter().any(|&v| vty(v) != ty) {
+                return Err("arithmetic operand type differs from result");
+            }
+        },
+        _ if opcode.flags().contains(OpFlag::CMP) => {
+            let (a, b) = ins.decode_VV();
+            if vty(a) != vty(b) { return Err("comparison operand types differ") }
+            if ty != Type::B1 { return Err("comparison result is not B1") }
+        },
+        SELECT => {
+            let (cond, tru, fal) = ins.decode_SELECT();
+            if vty(cond) != Type::B1 { return Err("SELECT condition is not B1") }
+            if vty(tru) != ty || vty(fal) != ty {
+                return Err("SELECT operand type differs from result");
+            }
+        },
+        _ => {}
+    }
+    Ok(())
+}
+
+fn verifypins(func: &Func, structure: &Structure) -> Result<(), (InsId, &'static str)> {
+    for (id, ins) in func.code.pairs() {
+        let op = ins.opcode();
+        let ctr = if op.is_control() {
+            id
+        } else if op.is_pinned() {
+            ins.decode_C()
+        } else {
+            continue
+        };
+        let Some(block) = structure.block(ctr) else { continue };
+        for &v in ins.inputs() {
+            let vins = func.code.at(v);
+            if !vins.opcode().is_pinned() { continue }
+            if let Some(vblock) = structure.block(vins.decode_C()) {
+                if !structure.dominates(vblock, block) {
+                    return Err((id, "pinned value used outside its dominated blocks"));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn verifyfunc(
+    funcs: &IndexSlice<FuncId, Func>,
+    fid: FuncId,
+    func: &Func,
+    structure: &mut StructureCache
+) -> Result<(), (InsId, &'static str)> {
+    if func.entry >= func.code.end() || func.code.at(func.entry).opcode().is_data() {
+        return Err((func.entry, "entry is not a control instruction"));
+    }
+    if func.ret > func.arg || func.arg > func.phis.end() {
+        return Err((func.entry, "bad signature"));
+    }
+    for (id, ins) in func.code.pairs() {
+        verifyins(funcs, func, ins).map_err(|e| (id, e))?;
+    }
+    // dominance is only computed once the control operands are known to be valid.
+    verifypins(func, structure.get(fid, func))
+}
+
+pub fn verify(
+    ir: &IR,
+    structure: &mut StructureCache,
+    pass: Option<OptPass>
+) -> Result<(), VerifyError> {
+    for (fid, func) in ir.funcs.pairs() {
+        if let Err((ins, what)) = verifyfunc(&ir.funcs, fid, func, structure) {
+            return Err(VerifyError { pass, func: fid, source: func.source, ins, what });
+        }
+    }
+    Ok(())
+}
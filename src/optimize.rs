@@ -2,51 +2,81 @@
 
 // TODO passes:
 // * conditional constant propagation
-// * remove unused parameters and return values
-// * merge identical functions
 // * merge functions with identical callers
 // * outline instance-invariant code
 // * loop optimizations: code motion, fusion
-// * load-store elimination and dead store elimination
 
+use alloc::vec::Vec;
 use enumset::{EnumSet, EnumSetType};
 
+use crate::audit::AuditLog;
+use crate::bump::Bump;
+use crate::callgraph::CallGraph;
 use crate::compile::{self, Ccx, Stage};
-use crate::controlflow::ControlFlow;
-use crate::dump::dump_ir;
-use crate::index::IndexSet;
-use crate::{index, opt_control};
-use crate::ir::{FuncId, PhiId, IR};
+use crate::controlflow::{ControlFlow, Structure};
+use crate::crash;
+use crate::dump::{dump_ir, dump_ir_dot};
+use crate::index::{IndexSet, IndexVec};
+use crate::interval::{self, IntervalCheck};
+use crate::{index, opt_cfg, opt_control, opt_mem, opt_range, opt_verify};
+use crate::ir::{Func, FuncId, FuncKind, InsId, PhiId, IR};
 use crate::opt_fold::Fold;
 use crate::opt_inline::Inline;
-use crate::trace::trace;
+use crate::opt_mem::MemOpt;
+use crate::opt_merge::Merge;
+use crate::opt_range::RangeOpt;
+use crate::opt_sig::Signature;
+use crate::opt_verify::VerifyError;
+#[cfg(feature="threads")]
+use crate::parallel;
+use crate::remap::FuncMap;
+use crate::session::{IrPoint, OptLevel};
+use crate::trace::{trace, trace_span};
 use crate::typestate::{Absent, R};
 
-const MAX_ITER: usize = 100; // TODO: make this configurable
-
-#[derive(EnumSetType)]
+#[derive(EnumSetType, Debug)]
 pub enum OptFlag {
     CCP,
+    CFG,
     FOLD,
     GOTO,
     INLINE,
     LOOP,
+    MEM,
+    MERGE,
+    PEEP,
     PHI,
-    SWITCH
+    RANGE,
+    SIG,
+    SWITCH,
+    VECTOR
 }
 
+// compatibility string form of `Options::flags`: one letter per flag, a digit for the flags of
+// an `OptLevel`, `a` for everything. a leading `-` inverts the set.
 pub fn parse_optflags(flags: &[u8]) -> EnumSet<OptFlag> {
     use OptFlag::*;
     let mut oflg: EnumSet<OptFlag> = EnumSet::empty();
     for &f in flags {
+        if let Some(level) = OptLevel::from_u8(f.wrapping_sub(b'0')) {
+            oflg.insert_all(level.flags());
+            continue;
+        }
         oflg.insert_all(match f {
+            b'b' => CFG.into(),
             b'c' => CCP.into(),
             b'f' => FOLD.into(),
             b'g' => GOTO.into(),
             b'i' => INLINE.into(),
             b'l' => LOOP.into(),
+            b'm' => MEM.into(),
+            b'd' => MERGE.into(),
+            b'e' => PEEP.into(),
             b'p' => PHI.into(),
+            b'n' => RANGE.into(),
+            b'r' => SIG.into(),
             b's' => SWITCH.into(),
+            b'v' => VECTOR.into(),
             b'a' => EnumSet::all(),
             _ => continue
         });
@@ -57,16 +87,138 @@ pub fn parse_optflags(flags: &[u8]) -> EnumSet<OptFlag> {
     oflg
 }
 
+// passes that can be ordered in the pipeline.
+// control, mem, fold and cfg are function passes, the rest are whole-IR passes.
+#[derive(EnumSetType, Debug)]
+pub enum OptPass {
+    INLINE,
+    MERGE,
+    SIG,
+    CONTROL,
+    MEM,
+    FOLD,
+    RANGE,
+    CFG
+}
+
+pub const NUM_PASS: usize = 8;
+
+impl OptPass {
+
+    pub fn name(self) -> &'static str {
+        use OptPass::*;
+        match self {
+            INLINE  => "inline",
+            MERGE   => "merge",
+            SIG     => "sig",
+            CONTROL => "control",
+            MEM     => "mem",
+            FOLD    => "fold",
+            RANGE   => "range",
+            CFG     => "cfg"
+        }
+    }
+
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        EnumSet::<Self>::all().iter().find(|p| p.name().as_bytes() == name)
+    }
+
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct PassStats {
+    pub runs: u32,
+    pub removed: i64, // instructions removed (negative if the pass added instructions)
+    pub time: u64     // nanoseconds (always zero without the `std` feature)
+}
+
+// pass order, iteration limit, statistics and decision log of the last optimizer run.
+// OptFlags still apply: a pass runs only if it's in `passes` and its flags are enabled.
+pub struct Pipeline {
+    pub passes: Vec<OptPass>,
+    pub max_iter: u32,
+    pub stats: [PassStats; NUM_PASS],
+    pub audit: AuditLog
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        use OptPass::*;
+        Self {
+            passes: [INLINE, MERGE, SIG, CONTROL, MEM, FOLD, RANGE, CFG].into(),
+            max_iter: 100,
+            stats: Default::default(),
+            audit: Default::default()
+        }
+    }
+}
+
+impl Pipeline {
+
+    pub fn enable(&mut self, pass: OptPass, on: bool) {
+        let pos = self.passes.iter().position(|&p| p == pass);
+        match (pos, on) {
+            (None, true) => self.passes.push(pass),
+            (Some(i), false) => { self.passes.remove(i); },
+            _ => {}
+        }
+    }
+
+    pub fn stats(&self, pass: OptPass) -> &PassStats {
+        &self.stats[pass as usize]
+    }
+
+}
+
 // TODO: remove *Pass traits and derive default here
 pub struct Optimize {
     pub fold: Fold,
     pub inline: Inline,
-    pub cf: ControlFlow, // TODO: make opt_inline use this
-    pub phi_mark: IndexSet<PhiId>
+    pub func: FuncScratch,
+    pub merge: Merge,
+    pub sig: Signature,
+    pub icheck: IntervalCheck,
+    pub structure: StructureCache,
+    pub callgraph: CallGraph,
+    pub funcmap: FuncMap
 }
 
 pub type Ocx<'a> = Ccx<Optimize, R<'a>>;
 
+// dominator trees and loop forests of each function, see controlflow::Structure.
+#[derive(Default)]
+pub struct StructureCache {
+    funcs: IndexVec<FuncId, Structure>
+}
+
+impl StructureCache {
+
+    // structure of `func`, recomputed only if its control flow changed since the last call.
+    pub fn get(&mut self, fid: FuncId, func: &Func) -> &Structure {
+        while self.funcs.end() <= fid {
+            self.funcs.push(Default::default());
+        }
+        let structure = &mut self.funcs[fid];
+        structure.update(func);
+        structure
+    }
+
+}
+
+// state of the function-local passes (control, mem, range, cfg). these only touch the function
+// they are optimizing, so with the `threads` feature each worker gets its own copy and functions
+// are optimized in parallel.
+#[derive(Default)]
+pub struct FuncScratch {
+    pub cf: ControlFlow, // TODO: make opt_inline use this
+    pub phi_mark: IndexSet<PhiId>,
+    pub mem: MemOpt,
+    pub range: RangeOpt,
+    pub tmp: Bump,
+    pub mark1: IndexSet<InsId>,
+    pub mark2: IndexSet<InsId>
+}
+
 pub trait FuncPass: Sized {
     fn new(ccx: &mut Ccx<Absent>) -> Self;
     fn run(ccx: &mut Ocx, fid: FuncId);
@@ -77,19 +229,135 @@ pub trait Pass: Sized {
     fn run(ccx: &mut Ocx);
 }
 
-fn optimize(ocx: &mut Ocx) {
+fn inscount(ir: &IR) -> i64 {
+    ir.funcs.raw.iter().map(|f| { let n: usize = f.code.end().into(); n as i64 }).sum()
+}
+
+fn runpass(ocx: &mut Ocx, pass: OptPass) -> Result<(), VerifyError> {
+    #[cfg(feature="std")]
+    extern crate std;
     use OptFlag::*;
-    if ocx.flags.contains(INLINE) {
-        Inline::run(ocx);
+    let flags = match pass {
+        OptPass::INLINE  => INLINE.into(),
+        OptPass::MERGE   => MERGE.into(),
+        OptPass::SIG     => SIG.into(),
+        OptPass::CONTROL => SWITCH|LOOP|PHI|CCP|GOTO,
+        OptPass::MEM     => MEM.into(),
+        OptPass::FOLD    => FOLD.into(),
+        OptPass::RANGE   => RANGE.into(),
+        OptPass::CFG     => CFG.into()
+    };
+    if (ocx.session.flags & flags).is_empty() {
+        return Ok(());
     }
-    for fid in index::iter_span(ocx.ir.funcs.end()) {
-        if !(ocx.flags & (SWITCH|LOOP|PHI|CCP|GOTO)).is_empty() {
-            opt_control::run(ocx, fid);
+    let _span = trace_span!("{}", pass.name());
+    crash::pass(pass.name());
+    #[cfg(feature="std")]
+    let start = std::time::Instant::now();
+    let size = inscount(&ocx.ir);
+    if ocx.session.audit {
+        ocx.pipeline.audit.begin(&ocx.ir);
+    }
+    match pass {
+        OptPass::INLINE => Inline::run(ocx),
+        OptPass::MERGE  => Merge::run(ocx),
+        OptPass::SIG    => Signature::run(ocx),
+        OptPass::CONTROL | OptPass::MEM | OptPass::CFG => funcpass(ocx, pass),
+        // range needs the cached dominator trees, so it runs sequentially.
+        OptPass::RANGE => {
+            let Optimize { func: fs, structure, .. } = &mut *ocx.data;
+            let mut audit = match ocx.session.audit {
+                true => Some(&mut ocx.pipeline.audit),
+                false => None
+            };
+            for (fid, func) in ocx.ir.funcs.pairs_mut() {
+                let _span = trace_span!("{} {:?}", pass.name(), fid);
+                opt_range::run(fs, structure.get(fid, func), &ocx.intern, func, fid,
+                    audit.as_deref_mut());
+            }
+        },
+        // fold interns new constants into the shared intern table, so it stays sequential.
+        _ /* FOLD */ => for fid in index::iter_span(ocx.ir.funcs.end()) {
+            let _span = trace_span!("{} {:?}", pass.name(), fid);
+            Fold::run(ocx, fid)
         }
-        if ocx.flags.contains(FOLD) {
-            Fold::run(ocx, fid);
+    }
+    let stats = &mut ocx.pipeline.stats[pass as usize];
+    stats.runs += 1;
+    stats.removed += size - inscount(&ocx.ir);
+    #[cfg(feature="std")]
+    { stats.time += start.elapsed().as_nanos() as u64; }
+    if ocx.session.audit {
+        ocx.pipeline.audit.end(&ocx.ir, pass);
+    }
+    ocx.session.observe(IrPoint::Pass(pass), &ocx.ir, &ocx.intern, &ocx.objs);
+    if ocx.session.verify {
+        opt_verify::verify(&ocx.ir, &mut ocx.data.structure, Some(pass))?;
+    }
+    Ok(())
+}
+
+fn runfunc(fs: &mut FuncScratch, flags: EnumSet<OptFlag>, pass: OptPass, fid: FuncId, func: &mut Func) {
+    let _span = trace_span!("{} {:?}", pass.name(), fid);
+    match pass {
+        OptPass::CONTROL => opt_control::run(fs, flags, func, fid),
+        OptPass::CFG     => opt_cfg::run(fs, func, fid),
+        _ /* MEM */      => opt_mem::run(&mut fs.mem, func, fid)
+    }
+}
+
+#[cfg(not(feature="threads"))]
+fn funcpass(ocx: &mut Ocx, pass: OptPass) {
+    let flags = ocx.session.flags;
+    let fs = &mut ocx.data.func;
+    for (fid, func) in ocx.ir.funcs.pairs_mut() {
+        runfunc(fs, flags, pass, fid, func);
+    }
+}
+
+#[cfg(feature="threads")]
+fn funcpass(ocx: &mut Ocx, pass: OptPass) {
+    let flags = ocx.session.flags;
+    let mut funcs: Vec<(FuncId, &mut Func)> = ocx.ir.funcs.pairs_mut().collect();
+    parallel::for_each(&mut funcs, FuncScratch::default,
+        |fs, (fid, func)| runfunc(fs, flags, pass, *fid, func));
+}
+
+fn optimize(ocx: &mut Ocx) -> Result<(), VerifyError> {
+    for i in 0..ocx.pipeline.passes.len() {
+        let pass = ocx.pipeline.passes[i];
+        runpass(ocx, pass)?;
+    }
+    Ok(())
+}
+
+// drop functions that no query reaches and renumber the rest. passes delete calls without
+// deleting the callees, and inlining only sweeps once before the other passes run, so this runs
+// once more after the pipeline.
+fn sweepfuncs(ocx: &mut Ocx) {
+    let Optimize { callgraph, funcmap, .. } = &mut *ocx.data;
+    let ir = &mut ocx.ir;
+    callgraph.build(ir);
+    let mut live: IndexSet<FuncId> = Default::default();
+    let mut stack: Vec<FuncId> = Vec::new();
+    for (fid, func) in ir.funcs.pairs() {
+        if !matches!(func.kind, FuncKind::Chunk(_)) {
+            live.insert(fid);
+            stack.push(fid);
         }
     }
+    while let Some(f) = stack.pop() {
+        for &g in callgraph.callees(f) {
+            if !live.test_and_set(g) {
+                stack.push(g);
+            }
+        }
+    }
+    let dead = funcmap.build(ir.funcs.end(), |f| live.contains(f));
+    if dead > 0 {
+        trace!(OPTIMIZE "sweep: {} dead functions", dead);
+        funcmap.apply(ir, |f| f);
+    }
 }
 
 // TODO: replace this with a sparse hash?
@@ -104,32 +372,58 @@ impl Stage for Optimize {
         Ok(Self {
             fold: Fold::new(ccx),
             inline: Inline::new(ccx),
-            cf: Default::default(),
-            phi_mark: Default::default()
+            func: Default::default(),
+            merge: Merge::new(ccx),
+            sig: Signature::new(ccx),
+            icheck: Default::default(),
+            structure: Default::default(),
+            callgraph: Default::default(),
+            funcmap: Default::default()
         })
     }
 
     fn run(ocx: &mut Ccx<Optimize>) -> compile::Result {
+        if ocx.session.icheck.is_some() {
+            ocx.data.icheck.snapshot(&ocx.ir, &ocx.intern);
+        }
         let mut size = irsize(&ocx.ir);
-        ocx.freeze_graph(|ocx| {
-            for i in 0..MAX_ITER {
-                optimize(ocx);
+        ocx.pipeline.stats = Default::default();
+        ocx.pipeline.audit.clear();
+        let result = ocx.freeze_graph(|ocx| {
+            if ocx.session.verify {
+                opt_verify::verify(&ocx.ir, &mut ocx.data.structure, None)?;
+            }
+            for i in 0..ocx.pipeline.max_iter {
+                optimize(ocx)?;
                 let newsize = irsize(&ocx.ir);
                 if size == newsize || newsize == 0 {
                     trace!(OPTIMIZE "converged in {} iterations", i+1);
                     break
                 } else {
                     trace!(OPTIMIZE "IR size {} -> {}", size, newsize);
-                    if trace!(OPTIMIZE) && !ocx.flags.is_empty() {
+                    if trace!(OPTIMIZE) && !ocx.session.flags.is_empty() {
                         let mut tmp = Default::default();
-                        dump_ir(&mut tmp, &ocx.ir, &ocx.intern, &ocx.objs);
+                        if trace!(DOT) {
+                            dump_ir_dot(&mut tmp, &ocx.ir, &ocx.intern, &ocx.objs);
+                        } else {
+                            dump_ir(&mut tmp, &ocx.ir, &ocx.intern, &ocx.objs);
+                        }
                         trace!("{}", core::str::from_utf8(tmp.as_slice()).unwrap());
                     }
                     size = newsize;
                 }
             }
+            sweepfuncs(ocx);
+            ocx.session.observe(IrPoint::Optimize, &ocx.ir, &ocx.intern, &ocx.objs);
+            Ok(())
         });
-        Ok(())
+        if let Err(e) = result {
+            return ocx.error(e);
+        }
+        match ocx.session.icheck {
+            Some(tol) => interval::check(ocx, tol),
+            None => Ok(())
+        }
     }
 
 }
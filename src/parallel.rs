@@ -0,0 +1,130 @@
+//! Thread pool for per-function work.
+
+// the workers are started on first use and live until the process exits, so passes that run
+// several times per compilation don't start new threads on every call.
+// one job runs at a time: the caller publishes it, runs it on its own thread too, and then waits
+// until every worker is done with it. the job borrows the caller's stack, which is fine because
+// the caller doesn't return before the workers have let go of it. jobs must not start jobs.
+
+extern crate std;
+
+use core::any::Any;
+use core::mem::transmute;
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+use std::sync::{Condvar, Mutex, OnceLock, PoisonError};
+
+use alloc::boxed::Box;
+
+type Job = *const (dyn Fn() + Sync + 'static);
+
+struct State {
+    job: Option<Job>,
+    seq: u64,       // incremented for each job
+    running: usize, // workers that haven't finished the current job
+    panic: Option<Box<dyn Any + Send>>
+}
+
+// safety: `job` is only dereferenced while the caller that published it is waiting.
+unsafe impl Send for State {}
+
+struct Pool {
+    call: Mutex<()>,
+    state: Mutex<State>,
+    start: Condvar,
+    done: Condvar
+}
+
+static POOL: Pool = Pool {
+    call: Mutex::new(()),
+    state: Mutex::new(State { job: None, seq: 0, running: 0, panic: None }),
+    start: Condvar::new(),
+    done: Condvar::new()
+};
+
+fn worker() {
+    let mut seen = 0;
+    let mut state = POOL.state.lock().unwrap();
+    loop {
+        while state.seq == seen {
+            state = POOL.start.wait(state).unwrap();
+        }
+        seen = state.seq;
+        let job = state.job.unwrap();
+        drop(state);
+        // safety: the caller keeps the job alive until `running` is zero.
+        let res = catch_unwind(AssertUnwindSafe(|| unsafe { (*job)() }));
+        state = POOL.state.lock().unwrap();
+        if let Err(e) = res {
+            state.panic.get_or_insert(e);
+        }
+        state.running -= 1;
+        if state.running == 0 {
+            POOL.done.notify_all();
+        }
+    }
+}
+
+// number of worker threads, not counting the caller.
+fn workers() -> usize {
+    static WORKERS: OnceLock<usize> = OnceLock::new();
+    *WORKERS.get_or_init(|| {
+        let num = std::thread::available_parallelism().map_or(1, |n| n.get()) - 1;
+        let mut spawned = 0;
+        while spawned < num
+            && std::thread::Builder::new().name("fhk-worker".into()).spawn(worker).is_ok()
+        {
+            spawned += 1;
+        }
+        spawned
+    })
+}
+
+// run `job` on the caller and every worker thread, and wait for all of them to finish.
+fn broadcast(job: &(dyn Fn() + Sync)) {
+    let workers = workers();
+    let _call = POOL.call.lock().unwrap_or_else(PoisonError::into_inner);
+    // safety: the pointer is cleared below before this function returns or unwinds.
+    let ptr: Job = unsafe { transmute(job) };
+    {
+        let mut state = POOL.state.lock().unwrap();
+        state.job = Some(ptr);
+        state.seq += 1;
+        state.running = workers;
+    }
+    POOL.start.notify_all();
+    let res = catch_unwind(AssertUnwindSafe(job));
+    let mut state = POOL.state.lock().unwrap();
+    while state.running > 0 {
+        state = POOL.done.wait(state).unwrap();
+    }
+    state.job = None;
+    let panic = state.panic.take();
+    drop(state);
+    if let Err(e) = res { resume_unwind(e) }
+    if let Some(e) = panic { resume_unwind(e) }
+}
+
+// run `work` on each item. every thread that gets an item creates its own state with `init`.
+// items are handed out one at a time, so uneven work sizes balance out.
+pub fn for_each<T, S>(
+    items: &mut [T],
+    init: impl Fn() -> S + Sync,
+    work: impl Fn(&mut S, &mut T) + Sync
+) where T: Send {
+    if items.len() <= 1 || workers() == 0 {
+        let mut state = init();
+        for item in items { work(&mut state, item); }
+        return;
+    }
+    let queue = Mutex::new(items.iter_mut());
+    broadcast(&|| {
+        let mut state = None;
+        loop {
+            let next = queue.lock().unwrap().next();
+            match next {
+                Some(item) => work(state.get_or_insert_with(&init), item),
+                None => break
+            }
+        }
+    });
+}
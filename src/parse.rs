@@ -1,5 +1,6 @@
 //! Source -> Graph.
 
+use alloc::vec::Vec;
 use core::cmp::max;
 use core::iter::repeat_n;
 use core::mem::replace;
@@ -12,9 +13,11 @@ use crate::compile;
 use crate::err::ErrorMessage;
 use crate::intern::IRef;
 use crate::lang::Lang;
-use crate::lex::Token;
-use crate::obj::{cast_args, BinOp, Intrinsic, LookupEntry, Obj, ObjRef, ObjectRef, BINOP, CALLX, CAT, DIM, EXPR, FLAT, GET, INTR, KINT, LEN, LOAD, MOD, SPLAT, TAB, TPRI, TTEN, TUPLE, VAR, VGET, VSET};
+use crate::lang_Host;
+use crate::lex::{self, typedvalue, Token};
+use crate::obj::{cast_args, BinOp, Intrinsic, LookupEntry, Obj, ObjRef, ObjectRef, BINOP, CALLX, CAT, DIM, EXPR, FLAT, GET, INTR, KDATA, KFP64, KINT, KINT64, LEN, LOAD, MOD, SPLAT, TAB, TPRI, TTEN, TUPLE, VAR, VGET, VSET};
 use crate::parser::{check, consume, defmacro, next, parse_name, parse_name_pattern, pushmacro, require, save, syntaxerr, Binding, DefinitionError, DefinitionErrorType, LangError, Namespace, ParenCounter, Pcx, TokenError};
+use crate::support::pow;
 use crate::typing::Primitive;
 
 const TOPLEVEL_KEYWORDS: EnumSet<Token> = enum_set!(
@@ -82,6 +85,140 @@ fn parse_vref(pcx: &mut Pcx) -> compile::Result<ObjRef<VAR>> {
     Ok(refvar(pcx, tab, name))
 }
 
+// memo(expr) -> anonymous variable with a single model computing `expr`.
+// the value is stored in the variable's slot, so it's computed at most once per instance.
+// equal expressions in the same table share the variable.
+fn newmemo(pcx: &mut Pcx, value: ObjRef<EXPR>) -> ObjRef<EXPR> {
+    let tab = pcx.data.tab;
+    debug_assert!(!tab.is_nil());
+    let memo = pcx.data.memo.iter()
+        .find(|&&(t, v, _)| t == tab && pcx.objs.equal(v.erase(), value.erase()))
+        .map(|&(_, _, var)| var);
+    let var = match memo {
+        Some(var) => var,
+        None => {
+            let var = pcx.objs.push(VAR::new(IRef::EMPTY, tab, ObjRef::NIL));
+            let vset = pcx.objs.push_args::<VSET>(VSET::new(0, var, value), &[]);
+            pcx.objs.push_args::<MOD>(MOD::new(IRef::EMPTY, tab, ObjRef::NIL.cast()), &[vset]);
+            pcx.data.memo.push((tab, value, var));
+            var
+        }
+    };
+    pcx.objs.push_args::<VGET>(VGET::new(0, ObjRef::NIL, var), &[]).cast()
+}
+
+fn newkint(pcx: &mut Pcx, k: i32) -> ObjRef<EXPR> {
+    pcx.objs.push(KINT::new(ObjRef::NIL, k)).cast()
+}
+
+fn newbinop(pcx: &mut Pcx, op: BinOp, left: ObjRef<EXPR>, right: ObjRef<EXPR>) -> ObjRef<EXPR> {
+    pcx.objs.push(BINOP::new(op as _, ObjRef::NIL, left, right)).cast()
+}
+
+fn newif(pcx: &mut Pcx, cond: ObjRef<EXPR>, tru: ObjRef<EXPR>, fal: ObjRef<EXPR>) -> ObjRef<EXPR> {
+    pcx.objs.push_args::<INTR>(INTR::new(Intrinsic::IF as _, ObjRef::NIL), &[cond, tru, fal]).cast()
+}
+
+// year, month or day of `days` since 1970-01-01, in integer arithmetic:
+//   z   = days + 719468            (days since 0000-03-01)
+//   era = z / 146097               (400-year cycles)
+//   doe = z - era*146097           (day of era)
+//   yoe = (doe - doe/1460 + doe/36524 - doe/146096) / 365
+//   doy = doe - (365*yoe + yoe/4 - yoe/100)
+//   mp  = (5*doy + 2) / 153        (month, counting from march)
+// valid from 0000-03-01 on, where the divisions don't need to round down.
+#[derive(Clone, Copy)]
+enum DatePart { Year, Month, Day }
+
+fn civilpart(pcx: &mut Pcx, days: ObjRef<EXPR>, part: DatePart) -> ObjRef<EXPR> {
+    use BinOp::*;
+    let k = |pcx: &mut Pcx, k| newkint(pcx, k);
+    let z = { let o = k(pcx, 719468); newbinop(pcx, ADD, days, o) };
+    let era = { let o = k(pcx, 146097); newbinop(pcx, DIV, z, o) };
+    let doe = { let o = k(pcx, 146097); let e = newbinop(pcx, MUL, era, o); newbinop(pcx, SUB, z, e) };
+    let yoe = {
+        let (k1460, k36524, k146096, k365) = (k(pcx, 1460), k(pcx, 36524), k(pcx, 146096), k(pcx, 365));
+        let a = newbinop(pcx, DIV, doe, k1460);
+        let b = newbinop(pcx, DIV, doe, k36524);
+        let c = newbinop(pcx, DIV, doe, k146096);
+        let x = newbinop(pcx, SUB, doe, a);
+        let x = newbinop(pcx, ADD, x, b);
+        let x = newbinop(pcx, SUB, x, c);
+        newbinop(pcx, DIV, x, k365)
+    };
+    let doy = {
+        let (k365, k4, k100) = (k(pcx, 365), k(pcx, 4), k(pcx, 100));
+        let a = newbinop(pcx, MUL, k365, yoe);
+        let b = newbinop(pcx, DIV, yoe, k4);
+        let c = newbinop(pcx, DIV, yoe, k100);
+        let x = newbinop(pcx, ADD, a, b);
+        let x = newbinop(pcx, SUB, x, c);
+        newbinop(pcx, SUB, doe, x)
+    };
+    let mp = {
+        let (k5, k2, k153) = (k(pcx, 5), k(pcx, 2), k(pcx, 153));
+        let x = newbinop(pcx, MUL, k5, doy);
+        let x = newbinop(pcx, ADD, x, k2);
+        newbinop(pcx, DIV, x, k153)
+    };
+    let k10 = k(pcx, 10);
+    let spring = newbinop(pcx, LT, mp, k10);
+    match part {
+        DatePart::Year => {
+            let k400 = k(pcx, 400);
+            let y = newbinop(pcx, MUL, era, k400);
+            let y = newbinop(pcx, ADD, yoe, y);
+            let (k0, k1) = (k(pcx, 0), k(pcx, 1));
+            let adj = newif(pcx, spring, k0, k1);
+            newbinop(pcx, ADD, y, adj)
+        },
+        DatePart::Month => {
+            let (k3, k9) = (k(pcx, 3), k(pcx, 9));
+            let a = newbinop(pcx, ADD, mp, k3);
+            let b = newbinop(pcx, SUB, mp, k9);
+            newif(pcx, spring, a, b)
+        },
+        DatePart::Day => {
+            let (k153, k2, k5, k1) = (k(pcx, 153), k(pcx, 2), k(pcx, 5), k(pcx, 1));
+            let x = newbinop(pcx, MUL, k153, mp);
+            let x = newbinop(pcx, ADD, x, k2);
+            let x = newbinop(pcx, DIV, x, k5);
+            let x = newbinop(pcx, SUB, doy, x);
+            newbinop(pcx, ADD, x, k1)
+        }
+    }
+}
+
+fn newabs(pcx: &mut Pcx, x: ObjRef<EXPR>) -> ObjRef<EXPR> {
+    let k0 = newkint(pcx, 0);
+    let neg = newbinop(pcx, BinOp::LT, x, k0);
+    let minus = newbinop(pcx, BinOp::SUB, k0, x);
+    newif(pcx, neg, minus, x)
+}
+
+// round(p/q), half away from zero, in integer arithmetic. the remainder is compared against what's
+// left of the divisor instead of doubling it, so this doesn't overflow for any p:
+//   t = p/q, r = |p - t*q|, d = |q|
+//   t + (if r < d-r then 0 else if (p < 0) == (q < 0) then 1 else -1)
+fn rounddiv(pcx: &mut Pcx, p: ObjRef<EXPR>, q: ObjRef<EXPR>) -> ObjRef<EXPR> {
+    use BinOp::*;
+    let (k0, k1, km1) = (newkint(pcx, 0), newkint(pcx, 1), newkint(pcx, -1));
+    let t = newbinop(pcx, DIV, p, q);
+    let tq = newbinop(pcx, MUL, t, q);
+    let r = newbinop(pcx, SUB, p, tq);
+    let r = newabs(pcx, r);
+    let d = newabs(pcx, q);
+    let rest = newbinop(pcx, SUB, d, r);
+    let down = newbinop(pcx, LT, r, rest);
+    let pneg = newbinop(pcx, LT, p, k0);
+    let qneg = newbinop(pcx, LT, q, k0);
+    let ifpneg = newif(pcx, qneg, k1, km1);
+    let ifppos = newif(pcx, qneg, km1, k1);
+    let away = newif(pcx, pneg, ifpneg, ifppos);
+    let adj = newif(pcx, down, k0, away);
+    newbinop(pcx, ADD, t, adj)
+}
+
 fn builtincall(pcx: &mut Pcx, name: IRef<[u8]>, base: BumpRef<u8>) -> Option<ObjRef<EXPR>> {
     const IDENT: u8 = Token::Ident as _;
     const INT: u8 = Token::Int as _;
@@ -124,10 +261,81 @@ fn builtincall(pcx: &mut Pcx, name: IRef<[u8]>, base: BumpRef<u8>) -> Option<Obj
             };
             Some(pcx.objs.push(LEN::new(dim as _, ObjRef::NIL, args[0])).cast())
         },
+        b"memo" if rest.is_empty() && args.len() == 1 => {
+            let value = args[0];
+            Some(newmemo(pcx, value))
+        },
+        // dates are i32 days since 1970-01-01, see lex::parsedate.
+        b"year" | b"month" | b"day" if rest.is_empty() && args.len() == 1 => {
+            let days = args[0];
+            let part = match stem {
+                b"year" => DatePart::Year,
+                b"month" => DatePart::Month,
+                _ => DatePart::Day
+            };
+            Some(civilpart(pcx, days, part))
+        },
+        // fixed-point decimals are integers scaled by 10^s. addition and subtraction are exact,
+        // multiplication and division round half away from zero:
+        //   fixmul(a, b, s) = round(a*b / 10^s)
+        //   fixdiv(a, b, s) = round(a*10^s / b)
+        // the operands must be integers, and the scale a constant.
+        b"fixmul" | b"fixdiv" if rest.is_empty() && args.len() == 3 => {
+            let (a, b) = (args[0], args[1]);
+            let mul = stem == b"fixmul";
+            let s = evalconst(pcx, args[2], None)?;
+            if !(0.0..=18.0).contains(&s) || s.fract() != 0.0 { return None }
+            let scale = match 10i64.pow(s as u32) {
+                k if k == k as i32 as i64 => newkint(pcx, k as _),
+                k => {
+                    let k: u32 = zerocopy::transmute!(pcx.intern.intern(&k.to_ne_bytes()).to_bump());
+                    let mut o = KINT::new(ObjRef::NIL, k as _);
+                    o.op = Obj::KINT64;
+                    pcx.objs.push(o).cast()
+                }
+            };
+            Some(match mul {
+                true => {
+                    let p = newbinop(pcx, BinOp::MUL, a, b);
+                    rounddiv(pcx, p, scale)
+                },
+                false => {
+                    let p = newbinop(pcx, BinOp::MUL, a, scale);
+                    rounddiv(pcx, p, b)
+                }
+            })
+        },
+        // poly(x, [c0, c1, ..., cn]) = c0 + x*(c1 + x*(... + x*cn)).
+        // the coefficients must be a literal list, fold takes care of constant x.
+        b"poly" if rest.is_empty() && args.len() == 2 => {
+            let x = args[0];
+            let ObjectRef::CAT(CAT { elems, .. }) = pcx.objs.get(args[1].erase()) else {
+                return None
+            };
+            if elems.iter().any(|&e| pcx.objs[e].op == Obj::SPLAT) { return None }
+            let coef: Vec<ObjRef<EXPR>> = elems.iter().copied().collect();
+            let Some((&last, rest)) = coef.split_last() else {
+                return Some(pcx.objs.push(KINT::new(ObjRef::NIL, 0)).cast())
+            };
+            let mut value = last;
+            for &c in rest.iter().rev() {
+                let mul = pcx.objs.push(BINOP::new(BinOp::MUL as _, ObjRef::NIL, x, value)).cast();
+                value = pcx.objs.push(BINOP::new(BinOp::ADD as _, ObjRef::NIL, c, mul)).cast();
+            }
+            Some(value)
+        },
         _ => None
     }
 }
 
+fn hostfunc(pcx: &Pcx, name: IRef<[u8]>) -> Option<usize> {
+    const IDENT: u8 = Token::Ident as _;
+    let name @ [IDENT, _, _, _, _] = pcx.intern.get_slice(name.cast()) else { return None };
+    let stem: [u8; 4] = name[1..5].try_into().unwrap();
+    let stem: &[u8] = pcx.intern.get_slice(zerocopy::transmute!(stem));
+    pcx.session.hostfuncs.iter().position(|f| &*f.name == stem)
+}
+
 fn parse_call(pcx: &mut Pcx, name: IRef<[u8]>) -> compile::Result<ObjRef<EXPR>> {
     next(pcx)?; // skip '('
     let base = pcx.tmp.end();
@@ -139,7 +347,10 @@ fn parse_call(pcx: &mut Pcx, name: IRef<[u8]>) -> compile::Result<ObjRef<EXPR>>
     consume(pcx, Token::RParen)?;
     let expr = match builtincall(pcx, name, base) {
         Some(expr) => expr,
-        None => todo!("user function call")
+        None => match hostfunc(pcx, name) {
+            Some(idx) => lang_Host::newcall(pcx, idx, base)?.cast(),
+            None => todo!("user function call")
+        }
     };
     pcx.tmp.truncate(base);
     Ok(expr)
@@ -230,13 +441,50 @@ fn parse_vget(pcx: &mut Pcx, var: ObjRef<VAR>) -> compile::Result<ObjRef<VGET>>
     Ok(vget)
 }
 
+// effect annotations: call [pure|writes|idempotent|reads(var, ...)] Lang ...
+fn parse_callfx(pcx: &mut Pcx) -> compile::Result<(u32, ObjRef<TUPLE>)> {
+    let mut fx = CALLX::FX_ANY;
+    let mut reads = ObjRef::NIL.cast();
+    while pcx.data.token == Token::Ident {
+        let name: IRef<[u8]> = zerocopy::transmute!(pcx.data.tdata);
+        match pcx.intern.get_slice(name) {
+            b"pure" => fx = CALLX::FX_PURE,
+            b"writes" => fx = CALLX::FX_WRITES,
+            b"idempotent" => fx = CALLX::FX_IDEMPOTENT,
+            b"reads" => {
+                next(pcx)?;
+                consume(pcx, Token::LParen)?;
+                let base = pcx.tmp.end();
+                while pcx.data.token != Token::RParen {
+                    let var = parse_vref(pcx)?;
+                    let vget = parse_vget(pcx, var)?;
+                    pcx.tmp.push(vget);
+                    if !check(pcx, Token::Comma)? { break }
+                }
+                require(pcx, Token::RParen)?;
+                reads = pcx.objs.push_args(TUPLE::new(ObjRef::NIL), &pcx.tmp[base.cast_up()..]);
+                pcx.tmp.truncate(base);
+                if fx == CALLX::FX_ANY { fx = CALLX::FX_PURE }
+            },
+            _ => break
+        }
+        next(pcx)?;
+    }
+    Ok((fx, reads))
+}
+
 fn parse_callx(pcx: &mut Pcx, n: usize) -> compile::Result<ObjRef<CALLX>> {
     consume(pcx, Token::Call)?;
+    let (fx, reads) = parse_callfx(pcx)?;
     require(pcx, Token::Ident)?;
-    let Some(lang) = Lang::from_name(&pcx.intern.get_slice(zerocopy::transmute!(pcx.data.tdata)))
-        else { return pcx.error(LangError) };
+    let name = pcx.intern.get_slice(zerocopy::transmute!(pcx.data.tdata));
+    let Some(lang) = Lang::from_name(name) else { return pcx.error(LangError) };
     next(pcx)?; // skip name
-    lang.parse(pcx, n)
+    let callx = lang.parse(pcx, n)?;
+    let obj = &mut pcx.objs[callx];
+    obj.fx = fx;
+    obj.reads = reads;
+    Ok(callx)
 }
 
 // TODO: this can't parse nested arrays etc. (should those even be exposed to the user?)
@@ -289,11 +537,167 @@ const PRIORITY: &'static [(u8, u8)] = &[
 const UNARY_PRIORITY: u8 = 8;
 
 fn parse_value(pcx: &mut Pcx) -> compile::Result<ObjRef<EXPR>> {
+    if pcx.data.depth >= pcx.data.max_depth {
+        return syntaxerr(pcx, ErrorMessage::TooDeep);
+    }
+    if pcx.data.max_words > 0 && {let n: u32 = zerocopy::transmute!(pcx.objs.end()); n}
+        > pcx.data.max_words
+    {
+        return syntaxerr(pcx, ErrorMessage::GraphTooLarge);
+    }
+    pcx.data.depth += 1;
+    let loc = lex::loc(&pcx.data.lex);
+    let value = parse_value1(pcx);
+    pcx.data.depth -= 1;
+    if let Ok(value) = value {
+        pcx.srclocs.entry(value).or_insert(loc);
+    }
+    value
+}
+
+// rows are separated by `;`, elements by whitespace or `,` (whitespace only in decimal comma
+// mode). returns (rows, cols).
+fn parse_datablock(text: &[u8], decimal_comma: bool, values: &mut Vec<f64>) -> Option<(usize, usize)> {
+    let mut num = Vec::new();
+    let mut rows = 0;
+    let mut cols = 0;
+    for row in text.split(|&c| c == b';') {
+        let start = values.len();
+        for elem in row.split(|&c| c.is_ascii_whitespace() || (c == b',' && !decimal_comma)) {
+            if elem.is_empty() { continue }
+            num.clear();
+            num.extend(elem.iter().map(|&c| if c == b',' { b'.' } else { c }));
+            values.push(core::str::from_utf8(&num).ok()?.parse().ok()?);
+        }
+        match values.len() - start {
+            0 => continue,
+            n if rows == 0 => cols = n,
+            n if n != cols => return None,
+            _ => {}
+        }
+        rows += 1;
+    }
+    match rows {
+        0 => None,
+        _ => Some((rows, cols))
+    }
+}
+
+// [| 1 2 3; 4 5 6 |] is a f64 matrix, [| 1 2 3 |] is a vector.
+// the whole block is one token and elements are parsed directly into constant data, so they
+// don't create objects.
+fn parse_data(pcx: &mut Pcx) -> compile::Result<ObjRef<EXPR>> {
+    let text: IRef<[u8]> = zerocopy::transmute!(pcx.data.tdata);
+    let mut values = Vec::new();
+    let Some((rows, cols)) = parse_datablock(pcx.intern.get_slice(text), pcx.data.decimal_comma,
+        &mut values) else { return syntaxerr(pcx, ErrorMessage::BadData) };
+    next(pcx)?;
+    let k = pcx.intern.intern(&values[..]).cast();
+    let addr = pcx.objs.push(KDATA::new(ObjRef::NIL, k)).cast();
+    let dims: &[usize] = match rows { 1 => &[cols], _ => &[rows, cols] };
+    let shape: Vec<ObjRef<EXPR>> = dims.iter()
+        .map(|&n| pcx.objs.push(KINT::new(ObjRef::NIL, n as _)).cast())
+        .collect();
+    let pri = pcx.objs.push(TPRI::new(Primitive::F64 as _)).erase();
+    let ann = pcx.objs.push(TTEN::new(shape.len() as _, pri)).erase();
+    Ok(pcx.objs.push_args::<LOAD>(LOAD::new(ann, addr), &shape).cast())
+}
+
+// value of a constant expression, where `arg`, if given, is the constant `x`.
+// returns None if the expression depends on anything else.
+fn evalconst(pcx: &Pcx, expr: ObjRef<EXPR>, arg: Option<(ObjRef<EXPR>, f64)>) -> Option<f64> {
+    if let Some((a, x)) = arg {
+        if a == expr { return Some(x) }
+    }
+    Some(match pcx.objs.get(expr.erase()) {
+        ObjectRef::KINT(&KINT { k, .. }) => k as f64,
+        ObjectRef::KINT64(&KINT64 { k, .. }) => pcx.intern.bump()[k].get() as f64,
+        ObjectRef::KFP64(&KFP64 { k, .. }) => pcx.intern.bump()[k].get(),
+        ObjectRef::INTR(&INTR { func, ref args, .. }) if func == Intrinsic::UNM as _
+            => -evalconst(pcx, args[0], arg)?,
+        ObjectRef::BINOP(&BINOP { binop, left, right, .. }) => {
+            let l = evalconst(pcx, left, arg)?;
+            let r = evalconst(pcx, right, arg)?;
+            match BinOp::from_u8(binop) {
+                BinOp::ADD => l + r,
+                BinOp::SUB => l - r,
+                BinOp::MUL => l * r,
+                BinOp::DIV => l / r,
+                BinOp::POW => unsafe { pow(l, r) },
+                _ => return None
+            }
+        },
+        _ => return None
+    })
+}
+
+// piecewise t = x { e0, b1: e1, ..., bn: en }
+// is
+//   if x < b1 then e0 else if x < b2 then e1 ... else en
+// with `t` bound to `x` in the segments. constant breakpoints must be increasing.
+// `piecewise continuous` also checks that adjacent segments agree at their breakpoint, where
+// both are constant functions of `t`.
+fn parse_piecewise(pcx: &mut Pcx) -> compile::Result<ObjRef<EXPR>> {
+    let continuous = pcx.data.token == Token::Ident
+        && pcx.intern.get_slice(zerocopy::transmute!(pcx.data.tdata)) == b"continuous";
+    if continuous { next(pcx)?; }
+    let name = parse_name(pcx)?;
+    consume(pcx, Token::Eq)?;
+    let arg = parse_expr(pcx)?;
+    let bindbase = pcx.data.bindings.len();
+    pcx.data.bindings.push(Binding { name, value: arg });
+    consume(pcx, Token::LCurly)?;
+    // segment, breakpoint, segment, ..., breakpoint, segment
+    let base = pcx.tmp.end();
+    let mut prevbrk: Option<f64> = None;
+    let mut seg = parse_expr(pcx)?;
+    pcx.tmp.push(seg);
+    while check(pcx, Token::Comma)? {
+        let brk = parse_expr(pcx)?;
+        consume(pcx, Token::Colon)?;
+        let b = evalconst(pcx, brk, None);
+        if let (Some(pb), Some(b)) = (prevbrk, b) {
+            if !(pb < b) { return syntaxerr(pcx, ErrorMessage::PiecewiseOrder) }
+        }
+        let next = parse_expr(pcx)?;
+        if let (true, Some(b)) = (continuous, b) {
+            let l = evalconst(pcx, seg, Some((arg, b)));
+            let r = evalconst(pcx, next, Some((arg, b)));
+            if let (Some(l), Some(r)) = (l, r) {
+                if (l - r).abs() > 1e-9 * l.abs().max(r.abs()).max(1.0) {
+                    return syntaxerr(pcx, ErrorMessage::Discontinuous);
+                }
+            }
+        }
+        pcx.tmp.push(brk);
+        pcx.tmp.push(next);
+        prevbrk = b;
+        seg = next;
+    }
+    consume(pcx, Token::RCurly)?;
+    pcx.data.bindings.truncate(bindbase);
+    let parts: BumpRef<ObjRef<EXPR>> = base.cast_up();
+    let mut cursor = pcx.tmp.end().cast::<ObjRef<EXPR>>().offset(-1);
+    let mut value = pcx.tmp[cursor];
+    while cursor > parts {
+        let brk = pcx.tmp[cursor.offset(-1)];
+        let seg = pcx.tmp[cursor.offset(-2)];
+        cursor = cursor.offset(-2);
+        let cond = pcx.objs.push(BINOP::new(BinOp::LT as _, ObjRef::NIL, arg, brk)).cast();
+        value = pcx.objs.push_args::<INTR>(INTR::new(Intrinsic::IF as _, ObjRef::NIL),
+            &[cond, seg, value]).cast();
+    }
+    pcx.tmp.truncate(base);
+    Ok(value)
+}
+
+fn parse_value1(pcx: &mut Pcx) -> compile::Result<ObjRef<EXPR>> {
     match pcx.data.token {
         Token::Scope | Token::Ident => {
             let name = parse_name(pcx)?;
             match pcx.data.token {
                 Token::LParen => parse_call(pcx, name),
+                Token::Ident if pcx.intern.get_slice(name) == b"piecewise" => parse_piecewise(pcx),
                 Token::Dot => {
                     next(pcx)?;
                     let tab = reftab(pcx, name);
@@ -302,7 +706,8 @@ fn parse_value(pcx: &mut Pcx) -> compile::Result<ObjRef<EXPR>> {
                     let vget = parse_vget(pcx, var)?;
                     Ok(vget.cast())
                 },
-                _ => match pcx.data.bindings.iter().find(|b| b.name == name) {
+                // innermost binding shadows outer ones.
+                _ => match pcx.data.bindings.iter().rev().find(|b| b.name == name) {
                     Some(v) => Ok(v.value),
                     None => {
                         let tab = implicittab(pcx)?;
@@ -314,9 +719,18 @@ fn parse_value(pcx: &mut Pcx) -> compile::Result<ObjRef<EXPR>> {
             }
         },
         Token::LParen => {
-            next(pcx)?;
-            let node = parse_expr(pcx)?;
-            consume(pcx, Token::RParen)?;
+            // nested parentheses are handled here without recursion:
+            //   ((a)+b)*c
+            // parses `a`, then continues `a` as a full expression after each closing paren.
+            let mut depth = 0;
+            while check(pcx, Token::LParen)? { depth += 1; }
+            let mut node = parse_expr(pcx)?;
+            for i in (0..depth).rev() {
+                consume(pcx, Token::RParen)?;
+                if i > 0 {
+                    node = parse_binop_rhs(pcx, 0, node)?;
+                }
+            }
             Ok(node)
         },
         Token::LBracket => {
@@ -346,55 +760,85 @@ fn parse_value(pcx: &mut Pcx) -> compile::Result<ObjRef<EXPR>> {
         Token::Let => {
             next(pcx)?;
             let bindbase = pcx.data.bindings.len();
-            let name = parse_name(pcx)?;
-            let ann = parse_maybeann(pcx)?;
-            match pcx.data.token {
-                Token::Eq => {
-                    next(pcx)?;
-                    let value = parse_expr(pcx)?;
-                    pcx.objs.annotate(value, ann);
-                    pcx.data.bindings.push(Binding { name, value });
-                },
-                Token::Comma => {
-                    #[derive(zerocopy::FromBytes, zerocopy::IntoBytes, zerocopy::Immutable)]
-                    #[repr(C)]
-                    struct PendingBinding { name: IRef<[u8]>, ann: ObjRef }
-                    let base = pcx.tmp.end();
-                    pcx.tmp.push(PendingBinding { name, ann });
-                    while check(pcx, Token::Comma)? {
-                        let name = parse_name(pcx)?;
-                        let ann = parse_maybeann(pcx)?;
+            // each binding is visible in the ones after it:
+            //   let a = x+1, b = a*a in ...
+            loop {
+                let name = parse_name(pcx)?;
+                let ann = parse_maybeann(pcx)?;
+                match pcx.data.token {
+                    Token::Eq => {
+                        next(pcx)?;
+                        let value = parse_expr(pcx)?;
+                        pcx.objs.annotate(value, ann);
+                        pcx.data.bindings.push(Binding { name, value });
+                    },
+                    Token::Comma => {
+                        #[derive(zerocopy::FromBytes, zerocopy::IntoBytes, zerocopy::Immutable)]
+                        #[repr(C)]
+                        struct PendingBinding { name: IRef<[u8]>, ann: ObjRef }
+                        let base = pcx.tmp.end();
                         pcx.tmp.push(PendingBinding { name, ann });
-                    }
-                    consume(pcx, Token::Eq)?;
-                    let n = pcx.tmp[base.cast_up::<PendingBinding>()..].len();
-                    let call = parse_callx(pcx, n)?;
-                    pcx.data.bindings.extend(
-                        pcx.tmp[base.cast_up::<PendingBinding>()..]
-                        .iter()
-                        .enumerate()
-                        .map(|(i,&PendingBinding { name, ann })| Binding {
-                            name,
-                            value: pcx.objs.push(GET::new(i as _, ann, call.cast())).cast()
-                        })
-                    );
-                    pcx.tmp.truncate(base);
-                },
-                _ => return pcx.error(TokenError { want: Token::Eq | Token::Comma })
+                        while check(pcx, Token::Comma)? {
+                            let name = parse_name(pcx)?;
+                            let ann = parse_maybeann(pcx)?;
+                            pcx.tmp.push(PendingBinding { name, ann });
+                        }
+                        consume(pcx, Token::Eq)?;
+                        let n = pcx.tmp[base.cast_up::<PendingBinding>()..].len();
+                        let call = parse_callx(pcx, n)?;
+                        pcx.data.bindings.extend(
+                            pcx.tmp[base.cast_up::<PendingBinding>()..]
+                            .iter()
+                            .enumerate()
+                            .map(|(i,&PendingBinding { name, ann })| Binding {
+                                name,
+                                value: pcx.objs.push(GET::new(i as _, ann, call.cast())).cast()
+                            })
+                        );
+                        pcx.tmp.truncate(base);
+                    },
+                    _ => return pcx.error(TokenError { want: Token::Eq | Token::Comma })
+                }
+                if !check(pcx, Token::Comma)? { break }
             }
             consume(pcx, Token::In)?;
             let value = parse_expr(pcx)?;
             pcx.data.bindings.truncate(bindbase);
             Ok(value)
         },
-        tok @ (Token::Minus | Token::Not) => {
+        Token::If => {
             next(pcx)?;
-            let value = parse_binop(pcx, UNARY_PRIORITY)?;
-            let func = match tok {
-                Token::Minus     => Intrinsic::UNM,
-                _ /* Not */      => Intrinsic::NOT,
-            };
-            Ok(pcx.objs.push_args::<INTR>(INTR::new(func as _, ObjRef::NIL), &[value]).cast())
+            // `if eager c then a else b` may evaluate both branches, which lets them be
+            // computed elementwise and selected without branching.
+            let eager = check(pcx, Token::Eager)?;
+            let cond = parse_expr(pcx)?;
+            consume(pcx, Token::Then)?;
+            let tru = parse_expr(pcx)?;
+            consume(pcx, Token::Else)?;
+            let fal = parse_expr(pcx)?;
+            let func = match eager { true => Intrinsic::SELECT, false => Intrinsic::IF };
+            Ok(pcx.objs.push_args::<INTR>(INTR::new(func as _, ObjRef::NIL), &[cond, tru, fal])
+                .cast())
+        },
+        Token::Minus | Token::Not => {
+            // unary chains are also collected without recursion.
+            let base = pcx.tmp.end();
+            while (Token::Minus | Token::Not).contains(pcx.data.token) {
+                pcx.tmp.push(match pcx.data.token {
+                    Token::Minus     => Intrinsic::UNM as u8,
+                    _ /* Not */      => Intrinsic::NOT as u8,
+                });
+                next(pcx)?;
+            }
+            let mut value = parse_binop(pcx, UNARY_PRIORITY)?;
+            let mut cursor = pcx.tmp.end().cast::<u8>();
+            while cursor > base.cast() {
+                cursor = cursor.offset(-1);
+                let func = pcx.tmp[cursor];
+                value = pcx.objs.push_args::<INTR>(INTR::new(func, ObjRef::NIL), &[value]).cast();
+            }
+            pcx.tmp.truncate(base);
+            Ok(value)
         },
         Token::Int | Token::Int64 | Token::Fp64 | Token::Literal => {
             let mut o = KINT::new(ObjRef::NIL, pcx.data.tdata as _);
@@ -406,7 +850,27 @@ fn parse_value(pcx: &mut Pcx) -> compile::Result<ObjRef<EXPR>> {
             }
             next(pcx)?;
             Ok(pcx.objs.push(o).cast())
-        }
+        },
+        Token::Typed => {
+            // suffixed literal: the constant is annotated with its primitive type
+            let (pri, value) = typedvalue(pcx.intern.get_slice(zerocopy::transmute!(pcx.data.tdata)));
+            let ann = pcx.objs.push(TPRI::new(pri as _)).erase();
+            let v = i64::from_ne_bytes(value);
+            let op = match pri {
+                Primitive::F32 | Primitive::F64 => Obj::KFP64,
+                _ if v == v as i32 as i64 => Obj::KINT,
+                _ => Obj::KINT64
+            };
+            let k: u32 = match op == Obj::KINT {
+                true  => v as _,
+                false => zerocopy::transmute!(pcx.intern.intern(&value).to_bump())
+            };
+            let mut o = KINT::new(ann, k as _);
+            o.op = op;
+            next(pcx)?;
+            Ok(pcx.objs.push(o).cast())
+        },
+        Token::Data => parse_data(pcx),
         Token::Call => Ok(parse_callx(pcx, 1)?.cast()),
         Token::True => { next(pcx)?; Ok(ObjRef::TRUE.cast()) },
         Token::False => { next(pcx)?; Ok(ObjRef::FALSE.cast()) },
@@ -414,28 +878,55 @@ fn parse_value(pcx: &mut Pcx) -> compile::Result<ObjRef<EXPR>> {
     }
 }
 
+// comparisons chain:
+//   a <= x < b
+// is parsed as
+//   a <= x and x < b
+// where both comparisons refer to the same `x` object, so it's evaluated only once.
 fn parse_binop_rhs(
     pcx: &mut Pcx,
     limit: u8,
     mut lhs: ObjRef<EXPR>
 ) -> compile::Result<ObjRef<EXPR>> {
+    const CMP: EnumSet<Token> = enum_set!(Token::Eq | Token::Ne | Token::Lt | Token::Le
+        | Token::Gt | Token::Ge);
+    let mut chain: Option<ObjRef<EXPR>> = None; // right operand of the previous comparison
     while pcx.data.token.is_binop() {
         let mut op = pcx.data.token;
         let (left, right) = PRIORITY[op as usize - Token::Or as usize];
         if left <= limit { break; }
+        let loc = lex::loc(&pcx.data.lex);
         next(pcx)?;
-        let mut rhs = parse_binop(pcx, right)?;
+        // right associative chains (a^b^c...) nest here without returning to parse_value,
+        // so they count towards the depth too.
+        pcx.data.depth += 1;
+        let rhs = parse_binop(pcx, right);
+        pcx.data.depth -= 1;
+        let rhs = rhs?;
+        let cmp = CMP.contains(op);
+        let prev = replace(&mut chain, match cmp { true => Some(rhs), false => None });
+        let (mut l, mut r) = match (cmp, prev) {
+            (true, Some(mid)) => (mid, rhs),
+            _ => (lhs, rhs)
+        };
         if (Token::Ge | Token::Gt).contains(op) {
             // Ge => Le, Gt => Lt
             op = unsafe { core::mem::transmute(op as u8 - 2) };
-            (lhs, rhs) = (rhs, lhs);
+            (l, r) = (r, l);
         }
-        lhs = pcx.objs.push(BINOP::new(
+        let node = pcx.objs.push(BINOP::new(
                 BinOp::OR as u8 + (op as u8 - Token::Or as u8),
                 ObjRef::NIL,
-                lhs,
-                rhs
+                l,
+                r
         )).cast();
+        lhs = match (cmp, prev) {
+            (true, Some(_)) => pcx.objs.push(BINOP::new(BinOp::AND as u8, ObjRef::NIL, lhs, node))
+                .cast(),
+            _ => node
+        };
+        pcx.srclocs.insert(node, loc);
+        pcx.srclocs.entry(lhs).or_insert(loc);
     }
     Ok(lhs)
 }
@@ -483,6 +974,7 @@ fn parse_table(pcx: &mut Pcx) -> compile::Result {
 
 fn parse_model_def(pcx: &mut Pcx, blockguard: Option<ObjRef<EXPR>>) -> compile::Result {
     let base = pcx.tmp.end();
+    let line = lex::loc(&pcx.data.lex).line;
     // note: vset.value = annotation
     loop {
         let var = parse_vref(pcx)?;
@@ -524,10 +1016,11 @@ fn parse_model_def(pcx: &mut Pcx, blockguard: Option<ObjRef<EXPR>>) -> compile::
         (None, None) => ObjRef::NIL.cast()
     };
     // pcx.data.tab is guaranteed to be set here because we came here from parse_model
-    pcx.objs.push_args::<MOD>(
+    let model = pcx.objs.push_args::<MOD>(
         MOD::new(IRef::EMPTY, pcx.data.tab, guard),
         cast_args(&pcx.tmp[vset_base..])
     );
+    pcx.srclines.insert(model, line);
     pcx.tmp.truncate(base);
     Ok(())
 }
@@ -12,13 +12,16 @@ use zerocopy::Unalign;
 
 use crate::bump::{Bump, BumpRef};
 use crate::compile::{self, Ccx, CompileError, Stage};
+use crate::diag::Span;
 use crate::err::ErrorMessage;
 use crate::hash::HashMap;
 use crate::index::{index, IndexOption, IndexVec};
 use crate::intern::{Intern, IRef};
-use crate::lex::{self, Token};
-use crate::obj::{ObjRef, EXPR, TAB};
+use crate::lang::Lang;
+use crate::lex::{self, typedvalue, Token};
+use crate::obj::{ObjRef, EXPR, TAB, VAR};
 use crate::typestate::{typestate_union, Absent, R};
+use crate::typing::Primitive;
 
 index!(pub struct ScopeId(u32) invalid(!0));
 index!(struct MacroId(u32) invalid(!0));
@@ -75,6 +78,11 @@ pub struct Parser<L=Absent> {
     pub undef_base: usize,
     pub this: ObjRef,
     pub rec: bool,
+    pub depth: u32,      // current expression nesting depth
+    pub max_depth: u32,  // max expression nesting depth
+    pub max_words: u32,  // max object graph size in 32-bit words, 0 = unlimited
+    pub decimal_comma: bool, // parse `1,5` as 1.5
+    pub memo: Vec<(ObjRef<TAB>, ObjRef<EXPR>, ObjRef<VAR>)>, // table, value, memo variable
     macros: IndexVec<MacroId, Macro>,
     chain: HashMap<(IRef<[u8]>, Namespace), (MacroId, MacroId)>, // stem -> (head, tail)
     stack: Vec<Frame>,
@@ -82,6 +90,9 @@ pub struct Parser<L=Absent> {
     snippet: Vec<u8>,
 }
 
+// deep enough for any sane model, shallow enough to not blow the stack.
+const DEFAULT_MAX_DEPTH: u32 = 1000;
+
 pub type PcxData<'a> = Parser<logos::Lexer<'a, Token>>;
 pub type Pcx<'a> = Ccx<PcxData<'a>>;
 
@@ -97,7 +108,7 @@ const SPACE_BETWEEN: u64 = {
     use Token::*;
     (1 << Ident as u64) | (1 << Dollar as u64) | (1 << CapName as u64) | (1 << CapPos as u64)
         | (1 << Literal as u64) | (1 << Scope as u64) | (1 << Int as u64) | (1 << Int64 as u64)
-        | (1 << Fp64 as u64) | (1 << Not as u64) | (1 << Call as u64) | (1 << Macro as u64)
+        | (1 << Fp64 as u64) | (1 << Typed as u64) | (1 << Not as u64) | (1 << Call as u64) | (1 << Macro as u64)
         | (1 << Var as u64) | (1 << Model as u64) | (1 << Table as u64) | (1 << Func as u64)
         | (1 << Where as u64) | (1 << Out as u64) | (1 << Let as u64) | (1 << In as u64)
         | (1 << True as u64) | (1 << False as u64)
@@ -125,6 +136,11 @@ pub fn stringify(buf: &mut Bump, intern: &Intern, body: &[u8], sty: SequenceType
                 i += TK_DATALEN;
             }
             match token {
+                Token::Data => {
+                    buf.write("[|");
+                    buf.write(intern.get_slice::<u8>(zerocopy::transmute!(data)));
+                    buf.write("|]");
+                },
                 tk @ (Token::Ident | Token::Literal | Token::CapName) => {
                     if tk == Token::CapName { buf.push(b'$'); }
                     // TODO: this doesn't properly quote idents or escape quotes in strings.
@@ -147,6 +163,14 @@ pub fn stringify(buf: &mut Bump, intern: &Intern, body: &[u8], sty: SequenceType
                     let data: BumpRef<Unalign<f64>> = zerocopy::transmute!(data);
                     write!(buf, "{}", intern.bump()[data].get()).unwrap();
                 },
+                Token::Typed => {
+                    let (pri, value) = typedvalue(intern.get_slice(zerocopy::transmute!(data)));
+                    match pri {
+                        Primitive::F32 | Primitive::F64 => write!(buf, "{}", f64::from_ne_bytes(value)),
+                        _ => write!(buf, "{}", i64::from_ne_bytes(value))
+                    }.unwrap();
+                    buf.write(pri.name());
+                },
                 tk => {
                     buf.write(tk.str());
                 }
@@ -238,6 +262,12 @@ impl<'a> CompileError<PcxData<'a>> for SyntaxError {
         write!(pcx.host.buf, "syntax error: {}\n", self.message.str()).unwrap();
         traceback(pcx);
     }
+    fn code(&self) -> &'static str {
+        self.message.code()
+    }
+    fn span(&self, pcx: &mut Ccx<PcxData<'a>, R, R>) -> Option<Span> {
+        Some(lex::span(&pcx.data.lex))
+    }
 }
 
 pub fn syntaxerr<T>(pcx: &mut Pcx, message: ErrorMessage) -> compile::Result<T> {
@@ -260,6 +290,12 @@ impl<'a> CompileError<PcxData<'a>> for TokenError {
         pcx.host.buf.write(b")\n");
         traceback(pcx)
     }
+    fn code(&self) -> &'static str {
+        "E0001"
+    }
+    fn span(&self, pcx: &mut Ccx<PcxData<'a>, R, R>) -> Option<Span> {
+        Some(lex::span(&pcx.data.lex))
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -290,6 +326,15 @@ impl<'a> CompileError<PcxData<'a>> for DefinitionError {
         pcx.host.buf.push(b'\n');
         traceback(pcx)
     }
+    fn code(&self) -> &'static str {
+        match self.what {
+            DefinitionErrorType::Undefined => "E0002",
+            DefinitionErrorType::Redefinition => "E0003"
+        }
+    }
+    fn span(&self, pcx: &mut Ccx<PcxData<'a>, R, R>) -> Option<Span> {
+        Some(lex::span(&pcx.data.lex))
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -297,8 +342,25 @@ pub struct LangError;
 
 impl<'a> CompileError<PcxData<'a>> for LangError {
     fn write(self, pcx: &mut Ccx<PcxData<'a>, R, R>) {
-        pcx.host.buf.write(b"unsupported language: ");
-        pcx.host.buf.write(pcx.intern.get_slice::<u8>(zerocopy::transmute!(pcx.data.tdata)));
+        let name = pcx.intern.get_slice::<u8>(zerocopy::transmute!(pcx.data.tdata));
+        let disabled = Lang::BUILTIN.iter().any(|&(n, on)| !on && n.as_bytes() == name);
+        let buf = &mut pcx.host.buf;
+        buf.write(b"unsupported language: ");
+        buf.write(name);
+        if disabled {
+            buf.write(b" (not enabled in this build)");
+        }
+        buf.write(b"\navailable languages:");
+        for (i, &(lang, _)) in Lang::BUILTIN.iter().filter(|&&(_, on)| on).enumerate() {
+            buf.write(if i == 0 { b" " as &[u8] } else { b", " });
+            buf.write(lang);
+        }
+    }
+    fn code(&self) -> &'static str {
+        "E0004"
+    }
+    fn span(&self, pcx: &mut Ccx<PcxData<'a>, R, R>) -> Option<Span> {
+        Some(lex::span(&pcx.data.lex))
     }
 }
 
@@ -794,7 +856,12 @@ impl Stage for Parser {
             stack: Default::default(),
             captures: Default::default(),
             snippet: Default::default(),
-            rec: false
+            rec: false,
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_words: 0,
+            decimal_comma: false,
+            memo: Default::default()
         })
     }
 
@@ -0,0 +1,101 @@
+//! Peephole optimizations on translated functions.
+
+// these run on the cranelift IR before it goes to cranelift, ie. before its egraph and
+// instruction selection, and cranelift has no hook to run them after. so only patterns that
+// neither of those handles belong here: B1 extensions and zero compares feeding branches, which
+// the egraph doesn't rewrite because branches are skeleton instructions. pure value patterns
+// (eg. select of equal operands) are left to the egraph, and constant address offsets to the
+// address modes of instruction selection.
+//
+// rules are listed in the `peephole!` table by the opcode of the root instruction. each rule
+// returns true if it rewrote the instruction, in which case the instruction is tried again.
+
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{Function, Inst, InstructionData, Opcode, ValueDef};
+
+use crate::trace::trace;
+
+type Rule = fn(&mut Function, Inst) -> bool;
+
+macro_rules! peephole {
+    ($( $opcode:ident => $($rule:ident),* ; )*) => {
+        fn rules(opcode: Opcode) -> &'static [Rule] {
+            match opcode {
+                $( Opcode::$opcode => &[$($rule),*], )*
+                _ => &[]
+            }
+        }
+    };
+}
+
+// match the instruction defining `v`:
+//   def!(func, v; Opcode) -> Option<Inst>
+macro_rules! def {
+    ($func:expr, $v:expr; $($opcode:ident)|*) => {
+        match $func.dfg.value_def($func.dfg.resolve_aliases($v)) {
+            ValueDef::Result(inst, 0) if matches!(
+                $func.dfg.insts[inst].opcode(),
+                $(Opcode::$opcode)|*
+            ) => Some(inst),
+            _ => None
+        }
+    };
+}
+
+peephole! {
+    Brif    => brif_ext, brif_cmp0;
+}
+
+/* ---- Branches ------------------------------------------------------------ */
+
+// brif (uextend x) => brif x
+fn brif_ext(func: &mut Function, inst: Inst) -> bool {
+    let cond = func.dfg.inst_args(inst)[0];
+    let Some(ext) = def!(func, cond; Uextend|Sextend) else { return false };
+    let x = func.dfg.inst_args(ext)[0];
+    func.dfg.inst_args_mut(inst)[0] = x;
+    true
+}
+
+// brif (icmp_imm ne x 0) a b => brif x a b
+// brif (icmp_imm eq x 0) a b => brif x b a
+fn brif_cmp0(func: &mut Function, inst: Inst) -> bool {
+    let cond = func.dfg.inst_args(inst)[0];
+    let Some(cmp) = def!(func, cond; IcmpImm) else { return false };
+    let InstructionData::IntCompareImm { cond, arg, imm, .. } = func.dfg.insts[cmp]
+        else { unreachable!() };
+    if imm.bits() != 0 || !(cond == IntCC::Equal || cond == IntCC::NotEqual) {
+        return false;
+    }
+    let InstructionData::Brif { arg: barg, blocks, .. } = &mut func.dfg.insts[inst]
+        else { unreachable!() };
+    *barg = arg;
+    if cond == IntCC::Equal {
+        blocks.swap(0, 1);
+    }
+    true
+}
+
+/* -------------------------------------------------------------------------- */
+
+pub fn run(func: &mut Function) {
+    let mut num = 0;
+    let mut block = func.layout.entry_block();
+    while let Some(b) = block {
+        let mut next = func.layout.first_inst(b);
+        while let Some(inst) = next {
+            next = func.layout.next_inst(inst);
+            'again: loop {
+                for rule in rules(func.dfg.insts[inst].opcode()) {
+                    if rule(func, inst) {
+                        num += 1;
+                        continue 'again;
+                    }
+                }
+                break;
+            }
+        }
+        block = func.layout.next_block(b);
+    }
+    trace!(CLIF "PEEPHOLE {} rewrites", num);
+}
@@ -0,0 +1,147 @@
+//! Profile-guided optimization.
+
+// a profile collected by a profiled image (see `Session::profile`) can be saved and fed back into a
+// later compilation of the same graph with `Ccx::set_profile`. the format is
+//
+//   magic | nfunc | (source, calls)* | ncase | (source, key, hits)*
+//
+// everything is little endian. functions are identified by their DebugSource, and switch cases
+// by the DebugSource of the function containing the switch and the case key, so the profile
+// survives recompilation as long as the graph is the same. entries that don't match anything are
+// ignored.
+//
+// the profile is used by
+//   * inlining: hot functions get a higher inlining threshold.
+//   * switch emission: the hottest case is tested first.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use crate::compile::{Ccx, CompileError};
+use crate::hash::HashMap;
+use crate::image::Profile;
+use crate::ir::DebugSource;
+use crate::typestate::R;
+
+const MAGIC: &[u8; 8] = b"fhkprof\x01";
+
+// a function is hot if it was called at least 1/HOT_RATIO times as often as the most called one.
+const HOT_RATIO: u64 = 8;
+
+#[derive(Default)]
+pub struct ProfileData {
+    raw: Box<[u8]>,
+    calls: HashMap<DebugSource, u64>,
+    hits: HashMap<(DebugSource, i64), u64>,
+    maxcalls: u64
+}
+
+#[derive(Clone, Copy)]
+pub enum PgoError {
+    BadProfile
+}
+
+impl CompileError for PgoError {
+    fn write(self, ccx: &mut Ccx<(), R, R>) {
+        ccx.host.buf.write(match self {
+            PgoError::BadProfile => "invalid profile data"
+        });
+    }
+}
+
+impl ProfileData {
+
+    // the serialized profile, for hashing.
+    pub fn raw(&self) -> &[u8] {
+        &self.raw
+    }
+
+    pub fn is_hot(&self, source: DebugSource) -> bool {
+        match self.calls.get(&source) {
+            Some(&calls) => calls > 0 && calls.saturating_mul(HOT_RATIO) >= self.maxcalls,
+            None => false
+        }
+    }
+
+    // how many times the switch in `source` took the case `key`.
+    pub fn hits(&self, source: DebugSource, key: i64) -> u64 {
+        self.hits.get(&(source, key)).cloned().unwrap_or(0)
+    }
+
+}
+
+fn put32(buf: &mut Vec<u8>, v: u32) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put64(buf: &mut Vec<u8>, v: u64) {
+    buf.extend_from_slice(&v.to_le_bytes());
+}
+
+pub fn save(profile: &Profile) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.extend_from_slice(MAGIC);
+    let called: Vec<usize> = (0..profile.counters.len())
+        .filter(|&i| profile.read(i).calls > 0)
+        .collect();
+    put32(&mut buf, called.len() as _);
+    for i in called {
+        put32(&mut buf, profile.sources[i].raw());
+        put64(&mut buf, profile.read(i).calls);
+    }
+    let ncase: usize = profile.switches.iter().map(|s| s.keys.len()).sum();
+    put32(&mut buf, ncase as _);
+    for switch in &profile.switches {
+        for (i, &key) in switch.keys.iter().enumerate() {
+            put32(&mut buf, switch.source.raw());
+            put64(&mut buf, key as _);
+            put64(&mut buf, switch.read(i));
+        }
+    }
+    buf
+}
+
+struct Reader<'a> {
+    data: &'a [u8]
+}
+
+impl<'a> Reader<'a> {
+
+    fn bytes(&mut self, n: usize) -> Option<&'a [u8]> {
+        let (head, rest) = self.data.split_at_checked(n)?;
+        self.data = rest;
+        Some(head)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+}
+
+pub fn parse(data: &[u8]) -> Option<ProfileData> {
+    let mut r = Reader { data };
+    if r.bytes(MAGIC.len())? != MAGIC {
+        return None;
+    }
+    let mut profile = ProfileData { raw: data.into(), ..Default::default() };
+    for _ in 0..r.u32()? {
+        let source = DebugSource::from_raw(r.u32()?);
+        let calls = r.u64()?;
+        *profile.calls.entry(source).or_default() += calls;
+    }
+    profile.maxcalls = profile.calls.values().cloned().max().unwrap_or(0);
+    for _ in 0..r.u32()? {
+        let source = DebugSource::from_raw(r.u32()?);
+        let key = r.u64()? as i64;
+        *profile.hits.entry((source, key)).or_default() += r.u64()?;
+    }
+    match r.data.is_empty() {
+        true => Some(profile),
+        false => None
+    }
+}
@@ -0,0 +1,150 @@
+//! Native function plugins.
+
+// a plugin is a shared library, built separately from fhk, that exports
+//
+//   const fhk_Plugin *fhk_plugin(void);
+//
+// returning a description of the functions it provides:
+//
+//   typedef struct {
+//       const char *name;       // called as name(x, ...) in models, like a host function
+//       const uint8_t *params;  // parameter types, see below
+//       uint32_t nparam;
+//       uint8_t ret;            // return type
+//       uint8_t pure;           // nonzero if calls can be merged, reordered or removed
+//       void *func;             // ret func(void *data, params...), C calling convention
+//       void *data;
+//   } fhk_PluginFunc;
+//
+//   typedef struct {
+//       uint32_t abi;           // FHK_PLUGIN_ABI the plugin was built against
+//       uint32_t nfunc;
+//       const fhk_PluginFunc *funcs;
+//   } fhk_Plugin;
+//
+// types are numbered 0 = f64, 1 = f32, 2 = i64, 3 = i32, 4 = i16, 5 = i8, 6 = u64, 7 = u32,
+// 8 = u16, 9 = u8 (ORDER PRI). the table, and everything it points to, must stay valid while the
+// library is loaded. the library is kept loaded as long as the graph or any image that calls one
+// of its functions.
+//
+// the ABI covers functions only. languages with their own syntax and lowering build objects and
+// IR directly, and those aren't stable enough to expose across a library boundary.
+//
+// `abi` changes whenever the layout of these structs or the meaning of a field changes. plugins
+// built against another version are rejected.
+
+use core::any::Any;
+use core::ffi::{c_char, c_void, CStr};
+use core::fmt::{self, Display};
+
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+
+use crate::dl::{self, LibBox};
+use crate::lang_Host::HostFunc;
+use crate::session::Session;
+use crate::typing::Primitive;
+
+pub const PLUGIN_ABI: u32 = 1;
+
+#[repr(C)]
+struct PluginFunc {
+    name: *const c_char,
+    params: *const u8,
+    nparam: u32,
+    ret: u8,
+    pure: u8,
+    func: *const c_void,
+    data: *const c_void
+}
+
+#[repr(C)]
+struct Plugin {
+    abi: u32,
+    nfunc: u32,
+    funcs: *const PluginFunc
+}
+
+pub enum PluginError {
+    Open,
+    NoPlugin,
+    Abi(u32),
+    Type(usize),
+    Name(usize),
+    TooMany
+}
+
+impl Display for PluginError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PluginError::Open => f.write_str("failed to load library"),
+            PluginError::NoPlugin => f.write_str("library doesn't export fhk_plugin"),
+            PluginError::Abi(abi) =>
+                write!(f, "plugin ABI version {} (expected {})", abi, PLUGIN_ABI),
+            PluginError::Type(i) => write!(f, "function {} has an unsupported type", i+1),
+            PluginError::Name(i) => write!(f, "function {} has no name or a taken name", i+1),
+            PluginError::TooMany => f.write_str("too many host functions")
+        }
+    }
+}
+
+fn pritype(raw: u8) -> Option<Primitive> {
+    match raw <= Primitive::U8 as u8 {
+        true => Some(Primitive::from_u8(raw)),
+        false => None
+    }
+}
+
+// safety: the plugin must describe its functions correctly. nothing is registered on error.
+pub unsafe fn load(session: &mut Session, path: &[u8]) -> Result<usize, PluginError> {
+    let mut name = Vec::with_capacity(path.len()+1);
+    name.extend_from_slice(path);
+    name.push(0);
+    let lib = dl::open(&name).ok_or(PluginError::Open)?;
+    let entry = lib.sym(c"fhk_plugin");
+    if entry.is_null() {
+        return Err(PluginError::NoPlugin);
+    }
+    let entry: extern "C" fn() -> *const Plugin = unsafe { core::mem::transmute(entry) };
+    let plugin = match unsafe { entry().as_ref() } {
+        Some(plugin) => plugin,
+        None => return Err(PluginError::NoPlugin)
+    };
+    if plugin.abi != PLUGIN_ABI {
+        return Err(PluginError::Abi(plugin.abi));
+    }
+    let lib: Rc<LibBox> = Rc::new(lib);
+    let mut funcs = Vec::with_capacity(plugin.nfunc as _);
+    for i in 0..plugin.nfunc as usize {
+        let f = unsafe { &*plugin.funcs.add(i) };
+        if f.name.is_null() || f.func.is_null() {
+            return Err(PluginError::Name(i));
+        }
+        let fname = unsafe { CStr::from_ptr(f.name) }.to_bytes();
+        if fname.is_empty() || session.hostfuncs.iter().any(|h| &*h.name == fname)
+            || funcs.iter().any(|h: &HostFunc| &*h.name == fname)
+        {
+            return Err(PluginError::Name(i));
+        }
+        let params = match f.nparam {
+            0 => &[][..],
+            n => unsafe { core::slice::from_raw_parts(f.params, n as _) }
+        };
+        let params: Vec<Primitive> = params.iter().map(|&p| pritype(p))
+            .collect::<Option<_>>().ok_or(PluginError::Type(i))?;
+        let ret = pritype(f.ret).ok_or(PluginError::Type(i))?;
+        let mut func = unsafe { HostFunc::new(fname, &params, ret, f.func, f.data) };
+        func.pure = f.pure != 0;
+        let keep: Rc<dyn Any> = lib.clone();
+        func.keep = Some(keep);
+        funcs.push(func);
+    }
+    let num = funcs.len();
+    if session.hostfuncs.len() + num > u16::MAX as usize + 1 {
+        return Err(PluginError::TooMany);
+    }
+    for func in funcs {
+        session.add_hostfunc(func).unwrap();
+    }
+    Ok(num)
+}
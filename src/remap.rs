@@ -0,0 +1,75 @@
+//! Function renumbering.
+
+// passes that delete functions (inlining, merging, dead function elimination) describe the
+// deletion with a `FuncMap` from old ids to new ids. kept functions stay in their original order.
+// `apply` then drops the deleted functions and rewrites every function operand in the IR, so no
+// pass has to know which instructions refer to functions.
+//
+// while optimizing, function ids only appear in instruction operands: `Deps` refers to the
+// lowered ids, and layout and emit run after the last renumbering.
+
+use alloc::vec::Vec;
+
+use crate::index::{self, IndexOption, IndexVec};
+use crate::ir::{FuncId, Ins, Opcode, Operand, IR};
+
+#[derive(Default)]
+pub struct FuncMap {
+    new: IndexVec<FuncId, IndexOption<FuncId>> // old -> new, none if deleted
+}
+
+impl FuncMap {
+
+    // keep the functions `keep` returns true for. returns the number of deleted functions.
+    pub fn build(&mut self, end: FuncId, mut keep: impl FnMut(FuncId) -> bool) -> usize {
+        self.new.clear();
+        let mut next: FuncId = 0.into();
+        let mut deleted = 0;
+        for fid in index::iter_span(end) {
+            self.new.push(match keep(fid) {
+                true => { let f = next; next += 1; Some(f) },
+                false => { deleted += 1; None }
+            }.into());
+        }
+        deleted
+    }
+
+    // drop the deleted functions and renumber the references to the rest.
+    // a reference to a deleted function `f` is redirected to `target(f)`, which must be kept.
+    // CINITs are the exception: a CINIT of a function deleted without a target (`target(f) = f`)
+    // is removed, and a redirected CINIT is removed if the same function already initializes the
+    // target, so that no chunk is initialized twice.
+    pub fn apply(&self, ir: &mut IR, target: impl Fn(FuncId) -> FuncId) {
+        let mut fid: FuncId = 0.into();
+        ir.funcs.raw.retain(|_| { let keep = self.new[fid].is_some(); fid += 1; keep });
+        let mut inits = Vec::new();
+        for func in &mut ir.funcs.raw {
+            // chunks this function initializes, by their old ids
+            inits.clear();
+            inits.extend(func.code.pairs().filter(|(_, ins)| ins.opcode() == Opcode::CINIT)
+                .map(|(_, ins)| ins.decode_CINIT().1));
+            for ins in &mut func.code.inner_mut().raw {
+                let opcode = ins.opcode();
+                let Some(i) = opcode.operands().iter().position(|&o| o == Operand::F) else {
+                    continue
+                };
+                let f: FuncId = zerocopy::transmute!(ins.abc()[i]);
+                let new = match self.new[f].unpack() {
+                    Some(g) => g,
+                    None if opcode == Opcode::CINIT => {
+                        let g = target(f);
+                        if g == f || inits.contains(&g) {
+                            *ins = Ins::NOP_FX;
+                            continue;
+                        }
+                        inits.push(g);
+                        self.new[g].unwrap()
+                    },
+                    None => self.new[target(f)].unwrap()
+                };
+                ins.abc_mut()[i] = zerocopy::transmute!(new);
+            }
+        }
+    }
+
+}
@@ -0,0 +1,413 @@
+//! Compiler configuration.
+
+// the session holds everything the embedder configures once and every compile of the graph then
+// reads: option flags and host functions. per-compile state stays
+// in `Ccx`, which owns its session.
+//
+// embedders change settings through `Options`, which checks the combination before writing it
+// into the session and pipeline.
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Display};
+
+use enumset::EnumSet;
+
+use crate::analysis::IrView;
+use crate::cost::CostModel;
+use crate::intern::Intern;
+use crate::ir::IR;
+use crate::lang_Host::HostFunc;
+use crate::obj::{BinOp, ObjRef, Objects, MOD, VAR};
+use crate::optimize::{OptFlag, OptPass, Pipeline};
+use crate::pgo::ProfileData;
+use crate::typing::Primitive;
+
+// pipeline points where observers can see the IR.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum IrPoint {
+    Lower,         // after lowering
+    Pass(OptPass), // after each run of an optimizer pass
+    Optimize       // after the optimizer has converged
+}
+
+impl IrPoint {
+
+    pub fn name(self) -> &'static str {
+        match self {
+            IrPoint::Lower => "lower",
+            IrPoint::Pass(pass) => pass.name(),
+            IrPoint::Optimize => "optimize"
+        }
+    }
+
+    pub fn from_name(name: &[u8]) -> Option<Self> {
+        match name {
+            b"lower" => Some(IrPoint::Lower),
+            b"optimize" => Some(IrPoint::Optimize),
+            _ => OptPass::from_name(name).map(IrPoint::Pass)
+        }
+    }
+
+}
+
+// an observer gets a read-only view of the IR at the points it lists, eg. for a test suite to
+// check structural properties of the optimized IR. it can't change the compilation.
+pub struct Observer {
+    pub points: Vec<IrPoint>,
+    pub observe: Box<dyn Fn(IrPoint, IrView)>
+}
+
+// one side of an assumption: a variable, compared at the same index, or a constant.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Bound {
+    Var(ObjRef<VAR>),
+    K(f64)
+}
+
+// a host-declared invariant `left op right` (op is LT, LE or EQ) that the optimizer may rely on.
+// at least one side is a variable, and two variables must be in the same table.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Assume {
+    pub left: Bound,
+    pub op: BinOp,
+    pub right: Bound
+}
+
+impl Assume {
+
+    pub fn mentions(&self, var: ObjRef<VAR>) -> bool {
+        self.left == Bound::Var(var) || self.right == Bound::Var(var)
+    }
+
+}
+
+pub struct Session {
+    // optimization flags
+    pub flags: EnumSet<OptFlag>,
+    // interval check tolerance (None = don't check)
+    pub icheck: Option<f64>,
+    // value for constant integer division by zero (None = don't fold, trap at runtime)
+    pub divzero: Option<i64>,
+    // minimum number of cases to emit an IF chain as a switch (0 = never)
+    pub switchmin: u32,
+    // tolerance of float `=` and `!=` in model guards (None = exact, with a warning)
+    pub guardeps: Option<f64>,
+    // execution cost per call site that inlining may add
+    pub inlinecost: u32,
+    // let the code generator optimize for speed rather than compile time
+    pub cgspeed: bool,
+    // let the code generator prefer compact code (implies cgspeed)
+    pub cgsize: bool,
+    // operation latencies of the target
+    pub cost: &'static CostModel,
+    // verify IR between optimizer passes
+    pub verify: bool,
+    // allow float rewrites that differ for signed zeros, infinities or nans
+    pub fastmath: bool,
+    // type of numeric values the model doesn't constrain (F64, or F32 for reduced precision)
+    pub float: Primitive,
+    // count calls and cycles of each emitted function
+    pub profile: bool,
+    // call the image's host hooks on entering and leaving each emitted function
+    pub hooks: bool,
+    // let queries compute the chunks they depend on ahead, on a host thread pool
+    pub parallel: bool,
+    // record applied rewrites in the pipeline's decision log
+    pub audit: bool,
+    // profile of a previous run, for profile-guided optimization
+    pub pgo: Option<ProfileData>,
+    // native functions callable from models
+    pub hostfuncs: Vec<HostFunc>,
+    // IR observers, called in order at their points
+    pub observers: Vec<Observer>,
+    // directory for internal compiler error reports (None = don't write reports)
+    pub crashdir: Option<String>,
+    // bytes of objects and interned data a compilation may start with (0 = unlimited)
+    pub maxmem: usize,
+    // IR instructions a compilation may produce (0 = unlimited)
+    pub maxins: u32,
+    // invariants on variable values
+    pub assumes: Vec<Assume>,
+    // check assumptions at runtime instead of trusting them
+    pub checkassume: bool,
+    // models tried only after every other model of their variables
+    pub fallbacks: Vec<ObjRef<MOD>>
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self {
+            flags: EnumSet::all(),
+            icheck: None,
+            divzero: None,
+            switchmin: 4,
+            guardeps: None,
+            inlinecost: 50,
+            cgspeed: true,
+            cgsize: false,
+            cost: CostModel::host(),
+            verify: false,
+            fastmath: false,
+            float: Primitive::F64,
+            profile: false,
+            hooks: false,
+            parallel: false,
+            audit: false,
+            pgo: None,
+            hostfuncs: Default::default(),
+            observers: Default::default(),
+            crashdir: None,
+            maxmem: 0,
+            maxins: 0,
+            assumes: Default::default(),
+            checkassume: cfg!(debug_assertions),
+            fallbacks: Default::default()
+        }
+    }
+}
+
+impl Session {
+
+    // returns None if the name is already taken
+    pub fn add_hostfunc(&mut self, func: HostFunc) -> Option<()> {
+        if self.hostfuncs.iter().any(|f| f.name == func.name)
+            || self.hostfuncs.len() > u16::MAX as _
+        {
+            return None;
+        }
+        self.hostfuncs.push(func);
+        Some(())
+    }
+
+    pub fn add_observer(&mut self, observer: Observer) {
+        self.observers.push(observer);
+    }
+
+    pub fn observe(&self, point: IrPoint, ir: &IR, intern: &Intern, objs: &Objects) {
+        for obs in &self.observers {
+            if obs.points.contains(&point) {
+                (obs.observe)(point, IrView::new(ir, intern, objs));
+            }
+        }
+    }
+
+    pub fn add_assume(&mut self, assume: Assume) {
+        self.assumes.push(assume);
+    }
+
+}
+
+// optimization level presets, from fastest compile to fastest code. a new session is O3.
+// Os is for images deployed to memory-constrained targets: no vectorized (unrolled) loops,
+// inlining only where it doesn't grow the caller, and compact machine code. merge is on,
+// and emit always moves init chunks and cold blocks to the end of the image.
+//
+//   level  flags                    inline cost  iterations  switch  codegen
+//   O0     none                     0            1           never   compile time
+//   O1     ccp cfg fold goto peep   0            4           8       compile time
+//   O2     all but vector           25           20          4       speed
+//   O3     all                      50           100         4       speed
+//   Os     all but vector           5            20          4       size
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OptLevel { O0, O1, O2, O3, Os }
+
+impl OptLevel {
+
+    pub fn flags(self) -> EnumSet<OptFlag> {
+        use OptFlag::*;
+        match self {
+            OptLevel::O0 => EnumSet::empty(),
+            OptLevel::O1 => CCP|CFG|FOLD|GOTO|PEEP,
+            OptLevel::O2 | OptLevel::Os => EnumSet::all() - VECTOR,
+            OptLevel::O3 => EnumSet::all()
+        }
+    }
+
+    // (inline cost, iterations, switch minimum, codegen speed, codegen size)
+    fn limits(self) -> (u32, u32, u32, bool, bool) {
+        match self {
+            OptLevel::O0 => (0, 1, 0, false, false),
+            OptLevel::O1 => (0, 4, 8, false, false),
+            OptLevel::O2 => (25, 20, 4, true, false),
+            OptLevel::O3 => (50, 100, 4, true, false),
+            OptLevel::Os => (5, 20, 4, true, true)
+        }
+    }
+
+    pub fn from_u8(level: u8) -> Option<Self> {
+        Some(match level {
+            0 => OptLevel::O0,
+            1 => OptLevel::O1,
+            2 => OptLevel::O2,
+            3 => OptLevel::O3,
+            _ => return None
+        })
+    }
+
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OptionsError {
+    MaxIter,
+    Tolerance(f64),
+    NeedsFold(&'static str)
+}
+
+impl Display for OptionsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OptionsError::MaxIter => f.write_str("iteration limit must be positive"),
+            OptionsError::Tolerance(tol) => write!(f, "invalid interval check tolerance: {}", tol),
+            OptionsError::NeedsFold(opt) => write!(f, "{} has no effect without fold", opt)
+        }
+    }
+}
+
+// typed settings. start from `current` (or `default`, which matches a new session), change what
+// you need, then `apply`.
+#[derive(Clone)]
+pub struct Options {
+    flags: EnumSet<OptFlag>,
+    passes: Vec<OptPass>,
+    max_iter: u32,
+    icheck: Option<f64>,
+    divzero: Option<i64>,
+    switchmin: u32,
+    inlinecost: u32,
+    cgspeed: bool,
+    cgsize: bool,
+    verify: bool,
+    fastmath: bool,
+    profile: bool
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self::current(&Default::default(), &Default::default())
+    }
+}
+
+impl Options {
+
+    pub fn current(session: &Session, pipeline: &Pipeline) -> Self {
+        Self {
+            flags: session.flags,
+            passes: pipeline.passes.clone(),
+            max_iter: pipeline.max_iter,
+            icheck: session.icheck,
+            divzero: session.divzero,
+            switchmin: session.switchmin,
+            inlinecost: session.inlinecost,
+            cgspeed: session.cgspeed,
+            cgsize: session.cgsize,
+            verify: session.verify,
+            fastmath: session.fastmath,
+            profile: session.profile
+        }
+    }
+
+    pub fn level(self, level: OptLevel) -> Self {
+        let (inlinecost, max_iter, switchmin, cgspeed, cgsize) = level.limits();
+        self.flags(level.flags())
+            .inlinecost(inlinecost)
+            .max_iter(max_iter)
+            .switchmin(switchmin)
+            .cgspeed(cgspeed)
+            .cgsize(cgsize)
+    }
+
+    pub fn flags(mut self, flags: EnumSet<OptFlag>) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    pub fn passes(mut self, passes: Vec<OptPass>) -> Self {
+        self.passes = passes;
+        self
+    }
+
+    pub fn max_iter(mut self, max_iter: u32) -> Self {
+        self.max_iter = max_iter;
+        self
+    }
+
+    pub fn icheck(mut self, tol: Option<f64>) -> Self {
+        self.icheck = tol;
+        self
+    }
+
+    pub fn divzero(mut self, value: Option<i64>) -> Self {
+        self.divzero = value;
+        self
+    }
+
+    pub fn switchmin(mut self, min: u32) -> Self {
+        self.switchmin = min;
+        self
+    }
+
+    pub fn inlinecost(mut self, cost: u32) -> Self {
+        self.inlinecost = cost;
+        self
+    }
+
+    pub fn cgspeed(mut self, on: bool) -> Self {
+        self.cgspeed = on;
+        self
+    }
+
+    pub fn cgsize(mut self, on: bool) -> Self {
+        self.cgsize = on;
+        self
+    }
+
+    pub fn verify(mut self, on: bool) -> Self {
+        self.verify = on;
+        self
+    }
+
+    pub fn fastmath(mut self, on: bool) -> Self {
+        self.fastmath = on;
+        self
+    }
+
+    pub fn profile(mut self, on: bool) -> Self {
+        self.profile = on;
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), OptionsError> {
+        if self.max_iter == 0 {
+            return Err(OptionsError::MaxIter);
+        }
+        if let Some(tol) = self.icheck {
+            if !(tol >= 0.0) { return Err(OptionsError::Tolerance(tol)); }
+        }
+        if !self.flags.contains(OptFlag::FOLD) {
+            if self.divzero.is_some() { return Err(OptionsError::NeedsFold("divzero")); }
+            if self.fastmath { return Err(OptionsError::NeedsFold("fastmath")); }
+        }
+        Ok(())
+    }
+
+    // on error, nothing is changed.
+    pub fn apply(self, session: &mut Session, pipeline: &mut Pipeline) -> Result<(), OptionsError> {
+        self.validate()?;
+        session.flags = self.flags;
+        session.icheck = self.icheck;
+        session.divzero = self.divzero;
+        session.switchmin = self.switchmin;
+        session.inlinecost = self.inlinecost;
+        session.cgspeed = self.cgspeed;
+        session.cgsize = self.cgsize;
+        session.verify = self.verify;
+        session.fastmath = self.fastmath;
+        session.profile = self.profile;
+        pipeline.passes = self.passes;
+        pipeline.max_iter = self.max_iter;
+        Ok(())
+    }
+
+}
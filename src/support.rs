@@ -2,12 +2,13 @@
 
 use core::mem::replace;
 
+use cfg_if::cfg_if;
 use cranelift_codegen::ir::{InstBuilder, TrapCode};
 use enumset::EnumSetType;
 
 use crate::controlflow::BlockId;
 use crate::emit::{block2cl, signature, Ecx, Signature, NATIVE_CALLCONV};
-use crate::image::{fhk_vmexit, DupHeader, Instance};
+use crate::image::{fhk_vmexit, DupHeader, Hooks, Instance};
 use crate::ir::Type;
 use crate::mem::Offset;
 
@@ -72,12 +73,15 @@ define_suppfuncs! {
 }
 
 define_nativefuncs! {
-    POWF64[pow]     F64 F64 -> F64;
-    EXPF64[exp]     F64 -> F64;
-    LOGF64[log]     F64 -> F64;
-    INIT[rt_init]   PTR PTR I32 I32;
-    ALLOC[rt_alloc] PTR I64 I64 -> PTR;
-    ABORT[rt_abort] PTR;
+    POWF64[pow]             F64 F64 -> F64;
+    EXPF64[exp]             F64 -> F64;
+    LOGF64[log]             F64 -> F64;
+    INIT[rt_init]           PTR PTR I32 I32;
+    ALLOC[rt_alloc]         PTR I64 I64 -> PTR;
+    ABORT[rt_abort]         PTR;
+    CLOCK[rt_clock]         -> I64;
+    HOOKENTER[rt_hookenter] PTR I32 I32 I32;
+    HOOKEXIT[rt_hookexit]   PTR I32 I32 I32;
 }
 
 impl SuppFunc {
@@ -112,13 +116,29 @@ impl NativeFunc {
         NATIVEFUNC_SIGNATURE[self as usize]
     }
 
+    // linker symbol name, for object file emission.
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Self::POWF64    => "pow",
+            Self::EXPF64    => "exp",
+            Self::LOGF64    => "log",
+            Self::INIT      => "fhk_rt_init",
+            Self::ALLOC     => "fhk_rt_alloc",
+            Self::ABORT     => "fhk_rt_abort",
+            Self::CLOCK     => "fhk_rt_clock",
+            Self::HOOKENTER => "fhk_rt_hookenter",
+            Self::HOOKEXIT  => "fhk_rt_hookexit"
+        }
+    }
+
 }
 
 /* ---- Math ---------------------------------------------------------------- */
 
 #[link(name="m")]
 unsafe extern "C" {
-    fn pow(x: f64, y: f64) -> f64;
+    pub fn pow(x: f64, y: f64) -> f64;
+    pub fn sqrt(x: f64) -> f64;
     fn exp(x: f64) -> f64;
     fn log(x: f64) -> f64;
 }
@@ -175,7 +195,8 @@ impl DynSlot {
 
 }
 
-unsafe extern "C" fn rt_init(vmctx: &mut Instance, slots: *const DynSlot, num: u32, size: u32) {
+#[unsafe(export_name="fhk_rt_init")]
+pub unsafe extern "C" fn rt_init(vmctx: &mut Instance, slots: *const DynSlot, num: u32, size: u32) {
     let slots = unsafe { core::slice::from_raw_parts(slots, num as _) };
     for &slot in slots {
         let ofs = slot.offset();
@@ -237,6 +258,7 @@ fn supp_init(ecx: &mut Ecx) {
 
 // TODO: host should compile this function
 
+#[unsafe(export_name="fhk_rt_alloc")]
 unsafe extern "C" fn rt_alloc(vmctx: &mut Instance, size: usize, align: usize) -> *mut u8 {
     vmctx.host.alloc(size, align)
 }
@@ -254,8 +276,11 @@ fn supp_alloc(ecx: &mut Ecx) {
 
 /* ---- Abort --------------------------------------------------------------- */
 
+pub const ABORT_MESSAGE: &[u8] = b"query aborted (no suitable model)";
+
+#[unsafe(export_name="fhk_rt_abort")]
 unsafe extern "C" fn rt_abort(vmctx: &mut Instance) -> ! {
-    vmctx.host.set_error(b"query aborted (no suitable model)");
+    vmctx.host.set_error(ABORT_MESSAGE);
     unsafe { fhk_vmexit(vmctx) }
 }
 
@@ -268,6 +293,40 @@ fn supp_abort(ecx: &mut Ecx) {
     emit.fb.ins().trap(TrapCode::User(0));
 }
 
+/* ---- Profiling ----------------------------------------------------------- */
+
+// cheapest available monotonic tick counter. the unit is whatever the hardware counts.
+#[unsafe(export_name="fhk_rt_clock")]
+extern "C" fn rt_clock() -> u64 {
+    cfg_if! {
+        if #[cfg(target_arch="x86_64")] {
+            unsafe { core::arch::x86_64::_rdtsc() }
+        } else if #[cfg(target_arch="aarch64")] {
+            let t: u64;
+            unsafe { core::arch::asm!("mrs {}, cntvct_el0", out(reg) t); }
+            t
+        } else {
+            0
+        }
+    }
+}
+
+/* ---- Hooks --------------------------------------------------------------- */
+
+#[unsafe(export_name="fhk_rt_hookenter")]
+unsafe extern "C" fn rt_hookenter(hooks: &Hooks, func: u32, start: u32, end: u32) {
+    if let Some(enter) = hooks.enter {
+        unsafe { enter(hooks.ud, func, start, end) }
+    }
+}
+
+#[unsafe(export_name="fhk_rt_hookexit")]
+unsafe extern "C" fn rt_hookexit(hooks: &Hooks, func: u32, start: u32, end: u32) {
+    if let Some(exit) = hooks.exit {
+        unsafe { exit(hooks.ud, func, start, end) }
+    }
+}
+
 /* -------------------------------------------------------------------------- */
 
 pub fn emitsupport(ecx: &mut Ecx, supp: SuppFunc) {
@@ -279,3 +338,4 @@ pub fn emitsupport(ecx: &mut Ecx, supp: SuppFunc) {
         SWAP   => unreachable!() // asm function
     }
 }
+
@@ -0,0 +1,223 @@
+//! Symbol table.
+
+// names live in the objects (TAB/VAR/FUNC name fields), and functions point back to their
+// objects through DebugSource. this module fills in the remaining directions:
+//   * name -> object: `Objects::tab` and `Objects::var`
+//   * object -> function: `Symbols`, built from the IR
+//   * object/function -> printable name
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use crate::bump::Bump;
+use crate::compile::Ccx;
+use crate::hash::HashMap;
+use crate::image::{CodeSize, HookTable, Hooks, ProfCounter, ProfSwitch, Profile, SizeEntry, SrcMap};
+use crate::intern::{IRef, Intern};
+use crate::ir::{DebugFlag, DebugSource, FuncId, IR};
+use crate::obj::{Obj, ObjRef, ObjectRef, Objects, Operator, EXPR, FUNC, MOD, TAB, VAR, VSET};
+use crate::parser::{stringify, SequenceType};
+
+// an object may lower to several functions (value, avail, init), so objects map to a range of
+// `funcs` rather than a single function.
+#[derive(Default)]
+pub struct Symbols {
+    funcs: Vec<FuncId>,                  // grouped by object
+    index: HashMap<ObjRef, (u32, u32)>   // object -> funcs[start..end]
+}
+
+impl Symbols {
+
+    pub fn new(ir: &IR) -> Self {
+        let mut index: HashMap<ObjRef, (u32, u32)> = Default::default();
+        for func in &ir.funcs.raw {
+            index.entry(func.source.obj()).or_default().1 += 1;
+        }
+        let mut start = 0;
+        for range in index.values_mut() {
+            let num = range.1;
+            *range = (start, start);
+            start += num;
+        }
+        // the second pass fills each range, leaving its end in place.
+        let mut funcs: Vec<FuncId> = vec![0.into(); ir.funcs.raw.len()];
+        for (fid, func) in ir.funcs.pairs() {
+            let range = index.get_mut(&func.source.obj()).unwrap();
+            funcs[range.1 as usize] = fid;
+            range.1 += 1;
+        }
+        Self { funcs, index }
+    }
+
+    // every function lowered from `obj`.
+    pub fn funcs(&self, obj: ObjRef) -> &[FuncId] {
+        match self.index.get(&obj) {
+            Some(&(start, end)) => &self.funcs[start as usize..end as usize],
+            None => &[]
+        }
+    }
+
+}
+
+fn write_name(buf: &mut Bump, intern: &Intern, name: IRef<[u8]>) {
+    stringify(buf, intern, intern.get_slice(name), SequenceType::Pattern);
+}
+
+// tab, tab.var, func, or the variables of a model.
+pub fn write_objname(buf: &mut Bump, intern: &Intern, objs: &Objects, obj: ObjRef) {
+    match objs.get(obj) {
+        ObjectRef::VAR(&VAR { name, tab, .. }) => {
+            write_name(buf, intern, objs[tab].name);
+            buf.push(b'.');
+            write_name(buf, intern, name);
+        },
+        ObjectRef::TAB(&TAB { name, .. }) | ObjectRef::FUNC(&FUNC { name, .. }) => {
+            write_name(buf, intern, name);
+        },
+        ObjectRef::MOD(MOD { value, .. }) => {
+            for (i, &vset) in value.iter().enumerate() {
+                if i>0 { buf.push(b','); }
+                let VSET { var, .. } = objs[vset];
+                write_name(buf, intern, objs[var].name);
+            }
+        },
+        _ => {}
+    }
+}
+
+// OPref(name)
+pub fn write_objdesc(buf: &mut Bump, intern: &Intern, objs: &Objects, obj: ObjRef) {
+    let op = objs[obj].operator();
+    write!(buf, "{}{:?}", op.name(), obj).unwrap();
+    if (Operator::VAR|Operator::TAB|Operator::FUNC|Operator::MOD).contains(op) {
+        buf.push(b'(');
+        write_objname(buf, intern, objs, obj);
+        buf.push(b')');
+    }
+}
+
+// OPref(name).value/.avail/.init
+pub fn write_source(buf: &mut Bump, intern: &Intern, objs: &Objects, src: DebugSource) {
+    write_objdesc(buf, intern, objs, src.obj());
+    let op = objs[src.obj()].operator();
+    let flags = src.flags();
+    if flags.contains(DebugFlag::VALUE) {
+        buf.write(".value");
+    } else if (Operator::VAR|Operator::MOD).contains(op) {
+        buf.write(".avail");
+    }
+    if flags.contains(DebugFlag::INIT) {
+        buf.write(".init");
+    }
+}
+
+// nul-terminated, returns the offset of the description in `text`.
+fn write_srcdesc<P>(text: &mut Bump, ccx: &Ccx<P>, src: DebugSource) -> u32 {
+    let ofs = text.end().ptr() as u32;
+    write_source(text, &ccx.intern, &ccx.objs, src);
+    if let Some(line) = ccx.srclines.get(&src.obj().cast()) {
+        write!(text, " on line {}", line).unwrap();
+    }
+    text.push(0u8);
+    ofs
+}
+
+// source descriptions for the emitted functions and expressions:
+//   OPref(name).value on line N
+//   OPref(name).value on line N col C
+// the line of a function is known for functions computing a model. an expression has the
+// location of its first token, or its operator for binary operators.
+pub fn build_srcmap<P>(ccx: &Ccx<P>) -> SrcMap {
+    let mut text = Bump::default();
+    let mut lines = ccx.mcode.lines.clone();
+    lines.sort_unstable_by_key(|&(start, _, _)| start);
+    let ranges: Vec<_> = lines.iter().map(|&(start, end, src)| {
+        (start, end, write_srcdesc(&mut text, ccx, src))
+    }).collect();
+    let mut descs: HashMap<(DebugSource, ObjRef<EXPR>), u32> = Default::default();
+    let mut exprs: Vec<_> = ccx.mcode.srclocs.iter().filter_map(|&(start, end, expr)| {
+        let loc = ccx.srclocs.get(&expr)?;
+        let func = lines.partition_point(|&(s, _, _)| s <= start).checked_sub(1)?;
+        let (_, fend, src) = lines[func];
+        if start >= fend {
+            return None;
+        }
+        let desc = *descs.entry((src, expr)).or_insert_with(|| {
+            let ofs = text.end().ptr() as u32;
+            write_source(&mut text, &ccx.intern, &ccx.objs, src);
+            write!(text, " on line {} col {}", loc.line, loc.col).unwrap();
+            text.push(0u8);
+            ofs
+        });
+        Some((start, end, desc))
+    }).collect();
+    exprs.sort_unstable_by_key(|&(start, _, _)| start);
+    SrcMap {
+        ranges: ranges.into_boxed_slice(),
+        exprs: exprs.into_boxed_slice(),
+        text: text.as_slice::<u8>().into()
+    }
+}
+
+// code size per object, with the source description of the object:
+//   OPref(name) on line N
+pub fn build_codesize<P>(ccx: &Ccx<P>) -> CodeSize {
+    let mut text = Bump::default();
+    let mut index: HashMap<ObjRef, usize> = Default::default();
+    let mut objs: Vec<SizeEntry> = Default::default();
+    for &(start, end, src) in &ccx.mcode.lines {
+        let obj = src.obj();
+        let idx = *index.entry(obj).or_insert_with(|| {
+            let name = text.end().ptr() as u32;
+            write_objdesc(&mut text, &ccx.intern, &ccx.objs, obj);
+            if let Some(line) = ccx.srclines.get(&obj.cast()) {
+                write!(text, " on line {}", line).unwrap();
+            }
+            text.push(0u8);
+            objs.push(SizeEntry { obj, bytes: 0, name, query: ccx.objs[obj].op == Obj::QUERY });
+            objs.len()-1
+        });
+        objs[idx].bytes += end - start;
+    }
+    objs.sort_by_key(|e| core::cmp::Reverse(e.bytes));
+    CodeSize {
+        objs: objs.into_boxed_slice(),
+        text: text.as_slice::<u8>().into(),
+        report: Default::default()
+    }
+}
+
+// same descriptions as the source map, one per counter.
+pub fn build_profile<P>(
+    ccx: &Ccx<P>,
+    counters: Box<[ProfCounter]>,
+    switches: Vec<ProfSwitch>
+) -> Profile {
+    let mut text = Bump::default();
+    let names = ccx.ir.funcs.raw.iter()
+        .map(|func| write_srcdesc(&mut text, ccx, func.source))
+        .collect();
+    Profile {
+        counters,
+        sources: ccx.ir.funcs.raw.iter().map(|func| func.source).collect(),
+        names,
+        text: text.as_slice::<u8>().into(),
+        switches: switches.into_boxed_slice(),
+        report: Default::default()
+    }
+}
+
+// same descriptions as the source map, one per IR function.
+pub fn build_hooks<P>(ccx: &Ccx<P>, hooks: Box<Hooks>) -> HookTable {
+    let mut text = Bump::default();
+    let names = ccx.ir.funcs.raw.iter()
+        .map(|func| write_srcdesc(&mut text, ccx, func.source))
+        .collect();
+    HookTable {
+        hooks,
+        names,
+        text: text.as_slice::<u8>().into()
+    }
+}
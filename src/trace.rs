@@ -1,12 +1,175 @@
 //! Debug output.
 
+use core::fmt::Write;
+
+/* ---- Binary format ------------------------------------------------------- */
+
+// with FHK_TRACE_FILE=path, trace output goes to `path` in a binary format instead of stderr.
+// the file is TRACE_MAGIC followed by records:
+//   +------+------+------+-----+-----+-----------+
+//   | time | subs | kind | tid | len |  message  |
+//   +------+------+------+-----+-----+-----------+
+//   |  u64 |  u8  |  u8  | u16 | u32 | [u8; len] |
+//   +------+------+------+-----+-----+-----------+
+// time is nanoseconds since tracing started, subs is the TraceFlag (or SUBS_NONE),
+// tid numbers the threads in the order they first traced something (0 is the first one),
+// integers are little endian.
+
+pub const TRACE_MAGIC: &[u8; 8] = b"fhktrc\x00\x01";
+pub const SUBS_NONE: u8 = !0;
+pub const REC_MESSAGE: u8 = 0;
+pub const REC_BEGIN: u8 = 1; // message is the span name
+pub const REC_END: u8 = 2;   // message is empty, ends the thread's innermost span
+
+// ORDER TRACEFLAG
+const SUBS_NAME: &[&str] = &[
+    "PARSE", "TYPE", "LOWER", "OPTIMIZE", "MEM", "SCHEDULE", "MCODE", "CLIF", "LINK", "SPAN",
+    "DOT"
+];
+
+const REC_HEADER: usize = 16;
+
+pub struct TraceRecord<'a> {
+    pub time: u64,
+    pub subs: u8,
+    pub kind: u8,
+    pub tid: u16,
+    pub message: &'a [u8]
+}
+
+impl<'a> TraceRecord<'a> {
+
+    pub fn subs_name(&self) -> &'static str {
+        SUBS_NAME.get(self.subs as usize).copied().unwrap_or("")
+    }
+
+    pub fn message_str(&self) -> &'a str {
+        core::str::from_utf8(self.message).unwrap_or("(invalid utf-8)")
+    }
+
+}
+
+pub struct TraceRecords<'a> {
+    data: &'a [u8]
+}
+
+impl<'a> Iterator for TraceRecords<'a> {
+    type Item = TraceRecord<'a>;
+    fn next(&mut self) -> Option<TraceRecord<'a>> {
+        // a truncated record (eg. the process died while writing) ends the trace.
+        let (head, rest) = self.data.split_at_checked(REC_HEADER)?;
+        let len = u32::from_le_bytes(head[12..16].try_into().unwrap()) as usize;
+        let (message, rest) = rest.split_at_checked(len)?;
+        self.data = rest;
+        Some(TraceRecord {
+            time: u64::from_le_bytes(head[0..8].try_into().unwrap()),
+            subs: head[8],
+            kind: head[9],
+            tid: u16::from_le_bytes(head[10..12].try_into().unwrap()),
+            message
+        })
+    }
+}
+
+pub fn trace_records(data: &[u8]) -> Option<TraceRecords<'_>> {
+    data.strip_prefix(TRACE_MAGIC).map(|data| TraceRecords { data })
+}
+
+pub fn encode_record(
+    buf: &mut alloc::vec::Vec<u8>,
+    time: u64,
+    subs: u8,
+    kind: u8,
+    tid: u16,
+    message: &[u8]
+) {
+    buf.extend_from_slice(&time.to_le_bytes());
+    buf.extend_from_slice(&[subs, kind]);
+    buf.extend_from_slice(&tid.to_le_bytes());
+    buf.extend_from_slice(&(message.len() as u32).to_le_bytes());
+    buf.extend_from_slice(message);
+}
+
+/* ---- Conversion ---------------------------------------------------------- */
+
+// returns false if `data` is not a binary trace.
+pub fn write_text(out: &mut impl Write, data: &[u8]) -> bool {
+    let Some(records) = trace_records(data) else { return false };
+    // spans nest within a thread, so each thread has its own depth.
+    let mut depths: alloc::vec::Vec<usize> = Default::default();
+    for rec in records {
+        let tid = rec.tid as usize;
+        if depths.len() <= tid {
+            depths.resize(tid+1, 0);
+        }
+        let depth = &mut depths[tid];
+        let (mark, indent) = match rec.kind {
+            REC_BEGIN => { *depth += 1; (">", *depth-1) },
+            REC_END => { *depth = depth.saturating_sub(1); ("<", *depth) },
+            _ => ("", *depth)
+        };
+        let indent = 2*indent;
+        write!(out, "{:12.6} {:3} {:-8} {:indent$}{}{}\n", rec.time as f64 / 1e9, rec.tid,
+            rec.subs_name(), "", mark, rec.message_str()).unwrap();
+    }
+    true
+}
+
+fn write_json_str(out: &mut impl Write, s: &str) {
+    out.write_char('"').unwrap();
+    for c in s.chars() {
+        match c {
+            '"'  => out.write_str("\\\"").unwrap(),
+            '\\' => out.write_str("\\\\").unwrap(),
+            '\n' => out.write_str("\\n").unwrap(),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.write_char(c).unwrap()
+        }
+    }
+    out.write_char('"').unwrap();
+}
+
+// chrome trace-event json, viewable in chrome://tracing or perfetto.
+// returns false if `data` is not a binary trace.
+pub fn write_json(out: &mut impl Write, data: &[u8]) -> bool {
+    let Some(records) = trace_records(data) else { return false };
+    out.write_str("{\"traceEvents\":[").unwrap();
+    for (i, rec) in records.enumerate() {
+        if i > 0 { out.write_char(',').unwrap(); }
+        out.write_str("{\"name\":").unwrap();
+        write_json_str(out, rec.message_str());
+        let ph = match rec.kind {
+            REC_BEGIN => "\"B\"",
+            REC_END   => "\"E\"",
+            _         => "\"i\",\"s\":\"g\""
+        };
+        write!(out, ",\"cat\":\"{}\",\"ph\":{},\"ts\":{},\"pid\":0,\"tid\":{}}}",
+            rec.subs_name(), ph, rec.time as f64 / 1e3, rec.tid).unwrap();
+    }
+    out.write_str("]}").unwrap();
+    true
+}
+
+/* ---- Tracing ------------------------------------------------------------- */
+
 #[cfg(feature="trace")]
 pub mod trace_impl {
 
-    use core::sync::atomic::{AtomicU8, Ordering};
+    extern crate std;
 
+    use core::sync::atomic::{AtomicU16, Ordering};
+    use std::fs::File;
+    use std::io::Write;
+    use std::sync::Mutex;
+    use std::time::Instant;
+
+    use alloc::string::String;
+    use alloc::vec::Vec;
     use enumset::{EnumSet, EnumSetType};
 
+    use super::{encode_record, REC_BEGIN, REC_END, SUBS_NAME, SUBS_NONE, TRACE_MAGIC};
+
+    // ORDER TRACEFLAG
     #[derive(EnumSetType)]
     pub enum TraceFlag {
         PARSE,
@@ -17,16 +180,42 @@ pub mod trace_impl {
         SCHEDULE,
         MCODE,
         CLIF,
-        LINK
+        LINK,
+        SPAN,
+        DOT   // IR and object dumps in graphviz format
+    }
+
+    const FLAGS_UNSET: u16 = !0;
+    static FLAGS: AtomicU16 = AtomicU16::new(FLAGS_UNSET);
+
+    static NEXT_TID: AtomicU16 = AtomicU16::new(0);
+
+    std::thread_local! {
+        static TID: u16 = NEXT_TID.fetch_add(1, Ordering::Relaxed);
     }
 
-    const FLAGS_UNSET: u8 = !0;
-    static FLAGS: AtomicU8 = AtomicU8::new(FLAGS_UNSET);
+    struct Sink {
+        file: File,
+        start: Instant
+    }
+
+    static SINK: Mutex<Option<Sink>> = Mutex::new(None);
+
+    #[cold]
+    fn init_sink() {
+        if let Ok(path) = std::env::var("FHK_TRACE_FILE") {
+            if let Ok(mut file) = File::create(path) {
+                if file.write_all(TRACE_MAGIC).is_ok() {
+                    *SINK.lock().unwrap() = Some(Sink { file, start: Instant::now() });
+                }
+            }
+        }
+    }
 
     #[cold]
     fn init_flags() -> EnumSet<TraceFlag> {
-        extern crate std;
         use TraceFlag::*;
+        init_sink();
         let mut flags: EnumSet<TraceFlag> = Default::default();
         if let Ok(v) = std::env::var("FHK_TRACE") {
             for &f in v.as_bytes() {
@@ -40,19 +229,65 @@ pub mod trace_impl {
                     b'c' => MCODE.into(),
                     b'f' => CLIF.into(),
                     b'k' => LINK.into(),
+                    b'e' => SPAN.into(),
+                    b'd' => DOT.into(),
                     b'a' => EnumSet::all(),
                     _ => continue
                 });
             }
         }
-        FLAGS.store(flags.as_u8_truncated(), Ordering::Relaxed);
+        FLAGS.store(flags.as_u16_truncated(), Ordering::Relaxed);
         flags
     }
 
     pub fn trace_flags() -> EnumSet<TraceFlag> {
         match FLAGS.load(Ordering::Relaxed) {
             FLAGS_UNSET => init_flags(),
-            flags       => EnumSet::from_u8_truncated(flags)
+            flags       => EnumSet::from_u16_truncated(flags)
+        }
+    }
+
+    pub fn emit(flag: Option<TraceFlag>, kind: u8, args: core::fmt::Arguments) {
+        trace_flags(); // make sure the sink is initialized
+        if let Some(sink) = &mut *SINK.lock().unwrap() {
+            let time = sink.start.elapsed().as_nanos() as u64;
+            let subs = flag.map(|f| f as u8).unwrap_or(SUBS_NONE);
+            let tid = TID.with(|&tid| tid);
+            let mut rec = Vec::new();
+            encode_record(&mut rec, time, subs, kind, tid, std::fmt::format(args).as_bytes());
+            let _ = sink.file.write_all(&rec);
+            return;
+        }
+        match (flag, kind) {
+            (_, REC_BEGIN|REC_END) => {},
+            (Some(f), _) => std::eprintln!("{:-6} {}", SUBS_NAME[f as usize], args),
+            (None, _) => std::eprintln!("{}", args)
+        }
+    }
+
+    // with a binary sink, spans are written as begin/end records.
+    // on stderr, the span is printed when it ends, with its duration.
+    pub struct Span {
+        name: String,
+        start: Instant
+    }
+
+    impl Span {
+        pub fn new(args: core::fmt::Arguments) -> Self {
+            let name = std::fmt::format(args);
+            emit(Some(TraceFlag::SPAN), REC_BEGIN, format_args!("{}", name));
+            Span { name, start: Instant::now() }
+        }
+    }
+
+    impl Drop for Span {
+        fn drop(&mut self) {
+            if SINK.lock().unwrap().is_some() {
+                emit(Some(TraceFlag::SPAN), REC_END, format_args!(""));
+            } else {
+                std::eprintln!("{:-6} {} ({:.3} ms)", SUBS_NAME[TraceFlag::SPAN as usize], self.name,
+                    self.start.elapsed().as_secs_f64() * 1e3);
+            }
         }
     }
 
@@ -62,22 +297,34 @@ pub mod trace_impl {
             $crate::trace::trace_impl::trace_flags()
                 .contains($crate::trace::trace_impl::TraceFlag::$flag)
         };
-        ($fmt:literal $($v:tt)*) => {{
-            extern crate std;
-            std::eprintln!($fmt $($v)*);
-        }};
+        ($fmt:literal $($v:tt)*) => {
+            $crate::trace::trace_impl::emit(
+                None,
+                $crate::trace::REC_MESSAGE,
+                format_args!($fmt $($v)*)
+            )
+        };
         ($flag:ident $($v:tt)+) => {
             if $crate::trace::trace_impl::trace!($flag) {
-                $crate::trace::trace_impl::trace!(
-                    "{:-6} {}",
-                    stringify!($flag),
+                $crate::trace::trace_impl::emit(
+                    Some($crate::trace::trace_impl::TraceFlag::$flag),
+                    $crate::trace::REC_MESSAGE,
                     format_args!($($v)+)
                 )
             }
         };
     }
 
-    pub(crate) use trace;
+    macro_rules! trace_span {
+        ($($v:tt)+) => {
+            match $crate::trace::trace_impl::trace!(SPAN) {
+                true  => Some($crate::trace::trace_impl::Span::new(format_args!($($v)+))),
+                false => None
+            }
+        };
+    }
+
+    pub(crate) use {trace, trace_span};
 
 }
 
@@ -89,7 +336,10 @@ mod trace_impl {
         ($fmt: literal $($v:tt)*) => { if false { let _ = format_args!($fmt $($v)*); } };
         ($_:ident $($v:tt)+) => { if false { let _ = format_args!($($v)*); } };
     }
-    pub(crate) use trace;
+    macro_rules! trace_span {
+        ($($v:tt)+) => {{ if false { let _ = format_args!($($v)+); } None::<()> }};
+    }
+    pub(crate) use {trace, trace_span};
 }
 
-pub(crate) use trace_impl::trace;
+pub(crate) use trace_impl::{trace, trace_span};
@@ -1,7 +1,11 @@
 //! IR -> Cranelift translation.
 
+use core::mem::replace;
+
 use cranelift_codegen::ir::condcodes::{FloatCC, IntCC};
-use cranelift_codegen::ir::{InstBuilder, MemFlags, TrapCode, Value};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use cranelift_codegen::ir::{InstBuilder, JumpTableData, MemFlags, TrapCode, Value};
 use zerocopy::Unalign;
 
 use crate::bump::BumpRef;
@@ -9,9 +13,14 @@ use crate::controlflow::BlockId;
 use crate::lang::Lang;
 use crate::mem::{CursorType, SizeClass};
 use crate::compile;
-use crate::emit::{block2cl, irt2cl, loadslot, storeslot, Ecx, Emit, InsValue, MEM_RESULT};
-use crate::ir::{Chunk, FuncKind, InsId, LangOp, Opcode, PhiId, Query, Type};
+use crate::emit::{block2cl, emithookexit, emitprofexit, emitprofinc, irt2cl, loadslot, storeslot, Ecx, Emit, InsValue, MEM_RESULT};
+use crate::hash::HashMap;
+use crate::image::ProfSwitch;
+use crate::index::{IndexSlice, IndexVec};
+use crate::intern::IRef;
+use crate::ir::{Chunk, FuncKind, Ins, InsId, LangOp, Opcode, PhiId, Query, Type};
 use crate::support::{NativeFunc, SuppFunc};
+use crate::trace::trace;
 
 fn ctrargs(emit: &mut Emit, target: BlockId, jmp: Option<(PhiId, Value)>) {
     let mut src = emit.blockparams[emit.block]
@@ -104,8 +113,297 @@ fn ins_if(ecx: &mut Ecx, id: InsId) {
     );
 }
 
+/* ---- Switches ------------------------------------------------------------ */
+
+// a chain of IFs comparing the same integer against constants:
+//   IF (EQ v k1) t1 (IF (EQ v k2) t2 (... (IF (EQ v kN) tN default)))
+// (or NE with the branches swapped) is emitted as a jump table when the keys are dense, and as a
+// balanced comparison tree otherwise. the intermediate IFs are still translated, but they are
+// unreachable from the head unless something else jumps to them.
+
+// minimum key density (in percent) for a jump table.
+const SWITCH_DENSITY: i64 = 50;
+// maximum jump table size.
+const SWITCH_MAXTABLE: i64 = 1 << 12;
+
+#[derive(Clone, Copy)]
+struct SwitchTarget {
+    block: cranelift_codegen::ir::Block,
+    args: (usize, usize) // range in the args vec
+}
+
+// sign extend to the width of the type, so that keys sort in the same order as `slt` compares.
+fn switchkey(type_: Type, k: i64) -> i64 {
+    let shift = 64 - 8*type_.size() as u32;
+    (k << shift) >> shift
+}
+
+// cranelift wants immediates of narrow types zero extended.
+fn switchimm(type_: Type, k: i64) -> i64 {
+    match type_.size() {
+        8 => k,
+        s => k & ((1 << (8*s)) - 1)
+    }
+}
+
+// if `id` is an IF comparing `v` against a constant, return (constant, hit, miss).
+fn switchcase(code: &[Ins], id: InsId, v: Option<InsId>) -> Option<(InsId, InsId, InsId)> {
+    let ins = code[usize::from(id)];
+    if ins.opcode() != Opcode::IF { return None }
+    let (cond, tru, fal) = ins.decode_IF();
+    let cins = code[usize::from(cond)];
+    if !(Opcode::EQ|Opcode::NE).contains(cins.opcode()) { return None }
+    let (a, k) = cins.decode_VV();
+    if v.is_some_and(|v| v != a) { return None }
+    if !(Opcode::KINT|Opcode::KINT64).contains(code[usize::from(k)].opcode()) { return None }
+    if !(Type::I8|Type::I16|Type::I32|Type::I64).contains(code[usize::from(a)].type_()) {
+        return None
+    }
+    Some(match cins.opcode() {
+        Opcode::EQ => (k, tru, fal),
+        _ => (k, fal, tru)
+    })
+}
+
+// can the switch skip over the block ending in `id`?
+// the block must contain nothing but the IF, its condition and the constants of the condition,
+// none of which may be used anywhere else. scheduling places constants late, so each case of
+// a chain usually gets its own constant, eg. the model index in a variable's value dispatch.
+fn switchpassthrough(code: &[Ins], uses: &IndexSlice<InsId, u32>, id: InsId) -> bool {
+    let idx: usize = id.into();
+    let cond = code[idx].decode_IF().0;
+    let mut i = idx;
+    while i > 0 && !code[i-1].opcode().is_control() {
+        i -= 1;
+        let this: InsId = i.into();
+        let only = match this == cond {
+            true => true,
+            false => (Opcode::KINT|Opcode::KINT64).contains(code[i].opcode())
+                && code[usize::from(cond)].inputs().contains(&this)
+        };
+        if !only || uses[this] != 1 {
+            return false;
+        }
+    }
+    true
+}
+
+fn countuses(code: &[Ins], uses: &mut IndexVec<InsId, u32>) {
+    uses.raw.clear();
+    uses.raw.resize(code.len(), 0);
+    for ins in code {
+        for &input in ins.inputs() {
+            uses[input] += 1;
+        }
+    }
+}
+
+fn switchtarget<'a>(
+    targets: &[SwitchTarget],
+    args: &'a [Value],
+    t: usize
+) -> (cranelift_codegen::ir::Block, &'a [Value]) {
+    let SwitchTarget { block, args: (start, end) } = targets[t];
+    (block, &args[start..end])
+}
+
+fn switchtargetidx(
+    emit: &mut Emit,
+    targets: &mut Vec<SwitchTarget>,
+    index: &mut HashMap<InsId, usize>,
+    t: InsId
+) -> usize {
+    if let Some(&idx) = index.get(&t) {
+        return idx;
+    }
+    let block = emit.values[t].block();
+    let start = emit.tmp_val.len();
+    ctrargs(emit, block, None);
+    targets.push(SwitchTarget { block: block2cl(block), args: (start, emit.tmp_val.len()) });
+    index.insert(t, targets.len() - 1);
+    targets.len() - 1
+}
+
+fn switchtree(
+    emit: &mut Emit,
+    v: Value,
+    type_: Type,
+    cases: &[(i64, usize)],
+    targets: &[SwitchTarget],
+    args: &[Value],
+    default: usize
+) {
+    if cases.len() <= 3 {
+        for (i, &(k, t)) in cases.iter().enumerate() {
+            let cond = emit.fb.ins().icmp_imm(IntCC::Equal, v, switchimm(type_, k));
+            let (hit, hitargs) = switchtarget(targets, args, t);
+            let (miss, missargs) = match i+1 == cases.len() {
+                true => switchtarget(targets, args, default),
+                false => (emit.fb.newblock(), &[][..])
+            };
+            emit.fb.ins().brif(cond, hit, hitargs, miss, missargs);
+            emit.fb.block = miss;
+        }
+    } else {
+        let mid = cases.len() / 2;
+        let cond = emit.fb.ins().icmp_imm(IntCC::SignedLessThan, v, switchimm(type_, cases[mid].0));
+        let left = emit.fb.newblock();
+        let right = emit.fb.newblock();
+        emit.fb.ins().brif(cond, left, &[], right, &[]);
+        emit.fb.block = left;
+        switchtree(emit, v, type_, &cases[..mid], targets, args, default);
+        emit.fb.block = right;
+        switchtree(emit, v, type_, &cases[mid..], targets, args, default);
+    }
+}
+
+fn switchtable(
+    emit: &mut Emit,
+    v: Value,
+    type_: Type,
+    cases: &[(i64, usize)],
+    targets: &[SwitchTarget],
+    args: &[Value],
+    default: usize
+) {
+    let min = cases[0].0;
+    let size = (cases[cases.len()-1].0.wrapping_sub(min) + 1) as usize;
+    let kmin = emit.fb.ins().iconst(irt2cl(type_), switchimm(type_, min));
+    let mut idx = emit.fb.ins().isub(v, kmin);
+    let SwitchTarget { block: dblock, args: (dstart, dend) } = targets[default];
+    match type_ {
+        Type::I64 => {
+            let oob = emit.fb.ins().icmp_imm(IntCC::UnsignedGreaterThanOrEqual, idx, size as i64);
+            let next = emit.fb.newblock();
+            emit.fb.ins().brif(oob, dblock, &args[dstart..dend], next, &[]);
+            emit.fb.block = next;
+            idx = emit.fb.ins().ireduce(irt2cl(Type::I32), idx);
+        },
+        Type::I32 => {},
+        _ => idx = emit.fb.ins().uextend(irt2cl(Type::I32), idx)
+    }
+    let dfg = &mut emit.fb.ctx.func.dfg;
+    let mut table = Vec::with_capacity(size);
+    let mut case = 0;
+    for i in 0..size {
+        let t = match cases.get(case) {
+            Some(&(k, t)) if (k - min) as usize == i => { case += 1; t },
+            _ => default
+        };
+        let SwitchTarget { block, args: (start, end) } = targets[t];
+        table.push(dfg.block_call(block, &args[start..end]));
+    }
+    let default = dfg.block_call(dblock, &args[dstart..dend]);
+    let jt = dfg.jump_tables.push(JumpTableData::new(default, &table));
+    emit.fb.ins().br_table(idx, jt);
+}
+
+// returns false if `id` isn't a switch head, or the chain is too short to be worth it.
+fn emitswitch(ecx: &mut Ecx, id: InsId) -> bool {
+    if ecx.session.switchmin == 0 {
+        return false;
+    }
+    let emit = &mut *ecx.data;
+    // the rest of a chain is unreachable once its head is a switch, so don't build another
+    // switch for each of its tails.
+    if emit.switched.contains(id) {
+        return false;
+    }
+    let Some((k, hit, mut miss)) = switchcase(&emit.code.raw, id, None) else { return false };
+    if emit.uses.is_empty() {
+        countuses(&emit.code.raw, &mut emit.uses);
+    }
+    let code = &emit.code.raw[..];
+    let v = code[usize::from(code[usize::from(id)].decode_IF().0)].decode_VV().0;
+    let mut chain = Vec::new();
+    let mut tails = Vec::new();
+    chain.push((k, hit));
+    while miss > id {
+        let Some((k, h, m)) = switchcase(code, miss, Some(v)) else { break };
+        if !switchpassthrough(code, &emit.uses, miss) { break }
+        chain.push((k, h));
+        tails.push(miss);
+        miss = m;
+    }
+    if chain.len() < ecx.session.switchmin as usize {
+        return false;
+    }
+    for tail in tails {
+        emit.switched.insert(tail);
+    }
+    let emit = &*ecx.data;
+    let type_ = emit.code[v].type_();
+    // collect keys, the first comparison wins for duplicates. the sort is stable, so the first
+    // one is also first among its equals.
+    let mut cases: Vec<(i64, InsId)> = chain.iter()
+        .map(|&(k, h)| (switchkey(type_, kintvalue(ecx, k)), h))
+        .collect();
+    cases.sort_by_key(|&(k, _)| k);
+    cases.dedup_by_key(|&mut (k, _)| k);
+    trace!(CLIF "SWITCH {:?} with {} cases", id, cases.len());
+    // compute block args once for each distinct target.
+    let source = ecx.ir.funcs[ecx.data.fid].source;
+    // profile-guided: test the hottest case first, if it takes at least half of the hits.
+    let hot = ecx.session.pgo.as_ref().and_then(|pgo| {
+        let hits: Vec<u64> = cases.iter().map(|&(k, _)| pgo.hits(source, k)).collect();
+        let total: u64 = hits.iter().sum();
+        let (i, &max) = hits.iter().enumerate().max_by_key(|&(_, &h)| h)?;
+        (max > 0 && 2*max >= total).then_some(i)
+    });
+    let emit = &mut *ecx.data;
+    let mut targets: Vec<SwitchTarget> = Vec::new();
+    let mut index: HashMap<InsId, usize> = Default::default();
+    emit.tmp_val.clear();
+    let mut tcases: Vec<(i64, usize)> = cases.iter()
+        .map(|&(key, h)| (key, switchtargetidx(emit, &mut targets, &mut index, h)))
+        .collect();
+    let default = switchtargetidx(emit, &mut targets, &mut index, miss);
+    let args: Vec<Value> = emit.tmp_val.clone();
+    let value = emit.values[v].value();
+    if !emit.prof.is_null() {
+        // profiling: route each case through a block that counts its hits.
+        let mut hits: Box<[u64]> = core::iter::repeat_n(0, tcases.len()).collect();
+        for (i, (_, t)) in tcases.iter_mut().enumerate() {
+            let block = emit.fb.newblock();
+            let head = replace(&mut emit.fb.block, block);
+            emitprofinc(emit, hits.as_mut_ptr().wrapping_add(i));
+            let (target, targs) = switchtarget(&targets, &args, *t);
+            emit.fb.ins().jump(target, targs);
+            emit.fb.block = head;
+            targets.push(SwitchTarget { block, args: (0, 0) });
+            *t = targets.len() - 1;
+        }
+        let keys = tcases.iter().map(|&(k, _)| k).collect();
+        ecx.mcode.profswitch.push(ProfSwitch { source, keys, hits });
+    }
+    if let Some(i) = hot {
+        let (k, t) = tcases.remove(i);
+        let cond = emit.fb.ins().icmp_imm(IntCC::Equal, value, switchimm(type_, k));
+        let (hit, hitargs) = switchtarget(&targets, &args, t);
+        let next = emit.fb.newblock();
+        emit.fb.ins().brif(cond, hit, hitargs, next, &[]);
+        emit.fb.block = next;
+        if tcases.is_empty() {
+            let (target, targs) = switchtarget(&targets, &args, default);
+            emit.fb.ins().jump(target, targs);
+            return true;
+        }
+    }
+    let range = tcases[tcases.len()-1].0 as i128 - tcases[0].0 as i128 + 1;
+    if range <= SWITCH_MAXTABLE as i128
+        && range * SWITCH_DENSITY as i128 <= tcases.len() as i128 * 100
+    {
+        switchtable(emit, value, type_, &tcases, &targets, &args, default);
+    } else {
+        switchtree(emit, value, type_, &tcases, &targets, &args, default);
+    }
+    true
+}
+
 fn ins_ret(ecx: &mut Ecx) {
     // TODO: user funcs return values here.
+    emitprofexit(&mut ecx.data);
+    emithookexit(&mut ecx.data);
     ecx.data.fb.ins().return_(&[]);
 }
 
@@ -152,19 +450,22 @@ fn ins_phi(ecx: &mut Ecx, id: InsId) {
     );
 }
 
-fn ins_kintx(ecx: &mut Ecx, id: InsId) {
-    use Type::*;
+fn kintvalue(ecx: &Ecx, id: InsId) -> i64 {
     let ins = ecx.data.code[id];
-    let type_ = ins.type_();
-    let data = ins.bc();
-    let k = match ins.opcode() {
-        Opcode::KINT => data as i32 as i64,
+    match ins.opcode() {
+        Opcode::KINT => ins.bc() as i32 as i64,
         Opcode::KINT64 => {
-            let data: BumpRef<Unalign<i64>> = zerocopy::transmute!(data);
+            let data: BumpRef<Unalign<i64>> = zerocopy::transmute!(ins.bc());
             ecx.intern.bump()[data].get()
         },
         _ => unreachable!()
-    };
+    }
+}
+
+fn ins_kintx(ecx: &mut Ecx, id: InsId) {
+    use Type::*;
+    let type_ = ecx.data.code[id].type_();
+    let k = kintvalue(ecx, id);
     let value = match type_ {
         I8 | I16 | I32 | I64 | PTR | B1 => ecx.data.fb.ins().iconst(irt2cl(type_), k),
         F32 | F64 => {
@@ -176,7 +477,7 @@ fn ins_kintx(ecx: &mut Ecx, id: InsId) {
             let ptr = ecx.data.fb.dataptr(data);
             ecx.data.fb.kload(type_, ptr)
         },
-        FX | LSV => unreachable!()
+        FX | LSV | F64X2 => unreachable!()
     };
     ecx.data.values[id] = InsValue::from_value(value);
 }
@@ -197,6 +498,15 @@ fn ins_kfp64(ecx: &mut Ecx, id: InsId) {
     emit.values[id] = InsValue::from_value(emit.fb.kload(type_, ptr));
 }
 
+fn ins_kstr(ecx: &mut Ecx, id: InsId) {
+    let emit = &mut *ecx.data;
+    let k: IRef<[u8]> = zerocopy::transmute!(emit.code[id].bc());
+    // align for any primitive, constant data blocks are loaded through this pointer
+    let data = ecx.mcode.data.intern_bytes(ecx.intern.get_slice(k), 8).to_bump().cast();
+    let data = emit.fb.importdataref(data);
+    emit.values[id] = InsValue::from_value(emit.fb.dataptr(data));
+}
+
 fn ins_mov(ecx: &mut Ecx, id: InsId) {
     let emit = &mut *ecx.data;
     let value = emit.code[id].decode_V();
@@ -213,15 +523,16 @@ fn ins_arith(ecx: &mut Ecx, id: InsId) {
     let left = emit.values[left].value();
     let right = emit.values[right].value();
     let value = match (ins.opcode(), ins.type_()) {
-        (ADD, F32|F64) => emit.fb.ins().fadd(left, right),
-        (SUB, F32|F64) => emit.fb.ins().fsub(left, right),
-        (MUL, F32|F64) => emit.fb.ins().fmul(left, right),
-        (DIV, F32|F64) => emit.fb.ins().fdiv(left, right),
+        (ADD, F32|F64|F64X2) => emit.fb.ins().fadd(left, right),
+        (SUB, F32|F64|F64X2) => emit.fb.ins().fsub(left, right),
+        (MUL, F32|F64|F64X2) => emit.fb.ins().fmul(left, right),
+        (DIV, F32|F64|F64X2) => emit.fb.ins().fdiv(left, right),
         (ADD, I8|I16|I32|I64) => emit.fb.ins().iadd(left, right),
         (SUB, I8|I16|I32|I64) => emit.fb.ins().isub(left, right),
         (MUL, I8|I16|I32|I64) => emit.fb.ins().imul(left, right),
         (DIV, I8|I16|I32|I64) => emit.fb.ins().sdiv(left, right),
         (UDIV, I8|I16|I32|I64) => emit.fb.ins().udiv(left, right),
+        (USHR, I8|I16|I32|I64) => emit.fb.ins().ushr(left, right),
         _ => unreachable!()
     };
     emit.values[id] = InsValue::from_value(value);
@@ -231,11 +542,21 @@ fn ins_pow(ecx: &mut Ecx, id: InsId) {
     let emit = &mut *ecx.data;
     let ins = emit.code[id];
     let (left, right) = ins.decode_VV();
-    let left = emit.values[left].value();
-    let right = emit.values[right].value();
+    let mut left = emit.values[left].value();
+    let mut right = emit.values[right].value();
+    // only the f64 pow is available, f32 goes through it.
+    let single = ins.type_() == Type::F32;
+    if single {
+        left = emit.fb.ins().fpromote(irt2cl(Type::F64), left);
+        right = emit.fb.ins().fpromote(irt2cl(Type::F64), right);
+    }
     let func = emit.fb.importnative(NativeFunc::POWF64);
     let call = emit.fb.ins().call(func, &[left, right]);
-    emit.values[id] = InsValue::from_value(emit.fb.ctx.func.dfg.inst_results(call)[0]);
+    let mut value = emit.fb.ctx.func.dfg.inst_results(call)[0];
+    if single {
+        value = emit.fb.ins().fdemote(irt2cl(Type::F32), value);
+    }
+    emit.values[id] = InsValue::from_value(value);
 }
 
 fn ins_addp(ecx: &mut Ecx, id: InsId) {
@@ -259,6 +580,21 @@ fn ins_neg(ecx: &mut Ecx, id: InsId) {
     emit.values[id] = InsValue::from_value(value);
 }
 
+fn ins_sqrt(ecx: &mut Ecx, id: InsId) {
+    let emit = &mut *ecx.data;
+    let operand = emit.values[emit.code[id].decode_V()].value();
+    emit.values[id] = InsValue::from_value(emit.fb.ins().sqrt(operand));
+}
+
+fn ins_select(ecx: &mut Ecx, id: InsId) {
+    let emit = &mut *ecx.data;
+    let (cond, tru, fal) = emit.code[id].decode_SELECT();
+    let cond = emit.values[cond].value();
+    let tru = emit.values[tru].value();
+    let fal = emit.values[fal].value();
+    emit.values[id] = InsValue::from_value(emit.fb.ins().select(cond, tru, fal));
+}
+
 fn ins_cmp(ecx: &mut Ecx, id: InsId) {
     use {Type::*, Opcode::*};
     let emit = &mut *ecx.data;
@@ -334,11 +670,31 @@ fn ins_load(ecx: &mut Ecx, id: InsId) {
     let ins = emit.code[id];
     let ptr = ins.decode_V();
     let ty = irt2cl(ins.type_());
+    // vector loads read tensor data, which is only aligned for the element type.
+    let flags = match ins.type_().is_vector() {
+        true  => MemFlags::new().with_notrap(),
+        false => MemFlags::trusted()
+    };
     emit.values[id] = InsValue::from_value(
-        emit.fb.ins().load(ty, MemFlags::trusted(), emit.values[ptr].value(), 0)
+        emit.fb.ins().load(ty, flags, emit.values[ptr].value(), 0)
     );
 }
 
+fn ins_splat(ecx: &mut Ecx, id: InsId) {
+    let emit = &mut *ecx.data;
+    let ins = emit.code[id];
+    let value = emit.values[ins.decode_V()].value();
+    emit.values[id] = InsValue::from_value(emit.fb.ins().splat(irt2cl(ins.type_()), value));
+}
+
+fn ins_vsum(ecx: &mut Ecx, id: InsId) {
+    let emit = &mut *ecx.data;
+    let vector = emit.values[emit.code[id].decode_V()].value();
+    let lo = emit.fb.ins().extractlane(vector, 0);
+    let hi = emit.fb.ins().extractlane(vector, 1);
+    emit.values[id] = InsValue::from_value(emit.fb.ins().fadd(lo, hi));
+}
+
 // TODO: use BOX in lang_C (but lang_Lua can't use it because the "frame" address is fixed)
 // (side note: a BOX equivalent for output parameters isn't useful since there's no CSE
 //  opportunity - just use ALLOC)
@@ -459,7 +815,7 @@ pub fn translate(ecx: &mut Ecx, id: InsId) -> compile::Result {
             NOP => { /* NOP */ },
             JMP => ins_jmp(ecx, id),
             GOTO => ins_goto(ecx, id),
-            IF => ins_if(ecx, id),
+            IF => if !emitswitch(ecx, id) { ins_if(ecx, id) },
             RET => ins_ret(ecx),
             TRET => todo!(),
             UB => ins_ub(ecx),
@@ -467,15 +823,19 @@ pub fn translate(ecx: &mut Ecx, id: InsId) -> compile::Result {
             PHI => ins_phi(ecx, id),
             KINT | KINT64 => ins_kintx(ecx, id),
             KFP64 => ins_kfp64(ecx, id),
-            KSTR => todo!(),
+            KSTR => ins_kstr(ecx, id),
             KREF => { /* NOP */ },
             MOV | MOVB | MOVF => ins_mov(ecx, id),
             CONV => todo!(),
-            ADD | SUB | MUL | DIV | UDIV => ins_arith(ecx, id),
+            ADD | SUB | MUL | DIV | UDIV | USHR => ins_arith(ecx, id),
             POW => ins_pow(ecx, id),
             ADDP => ins_addp(ecx, id),
             NEG => ins_neg(ecx, id),
+            SQRT => ins_sqrt(ecx, id),
+            SPLAT => ins_splat(ecx, id),
+            VSUM => ins_vsum(ecx, id),
             EQ | NE | LT | LE | ULT | ULE => ins_cmp(ecx, id),
+            SELECT => ins_select(ecx, id),
             ALLOC => ins_alloc(ecx, id),
             STORE => ins_store(ecx, id),
             LOAD => ins_load(ecx, id),
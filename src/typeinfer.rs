@@ -1,6 +1,7 @@
 //! Type inference.
 
 use core::cmp::min;
+use core::fmt::Write;
 use core::hash::Hasher;
 use core::iter::zip;
 
@@ -9,7 +10,7 @@ use enumset::{enum_set, EnumSet};
 use hashbrown::{hash_map, hash_table, HashTable};
 use rustc_hash::FxHasher;
 
-use crate::compile::{self, Ccx, Stage};
+use crate::compile::{self, Ccx, CompileError, Stage};
 use crate::dump::trace_objs;
 use crate::hash::HashMap;
 use crate::index::{index, IndexSlice, IndexVec};
@@ -81,7 +82,7 @@ enum TypeRepr {
 }
 
 enum Constraint {
-    BinOp(TypeVar, TypeVar, TypeVar), // a:dim, b:dim, c:dim :: a = b ∘ c
+    BinOp(TypeVar, TypeVar, TypeVar, ObjRef<BINOP>), // a:dim, b:dim, c:dim :: a = b ∘ c
     Index(TypeVar, Type, TypeVar),    // b:tensor, c:dim :: a = b[c]
 }
 
@@ -91,7 +92,30 @@ pub struct TypeInfer {
     tobj: HashTable<ObjRef>,
     ann: HashMap<ObjRef, Type>,
     tab: ObjRef<TAB>,
-    dim: (TypeVar, u8)
+    dim: (TypeVar, u8),
+    float: Primitive,
+    err: Option<RankError>
+}
+
+// tensor operands of a binary operator must have the same dimension.
+// scalars broadcast against anything.
+struct RankError {
+    expr: ObjRef<BINOP>,
+    left: u8,
+    right: u8
+}
+
+impl CompileError for RankError {
+    fn write(self, ccx: &mut Ccx<(), R, R>) {
+        write!(
+            ccx.host.buf,
+            "cannot broadcast {}-dimensional and {}-dimensional operands (BINOP{:?})",
+            self.left, self.right, self.expr
+        ).unwrap();
+    }
+    fn code(&self) -> &'static str {
+        "E0101"
+    }
 }
 
 type Tcx<'a> = Ccx<TypeInfer, R<'a>>;
@@ -108,11 +132,14 @@ const PRI_NUM: EnumSet<Primitive> = {
 
 fn kintpri(v: i64) -> EnumSet<Primitive> {
     use Primitive::*;
-    let mut pri = U64 | I64;
-    if v >= 0 { pri |= PTR }
-    if v == v as i8  as i64 { pri |= U8 | I8 };
-    if v == v as i16 as i64 { pri |= U16 | I16 };
-    if v == v as i32 as i64 { pri |= U32 | I32 };
+    let mut pri = I64.into();
+    if v >= 0 { pri |= U64 | PTR }
+    if v == v as i8  as i64 { pri |= I8 };
+    if v == v as i16 as i64 { pri |= I16 };
+    if v == v as i32 as i64 { pri |= I32 };
+    if v == v as u8  as i64 { pri |= U8 };
+    if v == v as u16 as i64 { pri |= U16 };
+    if v == v as u32 as i64 { pri |= U32 };
     if v == v as f32 as i64 { pri |= F32 };
     if v == v as f64 as i64 { pri |= F64 };
     pri
@@ -306,8 +333,13 @@ fn simplify_binop(
     a: TypeVar,
     b: TypeVar,
     c: TypeVar
-) -> bool {
-    match (
+) -> Result<bool, (u8, u8)> {
+    if let (Some(bd), Some(cd)) = (dimension(sub, Type::var(b)), dimension(sub, Type::var(c))) {
+        if bd != cd && bd > 0 && cd > 0 {
+            return Err((bd, cd));
+        }
+    }
+    Ok(match (
         shallowdimension(sub, Type::var(a)),
         shallowdimension(sub, Type::var(b)),
         shallowdimension(sub, Type::var(c)),
@@ -330,7 +362,7 @@ fn simplify_binop(
             true
         }
         _ => false
-    }
+    })
 }
 
 fn simplify_index(
@@ -411,7 +443,14 @@ fn simplify_index(
 
 fn constraint(ctx: &mut TypeInfer, con: Constraint) -> bool {
     if match con {
-        Constraint::BinOp(a, b, c) => simplify_binop(&mut ctx.sub, a, b, c),
+        Constraint::BinOp(a, b, c, expr) => match simplify_binop(&mut ctx.sub, a, b, c) {
+            Ok(r) => r,
+            Err((left, right)) => {
+                // drop the constraint, the error is reported after inference.
+                ctx.err.get_or_insert(RankError { expr, left, right });
+                true
+            }
+        },
         Constraint::Index(a, b, c) => simplify_index(&mut ctx.sub, a, b, c),
     } {
         true
@@ -783,6 +822,9 @@ fn visitintrinsic(tcx: &mut Tcx, func: Intrinsic, args: &[ObjRef<EXPR>]) -> Type
         ANY | ALL => I!(a,n :: a[Tensor Type::pri(Primitive::B1) n] => pri Primitive::B1),
         CONV => I!(a,b e n :: a[Tensor e n] => Tensor b n),
         REP => I!(a,e n m :: a[Tensor e n] => Tensor e m),
+        IF => I!(c t f :: c[pri Primitive::B1], f[{Type::var(t)}] => t),
+        SELECT => I!(c t f,e n :: c[Tensor Type::pri(Primitive::B1) n], t[Tensor e n],
+            f[{Type::var(t)}] => t),
     };
     tcx.tmp.truncate(base);
     ty
@@ -804,9 +846,18 @@ fn visitexpr(tcx: &mut Tcx, idx: ObjRef<EXPR>) -> Option<Type> {
             Some(Type::UNIT)
         },
         ObjectRef::KINT(&KINT { k, .. }) => Some(Type::pri(kintpri(k as _))),
-        ObjectRef::KINT64(&KINT64 { k, .. }) => Some(Type::pri(kintpri(tcx.intern.bump()[k].get()))),
+        ObjectRef::KINT64(&KINT64 { ann, k, .. }) => {
+            let v = tcx.intern.bump()[k].get();
+            // u64 literals above i64::MAX are stored as negative, see parse_value1.
+            Some(Type::pri(match objs.get(ann) {
+                ObjectRef::TPRI(&TPRI { ty, .. }) if v < 0 && ty == Primitive::U64 as u8
+                    => Primitive::U64.into(),
+                _ => kintpri(v)
+            }))
+        },
         ObjectRef::KFP64(&KFP64 { k, .. }) => Some(Type::pri(kfpri(tcx.intern.bump()[k].get()))),
         ObjectRef::KSTR(_) => Some(Type::pri(Primitive::STR)),
+        ObjectRef::KDATA(_) => Some(Type::pri(Primitive::PTR)),
         ObjectRef::DIM(_) => Some(Type::pri(PRI_IDX)),
         ObjectRef::LEN(&LEN { value, .. }) => {
             // TODO: make sure here that it has at least as many dimensions as our axis.
@@ -887,7 +938,7 @@ fn visitexpr(tcx: &mut Tcx, idx: ObjRef<EXPR>) -> Option<Type> {
             let (le, ld) = unpacktensor(&mut tcx.data.sub, Type::var(lty));
             let (re, rd) = unpacktensor(&mut tcx.data.sub, Type::var(rty));
             unifyvar(&mut tcx.data.sub, le, Type::var(re));
-            constraint(&mut tcx.data, Constraint::BinOp(td, ld, rd));
+            constraint(&mut tcx.data, Constraint::BinOp(td, ld, rd, idx.cast()));
             let res = match BinOp::from_u8(binop) {
                 OR | AND => {
                     unifyvar(&mut tcx.data.sub, le, Type::pri(Primitive::B1));
@@ -914,10 +965,13 @@ fn visitexpr(tcx: &mut Tcx, idx: ObjRef<EXPR>) -> Option<Type> {
             => Some(visitintrinsic(tcx, Intrinsic::from_u8(func), args)),
         ObjectRef::FREF(_) => todo!(),
         ObjectRef::CALL(_) => todo!(),
-        ObjectRef::CALLX(CALLX { inputs, .. }) => {
+        ObjectRef::CALLX(&CALLX { reads, ref inputs, .. }) => {
             for &input in inputs {
                 exprtype(tcx, input);
             }
+            if !reads.is_nil() {
+                exprtype(tcx, reads.cast());
+            }
             None
         },
         _ => unreachable!()
@@ -1018,21 +1072,23 @@ fn canonpair(sub: &mut IndexSlice<TypeVar, Type>, tv: TypeVar) -> Type {
 // * eliminate type variables
 // * multi pri => widest type
 // * tensor<t, 0> => t
-fn canonty(sub: &mut IndexSlice<TypeVar, Type>, tv: TypeVar) -> Type {
+// `float` is the session's float type, F64 unless compiling for reduced precision.
+fn canonty(sub: &mut IndexSlice<TypeVar, Type>, tv: TypeVar, float: Primitive) -> Type {
     use TypeRepr::*;
     let mut ty = sub[tv];
     ty = match ty.unpack() {
         // fresh type variable, ie. the type is completely unbounded.
-        // treat this as equivalent to pri(all), which evaluates to f64.
-        // (in other words, if there's nothing bounding a type, we choose it to be scalar f64).
-        Var(i) if i == tv => Type::pri(Primitive::F64),
-        Var(i) => canonty(sub, i),
+        // treat this as equivalent to pri(all), which evaluates to the float type.
+        // (in other words, if there's nothing bounding a type, we choose it to be a scalar float).
+        Var(i) if i == tv => Type::pri(float),
+        Var(i) => canonty(sub, i, float),
+        Pri(p) if p.contains(float) => Type::pri(float),
         Pri(p) if p.len() > 1 => {
             let pri = (p & PRI_NUM).as_u16_truncated() as i16;
             Type::pri(EnumSet::from_u16_truncated((pri & -pri) as _))
         },
         Con(Constructor::TENSOR, base) => {
-            canonty(sub, base);
+            canonty(sub, base, float);
             if canondim(sub, base+1) == Type::UNIT {
                 sub[base]
             } else {
@@ -1053,7 +1109,7 @@ fn fixvars(tcx: &mut Tcx) {
     for idx in tcx.objs.keys() {
         if tcx.objs[idx].op == Obj::VAR {
             let ty = tcx.data.ann[&idx];
-            canonty(&mut tcx.data.sub, zerocopy::transmute!(ty));
+            canonty(&mut tcx.data.sub, zerocopy::transmute!(ty), tcx.data.float);
         }
     }
 }
@@ -1064,9 +1120,10 @@ fn annotate(ccx: &mut Ccx<TypeInfer>) {
         idx = i;
         let op = ccx.objs[idx].op;
         if op == Obj::VAR || Operator::is_expr_raw(op) {
-            let mut ann = ccx.data.ann[&idx];
+            // expressions detached by an edit are not reachable from any model or query.
+            let Some(&(mut ann)) = ccx.data.ann.get(&idx) else { continue };
             if op != Obj::VAR {
-                ann = canonty(&mut ccx.data.sub, zerocopy::transmute!(ann));
+                ann = canonty(&mut ccx.data.sub, zerocopy::transmute!(ann), ccx.data.float);
             }
             let ann = typeobj(ccx, ann);
             let ofs = match op {
@@ -1080,7 +1137,7 @@ fn annotate(ccx: &mut Ccx<TypeInfer>) {
 
 impl Stage for TypeInfer {
 
-    fn new(_: &mut Ccx<Absent>) -> compile::Result<Self> {
+    fn new(ccx: &mut Ccx<Absent>) -> compile::Result<Self> {
         let mut sub: IndexVec<TypeVar, Type> = Default::default();
         // ORDER BUILTINTYPE
         sub.push(Type::UNIT);
@@ -1091,7 +1148,9 @@ impl Stage for TypeInfer {
             tobj: Default::default(),
             ann: Default::default(),
             tab: ObjRef::NIL.cast(),
-            dim: (TypeVar::V1D, 1)
+            dim: (TypeVar::V1D, 1),
+            float: ccx.session.float,
+            err: None
         })
     }
 
@@ -1102,6 +1161,9 @@ impl Stage for TypeInfer {
             fixvars(ccx);
             simplify(&mut ccx.data);
         });
+        if let Some(e) = ccx.data.err.take() {
+            return ccx.error(e);
+        }
         debug_assert!(ccx.data.con.is_empty());
         annotate(ccx);
         // TODO: check for errors
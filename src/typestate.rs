@@ -62,13 +62,13 @@ pub struct Absent;
 macro_rules! typestate_union {
     (
         $vis:vis union $name:ident : $union:ident
-        { $($field:ident: $ty:ty),* }
+        { $($(#[$meta:meta])* $field:ident: $ty:ty),* }
     ) => {
 
         #[repr(C)]
         union $union {
             __absent: crate::typestate::Absent,
-            $( $field: core::mem::ManuallyDrop<$ty> ),*
+            $( $(#[$meta])* $field: core::mem::ManuallyDrop<$ty> ),*
         }
 
         #[repr(transparent)]
@@ -81,6 +81,7 @@ macro_rules! typestate_union {
         }
 
         $(
+            $(#[$meta])*
             impl From<$ty> for $name<$ty> {
                 fn from(value: $ty) -> Self {
                     Self($union { $field: core::mem::ManuallyDrop::new(value) },
@@ -1,5 +1,7 @@
 //! Type system.
 
+use core::str;
+
 use enumset::EnumSetType;
 
 use crate::ir::{self, Type};
@@ -18,6 +20,11 @@ macro_rules! define_primitives {
                     _ => None
                 }
             }
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(Self::$name => str::from_utf8($lname).unwrap(),)*
+                }
+            }
         }
     };
 }
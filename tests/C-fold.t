@@ -0,0 +1,19 @@
+# vim: ft=fhk
+
+model global {
+	a = call pure C["sqrt"] (2.25: double): double
+	b = call pure C["libm.so.6":"pow"] (2: double, a+8.5: double): double
+}
+
+### result { a=1.5, b=1024 }
+### -- a call with constant arguments leaves no call in the IR, unless it has side effects.
+### local function calls(pure)
+###   local graph = fhk.newgraph()
+###   graph:define(string.format('model global a = call %s C["sqrt"] (2.25: double): double', pure))
+###   local q = graph:newquery("global", "a")
+###   check({q.query(graph:compile():newinstance(alloc)):unpack()}, {1.5})
+###   local _, n = graph:ir():gsub('"op":"LOVV"', "")
+###   return n
+### end
+### assert(calls("pure") == 0)
+### assert(calls("") > 0)
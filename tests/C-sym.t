@@ -0,0 +1,8 @@
+# vim: ft=fhk
+
+model global {
+	a = call C["sqrt"] (2.25: double): double
+	b = call C["libm.so.6":"pow"] (2: double, 10: double): double
+}
+
+### result { a=1.5, b=1024 }
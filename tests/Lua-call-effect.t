@@ -0,0 +1,10 @@
+# vim: ft=fhk
+
+model global {
+	x = 2
+	a = call pure Lua["return function(a) return a*a end"] (x)
+	b = call reads(x) Lua["return function(a) return a+1 end"] (x)
+	c = call writes Lua["return function(a) return a-1 end"] (x)
+}
+
+### result { a=4, b=3, c=1 }
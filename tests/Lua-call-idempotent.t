@@ -0,0 +1,10 @@
+# vim: ft=fhk
+
+model global {
+	x = 1
+	a = call idempotent Lua["return function(x) loads = (loads or 0) + 1 return x end"] (x)
+	b = call idempotent Lua["return function(x) loads = (loads or 0) + 1 return x end"] (x)
+	c = a + b
+}
+
+### result { c=2 }
@@ -0,0 +1,10 @@
+# vim: ft=fhk
+
+model global {
+	x = 1
+	a = call writes Lua["return function(x) n = x return 0 end"] (x)
+		+ (if x > 0 then call writes Lua["return function(x) n = 10*n + 2*x return 0 end"] (x) else 0)
+		+ call writes Lua["return function(x) n = 10*n + 3*x return n end"] (x)
+}
+
+### result { a=123 }
@@ -0,0 +1,8 @@
+# vim: ft=fhk
+
+model global {
+	s = call writes Lua["return function() n = 1 return n end"] ()
+		+ call writes Lua["return function() n = 10*n + 2 return n end"] ()
+}
+
+### result { s=13 }
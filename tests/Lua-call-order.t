@@ -0,0 +1,9 @@
+# vim: ft=fhk
+
+model global {
+	x = 1
+	a = call writes Lua["return function(x) n = x return n end"] (x)
+	b = call writes Lua["return function(x) n = 10*n + 2*x return n end"] (x)
+}
+
+### result { b=12, a=1 }
@@ -0,0 +1,9 @@
+# vim: ft=fhk
+
+model global {
+	x = 1
+	a = call writes Lua["return function(x) n = 10*x return 0 end"] (x)
+	b = call reads(a) Lua["return function(x) return n + x end"] (x)
+}
+
+### result { b=11 }
@@ -0,0 +1,13 @@
+# vim: ft=fhk
+
+model global {
+	x = 1
+	y = x+1
+}
+
+### result { y=2 }
+### assert(G:affected(G:var(nil, "y")) > 0)
+### assert(G:affected() == 0)
+### local y = G:var(nil, "y")
+### assert(G:affected(y, y) == G:affected(y))
+### assert(G:affected(G:var(nil, "x")) >= G:affected(y))
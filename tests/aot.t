@@ -0,0 +1,51 @@
+# vim: ft=fhk
+
+model global {
+	x = 3
+	y = x*2
+	z = y+x
+}
+
+### local q = G:newquery("global", "y", "z")
+### compile()
+### local base = os.tmpname()
+### if jit.arch ~= "x64" or jit.os == "Windows" then
+###   local ok, err = pcall(G.compileobject, G, base..".o", "m")
+###   assert(not ok and err:match("only supports x86_64 ELF"))
+###   os.remove(base)
+###   return
+### end
+### G:compileobject(base..".o", "m")
+### local fp = assert(io.open(base..".h", "w"))
+### fp:write(G:bindings("m", "c"))
+### fp:close()
+### -- link the object into a C program with the static library, which brings fhk_vmcall.
+### -- without a C compiler, there's nothing more to test.
+### fp = assert(io.open(base..".c", "w"))
+### fp:write(string.format([[
+### #include <stdio.h>
+### #include <stdlib.h>
+### #include "%s.h"
+### extern const struct { uint32_t size; uint32_t breakpoints[65]; } m_image;
+### int main(void) {
+###   fhk_Instance *instance = calloc(1, m_image.size);
+###   m_query0_result result;
+###   if (m_query0(instance, &result)) return 1;
+###   printf("%%g %%g\n", result.y, result.z);
+###   return 0;
+### }
+### ]], base))
+### fp:close()
+### local function ok(st) return st == 0 or st == true end
+### if ok(os.execute("cc --version >/dev/null 2>&1")) then
+###   local lib = string.format("../target/%s/libfhk.a", os.getenv("FHK_TARGET") or "debug")
+###   assert(ok(os.execute(string.format(
+###     "cc -o %s %s.c %s.o %s -Wl,--gc-sections -lm -ldl -lpthread", base, base, base, lib))))
+###   local out = assert(io.popen(base))
+###   assert(out:read("*a") == "6 9\n")
+###   out:close()
+### end
+### os.remove(base..".o")
+### os.remove(base..".h")
+### os.remove(base..".c")
+### os.remove(base)
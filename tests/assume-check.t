@@ -0,0 +1,10 @@
+# vim: ft=fhk
+### G:checkassume()
+### G:assume("x", "<", 0)
+
+model global {
+	x = 1
+	y = x+1
+}
+
+### fail("y", "aborted")
@@ -0,0 +1,15 @@
+# vim: ft=fhk
+### G:checkassume(false)
+### G:assume("x", ">", 0)
+### G:assume("x", "<=", "z")
+### G:assume("n", "==", 3)
+### assert(not pcall(G.assume, G, "x", "!=", 0))
+
+model global {
+	x = 5i32
+	z = 9i32
+	n = 3i32
+	y = (if x > 0 then x*2 else -1) + n
+}
+
+### result { y=13 }
@@ -0,0 +1,11 @@
+# vim: ft=fhk
+### G:audit()
+
+model global {
+	x = 2
+	y = x*1 + 0
+}
+
+### result { y=2 }
+### assert(G:auditlog():match("fold"))
+### assert(G:auditlog("json"):match('^%[{"pass":"fold"'))
@@ -0,0 +1,19 @@
+# vim: ft=fhk
+
+model global {
+	x = 1
+	y = [1, 2, 3]
+	z = x+1
+}
+
+### local q = G:newquery("global")
+### q:add("x")
+### q:add("y")
+### q:add("x+z")
+### compile()
+### local rs = G:bindings("m")
+### assert(rs:match("pub struct Query0 {\n    pub x: f64,\n    pub y: Tensor<f64, 1>,\n    pub v3: f64,\n}"))
+### assert(rs:match("fn m_query0%("))
+### local h = G:bindings("m", "c")
+### assert(h:match("struct { double %*e; int32_t n%[1%]; } y;"))
+### assert(h:match("int32_t m_query0%(fhk_Instance %*instance, m_query0_result %*result%);"))
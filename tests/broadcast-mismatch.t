@@ -0,0 +1,10 @@
+# vim: ft=fhk
+
+model global {
+	v = [| 1 2 3 |]
+	w = [| 1 2 |]
+}
+
+### fail("v+w", "aborted")
+### compilefail("sum([| 1 2 3 |] * [| 1 2 |])", "shape mismatch")
+### compilefail("[| 1 2; 3 4 |] + [| 1 2 |]", "cannot broadcast")
@@ -0,0 +1,13 @@
+# vim: ft=fhk
+
+model global {
+	v = [| 1 2 3 |]
+	w = [| 10 20 30 |]
+	m = [| 1 2; 3 4 |]
+	a = 2*v
+	b = v+w
+	c = sum(m-1)
+	d = sum(v*w)
+}
+
+### result { a={2,4,6}, b={11,22,33}, c=6, d=140 }
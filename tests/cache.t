@@ -0,0 +1,33 @@
+# vim: ft=fhk
+
+### local tmp = os.tmpname()
+### os.remove(tmp)
+### local dir = tmp:match("^(.*)/")
+### local src = "model global { x = 1 y = x+1 }"
+### local function cached(graph)
+###   return io.open(string.format("%s/%s.fhkimg", dir, graph:hash()), "rb")
+### end
+### -- the first compilation stores the image, the second one loads it without lowering anything.
+### local G1 = fhk.newgraph()
+### G1:define(src)
+### local q1 = G1:newquery("global", "y")
+### local image1 = G1:compile({cache=dir})
+### check({q1.query(image1:newinstance(alloc)):unpack()}, {2})
+### local fp = assert(cached(G1))
+### fp:close()
+### local G2 = fhk.newgraph()
+### G2:define(src)
+### local q2 = G2:newquery("global", "y")
+### local image2 = G2:compile({cache=dir})
+### assert(not G2:ir():match('"op":'))
+### check({q2.query(image2:newinstance(alloc)):unpack()}, {2})
+### os.remove(string.format("%s/%s.fhkimg", dir, G1:hash()))
+### -- function pointers don't survive the process, so images that call them aren't stored.
+### local ffi = require "ffi"
+### local fptr = ffi.cast("double (*)(double)", function(x) return 2*x end)
+### local G3 = fhk.newgraph()
+### G3:define(string.format("model global { fptr = 0x%x y = call C[fptr] (1: double): double }",
+###   ffi.cast("intptr_t", fptr)))
+### local q3 = G3:newquery("global", "y")
+### check({q3.query(G3:compile({cache=dir}):newinstance(alloc)):unpack()}, {2})
+### assert(not cached(G3))
@@ -0,0 +1,12 @@
+# vim: ft=fhk
+### G:verify()
+### G:passes({"inline", "fold", "cfg"})
+
+model global {
+	x = 3
+	a = if x > 1 then (if x > 2 then x*10 else 0) else -1
+	b = if x = 3 then a+1 else a-1
+	c = if x < 0 then 0 else if x < 1 then 1 else if x < 5 then 2 else 3
+}
+
+### result { a=30, b=31, c=2 }
@@ -0,0 +1,13 @@
+# vim: ft=fhk
+
+model global {
+	x = 5
+	a = 0 <= x < 10
+	b = 0 <= x < 5
+	c = 10 > x >= 5 > 1
+	y = 1 where 0 < x <= 3
+	y = 2 where 3 < x <= 6
+	y = 3
+}
+
+### result { a=true, b=false, c=true, y=2 }
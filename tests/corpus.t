@@ -0,0 +1,11 @@
+# vim: ft=fhk
+
+### local failed, results = fhk.runcorpus("corpus")
+### for _,r in ipairs(results) do assert(r.ok, r.err) end
+### assert(failed == 0 and #results == 3)
+### failed, results = fhk.runcorpus("corpus", {cases={"sum-input"}, tol=1e-9})
+### assert(failed == 0 and #results == 1 and results[1].name == "sum-input")
+### local ok, err = pcall(fhk.runcase, "corpus/no-such-case")
+### assert(not ok and err:match("missing model.fhk"))
+### ok, err = pcall(fhk.runcorpus, "corpus/sum-input")
+### assert(not ok and err:match("no opt.cases and no cases file"))
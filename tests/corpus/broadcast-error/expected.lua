@@ -0,0 +1,3 @@
+return {
+	global = { x = 0 }
+}
@@ -0,0 +1,3 @@
+return {
+	global = { t = 9 }
+}
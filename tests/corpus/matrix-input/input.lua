@@ -0,0 +1,3 @@
+return {
+	global = { m = {{1, 2, 3}, {4, 5, -6}} }
+}
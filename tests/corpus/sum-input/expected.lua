@@ -0,0 +1,3 @@
+return {
+	global = { y = 3, s = 6.5, v = {1, 2, 3.5} }
+}
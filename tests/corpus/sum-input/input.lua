@@ -0,0 +1,3 @@
+return {
+	global = { x = 2, v = {1, 2, 3.5} }
+}
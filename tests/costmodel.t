@@ -0,0 +1,12 @@
+# vim: ft=fhk
+### G:costmodel("aarch64")
+### G:fastmath()
+### assert(not pcall(G.costmodel, G, "z80"))
+
+model global {
+	x = 3
+	y = x/4 + x^3
+	z = x/3
+}
+
+### result { y=27.75, z=1 }
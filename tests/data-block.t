@@ -0,0 +1,13 @@
+# vim: ft=fhk
+
+model global {
+	v = [| 1 2 3.5 |]
+	m = [|
+		1 2 3;
+		4 5 6
+	|]
+	a = sum(v)
+	b = sum(m)
+}
+
+### result { v={1,2,3.5}, a=6.5, b=21 }
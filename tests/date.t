@@ -0,0 +1,15 @@
+# vim: ft=fhk
+
+model global {
+	d = @2024-03-01
+	e = d + 365
+	n = @2024-03-01 - @2024-02-01
+	y = year(e)
+	m = month(e)
+	dd = day(e)
+	y0 = year(@1969-12-31)
+	m0 = month(@2000-02-29)
+}
+
+### result { d=19783, n=29, y=2025, m=3, dd=1, y0=1969, m0=2 }
+### assert(not pcall(G.define, G, "model global x = @2023-02-29"))
@@ -0,0 +1,36 @@
+# vim: ft=fhk
+
+### local db = os.tmpname()
+### local src = os.tmpname()
+### local function write(path, s)
+###   local fp = assert(io.open(path, "w"))
+###   fp:write(s)
+###   fp:close()
+### end
+### -- failures are recorded, but not answered from the database: the second compilation fails
+### -- again on its own.
+### local G1 = fhk.newgraph()
+### G1:define([[model global x = call Lua["syntax error"]()]])
+### G1:newquery("global", "x")
+### for _=1, 2 do assert(not pcall(G1.compile, G1, {db=db})) end
+### local recs = fhk.history(db, G1:hash())
+### assert(#recs == 2 and recs[1].status == "error" and recs[2].status == "error")
+### assert(recs[2].message == recs[1].message)
+### -- the hash covers the files that the model depends on outside of the graph.
+### write(src, "return function() return 1 end")
+### local G2 = fhk.newgraph()
+### G2:define("model global { x = 1 }")
+### local h1 = G2:hash({src})
+### write(src, "return function() return 2 end")
+### assert(G2:hash({src}) ~= h1)
+### assert(G2:hash() ~= h1)
+### local q = G2:newquery("global", "x")
+### check({q.query(G2:compile({db=db, sources={src}}):newinstance(alloc)):unpack()}, {1})
+### recs = fhk.history(db, G2:hash({src}))
+### assert(#recs == 1 and recs[1].status == "ok")
+### -- compiling doesn't change the hash, so it matches the recorded compilation afterwards.
+### local h2 = G2:hash({src})
+### G2:compile({db=db, sources={src}})
+### assert(G2:hash({src}) == h2 and #fhk.history(db, h2) == 2)
+### os.remove(db)
+### os.remove(src)
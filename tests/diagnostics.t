@@ -0,0 +1,7 @@
+# vim: ft=fhk
+### assert(not pcall(G.define, G, "model global {\n\tx = = 1\n}"))
+### local text = G:diagnostics()
+### assert(text:match("^error%[E%d+%]: ") and text:match("line 2"))
+### local json = G:diagnostics("json", true)
+### assert(json:match('^%[{"code":"E%d+","severity":"error"') and json:match('"line":2'))
+### assert(G:diagnostics() == "")
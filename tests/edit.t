@@ -0,0 +1,20 @@
+# vim: ft=fhk
+
+model global {
+	x = 1
+	y = x+1
+}
+
+### local x, y = G:var(nil, "x"), G:var(nil, "y")
+### local function modelof(var)
+###   for o in G:objects() do
+###     if o.op == "MOD" and o.value[1].var == var then return o end
+###   end
+### end
+### G:setvalue(modelof(y), y, G:expr(nil, "x*10"))
+### local k = modelof(x).value[1].value
+### G:setconst(k, 3)
+### assert(not pcall(G.setconst, G, k, 0.5))
+### assert(not pcall(G.setvalue, G, modelof(y), y, G:expr(nil, "y+1")))
+### assert(not pcall(G.setvalue, G, modelof(x), y, G:expr(nil, "2")))
+### result { y=30 }
@@ -0,0 +1,15 @@
+# vim: ft=fhk
+
+model global {
+	price = 1999i64
+	qty = 250i64
+	total = fixmul(price, qty, 2)
+	neg = fixmul(-price, qty, 2)
+	unit = fixdiv(total, qty, 2)
+	third = fixdiv(100i64, 300, 2)
+	sum = price + total
+	half = fixdiv(-1i64, 2, 0)
+	big = fixdiv(500000000000000000i64, 3000000, 1)
+}
+
+### result { total=4998, neg=-4998, unit=1999, third=33, sum=6997, half=-1, big=1666666666667 }
@@ -0,0 +1,34 @@
+# vim: ft=fhk
+### G:divzero(0)
+
+model global {
+	x = 1.5
+	y = 2.5
+	n = 3
+	m = 7
+	a = x - -y
+	b = -x + y
+	c = n - -m
+	d = -(n-m)
+	e = 0-n
+	f = x * -1
+	g = 0/n
+	h = (n*2)*3
+	i = x < x
+	j = n == n
+	k = x / -1
+}
+
+### result { a=4, b=1, c=10, d=4, e=-3, f=-1.5, g=0, h=18, i=false, j=true, k=-1.5 }
+
+### local ffi = require "ffi"
+### local v = ffi.new("int64_t[1]", {5})
+### local G2 = fhk.newgraph()
+### G2:define(string.format("model global { x = load'i64(0x%x) w = x/x u = (2*x+1)/(2*x+1) }",
+###   ffi.cast("intptr_t", ffi.cast("void *", v))))
+### local q = G2:newquery("global", "w", "u")
+### local image = G2:compile()
+### -- x may be zero, so x/x keeps its trap. 2*x+1 is odd.
+### local _, ndiv = G2:ir("canonical"):gsub(" DIV", "")
+### assert(ndiv == 1)
+### check({q.query(image:newinstance(alloc)):unpack()}, {1, 1})
@@ -0,0 +1,14 @@
+# vim: ft=fhk
+
+model global {
+	x = 1.5
+	n = 3
+	a = x^5
+	b = (n+1)+2
+	c = x/4
+	d = -(-x)
+	e = n-n
+	f = not not true
+}
+
+### result { a=1.5^5, b=6, c=0.375, d=1.5, e=0, f=true }
@@ -0,0 +1,15 @@
+# vim: ft=fhk
+
+model global {
+	n = -(-127i8 - 1i8)
+	m = 100i8 + 100i8
+	k = 5i8 - (-127i8 - 1i8)
+}
+
+### result { n=-128, m=-56, k=-123 }
+### local G2 = fhk.newgraph()
+### G2:define("model global q = (-127i8 - 1i8) / (-1i8)")
+### G2:newquery("global", "q")
+### G2:compile()
+### -- MIN/-1 traps at runtime, so it must not fold.
+### assert(G2:ir("canonical"):match(" DIV"))
@@ -0,0 +1,10 @@
+# vim: ft=fhk
+
+model global {
+	x = 0.1 + 0.2
+	y = 1 where x = 0.3
+	y = 2
+}
+
+### result { y=2 }
+### assert(G:diagnostics():match("^warning%[W0202%]: exact float equality"))
@@ -0,0 +1,13 @@
+# vim: ft=fhk
+### G:guardeps(1e-9)
+
+model global {
+	x = 0.1 + 0.2
+	y = 1 where x = 0.3
+	y = 2
+	z = 1 where x != 0.3
+	z = 2
+}
+
+### result { y=1, z=2 }
+### assert(not G:diagnostics():match("W0202"))
@@ -0,0 +1,19 @@
+# vim: ft=fhk
+### G:hooks()
+
+model global {
+	x = 1
+	y = x+1
+}
+
+### local image = compile()
+### local enters, exits = {}, 0
+### image:sethooks(
+###   function(func, start, end_) table.insert(enters, image:hookname(func)) end,
+###   function() exits = exits+1 end
+### )
+### result { y=2 }
+### assert(#enters > 0 and exits == #enters)
+### local found = false
+### for _,name in ipairs(enters) do found = found or name:match("global") ~= nil end
+### assert(found)
@@ -0,0 +1,17 @@
+# vim: ft=fhk
+
+### local ffi = require "ffi"
+### mul = ffi.cast("double (*)(void *, double, double)", function(_, a, b) return a*b end)
+### G:hostfunc("mul", "f64", {"f64", "f64"}, mul, true)
+### local ok, err = pcall(G.hostfunc, G, "mul", "f64", {"f64"}, mul)
+### assert(not ok and err:match("host function name taken"))
+### ok, err = pcall(G.hostfunc, G, "bad", "str", {}, mul)
+### assert(not ok and err:match("invalid host function signature"))
+
+model global {
+	x = 3
+	y = mul(x, 2.5)
+	z = call Host["mul"] (y, 2)
+}
+
+### result { y=7.5, z=15 }
@@ -0,0 +1,27 @@
+# vim: ft=fhk
+### G:icheck(0)
+
+model global {
+	x = 3
+	a = x*x + 2*x + 1
+	b = if a > 10 then a/2 else a*2
+	c = a^0.5 + b^2
+}
+
+### result { a=16, b=8, c=68 }
+### -- the rewrites that fast math allows stay within the tolerance.
+### local G2 = fhk.newgraph()
+### G2:fastmath()
+### G2:icheck(1e-9)
+### G2:define("model global { x = 4 y = x^0.5 * x^0.5 + x^3 }")
+### local q = G2:newquery("global", "y")
+### check({q.query(G2:compile():newinstance(alloc)):unpack()}, {68})
+### -- with fast math, x/3 becomes x*(1/3), which rounds 10/3 down by one ulp. the check sees
+### -- both arms of the if and reports it with zero tolerance.
+### local G3 = fhk.newgraph()
+### G3:fastmath()
+### G3:icheck(0)
+### G3:define("model global { u = 1 z = (if u > 0 then 10.0 else 20.0) / 3 }")
+### G3:newquery("global", "z")
+### local ok, err = pcall(G3.compile, G3)
+### assert(not ok and err:match("interval check failed"), err)
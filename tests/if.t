@@ -0,0 +1,17 @@
+# vim: ft=fhk
+
+model global {
+	x = 2
+	a = if x > 1 then x*10 else call Lua["return function() error('not taken') end"] ()
+	b = if x < 1 then 0 else if x < 2 then 1 else 2
+	c = x > 0 or call Lua["return function() error('not taken') end"] ()
+	d = x < 0 and call Lua["return function() error('not taken') end"] ()
+	e = if eager x = 2 then 5 else 6
+	f = sum(if eager [true, false, true] then [1, 2, 3] else [10, 20, 30])
+	# keywords can still be names when quoted.
+	`eager` = x > 1
+	g = if `eager` then 1 else 2
+	h = if eager `eager` then 3 else 4
+}
+
+### result { a=20, b=2, c=true, d=false, e=5, f=24, g=1, h=3 }
@@ -0,0 +1,12 @@
+# vim: ft=fhk
+### G:impute("model global x = 0")
+### G:impute("model global w = 1 where z > 10")
+
+model global {
+	y = 2
+	x = y+1 where y > 5
+	z = 3
+}
+
+### result { x=0, y=2 }
+### fail("w", "aborted")
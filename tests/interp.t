@@ -0,0 +1,30 @@
+# vim: ft=fhk
+
+model global {
+	n = 7
+	s = sum([| 1 2 3 |]) + n
+}
+
+### result { s=13 }
+### local ffi = require "ffi"
+### if fhk.interp then
+###   -- the interpreter reports traps as errors instead of signals.
+###   local G2 = fhk.newgraph()
+###   G2:define("model global { n = 7 z = 0 q = n/z }")
+###   local q = G2:newquery("global", "q")
+###   local ok, err = pcall(q.query, G2:compile():newinstance(alloc))
+###   assert(not ok and err:match("integer division by zero"))
+###   local G3 = fhk.newgraph()
+###   G3:newquery("global", "call Lua[\"return function(x) return x end\"] (1)")
+###   ok, err = pcall(G3.compile, G3)
+###   assert(not ok and err:match("Lua calls are not supported by the interpreter"))
+###   -- host functions go through a language call too.
+###   local G4 = fhk.newgraph()
+###   local id = ffi.cast("double (*)(void *, double)", function(_, x) return x end)
+###   G4:hostfunc("id", "f64", {"f64"}, id)
+###   G4:define("model global { x = 1 y = id(x) }")
+###   G4:newquery("global", "y")
+###   ok, err = pcall(G4.compile, G4)
+###   assert(not ok and err:match("Host calls are not supported by the interpreter %(feature `interp`%)"))
+###   id:free()
+### end
@@ -0,0 +1,15 @@
+# vim: ft=fhk
+
+### local function canonical(src)
+###   local graph = fhk.newgraph()
+###   graph:define(src)
+###   graph:newquery("global"):add("z")
+###   graph:compile()
+###   return graph:ir("canonical")
+### end
+### local a = canonical("model global { x = 1 y = x+1 z = y*y }")
+### assert(a:match("FUNC query"))
+### assert(fhk.irdiff(a, a) == nil)
+### assert(fhk.irdiff(a, canonical("model global { x = 1 y = x+1 z = y*y }")) == nil)
+### local report = fhk.irdiff(a, canonical("model global { x = 1 y = x+2 z = y*y }"))
+### assert(report and report:match("~ "))
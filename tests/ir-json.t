@@ -0,0 +1,12 @@
+# vim: ft=fhk
+
+model global {
+	x = 1
+	y = x+1
+}
+
+### result { y=2 }
+### local ir = G:ir()
+### assert(ir:match('^%[{"id":0,'))
+### assert(ir:match('"role":"query","source":"QUERY'))
+### assert(ir:match('"op":"RET"'))
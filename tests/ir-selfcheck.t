@@ -0,0 +1,3 @@
+# vim: ft=fhk
+
+### G:selfcheck()
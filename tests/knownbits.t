@@ -0,0 +1,13 @@
+# vim: ft=fhk
+
+table tab[4]
+model tab[i] {
+	a = if 2*i = 1 then 100 else i
+	b = if 4*i+1 != 2 then 1 else 100
+}
+model global {
+	sa = sum(tab.a)
+	sb = sum(tab.b)
+}
+
+### result { sa=0+1+2+3, sb=4 }
@@ -0,0 +1,6 @@
+# vim: ft=fhk
+### local ok, err = pcall(G.define, G, "model global x = call Fortran(1: double): double")
+### assert(not ok)
+### assert(err:match("unsupported language: Fortran"))
+### assert(err:match("available languages: .*Host"))
+### assert(G:diagnostics("json", true):match('"code":"E0004"'))
@@ -0,0 +1,11 @@
+# vim: ft=fhk
+
+model global {
+	x = 2
+	a = let y = x+1, z = y*y in z-y
+	b = let x = 1 in let x = x+10 in x
+	c = let n = call Lua["local n = 0 return function() n = n+1 return n end"] () in n+n
+	d = let p, q = call Lua["return function() return 1, 2 end"] (), r = p+q in r*q
+}
+
+### result { a=6, b=11, c=2, d=6 }
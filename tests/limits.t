@@ -0,0 +1,36 @@
+# vim: ft=fhk
+
+model global {
+	x = 1
+	y = x+1
+	z = y*y + x*y
+}
+
+### G:limits(nil, nil, nil, 5)
+### compilefail("z", "model too large: %d+ IR instructions .*largest functions")
+### G:limits(nil, nil, 100)
+### compilefail("z", "model too large: %d+ bytes .*largest objects")
+### -- with the limit at the size of the lowered IR, the inliner keeps the calls it can't afford
+### -- instead of failing the compilation.
+### local function lowered()
+###   local graph = fhk.newgraph()
+###   graph:optimize(0)
+###   graph:define("model global { x = 1 y = x+1 z = y*y + x*y }")
+###   graph:newquery("global", "z")
+###   graph:compile()
+###   local _, n = graph:ir():gsub('"op":', "")
+###   return n
+### end
+### local G2 = fhk.newgraph()
+### G2:limits(nil, nil, nil, lowered())
+### G2:define("model global { x = 1 y = x+1 z = y*y + x*y }")
+### local q = G2:newquery("global", "z")
+### check({q.query(G2:compile():newinstance(alloc)):unpack()}, {6})
+### -- right associative operators nest without parentheses, and count towards the depth too.
+### local G3 = fhk.newgraph()
+### G3:limits(50)
+### local ok, err = pcall(G3.define, G3, "model global x = 1"..string.rep("^1", 100))
+### assert(not ok and err:match("expression nested too deeply"))
+### G3:define("model global x = 1"..string.rep("^1", 20))
+### ok, err = pcall(G3.define, G3, "model global y = 1"..string.rep("+1", 100))
+### assert(ok, err)
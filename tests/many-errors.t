@@ -0,0 +1,22 @@
+# vim: ft=fhk
+
+model global {
+	x = y
+	z = 1
+}
+
+### local qx = G:newquery("global")
+### qx:add("x")
+### local qz = G:newquery("global")
+### qz:add("z")
+### compile()
+### local insts = { newinstance(), newinstance() }
+### local errors = {}
+### local res = qx:many(insts, nil, nil, errors)
+### assert(res[1] == false and res[2] == false)
+### assert(#errors == 2 and errors[2].instance == 2)
+### assert(errors[1].kind == "abort" and errors[1].message:match("aborted"))
+### assert(not pcall(qx.many, qx, insts))
+### errors = {}
+### res = qz:many(insts, nil, nil, errors)
+### assert(#errors == 0 and res[1] and res[2])
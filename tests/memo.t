@@ -0,0 +1,20 @@
+# vim: ft=fhk
+
+model global {
+	x = 2
+	y = memo(x*3) + memo(x*3)
+	z = let m = memo(y+x) in m*m
+}
+
+### result { y=12, z=196 }
+### -- equal memos in the same table share one variable, so the call runs once.
+### local G2 = fhk.newgraph()
+### G2:define([[
+### model global {
+### 	a = memo(call Lua["return function(x) memocalls = memocalls + 1 return x end"] (1)) + 1
+### 	b = memo(call Lua["return function(x) memocalls = memocalls + 1 return x end"] (1)) + 2
+### }]])
+### local q = G2:newquery("global", "a", "b")
+### _G.memocalls = 0
+### check({q.query(G2:compile():newinstance(alloc)):unpack()}, {2, 3})
+### assert(_G.memocalls == 1)
@@ -0,0 +1,13 @@
+# vim: ft=fhk
+### G:verify()
+
+table tab[3]
+model tab[i] {
+	a = i*2
+	b = i*2
+	c = a+1
+	d = b+1
+}
+model global s = sum(tab.c) + sum(tab.d)
+
+### result { s=18 }
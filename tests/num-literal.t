@@ -0,0 +1,28 @@
+# vim: ft=fhk
+
+model global {
+	a = 0xff + 0b1010
+	b = 1_000_000
+	c = 1.5f32
+	d = 7i8
+	e = 0xffu8
+	f = 2.5e1f64
+}
+
+### result { a=265, b=1000000, c=1.5, d=7, e=255, f=25 }
+### -- typed literals must fit their type.
+### local function defined(src)
+###   local g = fhk.newgraph()
+###   return pcall(g.define, g, "model global x = "..src)
+### end
+### for _,src in ipairs({"256u8", "128i8", "0x100u8", "4294967296u32", "1e40f32"}) do
+###   local ok, err = defined(src)
+###   assert(not ok and err:match("literal out of range"), src)
+### end
+### for _,src in ipairs({"255u8", "127i8", "0xffffffffu32", "18446744073709551615u64"}) do
+###   assert(defined(src))
+### end
+### local G2 = fhk.newgraph()
+### G2:define("model global x = 0xffffffffffffffffu64")
+### local q = G2:newquery("global", "x")
+### assert(q.query(G2:compile():newinstance(alloc)):unpack() == 0xffffffffffffffffULL)
@@ -0,0 +1,15 @@
+# vim: ft=fhk
+### local seen = {}
+### G:observe({"lower", "fold", "optimize"}, function(point, ir)
+###   assert(ir:match('"op":'))
+###   seen[point] = (seen[point] or 0) + 1
+### end)
+### assert(not pcall(G.observe, G, "lower,nosuch", function() end))
+
+model global {
+	x = 1
+	y = x+1
+}
+
+### result { y=2 }
+### assert(seen.lower == 1 and seen.optimize == 1 and seen.fold >= 1)
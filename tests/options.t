@@ -0,0 +1,18 @@
+# vim: ft=fhk
+### G:optimize(1)
+### assert(not pcall(G.optimize, G, 7))
+### G:optimize("-f")
+### assert(not pcall(G.divzero, G, 0))
+### assert(not pcall(G.fastmath, G))
+### G:optimize(2)
+### G:divzero(0)
+### assert(not pcall(G.optimize, G, "-f"))
+### local ok, err = pcall(G.icheck, G, 0/0)
+### assert(not ok and err:match("invalid interval check tolerance"))
+
+model global {
+	x = 7
+	y = x*x + 3
+}
+
+### result { y=52 }
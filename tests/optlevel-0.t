@@ -0,0 +1,10 @@
+# vim: ft=fhk
+### G:optimize(0)
+
+model global {
+	x = 3
+	y = if x > 2 then x*10 else 0
+	c = if x < 0 then 0 else if x < 1 then 1 else if x < 5 then 2 else 3
+}
+
+### result { y=30, c=2 }
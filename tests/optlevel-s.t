@@ -0,0 +1,10 @@
+# vim: ft=fhk
+### G:optimize("Os")
+
+model global {
+	x = 7
+	y = x*x + 3
+	z = sum([1,2,3,4]) * y
+}
+
+### result { y=52, z=520 }
@@ -0,0 +1,16 @@
+# vim: ft=fhk
+### G:audit()
+
+model global {
+	x = 2
+	y = x*1 + 0
+}
+
+### result { y=2 }
+### assert(G:passeffects():match("fold %d+ %(%d+%)"))
+### assert(G:passeffects("json"):match('^%[{"source":'))
+### local folded = false
+### for _,f in ipairs(G:passeffects("table")) do
+###   if f.passes.fold and f.passes.fold.rewrites > 0 then folded = true end
+### end
+### assert(folded)
@@ -0,0 +1,15 @@
+# vim: ft=fhk
+### G:profile()
+
+model global {
+	x = 5
+	y = 1 where x < 3
+	y = 2 where x < 7
+	y = 3
+}
+
+### result { y=2 }
+### local data = G:saveprofile(compile())
+### assert(#data > 8)
+### G:setprofile(data)
+### assert(not pcall(G.setprofile, G, "not a profile"))
@@ -0,0 +1,13 @@
+# vim: ft=fhk
+### assert(not pcall(G.define, G, "model global p = piecewise continuous t = x { t, 1: 2*t }"))
+### assert(G:diagnostics("text", true):match("E0024"))
+### assert(not pcall(G.define, G, "model global p = piecewise t = x { t, 2: 0, 1: 1 }"))
+### assert(G:diagnostics("text", true):match("E0023"))
+
+model global {
+	x = 15
+	y = piecewise continuous t = x { 2*t, 10: 20 + (t-10), 20: 30 }
+	z = piecewise t = x-20 { -1, 0: 1 }
+}
+
+### result { y=25, z=-1 }
@@ -0,0 +1,34 @@
+# vim: ft=fhk
+
+### local ok, err = pcall(G.loadplugin, G, "./no-such-plugin.so")
+### assert(not ok and err:match("failed to load library"))
+### ok, err = pcall(G.loadplugin, G, "libm.so.6")
+### assert(not ok and err:match("doesn't export fhk_plugin"))
+### -- build a plugin with the system C compiler. without one, there's nothing more to test.
+### local base = os.tmpname()
+### local fp = assert(io.open(base..".c", "w"))
+### fp:write([[
+### #include <stdint.h>
+### typedef struct {
+###   const char *name; const uint8_t *params; uint32_t nparam; uint8_t ret; uint8_t pure;
+###   void *func; void *data;
+### } fhk_PluginFunc;
+### typedef struct { uint32_t abi; uint32_t nfunc; const fhk_PluginFunc *funcs; } fhk_Plugin;
+### static double twice(void *data, double x) { return 2*x; }
+### static const uint8_t params[] = { 0 };
+### static const fhk_PluginFunc funcs[] = { { "twice", params, 1, 0, 1, (void *) twice, 0 } };
+### static const fhk_Plugin plugin = { 1, 1, funcs };
+### const fhk_Plugin *fhk_plugin(void) { return &plugin; }
+### ]])
+### fp:close()
+### local st = os.execute(string.format("cc -shared -fPIC -o %s.so %s.c 2>/dev/null", base, base))
+### if st == 0 or st == true then
+###   assert(G:loadplugin(base..".so") == 1)
+###   ok, err = pcall(G.loadplugin, G, base..".so")
+###   assert(not ok and err:match("taken name"))
+###   G:define("model global { x = 3 y = twice(x) }")
+###   result { y=6 }
+### end
+### os.remove(base..".c")
+### os.remove(base..".so")
+### os.remove(base)
@@ -0,0 +1,10 @@
+# vim: ft=fhk
+
+model global {
+	x = 2.0
+	a = poly(x, [1, 2, 3])
+	b = poly(3, [0.5, 0, 1])
+	c = poly(x, [4])
+}
+
+### result { a=1+2*2+3*4, b=9.5, c=4 }
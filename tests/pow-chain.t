@@ -0,0 +1,12 @@
+# vim: ft=fhk
+### G:fastmath()
+
+model global {
+	x = 1.1
+	y = 2.25
+	a = x^15
+	b = x^7
+	c = y^0.5
+}
+
+### result { a=1.1^15, b=1.1^7, c=1.5 }
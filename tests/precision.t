@@ -0,0 +1,27 @@
+# vim: ft=fhk
+### G:floattype("f32")
+
+model global {
+	x = 0.1
+	y = x*3
+}
+
+### local q = query("global", "y")
+### local image = compile()
+### local y = q.query(image:newinstance(alloc)):unpack()
+### assert(y ~= 0.1*3 and math.abs(y-0.3) < 1e-6)
+### local stats = fhk.compareprecision(
+###   function(graph)
+###     graph:define([[
+###       model global {
+###         x = call Lua["return function() return precision_x end"]()
+###         z = x^2 + 1/3
+###       }
+###     ]])
+###   end,
+###   "global", {"z"},
+###   { samples=10, sample=function(i) _G.precision_x = i/7 end }
+### )
+### assert(#stats == 1 and stats[1].n == 10)
+### assert(stats[1].maxrel > 0 and stats[1].maxrel < 1e-6)
+### assert(stats[1].p99rel <= stats[1].maxrel and stats[1].meanrel <= stats[1].maxrel)
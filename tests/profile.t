@@ -0,0 +1,16 @@
+# vim: ft=fhk
+### G:profile()
+
+model global {
+	x = 1
+	y = x+1
+}
+
+### result { y=2 }
+### local image = compile()
+### local prof = image:profile()
+### assert(#prof > 0)
+### for _,p in ipairs(prof) do assert(p.calls == 1) end
+### assert(image:profreport():match("global"))
+### image:profreset()
+### assert(#image:profile() == 0)
@@ -0,0 +1,32 @@
+# vim: ft=fhk
+
+model global {
+	a = 1
+	b = 2
+	c = a+b
+	d = a*b
+	e = c+d
+	x = y
+	f = if e > 100 then x else e
+}
+
+### G:optimize(0)
+### G:parallel()
+### local ffi = require "ffi"
+### local query = G:newquery("global", "f")
+### local qx = G:newquery("global", "x")
+### local image = compile()
+### local tasks = 0
+### local pool = ffi.cast("fhk_Pool *", function(_, task, arg, num)
+###   for i=num-1, 0, -1 do
+###     tasks = tasks+1
+###     task(arg, i)
+###   end
+### end)
+### -- `x` fails when computed ahead, but the query never reads it.
+### check({query:parallel(image, newinstance(), pool):unpack()}, {5})
+### if not fhk.interp then assert(tasks > 0) end
+### check({query:parallel(image, newinstance()):unpack()}, {5})
+### local ok, err = pcall(qx.parallel, qx, image, newinstance(), pool)
+### assert(not ok and err:match("aborted"))
+### pool:free()
@@ -0,0 +1,18 @@
+# vim: ft=fhk
+### G:verify()
+### G:passes({"inline", "fold", "range", "cfg"})
+
+table tab[4]
+model tab[i] {
+	a = if i < 0 then 100 else i
+	b = if i*i >= 0 then 1 else 100
+}
+model global {
+	x = 3
+	y = if x+1 > x then x*2 else -1
+	sa = sum(tab.a)
+	sb = sum(tab.b)
+	sc = sum([1,2,3,4])
+}
+
+### result { y=6, sa=0+1+2+3, sb=4, sc=10 }
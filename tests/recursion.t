@@ -0,0 +1,13 @@
+# vim: ft=fhk
+
+model global {
+	x = 1
+	y = 1 where x > 0
+	y = y+1 where x < 0
+}
+
+### local query = G:newquery("global", "y")
+### compile()
+### local text = G:diagnostics()
+### assert(text:match("^warning%[W0201%]: ") and text:match(" %-> "))
+### check({query.query(newinstance()):unpack()}, {1})
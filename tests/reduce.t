@@ -0,0 +1,26 @@
+# vim: ft=fhk
+
+### local function interesting(src)
+###   local graph = fhk.newgraph()
+###   local ok, err = pcall(function()
+###     graph:define(src)
+###     graph:newquery("global"):add("d")
+###     graph:compile()
+###   end)
+###   return not ok and tostring(err):match("cannot broadcast") ~= nil
+### end
+### local src = [[
+### model global {
+###   a = 1
+###   b = a+2
+###   c = [| 1 2; 3 4 |] + [| 1 2 |]
+###   d = b*c
+### }
+### table t[2]
+### model t[i] e = i
+### ]]
+### local reduced, calls = fhk.reduce(src, interesting)
+### assert(interesting(reduced) and calls > 1)
+### assert(#reduced < #src)
+### assert(not reduced:match("a = 1") and not reduced:match("table t"))
+### assert(not pcall(fhk.reduce, "model global d = 1", interesting))
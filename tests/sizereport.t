@@ -0,0 +1,13 @@
+# vim: ft=fhk
+### G:profile()
+
+model global {
+	x = 1
+	y = x+1
+}
+
+### result { y=2 }
+### local report = compile():sizereport()
+### assert(report:match("code%%"))
+### assert(report:match("time%%"))
+### assert(report:match("MOD#*%d+%(x%) on line"))
@@ -0,0 +1,29 @@
+# vim: ft=fhk
+
+### local ffi = require "ffi"
+### local v = ffi.new("double[1]", {3})
+### G:define(string.format([[
+### model global {
+### 	x = load'f64(0x%x)
+### 	y = x*x + x
+### }]], ffi.cast("intptr_t", ffi.cast("void *", v))))
+### result { y=12 }
+### -- every address in the code maps to its function, and where the code generator kept track
+### -- of it, to the expression it was lowered from.
+### local image = compile()
+### local base = ffi.cast("uint8_t *", image:mcode())
+### local funcs, exprs = {}, {}
+### for i=0, 0xffff do
+###   local desc = image:srcloc(base+i)
+###   if desc then
+###     if desc:match(" col %d+$") then exprs[desc] = true else funcs[desc] = true end
+###   end
+### end
+### assert(next(funcs))
+### local load, value = false, false
+### for desc in pairs(exprs) do
+###   if desc:match(" on line 2 col 7$") then load = true end
+###   if desc:match(" on line 3 col %d+$") then value = true end
+### end
+### assert(load and value)
+### assert(not image:srcloc(base-1))
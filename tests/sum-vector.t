@@ -0,0 +1,14 @@
+# vim: ft=fhk
+
+model global {
+	v = [| 0.5 1 2 4 8 |]
+	w = [| 0.25 0.5 0.75 1 |]
+	u = [| 3.5 |]
+	m = [| 0.5 1 1.5; 2 2.5 3 |]
+	a = sum(v)
+	b = sum(w)
+	c = sum(u)
+	d = sum(m)
+}
+
+### result { a=15.5, b=2.5, c=3.5, d=10.5 }
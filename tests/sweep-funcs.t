@@ -0,0 +1,12 @@
+# vim: ft=fhk
+### G:verify()
+### G:passes({"fold", "cfg"})
+
+table tab[3]
+model tab[i] a = i*10
+model global {
+	x = 1
+	y = if x > 0 then 2 else sum(tab.a)
+}
+
+### result { y=2 }
@@ -0,0 +1,16 @@
+# vim: ft=fhk
+### G:switchmin(4)
+
+table tab[8]
+model tab[i] {
+	y = 10 where i = 0
+	y = 11 where i = 1
+	y = 12 where i = 2
+	y = 13 where i = 3
+	y = 14 where i = 4
+	y = 15 where i = 5
+	y = 16
+}
+model global s = sum(tab.y)
+
+### result { s=10+11+12+13+14+15+16+16 }
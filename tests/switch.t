@@ -0,0 +1,14 @@
+# vim: ft=fhk
+
+model global {
+	x = 3
+	y = 1000
+	dense = if x = 1 then 10 else if x = 2 then 20 else if x = 3 then 30 else if x = 4 then 40
+		else if x = 5 then 50 else 0
+	sparse = if y = 1 then 1 else if y = 10 then 2 else if y = 100 then 3 else if y = 1000 then 4
+		else if y = 10000 then 5 else 0
+	miss = if x = 10 then 1 else if x = 20 then 2 else if x = 30 then 3 else if x = 40 then 4
+		else -1
+}
+
+### result { dense=30, sparse=4, miss=-1 }
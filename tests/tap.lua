@@ -93,7 +93,7 @@ local function test_compile(T)
 end
 
 local function test_newinstance(T, prev, mask)
-	return test_compile(T):newinstance(T.alloc, nil, prev, mask)
+	return test_compile(T):newinstance(T.guard or T.alloc, nil, prev, mask)
 end
 
 local function test_result(T, results)
@@ -145,7 +145,6 @@ ffi.cdef [[
 
 local function test_alloc(T, _, size, align)
 	print(string.format("[alloc] %d (align: %d)", size, align))
-	-- TODO: it would also be a good idea to put some guard pages here
 	local p = ffi.C.malloc(size)
 	ffi.fill(p, size, 0x29)
 	table.insert(T.allocs, p)
@@ -160,6 +159,13 @@ local function newgraph()
 	if opt then
 		G:optimize(opt)
 	end
+	local icheck = os.getenv("FHK_ICHECK")
+	if icheck then
+		G:icheck(tonumber(icheck))
+	end
+	if os.getenv("FHK_VERIFY") then
+		G:verify()
+	end
 	return G
 end
 
@@ -167,7 +173,8 @@ local function testnew()
 	local env = setmetatable({
 		allocs  = {},
 		G       = newgraph(),
-		check   = check
+		check   = check,
+		fhk     = fhk
 	}, {__index=_G})
 	env.query = bind(env, test_query)
 	env.result = bind(env, test_result)
@@ -176,6 +183,9 @@ local function testnew()
 	env.compilefail = bind(env, test_compilefail)
 	env.newinstance = bind(env, test_newinstance)
 	env.alloc = ffi.cast("void *(*)(void *,size_t,size_t)", bind(env, test_alloc))
+	if os.getenv("FHK_GUARD") then
+		env.guard = fhk.newguard()
+	end
 	return env
 end
 
@@ -184,6 +194,12 @@ local function testfree(T)
 		ffi.C.free(p)
 	end
 	T.alloc:free()
+	if T.guard then
+		local p = T.guard:check()
+		if p then
+			error(string.format("instance padding overwritten at %s", p))
+		end
+	end
 end
 
 local function flush(T, buf, what)
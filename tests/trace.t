@@ -0,0 +1,40 @@
+# vim: ft=fhk
+
+model global {
+	a = 1
+	b = a+1
+	c = b*2
+	d = c+a
+}
+
+### result { d=5 }
+### -- a second compilation runs its passes on the same worker threads.
+### local G2 = fhk.newgraph()
+### G2:define("model global { a = 2 b = a*a c = b+a }")
+### local q = G2:newquery("global", "c")
+### check({q.query(G2:compile():newinstance(alloc)):unpack()}, {6})
+### local function u(n, size)
+###   local b = {}
+###   for i=1, size do b[i] = n%256; n = math.floor(n/256) end
+###   return string.char(unpack(b))
+### end
+### local function rec(time, kind, tid, msg)
+###   return u(time, 8) .. string.char(9, kind) .. u(tid, 2) .. u(#msg, 4) .. msg
+### end
+### local path = os.tmpname()
+### local fp = assert(io.open(path, "wb"))
+### fp:write("fhktrc\0\1",
+###   rec(1000, 1, 0, "optimize"),
+###   rec(2000, 1, 1, "fold f1"),
+###   rec(2500, 1, 0, "fold f0"),
+###   rec(3000, 2, 1, ""),
+###   rec(3500, 2, 0, ""),
+###   rec(4000, 2, 0, ""))
+### fp:close()
+### local text = fhk.trace(path)
+### assert(text:match("  1 SPAN     >fold f1\n"))
+### assert(text:match("  0 SPAN       >fold f0\n"))
+### local json = fhk.trace(path, "json")
+### os.remove(path)
+### assert(json:match('"name":"fold f1","cat":"SPAN","ph":"B","ts":2,"pid":0,"tid":1}'))
+### assert(json:match('"name":"fold f0","cat":"SPAN","ph":"B","ts":2.5,"pid":0,"tid":0}'))
@@ -0,0 +1,11 @@
+# vim: ft=fhk
+### G:decimalcomma()
+
+model global {
+	pituus = 1,5
+	määrä = 2
+	pinta_ala = pituus*määrä
+	summa = call Lua["return function(a, b) return a+b end"] (pituus, 0,25)
+}
+
+### result { ["määrä"]=2, pinta_ala=3, summa=1.75 }
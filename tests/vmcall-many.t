@@ -0,0 +1,28 @@
+# vim: ft=fhk
+
+model global x = 1
+
+table t[4]
+model t[i] v = i*global.x
+model global s = sum(t.v)
+
+### local query = G:newquery("global", "s")
+### compile()
+### local inst1 = newinstance()
+### local inst2 = newinstance()
+### local res = query:many({inst1, inst2})
+### check({res[1]:unpack()}, {6})
+### check({res[2]:unpack()}, {6})
+### local ffi = require "ffi"
+### local tasks = 0
+### local pool = ffi.cast("fhk_Pool *", function(_, task, arg, num)
+###   for i=num-1, 0, -1 do
+###     tasks = tasks+1
+###     task(arg, i)
+###   end
+### end)
+### res = query:many({newinstance(), newinstance()}, pool)
+### check({res[1]:unpack()}, {6})
+### check({res[2]:unpack()}, {6})
+### assert(tasks == 2)
+### pool:free()